@@ -0,0 +1,64 @@
+//! Compare batched field path resolution against resolving paths one by one.
+//!
+//! Run with `cargo bench --bench resolve_paths`; BTF is taken from `BTF_FILE` (raw BTF or ELF)
+//! or, by default, from /sys/kernel/btf/vmlinux.
+
+use std::time::Instant;
+
+use btf::types::*;
+
+fn main() {
+    let path = std::env::var("BTF_FILE").unwrap_or_else(|_| "/sys/kernel/btf/vmlinux".to_owned());
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Skipping benchmark, can't read {}: {}", path, e);
+            return;
+        }
+    };
+    let elf;
+    let btf = if Btf::raw_endian(&data).is_some() {
+        Btf::load_raw(&data).unwrap()
+    } else {
+        elf = object::File::parse(&*data).unwrap();
+        Btf::load(&elf).unwrap()
+    };
+
+    let mut paths = Vec::new();
+    for t in btf.types() {
+        if let BtfType::Struct(t) = t {
+            if t.name.is_empty() {
+                continue;
+            }
+            for m in btf.members(t).iter().filter(|m| !m.name.is_empty()) {
+                paths.push(format!("struct {}.{}", t.name, m.name));
+            }
+        }
+        if paths.len() >= 500 {
+            break;
+        }
+    }
+    let paths = paths.iter().map(|p| p.as_str()).collect::<Vec<&str>>();
+
+    let start = Instant::now();
+    let batched = btf.resolve_paths(&paths);
+    let batched_time = start.elapsed();
+
+    let start = Instant::now();
+    let single = paths
+        .iter()
+        .map(|p| btf.resolve_path(p))
+        .collect::<Vec<_>>();
+    let single_time = start.elapsed();
+
+    for (a, b) in batched.iter().zip(single.iter()) {
+        assert_eq!(a.as_ref().ok(), b.as_ref().ok());
+    }
+    println!(
+        "{} paths: batched {:?}, one by one {:?} ({:.1}x)",
+        paths.len(),
+        batched_time,
+        single_time,
+        single_time.as_secs_f64() / batched_time.as_secs_f64()
+    );
+}
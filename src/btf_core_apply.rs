@@ -0,0 +1,202 @@
+use std::mem::size_of;
+
+use object::{Object, ObjectSection};
+use scroll::{Pread, Pwrite};
+
+use crate::relocator::Reloc;
+use crate::types::*;
+use crate::{btf_error, BtfResult};
+
+const BPF_INSN_SZ: usize = 8;
+
+const BPF_LD: u8 = 0x00;
+const BPF_LDX: u8 = 0x01;
+const BPF_ST: u8 = 0x02;
+const BPF_STX: u8 = 0x03;
+const BPF_ALU: u8 = 0x04;
+const BPF_ALU64: u8 = 0x07;
+const BPF_K: u8 = 0x00;
+const BPF_LD_IMM64: u8 = 0x18;
+const BPF_JMP_CALL: u8 = 0x85;
+/// Helper ID that libbpf uses to poison instructions of failed relocations; verifier rejects
+/// such instruction only if it's reachable, with "invalid func unknown#195896080" error.
+const BPF_POISON_IMM: i32 = 0xbad2310;
+
+/// Apply CO-RE relocations, computed by Relocator against target BTFs (main target BTF, followed by
+/// module BTFs in the same order as added to Relocator), to BPF object file, the same way libbpf
+/// patches instructions at load time, and return relocated object.
+///
+/// Immediates of ALU instructions and 64-bit loads, as well as offsets of memory loads/stores are
+/// rewritten in place. Sizes of loads/stores are adjusted, if accessed field has different size
+/// in target BTF, but only for pointers and unsigned integers, where that's safe. CO-RE
+/// relocations are dropped from .BTF.ext, so that libbpf doesn't try to relocate patched
+/// instructions again; everything else in the object is preserved byte-for-byte.
+///
+/// Instructions of relocations that failed to resolve, given as (section index, record index)
+/// pairs in `poisoned`, are replaced with calls to invalid helper, like libbpf does, so that
+/// only programs actually reaching them fail verification.
+pub fn apply_core_relocs(
+    data: &[u8],
+    local_btf: &Btf,
+    targ_btfs: &[&Btf],
+    relocs: &[Reloc],
+    poisoned: &[(usize, usize)],
+) -> BtfResult<Vec<u8>> {
+    let elf = object::File::parse(data)?;
+    let endian = local_btf.endian();
+    let mut out = data.to_vec();
+    for &(sec_id, reloc_id) in poisoned {
+        let (start, len) = insn_range(&elf, &out, local_btf, sec_id, reloc_id)?;
+        for insn in out[start..start + len].chunks_mut(BPF_INSN_SZ) {
+            insn[0] = BPF_JMP_CALL;
+            insn[1] = 0;
+            insn.pwrite_with(0i16, 2, endian)?;
+            insn.pwrite_with(BPF_POISON_IMM, 4, endian)?;
+        }
+    }
+    for r in relocs {
+        let sec = &local_btf.core_reloc_secs()[r.sec_id];
+        let rec = &sec.recs[r.reloc_id];
+        let (start, len) = insn_range(&elf, &out, local_btf, r.sec_id, r.reloc_id)?;
+        let insn = &mut out[start..start + len];
+        let targ_btf = targ_btfs[r.targ_btf_idx];
+        if let Err(e) = patch_insn(insn, r, local_btf, targ_btf, endian) {
+            return btf_error(format!(
+                "failed to patch instruction #{} of '{}' for {}: {}",
+                rec.insn_off / 8,
+                sec.name,
+                r,
+                e
+            ));
+        }
+    }
+    if let Some((ext_off, _)) = elf
+        .section_by_name(BTF_EXT_ELF_SEC)
+        .and_then(|s| s.file_range())
+    {
+        let ext_off = ext_off as usize;
+        let hdr = out.pread_with::<btf_ext_header_v1>(ext_off, endian)?;
+        if hdr.hdr_len as usize >= size_of::<btf_ext_header_v2>() {
+            let mut hdr = out.pread_with::<btf_ext_header_v2>(ext_off, endian)?;
+            hdr.core_reloc_len = 0;
+            out.pwrite_with(hdr, ext_off, endian)?;
+        }
+    }
+    Ok(out)
+}
+
+/// File range of instruction (two instructions for 64-bit immediate load) of relocation record.
+fn insn_range(
+    elf: &object::File,
+    data: &[u8],
+    local_btf: &Btf,
+    sec_id: usize,
+    reloc_id: usize,
+) -> BtfResult<(usize, usize)> {
+    let sec = &local_btf.core_reloc_secs()[sec_id];
+    let rec = &sec.recs[reloc_id];
+    let (sec_off, sec_sz) = match elf.section_by_name(sec.name).and_then(|s| s.file_range()) {
+        Some(range) => range,
+        None => return btf_error(format!("ELF section '{}' not found", sec.name)),
+    };
+    let insn_off = u64::from(rec.insn_off);
+    let insn_cnt = if insn_off < sec_sz && data[(sec_off + insn_off) as usize] == BPF_LD_IMM64 {
+        2
+    } else {
+        1
+    };
+    if insn_off + (insn_cnt * BPF_INSN_SZ) as u64 > sec_sz {
+        return btf_error(format!(
+            "instruction #{} is out of bounds of section '{}'",
+            rec.insn_off / 8,
+            sec.name
+        ));
+    }
+    Ok(((sec_off + insn_off) as usize, insn_cnt * BPF_INSN_SZ))
+}
+
+fn patch_insn(
+    insn: &mut [u8],
+    r: &Reloc,
+    local_btf: &Btf,
+    targ_btf: &Btf,
+    endian: scroll::Endian,
+) -> BtfResult<()> {
+    let code = insn[0];
+    let new_val = r.targ_value;
+    match code & 0x07 {
+        BPF_ALU | BPF_ALU64 => {
+            if code & 0x08 != BPF_K {
+                return btf_error("ALU instruction doesn't use immediate".to_owned());
+            }
+            if new_val > u64::from(u32::MAX) && new_val as i64 > i64::from(i32::MAX) {
+                return btf_error(format!("value {} doesn't fit into 32 bits", new_val));
+            }
+            insn.pwrite_with(new_val as i32, 4, endian)?;
+        }
+        BPF_LDX | BPF_ST | BPF_STX => {
+            if new_val > i16::MAX as u64 {
+                return btf_error(format!("offset {} doesn't fit into 16 bits", new_val));
+            }
+            if r.kind == BtfCoreRelocKind::ByteOff {
+                adjust_mem_sz(insn, r, local_btf, targ_btf)?;
+            }
+            insn.pwrite_with(new_val as i16, 2, endian)?;
+        }
+        BPF_LD => {
+            if code != BPF_LD_IMM64 || insn.len() < 2 * BPF_INSN_SZ || insn[BPF_INSN_SZ] != 0 {
+                return btf_error("not a plain 64-bit immediate load".to_owned());
+            }
+            insn.pwrite_with(new_val as u32, 4, endian)?;
+            insn.pwrite_with((new_val >> 32) as u32, 12, endian)?;
+        }
+        _ => return btf_error(format!("unsupported instruction code {:#04x}", code)),
+    }
+    Ok(())
+}
+
+/// Change size of memory load/store to match size of relocated field in target BTF.
+fn adjust_mem_sz(insn: &mut [u8], r: &Reloc, local_btf: &Btf, targ_btf: &Btf) -> BtfResult<()> {
+    // bitfields are accessed with loads of their own size
+    if r.local_field_type_id == 0 || r.targ_field_type_id == 0 {
+        return Ok(());
+    }
+    let (local_id, targ_id) = (r.local_field_type_id, r.targ_field_type_id);
+    let (local_sz, targ_sz) = (r.local_field_sz, r.targ_field_sz);
+    if local_sz == targ_sz {
+        return Ok(());
+    }
+    let is_unsigned_int = |btf: &Btf, id: u32| matches!(btf.type_by_id(id), BtfType::Int(t) if t.encoding != BtfIntEncoding::Signed);
+    let both_ptrs = matches!(
+        (local_btf.type_by_id(local_id), targ_btf.type_by_id(targ_id)),
+        (BtfType::Ptr(_), BtfType::Ptr(_))
+    );
+    let both_uints = is_unsigned_int(local_btf, local_id) && is_unsigned_int(targ_btf, targ_id);
+    if !both_ptrs && !both_uints {
+        return btf_error(format!(
+            "can't adjust memory access size from {} to {} bytes for non-pointer, signed or non-integer field",
+            local_sz, targ_sz
+        ));
+    }
+    let insn_sz = match insn[0] & 0x18 {
+        0x00 => 4,
+        0x08 => 2,
+        0x10 => 1,
+        _ => 8,
+    };
+    if insn_sz != local_sz {
+        return btf_error(format!(
+            "memory access size {} doesn't match field size {}",
+            insn_sz, local_sz
+        ));
+    }
+    let bpf_sz = match targ_sz {
+        4 => 0x00,
+        2 => 0x08,
+        1 => 0x10,
+        8 => 0x18,
+        _ => return btf_error(format!("invalid memory access size {}", targ_sz)),
+    };
+    insn[0] = (insn[0] & !0x18) | bpf_sz;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write;
+
+use crate::types::*;
+
+/// Find structurally equivalent types.
+///
+/// `types` is a list of types along with arenas of their members, params and enumerators and
+/// their (already remapped into the same index space) type references; index 0 is expected to
+/// be VOID. Two types are equivalent if they have the
+/// same kind, name, size and layout, and all the types they reference are pairwise equivalent
+/// as well, which also correctly handles reference cycles. Returns, for each type, the index of
+/// the first type equivalent to it (so unique types map to themselves).
+///
+/// Unlike libbpf's dedup, FWDs are not resolved into full struct/union definitions.
+pub fn dedup_types(types: &[(&BtfType, &BtfArena, Vec<u32>)]) -> Vec<u32> {
+    let mut sigs = HashMap::new();
+    let mut classes = types
+        .iter()
+        .map(|(t, arena, _)| {
+            let next = sigs.len() as u32;
+            *sigs.entry(shallow_sig(arena, t)).or_insert(next)
+        })
+        .collect::<Vec<u32>>();
+    let mut class_cnt = sigs.len();
+    loop {
+        let mut keys = HashMap::new();
+        let new_classes = types
+            .iter()
+            .enumerate()
+            .map(|(i, (_, _, refs))| {
+                let mut key = Vec::with_capacity(refs.len() + 1);
+                key.push(classes[i]);
+                key.extend(refs.iter().map(|&r| classes[r as usize]));
+                let next = keys.len() as u32;
+                *keys.entry(key).or_insert(next)
+            })
+            .collect::<Vec<u32>>();
+        classes = new_classes;
+        if keys.len() == class_cnt {
+            break;
+        }
+        class_cnt = keys.len();
+    }
+
+    let mut first = HashMap::new();
+    classes
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| *first.entry(c).or_insert(i as u32))
+        .collect()
+}
+
+/// Everything about the type, except for referenced type IDs.
+pub(crate) fn shallow_sig(arena: &BtfArena, t: &BtfType) -> String {
+    let mut sig = String::new();
+    write_shallow_sig(&mut sig, arena, t).expect("writing into String can't fail");
+    sig
+}
+
+fn write_shallow_sig(sig: &mut String, arena: &BtfArena, t: &BtfType) -> fmt::Result {
+    write!(sig, "{:?}|{}", t.kind(), t.name())?;
+    match t {
+        BtfType::Int(t) => write!(sig, "|{}|{}|{}", t.bits, t.offset, t.encoding)?,
+        BtfType::Array(t) => write!(sig, "|{}", t.nelems)?,
+        BtfType::Struct(t) | BtfType::Union(t) => {
+            write!(sig, "|{}", t.sz)?;
+            for m in arena.members(t) {
+                write!(sig, "|{}:{}:{}", m.name, m.bit_offset, m.bit_size)?;
+            }
+        }
+        BtfType::Enum(t) => {
+            write!(sig, "|{}", t.sz)?;
+            for v in arena.enum_values(t) {
+                write!(sig, "|{}={}", v.name, v.value)?;
+            }
+        }
+        BtfType::Enum64(t) => {
+            write!(sig, "|{}|{}", t.sz, t.signed)?;
+            for v in arena.enum64_values(t) {
+                write!(sig, "|{}={}", v.name, v.value)?;
+            }
+        }
+        BtfType::Fwd(t) => write!(sig, "|{}", t.kind)?,
+        BtfType::Func(t) => write!(sig, "|{}", t.kind)?,
+        BtfType::FuncProto(t) => {
+            for p in arena.params(t) {
+                write!(sig, "|{}", p.name)?;
+            }
+        }
+        BtfType::Var(t) => write!(sig, "|{}", t.kind)?,
+        BtfType::Datasec(t) => {
+            write!(sig, "|{}", t.sz)?;
+            for v in &t.vars {
+                write!(sig, "|{}:{}", v.offset, v.sz)?;
+            }
+        }
+        BtfType::Float(t) => write!(sig, "|{}", t.sz)?,
+        BtfType::DeclTag(t) => write!(sig, "|{}", t.comp_idx)?,
+        BtfType::Void
+        | BtfType::Ptr(_)
+        | BtfType::Typedef(_)
+        | BtfType::Volatile(_)
+        | BtfType::Const(_)
+        | BtfType::Restrict(_)
+        | BtfType::TypeTag(_) => {}
+    }
+    Ok(())
+}
+
+/// Compare two BTFs structurally: returns IDs of types of `a` that have no equivalent type in
+/// `b`, and vice versa. Type IDs themselves don't matter, so this can be used to check that two
+/// differently produced deduplicated BTFs have the same canonical set of types.
+pub fn unmatched_types(a: &Btf, b: &Btf) -> (Vec<u32>, Vec<u32>) {
+    let a_cnt = a.type_cnt();
+    let rebase = |id: u32| if id == 0 { 0 } else { id + a_cnt - 1 };
+    let mut types: Vec<(&BtfType, &BtfArena, Vec<u32>)> =
+        vec![(&BtfType::Void, a.arena(), Vec::new())];
+    for t in &a.types()[1..] {
+        types.push((t, a.arena(), a.type_refs(t)));
+    }
+    for t in &b.types()[1..] {
+        let refs = b.type_refs(t).into_iter().map(rebase).collect();
+        types.push((t, b.arena(), refs));
+    }
+    let canon = dedup_types(&types);
+
+    let a_classes = canon[1..a_cnt as usize].iter().collect::<HashSet<&u32>>();
+    let b_classes = canon[a_cnt as usize..].iter().collect::<HashSet<&u32>>();
+    let a_only = (1..a_cnt)
+        .filter(|&id| !b_classes.contains(&canon[id as usize]))
+        .collect();
+    let b_only = (1..b.type_cnt())
+        .filter(|&id| !a_classes.contains(&canon[rebase(id) as usize]))
+        .collect();
+    (a_only, b_only)
+}
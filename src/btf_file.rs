@@ -0,0 +1,112 @@
+//! Reading of BTF input files, transparently decompressing gzip/zstd/xz-compressed files and
+//! unpacking tarballs (e.g., BTFHub archives) holding a single BTF file.
+
+use std::io::Read;
+use std::ops::Deref;
+
+use crate::{btf_error, BtfResult};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+const TAR_BLOCK_SZ: usize = 512;
+const TAR_MAGIC_OFF: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+/// Contents of input file, memory-mapped if it's stored uncompressed.
+pub enum FileData {
+    Mapped(memmap::Mmap),
+    Decompressed(Vec<u8>),
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Mapped(mmap) => mmap,
+            FileData::Decompressed(data) => data,
+        }
+    }
+}
+
+/// Memory-map file, or read and decompress it, if it's compressed or is a tarball.
+pub fn load_file<P: AsRef<std::path::Path>>(path: P) -> BtfResult<FileData> {
+    let file = std::fs::File::open(path)?;
+    let mmap = unsafe { memmap::Mmap::map(&file) }?;
+    match decompress(&mmap)? {
+        Some(data) => Ok(FileData::Decompressed(data)),
+        None => Ok(FileData::Mapped(mmap)),
+    }
+}
+
+/// Decompress gzip/zstd/xz data and unpack tarball, returns None if data is neither.
+pub fn decompress(data: &[u8]) -> BtfResult<Option<Vec<u8>>> {
+    let mut res = Vec::new();
+    if data.starts_with(GZIP_MAGIC) {
+        flate2::read::MultiGzDecoder::new(data).read_to_end(&mut res)?;
+    } else if data.starts_with(ZSTD_MAGIC) {
+        let mut dec = match ruzstd::StreamingDecoder::new(data) {
+            Ok(dec) => dec,
+            Err(e) => return btf_error(format!("Invalid zstd data: {}", e)),
+        };
+        dec.read_to_end(&mut res)?;
+    } else if data.starts_with(XZ_MAGIC) {
+        let mut input = data;
+        if let Err(e) = lzma_rs::xz_decompress(&mut input, &mut res) {
+            return btf_error(format!("Invalid xz data: {}", e));
+        }
+    } else if is_tar(data) {
+        return untar(data).map(Some);
+    } else {
+        return Ok(None);
+    }
+    if is_tar(&res) {
+        res = untar(&res)?;
+    }
+    Ok(Some(res))
+}
+
+fn is_tar(data: &[u8]) -> bool {
+    data.get(TAR_MAGIC_OFF..TAR_MAGIC_OFF + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+}
+
+/// Extract the only regular file (or the only *.btf file) of tarball.
+fn untar(data: &[u8]) -> BtfResult<Vec<u8>> {
+    let mut files = Vec::new();
+    let mut off = 0;
+    while let Some(hdr) = data.get(off..off + TAR_BLOCK_SZ) {
+        // archive ends with zero blocks
+        if hdr.iter().all(|&b| b == 0) {
+            break;
+        }
+        let name = tar_str(&hdr[0..100]);
+        let sz = match usize::from_str_radix(tar_str(&hdr[124..136]).trim(), 8) {
+            Ok(sz) => sz,
+            Err(_) => return btf_error(format!("Invalid size of tarball entry '{}'", name)),
+        };
+        let start = off + TAR_BLOCK_SZ;
+        let contents = match data.get(start..start.saturating_add(sz)) {
+            Some(contents) => contents,
+            None => return btf_error(format!("Truncated tarball entry '{}'", name)),
+        };
+        if hdr[156] == b'0' || hdr[156] == 0 {
+            files.push((name, contents));
+        }
+        off = start + sz.div_ceil(TAR_BLOCK_SZ) * TAR_BLOCK_SZ;
+    }
+    if files.len() > 1 {
+        files.retain(|(name, _)| name.ends_with(".btf"));
+    }
+    match files.as_slice() {
+        [(_, contents)] => Ok(contents.to_vec()),
+        [] => btf_error("No BTF file found in tarball".to_owned()),
+        _ => btf_error("Tarball contains multiple BTF files".to_owned()),
+    }
+}
+
+fn tar_str(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..len]).unwrap_or("")
+}
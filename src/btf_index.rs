@@ -4,6 +4,7 @@ use crate::types::*;
 
 #[derive(Debug)]
 pub struct BtfIndex<'a> {
+    btf: &'a Btf<'a>,
     name_index: HashMap<&'a str, Vec<u32>>,
 }
 
@@ -12,6 +13,7 @@ const EMPTY_ID_SLICE: &[u32] = &[];
 impl<'a> BtfIndex<'a> {
     pub fn new(btf: &'a Btf<'a>) -> BtfIndex<'a> {
         let mut index = BtfIndex {
+            btf,
             name_index: HashMap::new(),
         };
         for (i, t) in btf.types().iter().enumerate() {
@@ -30,4 +32,49 @@ impl<'a> BtfIndex<'a> {
             .map(|x| &x[..])
             .unwrap_or_else(|| EMPTY_ID_SLICE)
     }
+
+    /// IDs of types of given kind with given name, in increasing order.
+    pub fn get_by_name_kind<'s>(
+        &'s self,
+        name: &str,
+        kind: BtfKind,
+    ) -> impl Iterator<Item = u32> + 's {
+        self.get_by_name(name)
+            .iter()
+            .cloned()
+            .filter(move |&id| self.btf.type_by_id(id).kind() == kind)
+    }
+}
+
+/// Reverse type dependencies: for each type ID, IDs of types directly referencing it (as a
+/// struct/union member, typedef, pointer, modifier, array element, func proto param or return
+/// value, var, etc).
+#[derive(Debug)]
+pub struct BtfRefIndex {
+    referrers: Vec<Vec<u32>>,
+}
+
+impl BtfRefIndex {
+    pub fn new(btf: &Btf) -> BtfRefIndex {
+        let mut referrers = vec![Vec::new(); btf.types().len()];
+        for (i, t) in btf.types().iter().enumerate() {
+            for id in btf.type_refs(t) {
+                if let Some(refs) = referrers.get_mut(id as usize) {
+                    // type referencing the same type multiple times is recorded once
+                    if refs.last() != Some(&(i as u32)) {
+                        refs.push(i as u32);
+                    }
+                }
+            }
+        }
+        BtfRefIndex { referrers }
+    }
+
+    /// IDs of types directly referencing given type, in increasing order.
+    pub fn get_referrers(&self, type_id: u32) -> &[u32] {
+        self.referrers
+            .get(type_id as usize)
+            .map(|x| &x[..])
+            .unwrap_or(EMPTY_ID_SLICE)
+    }
 }
@@ -0,0 +1,136 @@
+//! Access to BTF objects loaded into the running kernel through bpf() syscall.
+
+use std::mem::size_of;
+
+use crate::BtfResult;
+
+const BPF_OBJ_GET_INFO_BY_FD: u32 = 15;
+const BPF_BTF_GET_FD_BY_ID: u32 = 19;
+const BPF_BTF_GET_NEXT_ID: u32 = 23;
+
+const BTF_NAME_MAX: usize = 64;
+
+#[repr(C)]
+#[derive(Default)]
+struct bpf_attr_get_id {
+    id: u32,
+    next_id: u32,
+    open_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct bpf_attr_info {
+    bpf_fd: u32,
+    info_len: u32,
+    info: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct bpf_btf_info {
+    btf: u64,
+    btf_size: u32,
+    id: u32,
+    name: u64,
+    name_len: u32,
+    kernel_btf: u32,
+}
+
+/// BTF object loaded into the kernel, along with its raw BTF data.
+#[derive(Debug)]
+pub struct KernelBtf {
+    pub id: u32,
+    /// Name of kernel's own BTF ("vmlinux" or module name), empty for BTFs loaded by BPF programs
+    pub name: String,
+    /// Whether it's vmlinux or kernel module BTF, as opposed to BTF loaded from user space
+    pub kernel_btf: bool,
+    pub data: Vec<u8>,
+}
+
+impl KernelBtf {
+    /// Kernel module BTF is split BTF on top of vmlinux BTF.
+    pub fn is_module(&self) -> bool {
+        self.kernel_btf && self.name != "vmlinux"
+    }
+}
+
+fn sys_bpf<T>(cmd: u32, attr: &mut T) -> BtfResult<i64> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T as *mut libc::c_void,
+            size_of::<T>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(ret)
+}
+
+/// IDs of all BTF objects currently loaded into the kernel.
+pub fn kernel_btf_ids() -> BtfResult<Vec<u32>> {
+    let mut ids = Vec::new();
+    let mut attr = bpf_attr_get_id::default();
+    loop {
+        match sys_bpf(BPF_BTF_GET_NEXT_ID, &mut attr) {
+            Ok(_) => {}
+            Err(crate::BtfError::Io(e)) if e.raw_os_error() == Some(libc::ENOENT) => break,
+            Err(e) => return Err(e),
+        }
+        ids.push(attr.next_id);
+        attr.id = attr.next_id;
+    }
+    Ok(ids)
+}
+
+/// Fetch BTF object with given ID from the kernel.
+pub fn kernel_btf_by_id(id: u32) -> BtfResult<KernelBtf> {
+    let mut attr = bpf_attr_get_id {
+        id,
+        ..Default::default()
+    };
+    let fd = sys_bpf(BPF_BTF_GET_FD_BY_ID, &mut attr)? as libc::c_int;
+    let res = btf_info_by_fd(fd);
+    unsafe { libc::close(fd) };
+    res
+}
+
+fn btf_info_by_fd(fd: libc::c_int) -> BtfResult<KernelBtf> {
+    // first query just the size of BTF data, then fetch data itself
+    let mut info = bpf_btf_info::default();
+    get_info_by_fd(fd, &mut info)?;
+
+    let mut data = vec![0u8; info.btf_size as usize];
+    let mut name = vec![0u8; BTF_NAME_MAX];
+    info = bpf_btf_info {
+        btf: data.as_mut_ptr() as u64,
+        btf_size: data.len() as u32,
+        name: name.as_mut_ptr() as u64,
+        name_len: name.len() as u32,
+        ..Default::default()
+    };
+    get_info_by_fd(fd, &mut info)?;
+    data.truncate(info.btf_size as usize);
+    let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    name.truncate(name_len);
+
+    Ok(KernelBtf {
+        id: info.id,
+        name: String::from_utf8(name)?,
+        kernel_btf: info.kernel_btf != 0,
+        data,
+    })
+}
+
+fn get_info_by_fd(fd: libc::c_int, info: &mut bpf_btf_info) -> BtfResult<()> {
+    let mut attr = bpf_attr_info {
+        bpf_fd: fd as u32,
+        info_len: size_of::<bpf_btf_info>() as u32,
+        info: info as *mut bpf_btf_info as u64,
+    };
+    sys_bpf(BPF_OBJ_GET_INFO_BY_FD, &mut attr)?;
+    Ok(())
+}
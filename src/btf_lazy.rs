@@ -0,0 +1,237 @@
+use std::cell::OnceCell;
+use std::sync::Arc;
+
+use object::{Object, ObjectSection};
+use scroll::Pread;
+
+use crate::types::*;
+use crate::{btf_error, BtfError, BtfResult};
+
+/// BTF whose types are decoded only when accessed (and cached), for quick lookups of a few types
+/// in huge BTFs, like vmlinux.
+///
+/// Loading only finds offsets of type records, so malformed types are reported only once they
+/// are accessed, and type graph (dangling references, cycles) isn't validated. Names and kinds
+/// of types are available without decoding them. Split BTF and .BTF.ext are not supported.
+pub struct LazyBtf<'a> {
+    /// BTF without types, providing endianness and strings
+    btf: Btf<'a>,
+    type_data: &'a [u8],
+    /// Offsets of type records in type section, by type ID
+    offs: Vec<usize>,
+    /// Decoded types, each with its own arena of members, params or enumerators
+    types: Vec<OnceCell<(BtfType<'a>, BtfArena<'a>)>>,
+}
+
+/// Limit of modifier/typedef/array chain followed to determine type's size.
+const MAX_RESOLVE_DEPTH: usize = 64;
+
+impl<'a> LazyBtf<'a> {
+    /// Index .BTF section of ELF file.
+    pub fn load(elf: &object::File<'a>) -> BtfResult<LazyBtf<'a>> {
+        let endian = if elf.is_little_endian() {
+            scroll::LE
+        } else {
+            scroll::BE
+        };
+        let ptr_sz = if elf.is_64() { 8 } else { 4 };
+        let data = elf
+            .section_by_name(BTF_ELF_SEC)
+            .ok_or_else(|| BtfError::new("No .BTF section found!"))?
+            .data()?;
+        LazyBtf::load_data(data, endian, ptr_sz)
+    }
+
+    /// Index raw BTF data (e.g., /sys/kernel/btf/vmlinux), assuming 64-bit target.
+    pub fn load_raw(data: &'a [u8]) -> BtfResult<LazyBtf<'a>> {
+        let endian = match Btf::raw_endian(data) {
+            Some(endian) => endian,
+            None => {
+                return Err(BtfError::InvalidHeader(
+                    "Not a raw BTF data: invalid magic".to_owned(),
+                ))
+            }
+        };
+        LazyBtf::load_data(data, endian, 8)
+    }
+
+    fn load_data(data: &'a [u8], endian: scroll::Endian, ptr_sz: u32) -> BtfResult<LazyBtf<'a>> {
+        let mut btf = Btf::new_empty(endian, ptr_sz, None)?;
+        let type_data = btf.load_sections(data)?;
+        let mut offs = vec![0];
+        let mut off = 0;
+        while off < type_data.len() {
+            let id = offs.len() as u32;
+            let t = match type_data.pread_with::<btf_type>(off, endian) {
+                Ok(t) => t,
+                Err(e) => {
+                    return Err(BtfError::TruncatedType {
+                        id,
+                        source: Arc::new(e),
+                    })
+                }
+            };
+            let kind = Btf::get_kind(t.info);
+            let sz = Btf::raw_type_size(t.info).ok_or(BtfError::UnknownKind { kind, id })?;
+            offs.push(off);
+            off += sz;
+        }
+        let mut types = Vec::new();
+        types.resize_with(offs.len(), OnceCell::new);
+        let _ = types[0].set((BtfType::Void, BtfArena::default()));
+        Ok(LazyBtf {
+            btf,
+            type_data,
+            offs,
+            types,
+        })
+    }
+
+    /// Override target's pointer size (in bytes), see Btf::set_ptr_sz().
+    pub fn set_ptr_sz(&mut self, ptr_sz: u32) {
+        self.btf.set_ptr_sz(ptr_sz);
+    }
+
+    pub fn type_cnt(&self) -> u32 {
+        self.offs.len() as u32
+    }
+
+    /// Decode type with given ID, if it wasn't decoded yet.
+    pub fn try_type_by_id(&self, type_id: u32) -> BtfResult<&BtfType<'a>> {
+        Ok(&self.decode(type_id)?.0)
+    }
+
+    /// Same as try_type_by_id(), but returns type's arena as well, to access its members,
+    /// params or enumerators.
+    pub fn try_type_with_arena(&self, type_id: u32) -> BtfResult<(&BtfType<'a>, &BtfArena<'a>)> {
+        let (t, arena) = self.decode(type_id)?;
+        Ok((t, arena))
+    }
+
+    fn decode(&self, type_id: u32) -> BtfResult<&(BtfType<'a>, BtfArena<'a>)> {
+        let cell = self
+            .types
+            .get(type_id as usize)
+            .ok_or(BtfError::InvalidTypeId { id: type_id })?;
+        if let Some(t) = cell.get() {
+            return Ok(t);
+        }
+        let data = &self.type_data[self.offs[type_id as usize]..];
+        let mut arena = BtfArena::default();
+        let t = match self
+            .btf
+            .load_type(type_id, data, self.btf.strs(), &mut arena)
+        {
+            Ok(t) => (t, arena),
+            Err(BtfError::Parse(source)) => {
+                return Err(BtfError::TruncatedType {
+                    id: type_id,
+                    source,
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(cell.get_or_init(|| t))
+    }
+
+    fn raw_type(&self, type_id: u32) -> BtfResult<btf_type> {
+        let off = match self.offs.get(type_id as usize) {
+            Some(&off) if type_id > 0 => off,
+            _ => return Err(BtfError::InvalidTypeId { id: type_id }),
+        };
+        Ok(self
+            .type_data
+            .pread_with::<btf_type>(off, self.btf.endian())?)
+    }
+
+    /// Name of type with given ID, without decoding it.
+    pub fn type_name(&self, type_id: u32) -> BtfResult<&'a str> {
+        if type_id == 0 {
+            return Ok("");
+        }
+        let t = self.raw_type(type_id)?;
+        self.btf.get_btf_str(self.btf.strs(), t.name_off)
+    }
+
+    /// Kind of type with given ID, without decoding it.
+    pub fn type_kind(&self, type_id: u32) -> BtfResult<BtfKind> {
+        if type_id == 0 {
+            return Ok(BtfKind::Void);
+        }
+        let kind = Btf::get_kind(self.raw_type(type_id)?.info);
+        Ok(match kind {
+            BTF_KIND_INT => BtfKind::Int,
+            BTF_KIND_PTR => BtfKind::Ptr,
+            BTF_KIND_ARRAY => BtfKind::Array,
+            BTF_KIND_STRUCT => BtfKind::Struct,
+            BTF_KIND_UNION => BtfKind::Union,
+            BTF_KIND_ENUM => BtfKind::Enum,
+            BTF_KIND_FWD => BtfKind::Fwd,
+            BTF_KIND_TYPEDEF => BtfKind::Typedef,
+            BTF_KIND_VOLATILE => BtfKind::Volatile,
+            BTF_KIND_CONST => BtfKind::Const,
+            BTF_KIND_RESTRICT => BtfKind::Restrict,
+            BTF_KIND_FUNC => BtfKind::Func,
+            BTF_KIND_FUNC_PROTO => BtfKind::FuncProto,
+            BTF_KIND_VAR => BtfKind::Var,
+            BTF_KIND_DATASEC => BtfKind::Datasec,
+            BTF_KIND_FLOAT => BtfKind::Float,
+            BTF_KIND_DECL_TAG => BtfKind::DeclTag,
+            BTF_KIND_TYPE_TAG => BtfKind::TypeTag,
+            BTF_KIND_ENUM64 => BtfKind::Enum64,
+            _ => return Err(BtfError::UnknownKind { kind, id: type_id }),
+        })
+    }
+
+    /// IDs of types with given name, found without decoding types.
+    pub fn find_by_name(&self, name: &str) -> BtfResult<Vec<u32>> {
+        let mut ids = Vec::new();
+        for id in 1..self.type_cnt() {
+            if self.type_name(id)? == name {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Same as Btf::get_size_of(), decoding only types on the way to the sized type.
+    pub fn get_size_of(&self, type_id: u32) -> BtfResult<u32> {
+        let mut id = type_id;
+        let mut nelems: u32 = 1;
+        for _ in 0..MAX_RESOLVE_DEPTH {
+            let sz = match self.try_type_by_id(id)? {
+                BtfType::Volatile(BtfVolatile { type_id })
+                | BtfType::Const(BtfConst { type_id })
+                | BtfType::Restrict(BtfRestrict { type_id })
+                | BtfType::Typedef(BtfTypedef { type_id, .. })
+                | BtfType::DeclTag(BtfDeclTag { type_id, .. })
+                | BtfType::TypeTag(BtfTypeTag { type_id, .. }) => {
+                    id = *type_id;
+                    continue;
+                }
+                BtfType::Array(t) => {
+                    nelems = nelems.saturating_mul(t.nelems);
+                    id = t.val_type_id;
+                    continue;
+                }
+                BtfType::Int(t) => t.bits.div_ceil(8),
+                BtfType::Ptr(_) => self.btf.ptr_sz(),
+                BtfType::Struct(t) | BtfType::Union(t) => t.sz,
+                BtfType::Enum(t) => t.sz,
+                BtfType::Enum64(t) => t.sz,
+                BtfType::Datasec(t) => t.sz,
+                BtfType::Float(t) => t.sz,
+                BtfType::Void
+                | BtfType::FuncProto(_)
+                | BtfType::Fwd(_)
+                | BtfType::Func(_)
+                | BtfType::Var(_) => 0,
+            };
+            return Ok(nelems.saturating_mul(sz));
+        }
+        btf_error(format!(
+            "Type [{}] has too long or cyclic chain of referenced types",
+            type_id
+        ))
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use crate::btf_writer::BtfWriter;
+use crate::relocator::Reloc;
+use crate::types::*;
+use crate::{btf_error, BtfResult};
+
+/// Builder of minimal target BTF sufficient to perform given CO-RE relocations, similar to
+/// `bpftool gen min_core_btf`.
+///
+/// Relocations of any number of BPF objects against the same target BTF can be added. Only
+/// types along relocation access paths are kept, and structs/unions retain only accessed
+/// members, but keep their original sizes and member offsets. Pointers to types that are not
+/// otherwise needed become `void *`.
+#[derive(Debug)]
+pub struct MinCoreBtf<'a> {
+    targ_btf: &'a Btf<'a>,
+    used: Vec<bool>,
+    used_members: HashMap<u32, Vec<bool>>,
+}
+
+impl<'a> MinCoreBtf<'a> {
+    pub fn new(targ_btf: &'a Btf<'a>) -> MinCoreBtf<'a> {
+        MinCoreBtf {
+            targ_btf,
+            used: vec![false; targ_btf.type_cnt() as usize],
+            used_members: HashMap::new(),
+        }
+    }
+
+    /// Record target types and fields used by relocations of given local BTF.
+    pub fn add_relocs(&mut self, local_btf: &Btf, relocs: &[Reloc]) -> BtfResult<()> {
+        for r in relocs {
+            let rec = &local_btf.core_reloc_secs()[r.sec_id].recs[r.reloc_id];
+            match rec.kind {
+                BtfCoreRelocKind::ByteOff
+                | BtfCoreRelocKind::ByteSz
+                | BtfCoreRelocKind::FieldExists
+                | BtfCoreRelocKind::Signed
+                | BtfCoreRelocKind::LShiftU64
+                | BtfCoreRelocKind::RShiftU64 => self.mark_field(r)?,
+                BtfCoreRelocKind::EnumvalExists | BtfCoreRelocKind::EnumvalValue => {
+                    self.mark_chain(r.targ_type_id);
+                }
+                _ => self.mark_type(r.targ_type_id),
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark type along with modifiers/typedefs leading to it, returns resolved type ID.
+    fn mark_chain(&mut self, type_id: u32) -> u32 {
+        let chain = self.targ_btf.resolve_chain(type_id);
+        for &id in &chain {
+            self.used[id as usize] = true;
+        }
+        *chain.last().unwrap()
+    }
+
+    /// Mark type and whatever is needed to preserve its size and layout: arrays and modifiers
+    /// are followed, but pointers and struct/union members are not.
+    fn mark_type(&mut self, type_id: u32) {
+        let id = self.mark_chain(type_id);
+        if let BtfType::Array(t) = self.targ_btf.type_by_id(id) {
+            self.mark_chain(t.idx_type_id);
+            self.mark_type(t.val_type_id);
+        }
+    }
+
+    fn mark_field(&mut self, r: &Reloc) -> BtfResult<()> {
+        let btf = self.targ_btf;
+        let mut id = self.mark_chain(r.targ_type_id);
+        for &idx in &r.targ_spec[1..] {
+            id = match btf.type_by_id(id) {
+                BtfType::Struct(t) | BtfType::Union(t) => {
+                    let used = self
+                        .used_members
+                        .entry(id)
+                        .or_insert_with(|| vec![false; t.members.len()]);
+                    used[idx] = true;
+                    self.mark_chain(btf.members(t)[idx].type_id)
+                }
+                BtfType::Array(t) => {
+                    self.mark_chain(t.idx_type_id);
+                    self.mark_chain(t.val_type_id)
+                }
+                t => {
+                    return btf_error(format!(
+                        "Unexpected type in access path of reloc {}: [{}] {}",
+                        r, id, t
+                    ))
+                }
+            };
+        }
+        self.mark_type(id);
+        Ok(())
+    }
+
+    /// Encode all marked types as a new raw BTF.
+    pub fn encode(&self) -> BtfResult<Vec<u8>> {
+        let mut id_map = vec![0u32; self.used.len()];
+        let mut next_id = 1;
+        for (id, &used) in self.used.iter().enumerate().skip(1) {
+            if used {
+                id_map[id] = next_id;
+                next_id += 1;
+            }
+        }
+
+        let mut w = BtfWriter::new(self.targ_btf.endian());
+        let remap = |id: u32| id_map[id as usize];
+        // members of structs/unions that are kept only partially
+        let mut arena = BtfArena::default();
+        for (id, t) in self.targ_btf.types().iter().enumerate().skip(1) {
+            if !self.used[id] {
+                continue;
+            }
+            match t {
+                BtfType::Struct(c) | BtfType::Union(c) => {
+                    let used = self.used_members.get(&(id as u32));
+                    let members = self
+                        .targ_btf
+                        .members(c)
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| used.is_some_and(|used| used[*i]))
+                        .map(|(_, m)| m.clone());
+                    let members = arena.add_members(members);
+                    let c = BtfComposite {
+                        is_struct: c.is_struct,
+                        name: c.name,
+                        sz: c.sz,
+                        bitfield_offs: c.bitfield_offs,
+                        members,
+                    };
+                    let t = if c.is_struct {
+                        BtfType::Struct(c)
+                    } else {
+                        BtfType::Union(c)
+                    };
+                    w.add_type(&t, &arena, &remap)?;
+                }
+                _ => {
+                    w.add_type(t, self.targ_btf.arena(), &remap)?;
+                }
+            }
+        }
+        w.finish()
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+
+use crate::btf_writer::BtfWriter;
+use crate::types::*;
+use crate::{btf_error, BtfError, BtfResult};
+
+/// Type or member whose referenced type is changed by [`BtfPatch::Retype`].
+#[derive(Debug, Clone)]
+pub enum RetypeTarget {
+    /// `var:<name>`
+    Var(String),
+    /// `typedef:<name>`
+    Typedef(String),
+    /// `<struct or union name>.<member name>`
+    Member(String, String),
+}
+
+impl std::str::FromStr for RetypeTarget {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(name) = s.strip_prefix("var:") {
+            Ok(RetypeTarget::Var(name.to_owned()))
+        } else if let Some(name) = s.strip_prefix("typedef:") {
+            Ok(RetypeTarget::Typedef(name.to_owned()))
+        } else if let Some((owner, member)) = s.split_once('.') {
+            Ok(RetypeTarget::Member(owner.to_owned(), member.to_owned()))
+        } else {
+            Err(BtfError::new_owned(format!(
+                "invalid retype target: '{}', expected var:<name>, typedef:<name> or <type>.<member>",
+                s
+            )))
+        }
+    }
+}
+
+/// Single scripted edit of BTF types.
+#[derive(Debug, Clone)]
+pub enum BtfPatch {
+    /// Rename all types with given name.
+    Rename { from: String, to: String },
+    /// Change type referenced by a variable, typedef or struct/union member.
+    Retype { target: RetypeTarget, type_id: u32 },
+    /// Change byte size of named struct, union, enum or datasec.
+    Resize { name: String, sz: u32 },
+}
+
+impl fmt::Display for RetypeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetypeTarget::Var(name) => write!(f, "var:{}", name),
+            RetypeTarget::Typedef(name) => write!(f, "typedef:{}", name),
+            RetypeTarget::Member(owner, member) => write!(f, "{}.{}", owner, member),
+        }
+    }
+}
+
+impl fmt::Display for BtfPatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BtfPatch::Rename { from, to } => write!(f, "rename {}={}", from, to),
+            BtfPatch::Retype { target, type_id } => write!(f, "retype {}={}", target, type_id),
+            BtfPatch::Resize { name, sz } => write!(f, "resize {}={}", name, sz),
+        }
+    }
+}
+
+fn split_arg<'s>(s: &'s str, what: &str) -> BtfResult<(&'s str, &'s str)> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() && !v.is_empty() => Ok((k, v)),
+        _ => btf_error(format!("invalid {} argument: '{}'", what, s)),
+    }
+}
+
+impl BtfPatch {
+    /// Parse `<old>=<new>`.
+    pub fn parse_rename(s: &str) -> BtfResult<BtfPatch> {
+        let (from, to) = split_arg(s, "rename")?;
+        Ok(BtfPatch::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        })
+    }
+
+    /// Parse `<target>=<type id>`.
+    pub fn parse_retype(s: &str) -> BtfResult<BtfPatch> {
+        let (target, id) = split_arg(s, "retype")?;
+        Ok(BtfPatch::Retype {
+            target: target.parse()?,
+            type_id: id.parse()?,
+        })
+    }
+
+    /// Parse `<name>=<size>`.
+    pub fn parse_resize(s: &str) -> BtfResult<BtfPatch> {
+        let (name, sz) = split_arg(s, "resize")?;
+        Ok(BtfPatch::Resize {
+            name: name.to_owned(),
+            sz: sz.parse()?,
+        })
+    }
+}
+
+/// Apply patches, in order, to a copy of BTF types and their arena, which are returned. Each
+/// patch has to match at least one type.
+pub fn patch_types<'a>(
+    btf: &'a Btf<'a>,
+    patches: &'a [BtfPatch],
+) -> BtfResult<(Vec<BtfType<'a>>, BtfArena<'a>)> {
+    let mut types = btf.types().to_vec();
+    let mut arena = btf.arena().clone();
+    for p in patches {
+        if let BtfPatch::Retype { type_id, .. } = p {
+            if *type_id >= btf.type_cnt() {
+                return btf_error(format!("{}: type ID {} doesn't exist", p, type_id));
+            }
+        }
+        let mut matched = 0;
+        for t in types.iter_mut() {
+            if apply_patch(t, &mut arena, p) {
+                matched += 1;
+            }
+        }
+        if matched == 0 {
+            return btf_error(format!("{}: no matching type found", p));
+        }
+    }
+    Ok((types, arena))
+}
+
+fn apply_patch<'a>(t: &mut BtfType<'a>, arena: &mut BtfArena<'a>, p: &'a BtfPatch) -> bool {
+    match p {
+        BtfPatch::Rename { from, to } => {
+            let name = match t {
+                BtfType::Int(t) => &mut t.name,
+                BtfType::Struct(t) | BtfType::Union(t) => &mut t.name,
+                BtfType::Enum(t) => &mut t.name,
+                BtfType::Enum64(t) => &mut t.name,
+                BtfType::Fwd(t) => &mut t.name,
+                BtfType::Typedef(t) => &mut t.name,
+                BtfType::Func(t) => &mut t.name,
+                BtfType::Var(t) => &mut t.name,
+                BtfType::Datasec(t) => &mut t.name,
+                BtfType::Float(t) => &mut t.name,
+                _ => return false,
+            };
+            if name != from {
+                return false;
+            }
+            *name = to;
+            true
+        }
+        BtfPatch::Retype { target, type_id } => match (t, target) {
+            (BtfType::Var(t), RetypeTarget::Var(name)) if t.name == name => {
+                t.type_id = *type_id;
+                true
+            }
+            (BtfType::Typedef(t), RetypeTarget::Typedef(name)) if t.name == name => {
+                t.type_id = *type_id;
+                true
+            }
+            (BtfType::Struct(t) | BtfType::Union(t), RetypeTarget::Member(owner, member))
+                if t.name == owner =>
+            {
+                match arena.members_mut(t).iter_mut().find(|m| m.name == member) {
+                    Some(m) => {
+                        m.type_id = *type_id;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        },
+        BtfPatch::Resize { name, sz } => {
+            let t_sz = match t {
+                BtfType::Struct(t) | BtfType::Union(t) if t.name == name => &mut t.sz,
+                BtfType::Enum(t) if t.name == name => &mut t.sz,
+                BtfType::Enum64(t) if t.name == name => &mut t.sz,
+                BtfType::Datasec(t) if t.name == name => &mut t.sz,
+                _ => return false,
+            };
+            *t_sz = *sz;
+            true
+        }
+    }
+}
+
+/// Apply patches and encode resulting types as raw BTF, type IDs are preserved.
+pub fn encode_patched(btf: &Btf, patches: &[BtfPatch]) -> BtfResult<Vec<u8>> {
+    let (types, arena) = patch_types(btf, patches)?;
+    let mut w = BtfWriter::new(btf.endian());
+    for t in &types[1..] {
+        w.add_type(t, &arena, &|id| id)?;
+    }
+    w.finish()
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use crate::types::*;
+use crate::{btf_error, BtfResult};
+
+/// Location of a field, resolved from a field path like `task_struct.se.vruntime`,
+/// `struct sk_buff.cb[4]` or `union bpf_attr.map_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldInfo {
+    /// Type ID of the field itself.
+    pub type_id: u32,
+    /// Offset in bits from the start of the root type.
+    pub bit_offset: u32,
+    /// Size of bitfield in bits, 0 for non-bitfield fields.
+    pub bit_size: u32,
+    /// Size of field's type in bytes.
+    pub size: u32,
+}
+
+impl FieldInfo {
+    pub fn byte_offset(&self) -> u32 {
+        self.bit_offset / 8
+    }
+}
+
+/// Innermost field covering given byte offset of root type, see field_at_offset().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldAtOffset {
+    /// Field path in the same form as accepted by resolve_path(), e.g., `task_struct.se.vruntime`
+    /// or `sk_buff.cb[4]`.
+    pub path: String,
+    pub field: FieldInfo,
+    /// Offset falls into padding of the struct/union the path points to, not into any member.
+    pub padding: bool,
+}
+
+/// Name lookup shared by all paths resolved in one batch.
+struct RootIndex<'b> {
+    structs: HashMap<&'b str, u32>,
+    unions: HashMap<&'b str, u32>,
+    typedefs: HashMap<&'b str, u32>,
+}
+
+impl<'a> Btf<'a> {
+    /// Resolve single field path, see resolve_paths().
+    pub fn resolve_path(&self, path: &str) -> BtfResult<FieldInfo> {
+        self.resolve_paths(&[path]).pop().unwrap()
+    }
+
+    /// Resolve field paths of the form `<root>.<field>[<idx>]...`, where root is a struct, union
+    /// or typedef name, optionally prefixed with `struct ` or `union `. Fields of anonymous
+    /// embedded structs/unions are accessible directly, as in C.
+    ///
+    /// Type names are indexed just once per batch and resolution of common path prefixes is
+    /// shared, which makes resolving hundreds of paths at once much cheaper than one by one.
+    pub fn resolve_paths(&self, paths: &[&str]) -> Vec<BtfResult<FieldInfo>> {
+        let index = self.root_index();
+        let mut cache: HashMap<&str, FieldInfo> = HashMap::new();
+        paths
+            .iter()
+            .map(|path| self.resolve_cached(path, &index, &mut cache))
+            .collect()
+    }
+
+    /// Find innermost field (looking through anonymous embedded structs/unions and array elements)
+    /// covering given byte offset of root type, which is given the same way as root of field
+    /// path. For unions, and bitfields sharing a byte, the first matching member is reported.
+    pub fn field_at_offset(&self, root: &str, byte_offset: u32) -> BtfResult<FieldAtOffset> {
+        let index = self.root_index();
+        let mut field = self.resolve_root(root, &index)?;
+        if byte_offset >= field.size {
+            return btf_error(format!(
+                "Offset {} is out of bounds of '{}' of size {}",
+                byte_offset, root, field.size
+            ));
+        }
+        let mut path = root.trim().to_owned();
+        let bit_offset = byte_offset * 8;
+        loop {
+            let id = self.skip_mods_and_typedefs(field.type_id);
+            match self.type_by_id(id) {
+                BtfType::Struct(t) | BtfType::Union(t) => {
+                    let m = self.members(t).iter().find(|m| {
+                        let start = field.bit_offset + m.bit_offset;
+                        let bits = match m.bit_size {
+                            0 => self.get_size_of(m.type_id) * 8,
+                            sz => u32::from(sz),
+                        };
+                        // bitfields cover all bytes they touch
+                        start / 8 * 8 <= bit_offset && bit_offset < start + bits
+                    });
+                    let m = match m {
+                        Some(m) => m,
+                        None => {
+                            return Ok(FieldAtOffset {
+                                path,
+                                field,
+                                padding: true,
+                            })
+                        }
+                    };
+                    if !m.name.is_empty() {
+                        path.push('.');
+                        path.push_str(m.name);
+                    }
+                    field = FieldInfo {
+                        type_id: m.type_id,
+                        bit_offset: field.bit_offset + m.bit_offset,
+                        bit_size: u32::from(m.bit_size),
+                        size: self.get_size_of(m.type_id),
+                    };
+                    if field.bit_size != 0 {
+                        break;
+                    }
+                }
+                BtfType::Array(t) => {
+                    let elem_sz = self.get_size_of(t.val_type_id);
+                    if elem_sz == 0 {
+                        break;
+                    }
+                    let idx = (bit_offset - field.bit_offset) / 8 / elem_sz;
+                    path.push_str(&format!("[{}]", idx));
+                    field = FieldInfo {
+                        type_id: t.val_type_id,
+                        bit_offset: field.bit_offset + idx * elem_sz * 8,
+                        bit_size: 0,
+                        size: elem_sz,
+                    };
+                }
+                _ => break,
+            }
+        }
+        Ok(FieldAtOffset {
+            path,
+            field,
+            padding: false,
+        })
+    }
+
+    fn root_index(&self) -> RootIndex<'_> {
+        let mut index = RootIndex {
+            structs: HashMap::new(),
+            unions: HashMap::new(),
+            typedefs: HashMap::new(),
+        };
+        for (id, t) in self.types().iter().enumerate() {
+            let map = match t {
+                BtfType::Struct(_) => &mut index.structs,
+                BtfType::Union(_) => &mut index.unions,
+                BtfType::Typedef(_) => &mut index.typedefs,
+                _ => continue,
+            };
+            if !t.name().is_empty() {
+                map.entry(t.name()).or_insert(id as u32);
+            }
+        }
+        index
+    }
+
+    fn resolve_cached<'p>(
+        &self,
+        path: &'p str,
+        index: &RootIndex,
+        cache: &mut HashMap<&'p str, FieldInfo>,
+    ) -> BtfResult<FieldInfo> {
+        // find longest already resolved prefix, ending right before a '.' separator
+        let mut prefix_end = path.len();
+        let mut field = loop {
+            if let Some(f) = cache.get(&path[..prefix_end]) {
+                break *f;
+            }
+            match path[..prefix_end].rfind('.') {
+                Some(pos) => prefix_end = pos,
+                None => {
+                    prefix_end = path.find('.').unwrap_or(path.len());
+                    let f = self.resolve_root(&path[..prefix_end], index)?;
+                    cache.insert(&path[..prefix_end], f);
+                    break f;
+                }
+            }
+        };
+        while prefix_end < path.len() {
+            let start = prefix_end + 1;
+            let end = path[start..]
+                .find('.')
+                .map_or(path.len(), |pos| start + pos);
+            field = self.resolve_step(field, &path[start..end], path)?;
+            cache.insert(&path[..end], field);
+            prefix_end = end;
+        }
+        Ok(field)
+    }
+
+    fn resolve_root(&self, spec: &str, index: &RootIndex) -> BtfResult<FieldInfo> {
+        let (name, subscripts) = split_subscripts(spec)?;
+        let name = name.trim();
+        let type_id = if let Some(name) = name.strip_prefix("struct ") {
+            index.structs.get(name.trim())
+        } else if let Some(name) = name.strip_prefix("union ") {
+            index.unions.get(name.trim())
+        } else {
+            index
+                .structs
+                .get(name)
+                .or_else(|| index.unions.get(name))
+                .or_else(|| index.typedefs.get(name))
+        };
+        let type_id = match type_id {
+            Some(&id) => id,
+            None => return btf_error(format!("Type '{}' not found", name)),
+        };
+        let field = FieldInfo {
+            type_id,
+            bit_offset: 0,
+            bit_size: 0,
+            size: self.get_size_of(type_id),
+        };
+        self.apply_subscripts(field, &subscripts, spec)
+    }
+
+    fn resolve_step(&self, field: FieldInfo, spec: &str, path: &str) -> BtfResult<FieldInfo> {
+        let (name, subscripts) = split_subscripts(spec)?;
+        let id = self.skip_mods_and_typedefs(field.type_id);
+        let mut bit_offset = field.bit_offset;
+        let m = match self.find_member(id, name, &mut bit_offset) {
+            Some(m) => m,
+            None => {
+                return btf_error(format!(
+                    "Field '{}' of path '{}' not found in [{}] {:?} '{}'",
+                    name,
+                    path,
+                    id,
+                    self.type_by_id(id).kind(),
+                    self.type_by_id(id).name()
+                ))
+            }
+        };
+        let field = FieldInfo {
+            type_id: m.type_id,
+            bit_offset: bit_offset + m.bit_offset,
+            bit_size: u32::from(m.bit_size),
+            size: self.get_size_of(m.type_id),
+        };
+        self.apply_subscripts(field, &subscripts, path)
+    }
+
+    /// Find member by name, looking into anonymous embedded structs/unions, accumulating their
+    /// offsets into `bit_offset`.
+    fn find_member(
+        &self,
+        type_id: u32,
+        name: &str,
+        bit_offset: &mut u32,
+    ) -> Option<&BtfMember<'_>> {
+        let members = match self.type_by_id(type_id) {
+            BtfType::Struct(t) | BtfType::Union(t) => self.members(t),
+            _ => return None,
+        };
+        for m in members {
+            if m.name == name {
+                return Some(m);
+            }
+            if m.name.is_empty() {
+                let mut off = *bit_offset + m.bit_offset;
+                let id = self.skip_mods_and_typedefs(m.type_id);
+                if let Some(m) = self.find_member(id, name, &mut off) {
+                    *bit_offset = off;
+                    return Some(m);
+                }
+            }
+        }
+        None
+    }
+
+    fn apply_subscripts(
+        &self,
+        mut field: FieldInfo,
+        subscripts: &[u32],
+        path: &str,
+    ) -> BtfResult<FieldInfo> {
+        for &idx in subscripts {
+            let id = self.skip_mods_and_typedefs(field.type_id);
+            let arr = match self.type_by_id(id) {
+                BtfType::Array(t) => t,
+                t => {
+                    return btf_error(format!(
+                        "Can't index non-array [{}] {:?} in path '{}'",
+                        id,
+                        t.kind(),
+                        path
+                    ))
+                }
+            };
+            if idx >= arr.nelems && arr.nelems != 0 {
+                return btf_error(format!(
+                    "Index {} out of bounds of [{}] in path '{}'",
+                    idx, id, path
+                ));
+            }
+            let elem_sz = self.get_size_of(arr.val_type_id);
+            field = FieldInfo {
+                type_id: arr.val_type_id,
+                bit_offset: field.bit_offset + idx * elem_sz * 8,
+                bit_size: 0,
+                size: elem_sz,
+            };
+        }
+        Ok(field)
+    }
+}
+
+/// Split `name[1][2]` into `name` and array indices.
+fn split_subscripts(spec: &str) -> BtfResult<(&str, Vec<u32>)> {
+    let (name, mut rest) = match spec.find('[') {
+        Some(pos) => (&spec[..pos], &spec[pos..]),
+        None => (spec, ""),
+    };
+    let mut subscripts = Vec::new();
+    while !rest.is_empty() {
+        let end = match (rest.starts_with('['), rest.find(']')) {
+            (true, Some(end)) => end,
+            _ => return btf_error(format!("Invalid array subscript in '{}'", spec)),
+        };
+        subscripts.push(rest[1..end].trim().parse::<u32>()?);
+        rest = &rest[end + 1..];
+    }
+    Ok((name, subscripts))
+}
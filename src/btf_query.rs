@@ -0,0 +1,373 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::types::*;
+use crate::{btf_error, BtfResult};
+
+/// Type filter expression, e.g., `kind==struct && size>4096 && name=~"^net"`.
+///
+/// Comparisons of type properties are combined with `&&`, `||`, `!` and parentheses, with usual
+/// precedence. Properties are:
+/// - `kind` (`==`, `!=`), compared to kind names as accepted by `--type`;
+/// - `name` (`==`, `!=`, and `=~`, `!~` for regex match), anonymous types have empty name;
+/// - `id`, `size` (in bytes, modifiers and typedefs resolved) and `vlen` (number of
+///   members, enum values, params or datasec vars), compared as numbers.
+///
+/// Values are numbers (decimal or 0x-prefixed hex), identifiers or double-quoted strings.
+#[derive(Debug)]
+pub enum Query {
+    Or(Box<Query>, Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Kind(bool, BtfKind),
+    Name(bool, String),
+    NameMatch(bool, Regex),
+    Num(NumProp, CmpOp, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumProp {
+    Id,
+    Size,
+    Vlen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(u64),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "'{}'", s),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Op(op) => write!(f, "'{}'", op),
+        }
+    }
+}
+
+/// Describe token for error messages, None being the end of query.
+fn token_str(t: Option<&Token>) -> String {
+    match t {
+        Some(t) => t.to_string(),
+        None => "end of query".to_owned(),
+    }
+}
+
+const OPS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "=~", "!~", "<", ">", "!", "(", ")",
+];
+
+impl Query {
+    pub fn parse(s: &str) -> BtfResult<Query> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let q = parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            return btf_error(format!(
+                "Unexpected {} in query '{}'",
+                parser.tokens[parser.pos], s
+            ));
+        }
+        Ok(q)
+    }
+
+    pub fn matches(&self, btf: &Btf, type_id: u32) -> bool {
+        let t = btf.type_by_id(type_id);
+        match self {
+            Query::Or(a, b) => a.matches(btf, type_id) || b.matches(btf, type_id),
+            Query::And(a, b) => a.matches(btf, type_id) && b.matches(btf, type_id),
+            Query::Not(q) => !q.matches(btf, type_id),
+            Query::Kind(eq, kind) => (t.kind() == *kind) == *eq,
+            Query::Name(eq, name) => (t.name() == name) == *eq,
+            Query::NameMatch(eq, re) => re.is_match(t.name()) == *eq,
+            Query::Num(prop, op, val) => {
+                let x = match prop {
+                    NumProp::Id => u64::from(type_id),
+                    NumProp::Size => u64::from(btf.get_size_of(type_id)),
+                    NumProp::Vlen => type_vlen(t) as u64,
+                };
+                match op {
+                    CmpOp::Eq => x == *val,
+                    CmpOp::Ne => x != *val,
+                    CmpOp::Lt => x < *val,
+                    CmpOp::Le => x <= *val,
+                    CmpOp::Gt => x > *val,
+                    CmpOp::Ge => x >= *val,
+                }
+            }
+        }
+    }
+}
+
+fn type_vlen(t: &BtfType) -> usize {
+    match t {
+        BtfType::Struct(t) | BtfType::Union(t) => t.members.len(),
+        BtfType::Enum(t) => t.values.len(),
+        BtfType::Enum64(t) => t.values.len(),
+        BtfType::FuncProto(t) => t.params.len(),
+        BtfType::Datasec(t) => t.vars.len(),
+        _ => 0,
+    }
+}
+
+fn tokenize(s: &str) -> BtfResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c == '"' {
+            let end = match rest[1..].find('"') {
+                Some(end) => end + 1,
+                None => return btf_error(format!("Unterminated string in query '{}'", s)),
+            };
+            tokens.push(Token::Str(rest[1..end].to_owned()));
+            rest = &rest[end + 1..];
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let num = match word.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => word.parse::<u64>().ok(),
+            };
+            match num {
+                Some(num) => tokens.push(Token::Num(num)),
+                None if c.is_ascii_digit() => {
+                    return btf_error(format!("Invalid number '{}' in query '{}'", word, s))
+                }
+                None => tokens.push(Token::Ident(word.to_owned())),
+            }
+            rest = &rest[end..];
+        } else {
+            return btf_error(format!("Unexpected '{}' in query '{}'", c, s));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> BtfResult<Query> {
+        let mut q = self.parse_and()?;
+        while self.eat_op("||") {
+            q = Query::Or(Box::new(q), Box::new(self.parse_and()?));
+        }
+        Ok(q)
+    }
+
+    fn parse_and(&mut self) -> BtfResult<Query> {
+        let mut q = self.parse_unary()?;
+        while self.eat_op("&&") {
+            q = Query::And(Box::new(q), Box::new(self.parse_unary()?));
+        }
+        Ok(q)
+    }
+
+    fn parse_unary(&mut self) -> BtfResult<Query> {
+        if self.eat_op("!") {
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat_op("(") {
+            let q = self.parse_or()?;
+            if !self.eat_op(")") {
+                return btf_error(format!(
+                    "Expected ')' in query, got {}",
+                    token_str(self.tokens.get(self.pos))
+                ));
+            }
+            return Ok(q);
+        }
+        self.parse_cmp()
+    }
+
+    fn parse_cmp(&mut self) -> BtfResult<Query> {
+        let prop = match self.next() {
+            Some(Token::Ident(prop)) => prop,
+            t => {
+                return btf_error(format!(
+                    "Expected type property in query, got {}",
+                    token_str(t.as_ref())
+                ))
+            }
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            t => {
+                return btf_error(format!(
+                    "Expected comparison in query, got {}",
+                    token_str(t.as_ref())
+                ))
+            }
+        };
+        let val = match self.next() {
+            Some(t @ Token::Ident(_)) | Some(t @ Token::Str(_)) | Some(t @ Token::Num(_)) => t,
+            t => {
+                return btf_error(format!(
+                    "Expected value in query, got {}",
+                    token_str(t.as_ref())
+                ))
+            }
+        };
+        let bad_op = || {
+            btf_error(format!(
+                "Comparison '{}' is not supported for '{}'",
+                op, prop
+            ))
+        };
+        match (prop.as_str(), val) {
+            ("kind", Token::Ident(kind)) | ("kind", Token::Str(kind)) => {
+                let kind = kind.parse::<BtfKind>()?;
+                match op {
+                    "==" => Ok(Query::Kind(true, kind)),
+                    "!=" => Ok(Query::Kind(false, kind)),
+                    _ => bad_op(),
+                }
+            }
+            ("name", Token::Ident(name)) | ("name", Token::Str(name)) => match op {
+                "==" => Ok(Query::Name(true, name)),
+                "!=" => Ok(Query::Name(false, name)),
+                "=~" => Ok(Query::NameMatch(true, Regex::new(&name)?)),
+                "!~" => Ok(Query::NameMatch(false, Regex::new(&name)?)),
+                _ => bad_op(),
+            },
+            (prop @ ("id" | "size" | "vlen"), Token::Num(val)) => {
+                let prop = match prop {
+                    "id" => NumProp::Id,
+                    "size" => NumProp::Size,
+                    _ => NumProp::Vlen,
+                };
+                let op = match op {
+                    "==" => CmpOp::Eq,
+                    "!=" => CmpOp::Ne,
+                    "<" => CmpOp::Lt,
+                    "<=" => CmpOp::Le,
+                    ">" => CmpOp::Gt,
+                    ">=" => CmpOp::Ge,
+                    _ => return bad_op(),
+                };
+                Ok(Query::Num(prop, op, val))
+            }
+            ("kind" | "name" | "id" | "size" | "vlen", val) => {
+                btf_error(format!("Invalid value {} for '{}' in query", val, prop))
+            }
+            _ => btf_error(format!(
+                "Unknown type property '{}' in query, expected kind, name, id, size or vlen",
+                prop
+            )),
+        }
+    }
+}
+
+/// Structural signature of struct/union, matching its members in order, e.g.,
+/// `next:sk_buff*,prev:sk_buff*,:union` or `tstamp,skb_mstamp_ns`.
+///
+/// Each member is given as `NAME` or `NAME:TYPE`, with empty name for anonymous members. Member
+/// type is compared, ignoring modifiers, to its name (typedefs are not resolved), with `*` for
+/// each pointer level and `[]` for arrays; anonymous types are `struct`, `union` or `enum`,
+/// function prototypes are `fn`. Trailing `...` matches any remaining members.
+#[derive(Debug)]
+pub struct Shape {
+    members: Vec<(String, Option<String>)>,
+    prefix: bool,
+}
+
+impl Shape {
+    pub fn parse(s: &str) -> BtfResult<Shape> {
+        let mut parts = s.split(',').map(str::trim).collect::<Vec<_>>();
+        let prefix = parts.last() == Some(&"...");
+        if prefix {
+            parts.pop();
+        }
+        let mut members = Vec::new();
+        for part in parts {
+            let (name, type_name) = match part.split_once(':') {
+                Some((name, type_name)) => (name.trim(), Some(type_name.replace(' ', ""))),
+                None => (part, None),
+            };
+            if name == "..." || type_name.as_deref() == Some("") {
+                return btf_error(format!("Invalid member '{}' in shape '{}'", part, s));
+            }
+            members.push((name.to_owned(), type_name));
+        }
+        Ok(Shape { members, prefix })
+    }
+
+    pub fn matches(&self, btf: &Btf, type_id: u32) -> bool {
+        let members = match btf.type_by_id(type_id) {
+            BtfType::Struct(t) | BtfType::Union(t) => btf.members(t),
+            _ => return false,
+        };
+        let len_ok = if self.prefix {
+            members.len() >= self.members.len()
+        } else {
+            members.len() == self.members.len()
+        };
+        len_ok
+            && self
+                .members
+                .iter()
+                .zip(members)
+                .all(|((name, type_name), m)| {
+                    m.name == name
+                        && type_name
+                            .as_ref()
+                            .is_none_or(|type_name| *type_name == shape_type_name(btf, m.type_id))
+                })
+    }
+}
+
+fn shape_type_name(btf: &Btf, type_id: u32) -> String {
+    let type_id = btf.skip_mods(type_id);
+    match btf.type_by_id(type_id) {
+        BtfType::Ptr(t) => format!("{}*", shape_type_name(btf, t.type_id)),
+        BtfType::Array(t) => format!("{}[]", shape_type_name(btf, t.val_type_id)),
+        BtfType::FuncProto(_) => "fn".to_owned(),
+        BtfType::Void => "void".to_owned(),
+        BtfType::Struct(t) if t.name.is_empty() => "struct".to_owned(),
+        BtfType::Union(t) if t.name.is_empty() => "union".to_owned(),
+        BtfType::Enum(t) if t.name.is_empty() => "enum".to_owned(),
+        BtfType::Enum64(t) if t.name.is_empty() => "enum".to_owned(),
+        t => t.name().to_owned(),
+    }
+}
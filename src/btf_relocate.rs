@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::btf_writer::BtfWriter;
+use crate::types::*;
+use crate::{btf_error, BtfResult};
+
+/// Check whether type of distilled base BTF can stand for given type of a full base BTF.
+///
+/// Distilled base keeps only named INTs, FLOATs, ENUMs, FWDs and member-less STRUCTs/UNIONs.
+/// Size of a struct/union is only checked if it's non-zero, i.e., if split BTF embeds it.
+fn is_match(dist_t: &BtfType, base_t: &BtfType) -> bool {
+    if dist_t.name() != base_t.name() {
+        return false;
+    }
+    match (dist_t, base_t) {
+        (BtfType::Int(a), BtfType::Int(b)) => {
+            a.bits == b.bits && a.offset == b.offset && a.encoding == b.encoding
+        }
+        (BtfType::Float(a), BtfType::Float(b)) => a.sz == b.sz,
+        (BtfType::Enum(a), BtfType::Enum(b)) => a.sz == b.sz,
+        (BtfType::Enum(a), BtfType::Enum64(b)) => a.sz == b.sz,
+        (BtfType::Enum64(a), BtfType::Enum(b)) => a.sz == b.sz,
+        (BtfType::Enum64(a), BtfType::Enum64(b)) => a.sz == b.sz,
+        (BtfType::Struct(a), BtfType::Struct(b)) | (BtfType::Union(a), BtfType::Union(b)) => {
+            a.sz == 0 || a.sz == b.sz
+        }
+        (BtfType::Fwd(a), BtfType::Struct(_)) => a.kind == BtfFwdKind::Struct,
+        (BtfType::Fwd(a), BtfType::Union(_)) => a.kind == BtfFwdKind::Union,
+        (BtfType::Fwd(a), BtfType::Fwd(b)) => a.kind == b.kind,
+        _ => false,
+    }
+}
+
+/// Map each type of distilled base BTF to its counterpart in a new full base BTF.
+fn map_distilled_base(dist: &Btf, base: &Btf) -> BtfResult<Vec<u32>> {
+    let mut by_name: HashMap<&str, Vec<u32>> = HashMap::new();
+    for (id, t) in base.types().iter().enumerate().skip(1) {
+        if !t.name().is_empty() {
+            by_name.entry(t.name()).or_default().push(id as u32);
+        }
+    }
+
+    let mut id_map = vec![0u32; dist.type_cnt() as usize];
+    for (id, t) in dist.types().iter().enumerate().skip(1) {
+        if t.name().is_empty() {
+            return btf_error(format!("Unexpected anonymous distilled base type [{}]", id));
+        }
+        let cands = by_name
+            .get(t.name())
+            .map(|ids| {
+                ids.iter()
+                    .cloned()
+                    .filter(|&cand| is_match(t, base.type_by_id(cand)))
+                    .collect::<Vec<u32>>()
+            })
+            .unwrap_or_default();
+        // types referenced only by pointer don't need exact counterpart, any will do
+        let exact = match t {
+            BtfType::Struct(t) | BtfType::Union(t) => t.sz != 0,
+            BtfType::Fwd(_) => false,
+            _ => true,
+        };
+        id_map[id] = match cands.as_slice() {
+            [] => {
+                return btf_error(format!(
+                    "No match for distilled base type [{}] {} in base BTF",
+                    id, t
+                ))
+            }
+            [cand] => *cand,
+            [cand, ..] if !exact => *cand,
+            _ => {
+                return btf_error(format!(
+                    "Ambiguous match for distilled base type [{}] {} in base BTF: {:?}",
+                    id, t, cands
+                ))
+            }
+        };
+    }
+    Ok(id_map)
+}
+
+/// Relocate split BTF, built on top of distilled base BTF (.BTF.base), onto a new full base BTF,
+/// similarly to libbpf's btf__relocate().
+///
+/// References to distilled base types are redirected to matching types of the new base, split
+/// types are renumbered to follow new base's types, and names are re-encoded against new
+/// base's string section. Result is written out as raw split BTF.
+pub fn relocate_split(split: &Btf, base: &Btf) -> BtfResult<Vec<u8>> {
+    let dist = match split.distilled_base() {
+        Some(dist) => dist,
+        None => return btf_error("Split BTF has no distilled base BTF".to_owned()),
+    };
+    if split.endian() != base.endian() {
+        return btf_error("Split BTF endianness doesn't match base BTF".to_owned());
+    }
+    let dist_map = map_distilled_base(dist, base)?;
+
+    let start_id = split.start_id();
+    let new_start_id = base.type_cnt();
+    let remap = |id: u32| {
+        if id < start_id {
+            dist_map[id as usize]
+        } else {
+            id - start_id + new_start_id
+        }
+    };
+
+    let mut w = BtfWriter::new_split(base)?;
+    for t in &split.types()[start_id as usize..] {
+        w.add_type(t, split.arena(), &remap)?;
+    }
+    w.finish()
+}
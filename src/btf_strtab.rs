@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+/// Builder of BTF string section.
+///
+/// Strings are interned and deduplicated as they are added, each unique string gets an ID.
+/// Actual offsets are assigned only when the string section is built, at which point strings
+/// that are suffixes of other strings (e.g., "next" and "sched_next") share the same bytes.
+/// Empty string always has ID 0 and offset 0.
+#[derive(Debug)]
+pub struct BtfStrtab {
+    strs: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl Default for BtfStrtab {
+    fn default() -> Self {
+        BtfStrtab::new()
+    }
+}
+
+impl BtfStrtab {
+    pub fn new() -> BtfStrtab {
+        let mut ids = HashMap::new();
+        ids.insert(String::new(), 0);
+        BtfStrtab {
+            strs: vec![String::new()],
+            ids,
+        }
+    }
+
+    /// Intern string, returns its ID.
+    pub fn add(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.ids.get(s) {
+            return id;
+        }
+        let id = self.strs.len() as u32;
+        self.strs.push(s.to_owned());
+        self.ids.insert(s.to_owned(), id);
+        id
+    }
+
+    /// Number of unique strings, including empty one.
+    pub fn len(&self) -> usize {
+        self.strs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strs.len() == 1
+    }
+
+    /// Lay out string section, returns its data along with offsets of each string, indexed by
+    /// string ID.
+    pub fn build(&self) -> (Vec<u8>, Vec<u32>) {
+        // with strings sorted by their reversed bytes, any string that is a suffix of some
+        // other string immediately precedes a string it's a suffix of (or another such string)
+        let rev = |id: u32| self.strs[id as usize].bytes().rev();
+        let mut order = (1..self.strs.len() as u32).collect::<Vec<u32>>();
+        order.sort_by(|&a, &b| rev(a).cmp(rev(b)));
+
+        let mut data = vec![0u8];
+        let mut offs = vec![0u32; self.strs.len()];
+        let mut last: Option<(&str, u32)> = None;
+        for &id in order.iter().rev() {
+            let s = self.strs[id as usize].as_str();
+            offs[id as usize] = match last {
+                Some((l, off)) if l.ends_with(s) => off + (l.len() - s.len()) as u32,
+                _ => {
+                    let off = data.len() as u32;
+                    data.extend_from_slice(s.as_bytes());
+                    data.push(0);
+                    last = Some((s, off));
+                    off
+                }
+            };
+        }
+        (data, offs)
+    }
+}
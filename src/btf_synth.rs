@@ -0,0 +1,102 @@
+//! Helpers constructing synthetic types on top of existing (e.g., kernel) types, so that ad-hoc
+//! layouts, like a packet header followed by an array of records, can be described without C.
+//!
+//! All helpers append types to given BTF and return ID of the resulting type.
+
+use crate::types::*;
+use crate::{btf_error, BtfError, BtfResult};
+
+/// Name of the integer type used as array index type, same as LLVM uses.
+pub const ARRAY_IDX_TYPE_NAME: &str = "__ARRAY_SIZE_TYPE__";
+
+/// Add pointer to given type.
+pub fn add_ptr<'a>(btf: &mut Btf<'a>, type_id: u32) -> BtfResult<u32> {
+    btf.add_type(BtfType::Ptr(BtfPtr { type_id }))
+}
+
+/// Add array of `nelems` elements of given type, reusing existing array index type, if any.
+pub fn add_array<'a>(btf: &mut Btf<'a>, elem_type_id: u32, nelems: u32) -> BtfResult<u32> {
+    let idx_type_id = array_idx_type(btf)?;
+    btf.add_type(BtfType::Array(BtfArray {
+        nelems,
+        idx_type_id,
+        val_type_id: elem_type_id,
+    }))
+}
+
+/// Add struct with given fields, laid out one after another with natural C alignment.
+pub fn add_struct<'a>(
+    btf: &mut Btf<'a>,
+    name: &'a str,
+    fields: &[(&'a str, u32)],
+) -> BtfResult<u32> {
+    let mut members = Vec::new();
+    let mut off = 0u32;
+    let mut struct_align = 1;
+    for &(field_name, type_id) in fields {
+        if type_id >= btf.type_cnt() {
+            return Err(BtfError::InvalidTypeId { id: type_id });
+        }
+        let sz = btf.get_size_of(type_id);
+        let align = btf.get_align_of(type_id);
+        if sz == 0 || align == 0 {
+            return btf_error(format!(
+                "Field '{}' type [{}] has no size: {}",
+                field_name,
+                type_id,
+                btf.type_by_id(type_id)
+            ));
+        }
+        off = off.div_ceil(align) * align;
+        members.push(BtfMember {
+            name: field_name,
+            type_id,
+            bit_offset: off * 8,
+            bit_size: 0,
+        });
+        off += sz;
+        struct_align = struct_align.max(align);
+    }
+    let members = btf.arena_mut().add_members(members);
+    btf.add_type(BtfType::Struct(BtfComposite {
+        is_struct: true,
+        name,
+        sz: off.div_ceil(struct_align) * struct_align,
+        bitfield_offs: false,
+        members,
+    }))
+}
+
+/// Add struct of two fields, `first` and `second`, of given types.
+pub fn add_pair<'a>(btf: &mut Btf<'a>, name: &'a str, first: u32, second: u32) -> BtfResult<u32> {
+    add_struct(btf, name, &[("first", first), ("second", second)])
+}
+
+/// Add struct of fixed-size header type immediately followed by an array of `nelems` records,
+/// e.g., a packet with a number of options.
+pub fn add_header_with_array<'a>(
+    btf: &mut Btf<'a>,
+    name: &'a str,
+    hdr_type_id: u32,
+    elem_type_id: u32,
+    nelems: u32,
+) -> BtfResult<u32> {
+    let arr_id = add_array(btf, elem_type_id, nelems)?;
+    add_struct(btf, name, &[("hdr", hdr_type_id), ("data", arr_id)])
+}
+
+fn array_idx_type(btf: &mut Btf) -> BtfResult<u32> {
+    let found = btf.types().iter().position(|t| match t {
+        BtfType::Int(t) => t.name == ARRAY_IDX_TYPE_NAME,
+        _ => false,
+    });
+    match found {
+        Some(id) => Ok(id as u32),
+        None => btf.add_type(BtfType::Int(BtfInt {
+            name: ARRAY_IDX_TYPE_NAME,
+            bits: 32,
+            offset: 0,
+            encoding: BtfIntEncoding::None,
+        })),
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use scroll::{IOwrite, Pwrite};
+
+use crate::btf_dedup::dedup_types;
+use crate::btf_strtab::BtfStrtab;
+use crate::types::*;
+use crate::{btf_error, BtfResult};
+
+/// Encoder producing raw BTF data (the format of /sys/kernel/btf/vmlinux).
+///
+/// Types are appended one by one, type IDs are assigned sequentially starting from 1. All type
+/// references are passed through the provided remapping function, so types can be dropped or
+/// reordered relative to the source BTF.
+pub struct BtfWriter {
+    endian: scroll::Endian,
+    type_cnt: u32,
+    types: Vec<u8>,
+    strtab: BtfStrtab,
+    // (position in type data, string ID) of name offsets to fill in once strings are laid out
+    str_fixups: Vec<(usize, u32)>,
+    // for split BTF: offsets of base BTF strings and size of base string section
+    base_strs: HashMap<String, u32>,
+    base_strs_len: u32,
+}
+
+impl BtfWriter {
+    pub fn new(endian: scroll::Endian) -> BtfWriter {
+        BtfWriter {
+            endian,
+            type_cnt: 1,
+            types: Vec::new(),
+            strtab: BtfStrtab::new(),
+            str_fixups: Vec::new(),
+            base_strs: HashMap::new(),
+            base_strs_len: 0,
+        }
+    }
+
+    /// Create encoder of split BTF on top of given base BTF: type IDs are assigned starting
+    /// right after base's types, names already present in base's string section are reused.
+    pub fn new_split(base: &Btf) -> BtfResult<BtfWriter> {
+        if base.is_split() {
+            return btf_error("Base BTF can't be a split BTF itself".to_owned());
+        }
+        let mut w = BtfWriter::new(base.endian());
+        w.type_cnt = base.type_cnt();
+        for (off, s) in base.strings() {
+            w.base_strs_len = off + s.len() as u32 + 1;
+            w.base_strs.entry(s.into_owned()).or_insert(off);
+        }
+        Ok(w)
+    }
+
+    pub fn type_cnt(&self) -> u32 {
+        self.type_cnt
+    }
+
+    /// Intern name of a record that is about to be written next; all BTF records start with
+    /// name offset, which is patched in finish().
+    fn add_name(&mut self, s: &str) -> u32 {
+        if let Some(&off) = self.base_strs.get(s) {
+            return off;
+        }
+        let id = self.strtab.add(s);
+        self.str_fixups.push((self.types.len(), id));
+        id
+    }
+
+    /// Append type, remapping all referenced type IDs; returns ID of added type. Type's members,
+    /// params or enumerators are taken from given arena.
+    pub fn add_type(
+        &mut self,
+        bt: &BtfType,
+        arena: &BtfArena,
+        remap: &dyn Fn(u32) -> u32,
+    ) -> BtfResult<u32> {
+        if let BtfType::Void = bt {
+            return Ok(0);
+        }
+        let name_off = self.add_name(bt.name());
+        match bt {
+            BtfType::Void => {}
+            BtfType::Int(t) => {
+                self.write_hdr(name_off, BTF_KIND_INT, 0, false, t.bits.div_ceil(8))?;
+                let enc = match t.encoding {
+                    BtfIntEncoding::None => 0,
+                    BtfIntEncoding::Signed => BTF_INT_SIGNED,
+                    BtfIntEncoding::Char => BTF_INT_CHAR,
+                    BtfIntEncoding::Bool => BTF_INT_BOOL,
+                };
+                self.write_u32((enc << 24) | (t.offset << 16) | t.bits)?;
+            }
+            BtfType::Ptr(t) => {
+                self.write_hdr(name_off, BTF_KIND_PTR, 0, false, remap(t.type_id))?
+            }
+            BtfType::Array(t) => {
+                self.write_hdr(name_off, BTF_KIND_ARRAY, 0, false, 0)?;
+                self.write(btf_array {
+                    val_type_id: remap(t.val_type_id),
+                    idx_type_id: remap(t.idx_type_id),
+                    nelems: t.nelems,
+                })?;
+            }
+            BtfType::Struct(t) | BtfType::Union(t) => {
+                let kind = if t.is_struct {
+                    BTF_KIND_STRUCT
+                } else {
+                    BTF_KIND_UNION
+                };
+                let members = arena.members(t);
+                let bitfields = t.bitfield_offs || members.iter().any(|m| m.bit_size != 0);
+                let vlen = members.len() as u32;
+                self.write_hdr(name_off, kind, vlen, bitfields, t.sz)?;
+                for m in members {
+                    let m = btf_member {
+                        name_off: self.add_name(m.name),
+                        type_id: remap(m.type_id),
+                        offset: if bitfields {
+                            (u32::from(m.bit_size) << 24) | m.bit_offset
+                        } else {
+                            m.bit_offset
+                        },
+                    };
+                    self.write(m)?;
+                }
+            }
+            BtfType::Enum(t) => {
+                let values = arena.enum_values(t);
+                let vlen = values.len() as u32;
+                self.write_hdr(name_off, BTF_KIND_ENUM, vlen, false, t.sz)?;
+                for v in values {
+                    let v = btf_enum {
+                        name_off: self.add_name(v.name),
+                        val: v.value,
+                    };
+                    self.write(v)?;
+                }
+            }
+            BtfType::Fwd(t) => {
+                let is_union = t.kind == BtfFwdKind::Union;
+                self.write_hdr(name_off, BTF_KIND_FWD, 0, is_union, 0)?;
+            }
+            BtfType::Typedef(t) => {
+                self.write_hdr(name_off, BTF_KIND_TYPEDEF, 0, false, remap(t.type_id))?
+            }
+            BtfType::Volatile(t) => {
+                self.write_hdr(name_off, BTF_KIND_VOLATILE, 0, false, remap(t.type_id))?
+            }
+            BtfType::Const(t) => {
+                self.write_hdr(name_off, BTF_KIND_CONST, 0, false, remap(t.type_id))?
+            }
+            BtfType::Restrict(t) => {
+                self.write_hdr(name_off, BTF_KIND_RESTRICT, 0, false, remap(t.type_id))?
+            }
+            BtfType::Func(t) => {
+                let linkage = match t.kind {
+                    BtfFuncKind::Static | BtfFuncKind::Unknown => BTF_FUNC_STATIC,
+                    BtfFuncKind::Global => BTF_FUNC_GLOBAL,
+                    BtfFuncKind::Extern => BTF_FUNC_EXTERN,
+                };
+                let proto_id = remap(t.proto_type_id);
+                self.write_hdr(name_off, BTF_KIND_FUNC, linkage, false, proto_id)?;
+            }
+            BtfType::FuncProto(t) => {
+                let params = arena.params(t);
+                let vlen = params.len() as u32;
+                let res_id = remap(t.res_type_id);
+                self.write_hdr(name_off, BTF_KIND_FUNC_PROTO, vlen, false, res_id)?;
+                for p in params {
+                    let p = btf_param {
+                        name_off: self.add_name(p.name),
+                        type_id: remap(p.type_id),
+                    };
+                    self.write(p)?;
+                }
+            }
+            BtfType::Var(t) => {
+                self.write_hdr(name_off, BTF_KIND_VAR, 0, false, remap(t.type_id))?;
+                self.write_u32(match t.kind {
+                    BtfVarKind::Static => BTF_VAR_STATIC,
+                    BtfVarKind::GlobalAlloc => BTF_VAR_GLOBAL_ALLOCATED,
+                    BtfVarKind::GlobalExtern => BTF_VAR_GLOBAL_EXTERNAL,
+                })?;
+            }
+            BtfType::Datasec(t) => {
+                let vlen = t.vars.len() as u32;
+                self.write_hdr(name_off, BTF_KIND_DATASEC, vlen, false, t.sz)?;
+                for v in &t.vars {
+                    self.write(btf_datasec_var {
+                        type_id: remap(v.type_id),
+                        offset: v.offset,
+                        size: v.sz,
+                    })?;
+                }
+            }
+            BtfType::Float(t) => self.write_hdr(name_off, BTF_KIND_FLOAT, 0, false, t.sz)?,
+            BtfType::DeclTag(t) => {
+                self.write_hdr(name_off, BTF_KIND_DECL_TAG, 0, false, remap(t.type_id))?;
+                self.write_u32(t.comp_idx)?;
+            }
+            BtfType::TypeTag(t) => {
+                self.write_hdr(name_off, BTF_KIND_TYPE_TAG, 0, false, remap(t.type_id))?
+            }
+            BtfType::Enum64(t) => {
+                let values = arena.enum64_values(t);
+                let vlen = values.len() as u32;
+                self.write_hdr(name_off, BTF_KIND_ENUM64, vlen, t.signed, t.sz)?;
+                for v in values {
+                    let v = btf_enum64 {
+                        name_off: self.add_name(v.name),
+                        val_lo32: v.value as u32,
+                        val_hi32: (v.value >> 32) as u32,
+                    };
+                    self.write(v)?;
+                }
+            }
+        }
+        self.type_cnt += 1;
+        Ok(self.type_cnt - 1)
+    }
+
+    /// Produce complete raw BTF data: header, type section and string section.
+    pub fn finish(mut self) -> BtfResult<Vec<u8>> {
+        let (strs, str_offs) = self.strtab.build();
+        for &(pos, id) in &self.str_fixups {
+            let off = self.base_strs_len + str_offs[id as usize];
+            self.types.pwrite_with(off, pos, self.endian)?;
+        }
+
+        let hdr_len = size_of::<btf_header>() as u32;
+        let mut data = Vec::with_capacity(hdr_len as usize + self.types.len() + strs.len());
+        data.iowrite_with(
+            btf_header {
+                magic: BTF_MAGIC,
+                version: BTF_VERSION,
+                flags: 0,
+                hdr_len,
+                type_off: 0,
+                type_len: self.types.len() as u32,
+                str_off: self.types.len() as u32,
+                str_len: strs.len() as u32,
+            },
+            self.endian,
+        )?;
+        data.extend_from_slice(&self.types);
+        data.extend_from_slice(&strs);
+        Ok(data)
+    }
+
+    fn write_hdr(
+        &mut self,
+        name_off: u32,
+        kind: u32,
+        vlen: u32,
+        kind_flag: bool,
+        size_or_type: u32,
+    ) -> BtfResult<()> {
+        self.write(btf_type {
+            name_off,
+            info: (u32::from(kind_flag) << 31) | (kind << 24) | vlen,
+            type_id: size_or_type,
+        })
+    }
+
+    fn write_u32(&mut self, v: u32) -> BtfResult<()> {
+        self.types.iowrite_with(v, self.endian)?;
+        Ok(())
+    }
+
+    fn write<T: scroll::ctx::SizeWith<scroll::Endian> + scroll::ctx::IntoCtx<scroll::Endian>>(
+        &mut self,
+        v: T,
+    ) -> BtfResult<()> {
+        self.types.iowrite_with(v, self.endian)?;
+        Ok(())
+    }
+}
+
+/// Encode subset of types marked in `keep` (indexed by type ID) as a new raw BTF.
+///
+/// Named structs/unions that are dropped, but still referenced from kept types, are replaced
+/// with forward declarations. References to any other dropped type are turned into `void`.
+/// DECL_TAGs are dropped along with the types they annotate.
+pub fn encode_subset(btf: &Btf, keep: &[bool]) -> BtfResult<Vec<u8>> {
+    let mut keep = keep.to_vec();
+    keep[0] = true;
+    for (id, t) in btf.types().iter().enumerate() {
+        if let BtfType::DeclTag(t) = t {
+            keep[id] = keep[id] && keep[t.type_id as usize];
+        }
+    }
+
+    let mut fwds = vec![false; keep.len()];
+    for (id, t) in btf.types().iter().enumerate() {
+        if !keep[id] {
+            continue;
+        }
+        for ref_id in btf.type_refs(t) {
+            if keep[ref_id as usize] {
+                continue;
+            }
+            match btf.type_by_id(ref_id) {
+                BtfType::Struct(t) | BtfType::Union(t) if !t.name.is_empty() => {
+                    fwds[ref_id as usize] = true;
+                }
+                BtfType::Fwd(_) => fwds[ref_id as usize] = true,
+                _ => {}
+            }
+        }
+    }
+
+    let mut id_map = vec![0u32; keep.len()];
+    let mut next_id = 1;
+    for id in 1..keep.len() {
+        if keep[id] || fwds[id] {
+            id_map[id] = next_id;
+            next_id += 1;
+        }
+    }
+
+    let mut w = BtfWriter::new(btf.endian());
+    let remap = |id: u32| id_map[id as usize];
+    for (id, t) in btf.types().iter().enumerate().skip(1) {
+        if keep[id] {
+            w.add_type(t, btf.arena(), &remap)?;
+        } else if fwds[id] {
+            let fwd = match t {
+                BtfType::Struct(t) | BtfType::Union(t) => BtfType::Fwd(BtfFwd {
+                    name: t.name,
+                    kind: if t.is_struct {
+                        BtfFwdKind::Struct
+                    } else {
+                        BtfFwdKind::Union
+                    },
+                }),
+                BtfType::Fwd(t) => BtfType::Fwd(BtfFwd {
+                    name: t.name,
+                    kind: t.kind,
+                }),
+                _ => unreachable!(),
+            };
+            w.add_type(&fwd, btf.arena(), &remap)?;
+        }
+    }
+    w.finish()
+}
+
+/// Encode all types of given BTFs as a single raw BTF, type IDs of each subsequent BTF are
+/// re-based to follow types of preceding ones. If `dedup` is true, structurally identical
+/// types are emitted just once.
+pub fn encode_merged(btfs: &[&Btf], dedup: bool) -> BtfResult<Vec<u8>> {
+    let void_arena = BtfArena::default();
+    let mut types: Vec<(&BtfType, &BtfArena, Vec<u32>)> =
+        vec![(&BtfType::Void, &void_arena, Vec::new())];
+    for btf in btfs {
+        let base = types.len() as u32 - 1;
+        let rebase = |id: u32| if id == 0 { 0 } else { id + base };
+        for t in &btf.types()[1..] {
+            let refs = btf.type_refs(t).into_iter().map(rebase).collect();
+            types.push((t, btf.arena(), refs));
+        }
+    }
+
+    let canon = if dedup {
+        dedup_types(&types)
+    } else {
+        (0..types.len() as u32).collect()
+    };
+    let mut id_map = vec![0u32; types.len()];
+    let mut next_id = 1;
+    for i in 1..types.len() {
+        if canon[i] == i as u32 {
+            id_map[i] = next_id;
+            next_id += 1;
+        }
+    }
+
+    let endian = btfs.first().map_or(scroll::LE, |btf| btf.endian());
+    let mut w = BtfWriter::new(endian);
+    let mut base = 0;
+    for btf in btfs {
+        let remap = |id: u32| {
+            if id == 0 {
+                0
+            } else {
+                id_map[canon[(id + base) as usize] as usize]
+            }
+        };
+        for (id, t) in btf.types().iter().enumerate().skip(1) {
+            let gid = id + base as usize;
+            if canon[gid] == gid as u32 {
+                w.add_type(t, btf.arena(), &remap)?;
+            }
+        }
+        base += btf.type_cnt() - 1;
+    }
+    w.finish()
+}
+
+/// Encode BTF with newer BTF kinds downgraded for older kernels, similar to libbpf's sanitization.
+///
+/// ENUM64 becomes ENUM if all values fit into 32 bits, or a UNION of the same size otherwise,
+/// with a member per enumerator, all of a shared 1-byte INT placeholder type appended at the end.
+/// FLOAT becomes INT of the same size, or an empty STRUCT of the same size, if it's not a valid
+/// INT size. TYPE_TAGs are skipped over, DECL_TAGs are dropped, extern VARs are turned into global
+/// ones.
+pub fn encode_sanitized(btf: &Btf) -> BtfResult<Vec<u8>> {
+    let mut id_map = vec![0u32; btf.type_cnt() as usize];
+    let mut next_id = 1;
+    for (id, t) in btf.types().iter().enumerate().skip(1) {
+        match t {
+            BtfType::DeclTag(_) | BtfType::TypeTag(_) => {}
+            _ => {
+                id_map[id] = next_id;
+                next_id += 1;
+            }
+        }
+    }
+    let remap = |mut id: u32| {
+        while let BtfType::TypeTag(t) = btf.type_by_id(id) {
+            id = t.type_id;
+        }
+        id_map[id as usize]
+    };
+
+    // ID of ENUM64 placeholder INT, added after all the other types, if needed
+    let placeholder_id = next_id;
+    let mut need_placeholder = false;
+    // members and enumerators of types replacing ENUM64s
+    let mut arena = BtfArena::default();
+
+    let mut w = BtfWriter::new(btf.endian());
+    for t in &btf.types()[1..] {
+        let sanitized = match t {
+            BtfType::DeclTag(_) | BtfType::TypeTag(_) => continue,
+            BtfType::Enum64(t) => {
+                let fits = |v: i64| {
+                    if t.signed {
+                        v >= i64::from(i32::MIN) && v <= i64::from(u32::MAX)
+                    } else {
+                        v as u64 <= u64::from(u32::MAX)
+                    }
+                };
+                let values = btf.enum64_values(t);
+                if t.sz <= 4 && values.iter().all(|v| fits(v.value)) {
+                    BtfType::Enum(BtfEnum {
+                        name: t.name,
+                        sz: t.sz,
+                        values: arena.add_enum_values(values.iter().map(|v| BtfEnumValue {
+                            name: v.name,
+                            value: v.value as i32,
+                        })),
+                    })
+                } else {
+                    need_placeholder = true;
+                    let u = BtfType::Union(BtfComposite {
+                        is_struct: false,
+                        name: t.name,
+                        sz: t.sz,
+                        bitfield_offs: false,
+                        members: arena.add_members(values.iter().map(|v| BtfMember {
+                            name: v.name,
+                            type_id: 0,
+                            bit_offset: 0,
+                            bit_size: 0,
+                        })),
+                    });
+                    w.add_type(&u, &arena, &|_| placeholder_id)?;
+                    continue;
+                }
+            }
+            BtfType::Float(t) if matches!(t.sz, 1 | 2 | 4 | 8 | 16) => BtfType::Int(BtfInt {
+                name: t.name,
+                bits: t.sz * 8,
+                offset: 0,
+                encoding: BtfIntEncoding::None,
+            }),
+            BtfType::Float(t) => BtfType::Struct(BtfComposite {
+                is_struct: true,
+                name: t.name,
+                sz: t.sz,
+                bitfield_offs: false,
+                members: 0..0,
+            }),
+            BtfType::Var(t) if t.kind == BtfVarKind::GlobalExtern => BtfType::Var(BtfVar {
+                name: t.name,
+                type_id: t.type_id,
+                kind: BtfVarKind::GlobalAlloc,
+            }),
+            _ => {
+                w.add_type(t, btf.arena(), &remap)?;
+                continue;
+            }
+        };
+        w.add_type(&sanitized, &arena, &remap)?;
+    }
+    if need_placeholder {
+        let placeholder = BtfType::Int(BtfInt {
+            name: "enum64_placeholder",
+            bits: 8,
+            offset: 0,
+            encoding: BtfIntEncoding::None,
+        });
+        w.add_type(&placeholder, &arena, &remap)?;
+    }
+    w.finish()
+}
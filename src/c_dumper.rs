@@ -1,10 +1,43 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
 
 use lazy_static::lazy_static;
 use regex::RegexSet;
 
+use crate::btf_dedup::shallow_sig;
 use crate::types::*;
-use crate::{btf_error, BtfResult};
+use crate::{btf_error, fnv1a, BtfError, BtfResult};
+
+macro_rules! emit {
+    ($out:expr, $($arg:tt)*) => {
+        $out.write_fmt(format_args!($($arg)*))
+    };
+}
+
+macro_rules! emitln {
+    ($out:expr, $($arg:tt)*) => {{
+        $out.write_fmt(format_args!($($arg)*));
+        $out.write_fmt(format_args!("\n"));
+    }};
+}
+
+/// Sink that C code is emitted into. The first write error is remembered and reported once the
+/// type is dumped, so emitting code doesn't have to handle errors of each write.
+struct Out<'w> {
+    w: &'w mut dyn io::Write,
+    err: Option<io::Error>,
+}
+
+impl Out<'_> {
+    fn write_fmt(&mut self, args: fmt::Arguments) {
+        if self.err.is_none() {
+            if let Err(e) = self.w.write_fmt(args) {
+                self.err = Some(e);
+            }
+        }
+    }
+}
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum OrderState {
@@ -40,78 +73,893 @@ struct TypeState {
     name: String,
 }
 
+/// Layout of struct/union that C compiler reproduces exactly, see CDumperCfg::exact_layout.
+#[derive(Debug)]
+struct CompositeLayout {
+    packed: bool,
+    /// Alignment of struct/union as emitted
+    align: u32,
+    /// [start, end) bit ranges of padding before each member, followed by trailing padding
+    pads: Vec<(u32, u32)>,
+}
+
+/// Member of BTF-defined map definition, as declared with libbpf's macros.
+#[derive(Debug, Copy, Clone)]
+enum MapDefField<'a> {
+    /// `__uint(name, val)`, encoded as `int (*name)[val]`
+    Uint(&'a str, u32),
+    /// `__type(name, T)`, encoded as `T *name`
+    Type(&'a str, u32),
+    /// `__array(name, T)`, encoded as `T *name[]`
+    Array(&'a str, u32),
+}
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 enum NamedKind {
     Type,
     Ident,
 }
 
+/// Order in which matching types are dumped; types they depend on are still emitted first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TypeOrder {
+    /// Order of type IDs, i.e., as types appear in BTF
+    Id,
+    /// Sorted by name, stable across BTFs that only differ in type numbering
+    Name,
+    /// Sorted by size, then by name
+    Size,
+}
+
+impl std::str::FromStr for TypeOrder {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(TypeOrder::Id),
+            "name" => Ok(TypeOrder::Name),
+            "size" => Ok(TypeOrder::Size),
+            _ => Err(BtfError::new_owned(format!(
+                "unrecognized sort order: '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Language of generated declarations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lang {
+    C,
+    /// C declarations that can also be compiled as C++: identifiers that are C++ keywords get
+    /// '_' suffix, enums with clashing value names become `enum class`, etc.
+    Cpp,
+}
+
+impl std::str::FromStr for Lang {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(Lang::C),
+            "c++" | "cpp" => Ok(Lang::Cpp),
+            _ => Err(BtfError::new_owned(format!(
+                "unrecognized language: '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// How packed structs are marked as such.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PackStyle {
+    /// `__attribute__((packed))` after struct definition
+    Attribute,
+    /// Struct definition wrapped into `#pragma pack(push, 1)` and `#pragma pack(pop)`; anonymous
+    /// structs and those embedding non-packed anonymous structs/unions still get the attribute
+    Pragma,
+}
+
+impl std::str::FromStr for PackStyle {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "attribute" => Ok(PackStyle::Attribute),
+            "pragma" => Ok(PackStyle::Pragma),
+            _ => Err(BtfError::new_owned(format!(
+                "unrecognized pack style: '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// How types that dumped types depend on, but that weren't selected for dumping themselves, are
+/// emitted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DepsMode {
+    /// Complete definitions, so that output is self-contained
+    All,
+    /// Forward declarations of named structs/unions and fwds only; enums and typedefs are omitted
+    FwdOnly,
+    /// Nothing, output only has definitions of selected types
+    None,
+}
+
+impl std::str::FromStr for DepsMode {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(DepsMode::All),
+            "fwd-only" => Ok(DepsMode::FwdOnly),
+            "none" => Ok(DepsMode::None),
+            _ => Err(BtfError::new_owned(format!(
+                "unrecognized deps mode: '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Unit of indentation of nested declarations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Indent {
+    Tab,
+    Spaces(usize),
+}
+
+impl std::str::FromStr for Indent {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tab" => Ok(Indent::Tab),
+            _ => match s.parse() {
+                Ok(n) => Ok(Indent::Spaces(n)),
+                Err(_) => Err(BtfError::new_owned(format!(
+                    "unrecognized indent: '{}', expected 'tab' or number of spaces",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CDumperCfg {
     pub verbose: bool,
     pub union_as_struct: bool,
+    /// Skip types that fail to be dumped instead of aborting
+    pub keep_going: bool,
+    /// Emit variables of each DATASEC as C declarations, grouped by section
+    pub emit_vars: bool,
+    /// Emit FUNCs as C function prototypes
+    pub emit_funcs: bool,
+    /// Order of dumped types
+    pub sort: TypeOrder,
+    /// Emit _Static_assert checks of size and member offsets after each struct/union
+    pub emit_asserts: bool,
+    /// Give anonymous structs/unions/enums (except anonymous embedded members) deterministic
+    /// names derived from their contents and define them separately instead of inlining
+    pub name_anon: bool,
+    /// Emit trailing zero-length arrays GNU-style as `[0]` instead of C99 flexible array members
+    pub zero_len_arrays: bool,
+    /// Language to emit declarations in
+    pub lang: Lang,
+    /// Emit only forward declarations of named structs/unions, typedefs and enums (which can't
+    /// be forward-declared in C) instead of complete definitions
+    pub fwd_decls_only: bool,
+    /// Emit enum values as #define macros, referencing enums as integers of the same size
+    pub enums_as_macros: bool,
+    /// How packed structs are emitted
+    pub pack_style: PackStyle,
+    /// Indentation of struct/union members and enum values
+    pub indent: Indent,
+    /// Put opening brace of struct/union/enum definition on its own line
+    pub brace_on_new_line: bool,
+    /// Pad and pack structs/unions so that C compiler reproduces BTF offsets and sizes exactly,
+    /// instead of relying on natural padding and alignment
+    pub exact_layout: bool,
+    /// Names of weak extern symbols (e.g., from ELF symbol table of BPF object), that get
+    /// `__weak` attribute when declared as .ksyms/.kconfig externs
+    pub weak_externs: HashSet<String>,
+    /// Emit each declaration on a single line with single spaces between tokens and no blank
+    /// lines, so that line-based diffs of dumps show only changed declarations
+    pub diff_friendly: bool,
+    /// Emit struct/union members sorted by name (a trailing flexible array member stays last).
+    /// Resulting structs don't have original layout, so this is meant for diffing only
+    pub sort_members: bool,
+    /// Don't emit typedefs of kernel integer types (u32, __u32, __le32, etc.) that resolve to
+    /// integers of expected size and signedness, and emit a fixed preamble of them instead
+    pub std_int_types: bool,
+    /// Disambiguate types with clashing names by a hash of their definition (e.g.,
+    /// `foo___1a2b3c4d`) instead of a counter in order of emitting, keeping the original name
+    /// for the type with the lowest ID, so names don't depend on filtering and sorting
+    pub hash_suffixes: bool,
+    /// Don't emit definitions of types with matching names
+    pub exclude_names: RegexSet,
+    /// If set, emit definitions only of types with matching names (anonymous types are always
+    /// emitted)
+    pub include_names: Option<RegexSet>,
+    /// How named types that are not dumped themselves, but dumped types depend on, are emitted
+    pub deps: DepsMode,
+}
+
+impl Default for CDumperCfg {
+    /// Plain C output of all types, as `btf dump --format c` emits it without any options.
+    fn default() -> Self {
+        CDumperCfg {
+            verbose: false,
+            union_as_struct: false,
+            keep_going: false,
+            emit_vars: false,
+            emit_funcs: false,
+            sort: TypeOrder::Id,
+            emit_asserts: false,
+            name_anon: false,
+            zero_len_arrays: false,
+            lang: Lang::C,
+            fwd_decls_only: false,
+            enums_as_macros: false,
+            pack_style: PackStyle::Attribute,
+            indent: Indent::Tab,
+            brace_on_new_line: false,
+            exact_layout: false,
+            weak_externs: HashSet::new(),
+            diff_friendly: false,
+            sort_members: false,
+            std_int_types: false,
+            hash_suffixes: false,
+            exclude_names: RegexSet::empty(),
+            include_names: None,
+            deps: DepsMode::All,
+        }
+    }
+}
+
+/// Maps BTF names to identifiers emitted in C output.
+pub trait IdentSanitizer {
+    fn sanitize(&self, name: &str) -> String;
+}
+
+impl<F> IdentSanitizer for F
+where
+    F: Fn(&str) -> String,
+{
+    fn sanitize(&self, name: &str) -> String {
+        self(name)
+    }
+}
+
+/// Default sanitizer: every character that is not allowed in C identifier (e.g., '.' in
+/// LTO-generated `foo.llvm.123` or '$' from asm renames) is replaced with '_', and names
+/// starting with a digit are prefixed with '_'. Valid identifiers are left intact.
+#[derive(Debug, Default)]
+pub struct DefaultIdentSanitizer;
+
+impl IdentSanitizer for DefaultIdentSanitizer {
+    fn sanitize(&self, name: &str) -> String {
+        let mut ident = String::with_capacity(name.len() + 1);
+        if name.starts_with(|c: char| c.is_ascii_digit()) {
+            ident.push('_');
+        }
+        ident.extend(name.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        }));
+        ident
+    }
 }
 
 pub struct CDumper<'a> {
     btf: &'a Btf<'a>,
     cfg: CDumperCfg,
     state: Vec<TypeState>,
-    names: HashMap<(NamedKind, &'a str), u32>,
+    names: HashMap<(NamedKind, String), u32>,
+    /// Lowest ID of a type with given name, that keeps it unsuffixed, see CDumperCfg::hash_suffixes
+    name_owners: HashMap<(NamedKind, &'a str), u32>,
+    sanitizer: Box<dyn IdentSanitizer + 'a>,
+    /// Synthetic names of anonymous types, see CDumperCfg::name_anon
+    anon_names: HashMap<u32, String>,
+    /// Anonymous types identical to another anonymous type, mapped to the first of them
+    anon_dups: HashMap<u32, u32>,
+    /// Array type of a member being emitted, that is a flexible array member
+    fam_array: Option<u32>,
+    /// Nesting level of ordering types that have to be complete (members, array elements)
+    complete_lvl: u32,
+    /// Names of types, members and params, that enum value macros must not collide with
+    macro_reserved: HashSet<String>,
+    /// Emitting struct definition inside of `#pragma pack(push, 1)`
+    in_pragma_pack: bool,
+    /// One level of indentation, according to CDumperCfg::indent
+    indent: String,
+    /// Planned layouts of structs/unions (None, if it can't be reproduced) in exact_layout mode
+    layouts: HashMap<u32, Option<CompositeLayout>>,
+    /// libbpf's macro definitions (MAP_DEF_MACROS, etc.) emitted already
+    macros_emitted: HashSet<&'static str>,
+    std_int_types_emitted: bool,
+    /// Source file and line of FUNCs, from line info of their first instruction
+    func_locs: HashMap<u32, (&'a str, u32)>,
+    /// Types requested to be dumped, unlike their dependencies, see CDumperCfg::deps
+    roots: HashSet<u32>,
 }
 
 impl<'a> CDumper<'a> {
-    pub fn new(btf: &'a Btf<'a>, cfg: CDumperCfg) -> CDumper<'a> {
+    pub fn new(btf: &'a Btf<'a>, cfg: CDumperCfg) -> BtfResult<CDumper<'a>> {
+        let indent = match cfg.indent {
+            Indent::Tab => "\t".to_string(),
+            Indent::Spaces(n) => " ".repeat(n),
+        };
         let mut dumper = CDumper {
             btf: btf,
             cfg: cfg,
             state: Vec::new(),
             names: HashMap::new(),
+            name_owners: HashMap::new(),
+            sanitizer: Box::new(DefaultIdentSanitizer),
+            anon_names: HashMap::new(),
+            anon_dups: HashMap::new(),
+            fam_array: None,
+            complete_lvl: 0,
+            macro_reserved: HashSet::new(),
+            in_pragma_pack: false,
+            indent,
+            layouts: HashMap::new(),
+            macros_emitted: HashSet::new(),
+            std_int_types_emitted: false,
+            func_locs: HashMap::new(),
+            roots: HashSet::new(),
         };
         dumper
             .state
             .resize_with(btf.type_cnt() as usize, Default::default);
-        dumper
+        if dumper.cfg.name_anon {
+            dumper.name_anon_types()?;
+        }
+        if btf.has_ext() {
+            dumper.collect_func_locs();
+        }
+        if dumper.cfg.hash_suffixes {
+            for id in (1..btf.type_cnt()).rev() {
+                let kind = match btf.try_type_by_id(id)? {
+                    BtfType::Struct(_)
+                    | BtfType::Union(_)
+                    | BtfType::Enum(_)
+                    | BtfType::Enum64(_)
+                    | BtfType::Fwd(_) => NamedKind::Type,
+                    BtfType::Typedef(_) | BtfType::Func(_) | BtfType::Var(_) => NamedKind::Ident,
+                    _ => continue,
+                };
+                let t = btf.try_type_by_id(id)?;
+                let vals: Vec<&str> = match t {
+                    BtfType::Enum(t) => btf.enum_values(t).iter().map(|v| v.name).collect(),
+                    BtfType::Enum64(t) => btf.enum64_values(t).iter().map(|v| v.name).collect(),
+                    _ => Vec::new(),
+                };
+                for val in vals {
+                    dumper.name_owners.insert((NamedKind::Ident, val), id);
+                }
+                if !t.name().is_empty() {
+                    dumper.name_owners.insert((kind, t.name()), id);
+                }
+            }
+        }
+        if dumper.cfg.enums_as_macros {
+            dumper.reserve_macro_names();
+        }
+        if dumper.cfg.std_int_types {
+            // names in preamble can't be reused by other types
+            for &(name, _, _) in STD_INT_TYPES {
+                dumper.names.insert((NamedKind::Ident, name.to_owned()), 1);
+            }
+        }
+        if dumper.cfg.exact_layout {
+            for id in btf.start_id()..btf.type_cnt() {
+                dumper.emitted_align(id)?;
+            }
+        }
+        Ok(dumper)
+    }
+
+    fn name_anon_types(&mut self) -> BtfResult<()> {
+        let btf = self.btf;
+        // anonymous embedded structs/unions have to stay anonymous to remain embedded
+        let mut embedded = vec![false; btf.type_cnt() as usize];
+        for t in btf.types() {
+            if let BtfType::Struct(t) | BtfType::Union(t) = t {
+                for m in btf.members(t).iter().filter(|m| m.name.is_empty()) {
+                    embedded[btf.skip_mods(m.type_id) as usize] = true;
+                }
+            }
+        }
+        let mut first_by_name = HashMap::new();
+        for (id, t) in btf.types().iter().enumerate() {
+            let kind = match t {
+                BtfType::Struct(_) => "struct",
+                BtfType::Union(_) => "union",
+                BtfType::Enum(_) | BtfType::Enum64(_) => "enum",
+                _ => continue,
+            };
+            if !t.name().is_empty() || embedded[id] {
+                continue;
+            }
+            let mut sig = String::new();
+            anon_sig(btf, id as u32, &mut sig, 0)?;
+            let name = format!("anon_{}__{:016x}", kind, fnv1a(sig.as_bytes()));
+            match first_by_name.get(&name) {
+                Some(&first) => {
+                    self.anon_dups.insert(id as u32, first);
+                }
+                None => {
+                    first_by_name.insert(name.clone(), id as u32);
+                    self.anon_names.insert(id as u32, name);
+                }
+            }
+        }
+        Ok(())
     }
 
+    fn collect_func_locs(&mut self) {
+        for sec in self.btf.func_secs() {
+            let line_sec = self.btf.line_secs().iter().find(|s| s.name == sec.name);
+            let lines = match line_sec {
+                Some(line_sec) => &line_sec.recs,
+                None => continue,
+            };
+            for f in &sec.recs {
+                // line info records are sorted by instruction offset
+                if let Ok(i) = lines.binary_search_by_key(&f.insn_off, |l| l.insn_off) {
+                    self.func_locs
+                        .insert(f.type_id, (lines[i].file_name, lines[i].line_num));
+                }
+            }
+        }
+    }
+
+    /// Macros are not scoped, so they'd clobber any other identifier with the same name.
+    fn reserve_macro_names(&mut self) {
+        for t in self.btf.types() {
+            let names: Vec<&str> = match t {
+                BtfType::Struct(t) | BtfType::Union(t) => std::iter::once(t.name)
+                    .chain(self.btf.members(t).iter().map(|m| m.name))
+                    .collect(),
+                BtfType::FuncProto(t) => self.btf.params(t).iter().map(|p| p.name).collect(),
+                BtfType::Enum(_) | BtfType::Enum64(_) => continue,
+                t => vec![t.name()],
+            };
+            for name in names.into_iter().filter(|n| !n.is_empty()) {
+                let name = self.field_name(&self.sanitizer.sanitize(name));
+                self.macro_reserved.insert(name);
+            }
+        }
+    }
+
+    /// Map anonymous type to identical anonymous type that is dumped instead of it.
+    fn canon_id(&self, id: u32) -> u32 {
+        self.anon_dups.get(&id).cloned().unwrap_or(id)
+    }
+
+    /// Whether type has neither a name nor a synthetic name, so it has to be defined inline.
+    fn is_anon(&self, id: u32, name: &str) -> bool {
+        name.is_empty() && !self.anon_names.contains_key(&self.canon_id(id))
+    }
+
+    /// Replace default identifier sanitizer; has to be set before any type is dumped.
+    pub fn set_ident_sanitizer(&mut self, sanitizer: Box<dyn IdentSanitizer + 'a>) {
+        self.sanitizer = sanitizer;
+    }
+
+    /// Dump all types matching filter as C definitions into given sink. With keep_going, types
+    /// that failed to be dumped are skipped and returned along with their errors.
     pub fn dump_types(
         &mut self,
         filter: Box<dyn Fn(u32, &'a BtfType<'a>) -> bool>,
-    ) -> BtfResult<()> {
-        for id in 1..self.btf.type_cnt() {
-            let bt = self.btf.type_by_id(id);
-            if filter(id, bt) {
-                self.dump_type(id)?;
+        w: &mut dyn io::Write,
+    ) -> BtfResult<Vec<(u32, BtfError)>> {
+        let mut failures = Vec::new();
+        let mut ids = Vec::new();
+        for id in self.btf.start_id()..self.btf.type_cnt() {
+            if filter(id, self.btf.try_type_by_id(id)?) {
+                ids.push(id);
+            }
+        }
+        self.sort_ids(&mut ids)?;
+        if self.cfg.deps != DepsMode::All {
+            let roots = ids.iter().map(|&id| self.canon_id(id)).collect::<Vec<_>>();
+            self.roots.extend(roots);
+        }
+        for id in ids {
+            let bt = self.btf.try_type_by_id(id)?;
+            match self.dump_type(id, w) {
+                Ok(()) => {}
+                Err(e) if self.cfg.keep_going => {
+                    let sep = if self.cfg.diff_friendly { "" } else { "\n" };
+                    writeln!(
+                        w,
+                        "/* ERROR: failed to dump [{}] {}: {} */{}",
+                        id,
+                        self.btf.type_display(bt),
+                        e,
+                        sep
+                    )?;
+                    self.reset_in_progress_states();
+                    failures.push((id, e));
+                }
+                Err(e) => return Err(e),
             }
         }
+        Ok(failures)
+    }
+
+    fn sort_ids(&self, ids: &mut [u32]) -> BtfResult<()> {
+        let btf = self.btf;
+        let mut keyed = Vec::with_capacity(ids.len());
+        for &id in ids.iter() {
+            let t = btf.try_type_by_id(id)?;
+            keyed.push((id, (t.name(), t.kind() as u32, btf.get_size_of(id))));
+        }
+        match self.cfg.sort {
+            TypeOrder::Id => return Ok(()),
+            TypeOrder::Name => keyed.sort_by_key(|&(id, name_key)| (name_key, id)),
+            TypeOrder::Size => keyed.sort_by_key(|&(id, name_key)| (name_key.2, name_key, id)),
+        }
+        for (dst, (id, _)) in ids.iter_mut().zip(keyed) {
+            *dst = id;
+        }
         Ok(())
     }
 
-    pub fn dump_type(&mut self, id: u32) -> BtfResult<()> {
+    /// Forget ordering/emitting of types that was interrupted by an error.
+    fn reset_in_progress_states(&mut self) {
+        for s in &mut self.state {
+            if s.order_state == OrderState::Ordering {
+                s.order_state = OrderState::NotOrdered;
+            }
+            if s.emit_state == EmitState::Emitting {
+                s.emit_state = EmitState::NotEmitted;
+            }
+        }
+    }
+
+    /// Dump C definition of given type, along with all types it depends on that weren't
+    /// dumped yet, into given sink.
+    pub fn dump_type(&mut self, id: u32, w: &mut dyn io::Write) -> BtfResult<()> {
+        if !self.cfg.diff_friendly {
+            return self.emit_into(id, w);
+        }
+        let mut joiner = DeclJoiner::new(w);
+        let res = self.emit_into(id, &mut joiner);
+        joiner.finish()?;
+        res
+    }
+
+    fn emit_into(&mut self, id: u32, w: &mut dyn io::Write) -> BtfResult<()> {
+        let mut out = Out { w, err: None };
+        let res = self.order_and_emit_type(&mut out, id);
+        match out.err {
+            Some(e) => Err(e.into()),
+            None => res,
+        }
+    }
+
+    /// Same as dump_type(), but instead of writing into a sink returns emitted C definitions.
+    /// Types already dumped by this dumper are not repeated.
+    pub fn type_to_string(&mut self, id: u32) -> BtfResult<String> {
+        let mut out = Vec::new();
+        self.dump_type(id, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Same as dump_types(), but instead of writing into a sink returns emitted C definitions.
+    pub fn types_to_string(
+        &mut self,
+        filter: Box<dyn Fn(u32, &'a BtfType<'a>) -> bool>,
+    ) -> BtfResult<String> {
+        let mut out = Vec::new();
+        let failures = self.dump_types(filter, &mut out)?;
+        if !failures.is_empty() {
+            return btf_error(format!("Failed to dump {} type(s)", failures.len()));
+        }
+        Ok(String::from_utf8(out)?)
+    }
+
+    fn order_and_emit_type(&mut self, out: &mut Out, id: u32) -> BtfResult<()> {
+        if self.cfg.deps != DepsMode::All && id < self.btf.type_cnt() {
+            self.roots.insert(self.canon_id(id));
+        }
+        if self.cfg.fwd_decls_only && id < self.btf.type_cnt() {
+            let id = self.canon_id(id);
+            if let BtfType::Struct(t) | BtfType::Union(t) = self.btf.try_type_by_id(id)? {
+                if !self.is_anon(id, t.name) {
+                    let emitted = self.get_emit_state(id) == EmitState::Emitted;
+                    if !emitted && !self.get_fwd_emitted(id) && !self.is_excluded(t.name) {
+                        self.emit_composite_fwd(out, id, t)?;
+                        emitln!(out, ";\n");
+                    }
+                    self.set_fwd_emitted(id, true);
+                    return Ok(());
+                }
+            }
+        }
         let mut order = Vec::new();
         if self.cfg.verbose {
-            println!("===================================================");
-            println!("ORDERING id: {}, type: {}", id, self.btf.type_by_id(id));
+            emitln!(out, "===================================================");
+            emitln!(
+                out,
+                "ORDERING id: {}, type: {}",
+                id,
+                self.btf.type_display(self.btf.try_type_by_id(id)?)
+            );
         }
-        self.order_type(id, false, &mut order)?;
+        self.order_type(out, id, false, &mut order)?;
         if self.cfg.verbose {
             for (i, &id) in order.iter().enumerate() {
-                println!("ORDER #{} id: {}, type: {}", i, id, self.btf.type_by_id(id));
+                emitln!(
+                    out,
+                    "ORDER #{} id: {}, type: {}",
+                    i,
+                    id,
+                    self.btf.type_display(self.btf.try_type_by_id(id)?)
+                );
             }
         }
         // emit struct/union and fwds required by them in correct order
         for id in order {
-            self.emit_type(id, 0)?;
+            self.emit_type(out, id, 0)?;
+        }
+        match self.btf.try_type_by_id(id)? {
+            BtfType::Datasec(t) if self.cfg.emit_vars => self.emit_datasec_vars(out, t)?,
+            BtfType::Func(t) if self.cfg.emit_funcs => self.emit_func_proto(out, id, t)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn emit_func_proto(&mut self, out: &mut Out, id: u32, t: &'a BtfFunc) -> BtfResult<()> {
+        self.order_and_emit_func_proto_types(out, id, t)?;
+        if let Some((file, line)) = self.func_locs.get(&id) {
+            emitln!(out, "/* defined in {}:{} */", file, line);
+        }
+        if let BtfFuncKind::Static = t.kind {
+            emit!(out, "static ");
+        } else {
+            emit!(out, "extern ");
+        }
+        let name = self.resolve_type_name(NamedKind::Ident, id, t.name)?;
+        self.emit_type_decl(out, t.proto_type_id, &name, 0)?;
+        emitln!(out, ";\n");
+        Ok(())
+    }
+
+    fn order_and_emit_func_proto_types(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        t: &'a BtfFunc,
+    ) -> BtfResult<()> {
+        self.order_and_emit_type(out, t.proto_type_id)?;
+        // typedefs used through pointers still have to be complete to be used in prototype
+        if let BtfType::FuncProto(proto) = self.btf.try_type_by_id(t.proto_type_id)? {
+            let ids = std::iter::once(proto.res_type_id)
+                .chain(self.btf.params(proto).iter().map(|p| p.type_id));
+            for type_id in ids {
+                let leaf = self.skip_decl_chain(type_id)?;
+                if let BtfType::Typedef(_) = self.btf.try_type_by_id(leaf)? {
+                    self.order_and_emit_type(out, leaf)?;
+                }
+            }
+        }
+        // emit fwds for structs/unions referenced through pointers
+        self.emit_type(out, t.proto_type_id, id)
+    }
+
+    fn emit_datasec_vars(&mut self, out: &mut Out, t: &'a BtfDatasec) -> BtfResult<()> {
+        let is_maps = t.name == ".maps";
+        // libbpf externs, resolved at load time
+        let extern_attr = match t.name {
+            ".ksyms" => Some("__ksym"),
+            ".kconfig" => Some("__kconfig"),
+            _ => None,
+        };
+        // variable declarations go after all the types they need
+        for v in &t.vars {
+            match self.btf.try_type_by_id(v.type_id)? {
+                BtfType::Var(var) => {
+                    self.order_and_emit_type(out, var.type_id)?;
+                    if is_maps {
+                        self.order_and_emit_map_def_types(out, var.type_id)?;
+                    }
+                }
+                BtfType::Func(f) if extern_attr.is_some() => {
+                    self.order_and_emit_func_proto_types(out, v.type_id, f)?
+                }
+                _ => {}
+            }
+        }
+        let macros = match (is_maps, extern_attr) {
+            (true, _) => Some(MAP_DEF_MACROS),
+            (_, Some(_)) => Some(EXTERN_MACROS),
+            _ => None,
+        };
+        if let Some(macros) = macros {
+            if self.macros_emitted.insert(macros) {
+                emitln!(out, "{}", macros);
+            }
+        }
+        emitln!(out, "/* {} */", t.name);
+        for v in &t.vars {
+            if let Some(attr) = extern_attr {
+                self.emit_libbpf_extern(out, v.type_id, attr)?;
+                continue;
+            }
+            let var = match self.btf.try_type_by_id(v.type_id)? {
+                BtfType::Var(var) => var,
+                _ => continue,
+            };
+            match var.kind {
+                BtfVarKind::Static => emit!(out, "static "),
+                BtfVarKind::GlobalAlloc => {}
+                BtfVarKind::GlobalExtern => emit!(out, "extern "),
+            }
+            let name = self.resolve_type_name(NamedKind::Ident, v.type_id, var.name)?;
+            let fields = if is_maps {
+                self.map_def_fields(var.type_id)?
+            } else {
+                None
+            };
+            match fields {
+                Some(fields) => {
+                    self.emit_map_def(out, &fields, 0)?;
+                    emitln!(out, " {} SEC(\"{}\");", name, t.name);
+                }
+                None => {
+                    self.emit_type_decl(out, var.type_id, &name, 0)?;
+                    emitln!(out, ";");
+                }
+            }
         }
+        emitln!(out, "");
         Ok(())
     }
 
-    fn order_type(&mut self, id: u32, has_ptr: bool, order: &mut Vec<u32>) -> BtfResult<bool> {
+    /// Emit declaration of .ksyms/.kconfig extern variable or kfunc, as written in BPF programs.
+    fn emit_libbpf_extern(&mut self, out: &mut Out, id: u32, attr: &str) -> BtfResult<()> {
+        let (name, type_id) = match self.btf.try_type_by_id(id)? {
+            BtfType::Var(var) => (var.name, var.type_id),
+            BtfType::Func(f) => (f.name, f.proto_type_id),
+            _ => return Ok(()),
+        };
+        emit!(out, "extern ");
+        // libbpf fills .kconfig values in read-only map, compiler shouldn't assume their values
+        if attr == "__kconfig" && self.btf.try_type_by_id(id)?.kind() == BtfKind::Var {
+            let btf = self.btf;
+            let mut kinds = Vec::new();
+            for id in btf.skip_mods_chain(type_id) {
+                kinds.push(btf.try_type_by_id(id)?.kind());
+            }
+            let has_mod = |kind| kinds.contains(&kind);
+            let is_ptr = kinds.last() == Some(&BtfKind::Ptr);
+            if !is_ptr && !has_mod(BtfKind::Const) {
+                emit!(out, "const ");
+            }
+            if !is_ptr && !has_mod(BtfKind::Volatile) {
+                emit!(out, "volatile ");
+            }
+        }
+        let uniq_name = self.resolve_type_name(NamedKind::Ident, id, name)?;
+        self.emit_type_decl(out, type_id, &uniq_name, 0)?;
+        emit!(out, " {}", attr);
+        if self.cfg.weak_externs.contains(name) {
+            emit!(out, " __weak");
+        }
+        emitln!(out, ";");
+        Ok(())
+    }
+
+    /// Recognize struct of BTF-defined map, declared with libbpf's __uint/__type/__array macros.
+    fn map_def_fields(&self, id: u32) -> BtfResult<Option<Vec<MapDefField<'a>>>> {
+        let btf = self.btf;
+        let t = match btf.try_type_by_id(btf.skip_mods(id))? {
+            BtfType::Struct(t) if !t.members.is_empty() => t,
+            _ => return Ok(None),
+        };
+        let mut fields = Vec::new();
+        for m in btf.members(t) {
+            if m.bit_size != 0 || m.name.is_empty() {
+                return Ok(None);
+            }
+            fields.push(match btf.try_type_by_id(m.type_id)? {
+                BtfType::Ptr(p) => match btf.try_type_by_id(p.type_id)? {
+                    BtfType::Array(a) => MapDefField::Uint(m.name, a.nelems),
+                    _ => MapDefField::Type(m.name, p.type_id),
+                },
+                BtfType::Array(a) if a.nelems == 0 => match btf.try_type_by_id(a.val_type_id)? {
+                    BtfType::Ptr(p) => MapDefField::Array(m.name, p.type_id),
+                    _ => return Ok(None),
+                },
+                _ => return Ok(None),
+            });
+        }
+        Ok(Some(fields))
+    }
+
+    /// Map key/value types are only referenced through pointers, but they are what map definition
+    /// is about, so emit their complete definitions.
+    fn order_and_emit_map_def_types(&mut self, out: &mut Out, id: u32) -> BtfResult<()> {
+        for f in self.map_def_fields(id)?.unwrap_or_default() {
+            match f {
+                MapDefField::Uint(_, _) => {}
+                MapDefField::Type(_, type_id) => self.order_and_emit_type(out, type_id)?,
+                MapDefField::Array(_, type_id) => {
+                    self.order_and_emit_type(out, type_id)?;
+                    self.order_and_emit_map_def_types(out, type_id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_map_def(
+        &mut self,
+        out: &mut Out,
+        fields: &[MapDefField<'a>],
+        lvl: usize,
+    ) -> BtfResult<()> {
+        emit!(out, "struct{}", self.open_brace(lvl));
+        for f in fields {
+            emit!(out, "\n{}", self.pfx(lvl + 1));
+            match *f {
+                MapDefField::Uint(name, val) => {
+                    emit!(out, "__uint({}, {});", self.field_name(name), val);
+                }
+                MapDefField::Type(name, type_id) => {
+                    emit!(out, "__type({}, ", self.field_name(name));
+                    self.emit_type_decl(out, type_id, "", lvl + 1)?;
+                    emit!(out, ");");
+                }
+                MapDefField::Array(name, type_id) => {
+                    emit!(out, "__array({}, ", self.field_name(name));
+                    // inner map definitions of map-in-maps are usually inlined anonymous structs
+                    let inner_id = self.btf.skip_mods(type_id);
+                    let inner = self.btf.try_type_by_id(inner_id)?;
+                    match self.map_def_fields(type_id)? {
+                        Some(inner_fields) if self.is_anon(inner_id, inner.name()) => {
+                            self.emit_map_def(out, &inner_fields, lvl + 1)?
+                        }
+                        _ => self.emit_type_decl(out, type_id, "", lvl + 1)?,
+                    }
+                    emit!(out, ");");
+                }
+            }
+        }
+        emit!(out, "\n{}}}", self.pfx(lvl));
+        Ok(())
+    }
+
+    fn order_type(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        has_ptr: bool,
+        order: &mut Vec<u32>,
+    ) -> BtfResult<bool> {
+        if id >= self.btf.type_cnt() {
+            return btf_error(format!("Dangling type reference, id: {}", id));
+        }
+        let id = self.canon_id(id);
         if self.cfg.verbose && self.get_order_state(id) != OrderState::Ordered {
-            println!(
+            emitln!(
+                out,
                 "ORDER TYPE id:{}, has_ptr:{}, type:{}, order_state:{:?}",
                 id,
                 has_ptr,
-                self.btf.type_by_id(id),
+                self.btf.type_display(self.btf.try_type_by_id(id)?),
                 self.get_order_state(id)
             );
         }
@@ -124,41 +972,54 @@ impl<'a> CDumper<'a> {
         // OrderState::Ordered only if it forms a strong link.
         match self.get_order_state(id) {
             OrderState::NotOrdered => {}
-            OrderState::Ordering => match self.btf.type_by_id(id) {
-                BtfType::Struct(t) | BtfType::Union(t) if has_ptr && !t.name.is_empty() => {
+            OrderState::Ordering => match self.btf.try_type_by_id(id)? {
+                BtfType::Struct(t) | BtfType::Union(t) if has_ptr && !self.is_anon(id, t.name) => {
                     return Ok(false);
                 }
                 _ => {
                     return btf_error(format!(
                         "Unsatisfiable type cycle, id: {}, type: {}",
                         id,
-                        self.btf.type_by_id(id)
+                        self.btf.try_type_by_id(id)?
                     ));
                 }
             },
-            // return true, letting typedefs know that it's ok to be emitted
-            OrderState::Ordered => return Ok(true),
+            OrderState::Ordered => {
+                // typedef emitted with just a fwd of its struct, but now it's needed complete
+                if let BtfType::Typedef(t) = self.btf.try_type_by_id(id)? {
+                    if self.is_fwd_only_typedef(t)? && self.complete_lvl > 0 && !has_ptr {
+                        self.order_type(out, t.type_id, false, order)?;
+                    }
+                }
+                // return true, letting typedefs know that it's ok to be emitted
+                return Ok(true);
+            }
         }
-        match self.btf.type_by_id(id) {
+        match self.btf.try_type_by_id(id)? {
             BtfType::Func(_) | BtfType::Var(_) | BtfType::Datasec(_) | BtfType::DeclTag(_) => {}
             BtfType::Void | BtfType::Int(_) | BtfType::Float(_) => {
                 self.set_order_state(id, OrderState::Ordered);
                 return Ok(false);
             }
-            BtfType::Volatile(t) => return self.order_type(t.type_id, has_ptr, order),
-            BtfType::Const(t) => return self.order_type(t.type_id, has_ptr, order),
-            BtfType::Restrict(t) => return self.order_type(t.type_id, has_ptr, order),
-            BtfType::TypeTag(t) => return self.order_type(t.type_id, has_ptr, order),
+            BtfType::Volatile(t) => return self.order_type(out, t.type_id, has_ptr, order),
+            BtfType::Const(t) => return self.order_type(out, t.type_id, has_ptr, order),
+            BtfType::Restrict(t) => return self.order_type(out, t.type_id, has_ptr, order),
+            BtfType::TypeTag(t) => return self.order_type(out, t.type_id, has_ptr, order),
             BtfType::Ptr(t) => {
-                let res = self.order_type(t.type_id, true, order);
+                let res = self.order_type(out, t.type_id, true, order);
                 self.set_order_state(id, OrderState::Ordered);
                 return res;
             }
-            BtfType::Array(t) => return self.order_type(t.val_type_id, has_ptr, order),
+            BtfType::Array(t) => {
+                self.complete_lvl += 1;
+                let res = self.order_type(out, t.val_type_id, has_ptr, order);
+                self.complete_lvl -= 1;
+                return res;
+            }
             BtfType::FuncProto(t) => {
-                let mut is_strong = self.order_type(t.res_type_id, has_ptr, order)?;
-                for p in &t.params {
-                    if self.order_type(p.type_id, has_ptr, order)? {
+                let mut is_strong = self.order_type(out, t.res_type_id, has_ptr, order)?;
+                for p in self.btf.params(t) {
+                    if self.order_type(out, p.type_id, has_ptr, order)? {
                         is_strong = true;
                     }
                 }
@@ -167,14 +1028,17 @@ impl<'a> CDumper<'a> {
             BtfType::Struct(t) | BtfType::Union(t) => {
                 // struct/union is part of strong link, only if it's embedded (so no ptr in a path)
                 // or it's anonymous (so has to be defined inline, even if declared through ptr)
-                if !has_ptr || t.name.is_empty() {
+                if !has_ptr || self.is_anon(id, t.name) {
                     self.set_order_state(id, OrderState::Ordering);
 
-                    for m in &t.members {
-                        self.order_type(m.type_id, false, order)?;
-                    }
+                    self.complete_lvl += 1;
+                    let res = self.btf.members(t).iter().try_for_each(|m| {
+                        self.order_type(out, m.type_id, false, order).map(|_| ())
+                    });
+                    self.complete_lvl -= 1;
+                    res?;
                     // no need to explicitly order anonymous embedded struct
-                    if !t.name.is_empty() {
+                    if !self.is_anon(id, t.name) {
                         order.push(id);
                     }
 
@@ -184,7 +1048,7 @@ impl<'a> CDumper<'a> {
                 }
             }
             BtfType::Enum(t) => {
-                if !t.name.is_empty() {
+                if !self.is_anon(id, t.name) {
                     order.push(id);
                 }
                 self.set_order_state(id, OrderState::Ordered);
@@ -192,7 +1056,7 @@ impl<'a> CDumper<'a> {
                 return Ok(true);
             }
             BtfType::Enum64(t) => {
-                if !t.name.is_empty() {
+                if !self.is_anon(id, t.name) {
                     order.push(id);
                 }
                 self.set_order_state(id, OrderState::Ordered);
@@ -208,7 +1072,9 @@ impl<'a> CDumper<'a> {
                 return Ok(true);
             }
             BtfType::Typedef(t) => {
-                let is_strong = self.order_type(t.type_id, has_ptr, order)?;
+                // typedef of named struct/union only needs its forward declaration
+                let weak = self.is_fwd_only_typedef(t)? && self.complete_lvl == 0;
+                let is_strong = self.order_type(out, t.type_id, has_ptr || weak, order)?;
                 if !has_ptr || is_strong {
                     order.push(id);
                     self.set_order_state(id, OrderState::Ordered);
@@ -220,16 +1086,18 @@ impl<'a> CDumper<'a> {
         Ok(false)
     }
 
-    fn emit_type(&mut self, id: u32, cont_id: u32) -> BtfResult<()> {
+    fn emit_type(&mut self, out: &mut Out, id: u32, cont_id: u32) -> BtfResult<()> {
+        let id = self.canon_id(id);
         let top_level_def = cont_id == 0;
         if self.cfg.verbose {
-            println!(
+            emitln!(
+                out,
                 "EMIT_TYPE id: {}, cont_id: {}, is_def: {}, state: {:?}, type: {}",
                 id,
                 cont_id,
                 top_level_def,
                 self.get_emit_state(id),
-                self.btf.type_by_id(id)
+                self.btf.type_display(self.btf.try_type_by_id(id)?)
             );
         }
         match self.get_emit_state(id) {
@@ -238,22 +1106,23 @@ impl<'a> CDumper<'a> {
                 if self.get_fwd_emitted(id) {
                     return Ok(());
                 }
-                match self.btf.type_by_id(id) {
+                match self.btf.try_type_by_id(id)? {
                     BtfType::Struct(t) | BtfType::Union(t) => {
                         // fwd was already emitted or no need for fwd declare if we are referencing
                         // a struct/union we are part of
                         if id == cont_id {
                             return Ok(());
                         }
-                        if t.name.is_empty() {
+                        if self.is_anon(id, t.name) {
                             return btf_error(format!(
                                 "anonymous struct loop, id: {}, type: {}",
                                 id,
-                                self.btf.type_by_id(id)
+                                self.btf.try_type_by_id(id)?
                             ));
                         }
-                        if self.emit_composite_fwd(id, t) {
-                            println!(";\n");
+                        if !self.is_fwd_omitted(id, t.name) {
+                            self.emit_composite_fwd(out, id, t)?;
+                            emitln!(out, ";\n");
                         }
                         self.set_fwd_emitted(id, true);
                         return Ok(());
@@ -261,8 +1130,9 @@ impl<'a> CDumper<'a> {
                     BtfType::Typedef(t) => {
                         // for typedef fwd_emitted means typedef definition was emitted, but it can
                         // be used only for "weak" references through pointer only
-                        if self.emit_typedef_def(id, t, 0) {
-                            println!(";\n");
+                        if !self.is_def_omitted(id, t.name) {
+                            self.emit_typedef_def(out, id, t, 0)?;
+                            emitln!(out, ";\n");
                         }
                         self.set_fwd_emitted(id, true);
                         return Ok(());
@@ -273,77 +1143,157 @@ impl<'a> CDumper<'a> {
             EmitState::Emitted => return Ok(()),
         }
 
-        if top_level_def && self.btf.type_by_id(id).name().is_empty() {
+        if top_level_def && self.is_anon(id, self.btf.try_type_by_id(id)?.name()) {
             return btf_error(format!(
                 "unexpected nameless definition, id: {}, type: {}",
                 id,
-                self.btf.type_by_id(id)
+                self.btf.try_type_by_id(id)?
             ));
         }
 
-        match self.btf.type_by_id(id) {
+        match self.btf.try_type_by_id(id)? {
             BtfType::Func(_) | BtfType::Var(_) | BtfType::Datasec(_) | BtfType::DeclTag(_) => {}
             BtfType::Void | BtfType::Int(_) | BtfType::Float(_) => {}
-            BtfType::Volatile(t) => self.emit_type(t.type_id, cont_id)?,
-            BtfType::Const(t) => self.emit_type(t.type_id, cont_id)?,
-            BtfType::Restrict(t) => self.emit_type(t.type_id, cont_id)?,
-            BtfType::TypeTag(t) => self.emit_type(t.type_id, cont_id)?,
-            BtfType::Ptr(t) => self.emit_type(t.type_id, cont_id)?,
-            BtfType::Array(t) => self.emit_type(t.val_type_id, cont_id)?,
+            BtfType::Volatile(t) => self.emit_type(out, t.type_id, cont_id)?,
+            BtfType::Const(t) => self.emit_type(out, t.type_id, cont_id)?,
+            BtfType::Restrict(t) => self.emit_type(out, t.type_id, cont_id)?,
+            BtfType::TypeTag(t) => self.emit_type(out, t.type_id, cont_id)?,
+            BtfType::Ptr(t) => self.emit_type(out, t.type_id, cont_id)?,
+            BtfType::Array(t) => self.emit_type(out, t.val_type_id, cont_id)?,
             BtfType::FuncProto(t) => {
-                self.emit_type(t.res_type_id, cont_id)?;
-                for p in &t.params {
-                    self.emit_type(p.type_id, cont_id)?;
+                self.emit_type(out, t.res_type_id, cont_id)?;
+                for p in self.btf.params(t) {
+                    self.emit_type(out, p.type_id, cont_id)?;
+                }
+            }
+            BtfType::Struct(t) | BtfType::Union(t)
+                if top_level_def && self.is_dep_omitted(id, t.name) =>
+            {
+                // dependency that is not defined, so its members don't need anything declared
+                if !self.get_fwd_emitted(id) && !self.is_fwd_omitted(id, t.name) {
+                    self.emit_composite_fwd(out, id, t)?;
+                    emitln!(out, ";\n");
                 }
+                self.set_fwd_emitted(id, true);
+                self.set_emit_state(id, EmitState::Emitted);
             }
             BtfType::Struct(t) | BtfType::Union(t) => {
                 self.set_emit_state(id, EmitState::Emitting);
-                if top_level_def || t.name.is_empty() {
+                if top_level_def || self.is_anon(id, t.name) {
                     // top-level struct definition or embedded anonymous struct, ensure all field
                     // types have their fwds declared
-                    for m in &t.members {
-                        self.emit_type(m.type_id, if t.name.is_empty() { cont_id } else { id })?;
+                    for m in self.btf.members(t) {
+                        let cont_id = if self.is_anon(id, t.name) {
+                            cont_id
+                        } else {
+                            id
+                        };
+                        self.emit_type(out, m.type_id, cont_id)?;
                     }
                 } else if !self.get_fwd_emitted(id) && id != cont_id {
-                    if self.emit_composite_fwd(id, t) {
-                        println!(";\n");
+                    if !self.is_fwd_omitted(id, t.name) {
+                        self.emit_composite_fwd(out, id, t)?;
+                        emitln!(out, ";\n");
                     }
                     self.set_fwd_emitted(id, true);
                 }
                 if top_level_def {
-                    self.emit_composite_def(id, t, 0);
-                    println!(";\n");
+                    if !self.is_excluded(t.name) {
+                        let pragma = self.cfg.pack_style == PackStyle::Pragma
+                            && self.is_struct_packed(id, t)
+                            && self.is_pragma_packable(t)?;
+                        if pragma {
+                            emitln!(out, "#pragma pack(push, 1)");
+                            self.in_pragma_pack = true;
+                        }
+                        let res = self.emit_composite_def(out, id, t, 0);
+                        self.in_pragma_pack = false;
+                        res?;
+                        emitln!(out, ";");
+                        if pragma {
+                            emitln!(out, "#pragma pack(pop)");
+                        }
+                        emitln!(out, "");
+                        if self.cfg.emit_asserts {
+                            self.emit_layout_asserts(out, id, t)?;
+                        }
+                    }
                     self.set_emit_state(id, EmitState::Emitted);
                 } else {
                     self.set_emit_state(id, EmitState::NotEmitted);
                 }
             }
             BtfType::Enum(t) => {
-                if top_level_def {
-                    self.emit_enum_def(id, t, 0);
-                    println!(";\n");
+                if self.cfg.enums_as_macros {
+                    // values of anonymous enums have to be defined before they are inlined
+                    if (top_level_def || self.is_anon(id, t.name))
+                        && !self.is_def_omitted(id, t.name)
+                    {
+                        let vals = self
+                            .btf
+                            .enum_values(t)
+                            .iter()
+                            .map(|v| (v.name, enum_val_str(v.value)));
+                        self.emit_enum_macros(out, t.name, vals.collect());
+                    }
+                } else if top_level_def && !self.is_def_omitted(id, t.name) {
+                    self.emit_enum_def(out, id, t, 0)?;
+                    emitln!(out, ";\n");
                 }
                 self.set_emit_state(id, EmitState::Emitted);
             }
             BtfType::Enum64(t) => {
-                if top_level_def {
-                    self.emit_enum64_def(id, t, 0);
-                    println!(";\n");
+                if self.cfg.enums_as_macros {
+                    if (top_level_def || self.is_anon(id, t.name))
+                        && !self.is_def_omitted(id, t.name)
+                    {
+                        let vals = self.btf.enum64_values(t).iter().map(|v| {
+                            let val = enum64_val_str(t, v.value);
+                            (
+                                v.name,
+                                if val.starts_with('-') {
+                                    format!("({})", val)
+                                } else {
+                                    val
+                                },
+                            )
+                        });
+                        self.emit_enum_macros(out, t.name, vals.collect());
+                    }
+                } else if top_level_def && !self.is_def_omitted(id, t.name) {
+                    self.emit_enum64_def(out, id, t, 0)?;
+                    emitln!(out, ";\n");
                 }
                 self.set_emit_state(id, EmitState::Emitted);
             }
             BtfType::Fwd(t) => {
-                self.emit_fwd_def(id, t);
-                println!(";\n");
+                if !self.is_fwd_omitted(id, t.name) {
+                    self.emit_fwd_def(out, id, t)?;
+                    emitln!(out, ";\n");
+                }
+                self.set_emit_state(id, EmitState::Emitted);
+            }
+            BtfType::Typedef(t) if self.cfg.std_int_types && self.is_std_int_type(t)? => {
+                if !self.std_int_types_emitted {
+                    self.emit_std_int_types(out);
+                    self.std_int_types_emitted = true;
+                }
+                self.state[id as usize].name = t.name.to_owned();
+                self.set_fwd_emitted(id, true);
+                self.set_emit_state(id, EmitState::Emitted);
+            }
+            BtfType::Typedef(t) if self.is_dep_omitted(id, t.name) => {
+                self.set_fwd_emitted(id, true);
                 self.set_emit_state(id, EmitState::Emitted);
             }
             BtfType::Typedef(t) => {
                 self.set_emit_state(id, EmitState::Emitting);
-                self.emit_type(t.type_id, id)?;
+                self.emit_type(out, t.type_id, id)?;
                 if !self.get_fwd_emitted(id) {
                     // emit typedef right now, if someone depends on it "weakly" (though pointer)
-                    if self.emit_typedef_def(id, t, 0) {
-                        println!(";\n");
+                    if !self.is_excluded(t.name) {
+                        self.emit_typedef_def(out, id, t, 0)?;
+                        emitln!(out, ";\n");
                     }
                     self.set_fwd_emitted(id, true);
                 }
@@ -353,6 +1303,67 @@ impl<'a> CDumper<'a> {
         Ok(())
     }
 
+    /// Whether definition of named type should be omitted from output. References to it are
+    /// still emitted, so it's expected to be provided elsewhere (e.g., by included headers).
+    fn is_excluded(&self, name: &str) -> bool {
+        if name.is_empty() {
+            return false;
+        }
+        NAMES_BLACKLIST.is_match(name)
+            || self.cfg.exclude_names.is_match(name)
+            || !self
+                .cfg
+                .include_names
+                .as_ref()
+                .is_none_or(|s| s.is_match(name))
+    }
+
+    /// Whether named type is only a dependency of dumped types, that is not defined according to
+    /// CDumperCfg::deps.
+    fn is_dep_omitted(&self, id: u32, name: &str) -> bool {
+        self.cfg.deps != DepsMode::All
+            && !self.is_anon(id, name)
+            && !self.roots.contains(&self.canon_id(id))
+    }
+
+    /// Whether definition of named type is omitted, either excluded or as a dependency.
+    fn is_def_omitted(&self, id: u32, name: &str) -> bool {
+        self.is_excluded(name) || self.is_dep_omitted(id, name)
+    }
+
+    /// Whether even forward declaration of named struct/union/fwd is omitted.
+    fn is_fwd_omitted(&self, id: u32, name: &str) -> bool {
+        self.is_excluded(name) || (self.cfg.deps == DepsMode::None && self.is_dep_omitted(id, name))
+    }
+
+    /// Whether in fwd_decls_only mode typedef can be emitted with only a fwd of its struct/union.
+    fn is_fwd_only_typedef(&self, t: &BtfTypedef) -> BtfResult<bool> {
+        if !self.cfg.fwd_decls_only {
+            return Ok(false);
+        }
+        let id = self.canon_id(self.btf.skip_mods(t.type_id));
+        Ok(match self.btf.try_type_by_id(id)? {
+            BtfType::Struct(t) | BtfType::Union(t) => !self.is_anon(id, t.name),
+            BtfType::Fwd(_) => true,
+            _ => false,
+        })
+    }
+
+    /// Skip pointers, arrays and modifiers down to the type declaration is based on.
+    fn skip_decl_chain(&self, mut id: u32) -> BtfResult<u32> {
+        loop {
+            id = match self.btf.try_type_by_id(id)? {
+                BtfType::Ptr(t) => t.type_id,
+                BtfType::Const(t) => t.type_id,
+                BtfType::Volatile(t) => t.type_id,
+                BtfType::Restrict(t) => t.type_id,
+                BtfType::TypeTag(t) => t.type_id,
+                BtfType::Array(t) => t.val_type_id,
+                _ => return Ok(id),
+            }
+        }
+    }
+
     fn get_fwd_emitted(&self, id: u32) -> bool {
         self.state[id as usize].fwd_emitted
     }
@@ -377,10 +1388,7 @@ impl<'a> CDumper<'a> {
         self.state[id as usize].emit_state = state;
     }
 
-    fn emit_composite_fwd(&mut self, id: u32, t: &'a BtfComposite) -> bool {
-        if NAMES_BLACKLIST.is_match(&t.name) {
-            return false;
-        }
+    fn emit_composite_fwd(&mut self, out: &mut Out, id: u32, t: &'a BtfComposite) -> BtfResult<()> {
         let keyword = if !t.is_struct && self.cfg.union_as_struct {
             "struct /*union*/"
         } else if t.is_struct {
@@ -388,18 +1396,30 @@ impl<'a> CDumper<'a> {
         } else {
             "union"
         };
-        print!(
-            "{} {}",
-            keyword,
-            self.resolve_type_name(NamedKind::Type, id, t.name)
-        );
-        return true;
+        let name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
+        emit!(out, "{} {}", keyword, name);
+        Ok(())
     }
 
-    fn emit_composite_def(&mut self, id: u32, t: &'a BtfComposite, lvl: usize) {
-        if NAMES_BLACKLIST.is_match(&t.name) {
-            return;
+    fn pfx(&self, lvl: usize) -> String {
+        self.indent.repeat(lvl.min(MAX_INDENT_LVL))
+    }
+
+    fn open_brace(&self, lvl: usize) -> String {
+        if self.cfg.brace_on_new_line {
+            format!("\n{}{{", self.pfx(lvl))
+        } else {
+            " {".to_string()
         }
+    }
+
+    fn emit_composite_def(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        t: &'a BtfComposite,
+        lvl: usize,
+    ) -> BtfResult<()> {
         let keyword = if !t.is_struct && self.cfg.union_as_struct {
             "struct /*union*/"
         } else if t.is_struct {
@@ -408,33 +1428,182 @@ impl<'a> CDumper<'a> {
             "union"
         };
         let packed = self.is_struct_packed(id, t);
-        let name = self.resolve_type_name(NamedKind::Type, id, t.name);
-        print!("{}{}{} {{", keyword, sep(&name), name);
+        let name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
+        emit!(
+            out,
+            "{}{}{}{}",
+            keyword,
+            sep(&name),
+            name,
+            self.open_brace(lvl)
+        );
+        // this might be an anonymous struct inlined into declaration of outer flexible array
+        let outer_fam_array = self.fam_array.take();
+        let pads = match self.layouts.get(&id) {
+            Some(Some(layout)) => Some(layout.pads.clone()),
+            _ => None,
+        };
+        let tail_pad = pads.as_ref().and_then(|pads| pads.last().cloned());
+        let tail_pad = tail_pad.filter(|&(start, end)| start < end);
+        let mut members = self.btf.members(t).iter().collect::<Vec<_>>();
+        if self.cfg.sort_members {
+            let fam = members.len() > 1 && self.is_fam(t, members[members.len() - 1])?;
+            let n = members.len() - fam as usize;
+            members[..n].sort_by_key(|m| m.name);
+        }
         let mut offset = 0;
-        for m in &t.members {
-            self.emit_bit_padding(offset, m, packed, lvl + 1);
+        for (i, &m) in members.iter().enumerate() {
+            match &pads {
+                Some(pads) => self.emit_exact_padding(out, pads[i], lvl + 1),
+                // implicit padding is meaningless once members are reordered
+                None if self.cfg.sort_members => {}
+                None => self.emit_bit_padding(out, offset, m, packed, lvl + 1),
+            }
 
-            print!("\n{}", pfx(lvl + 1));
-            self.emit_type_decl(m.type_id, &m.name, lvl + 1);
+            emit!(out, "\n{}", self.pfx(lvl + 1));
+            // flexible array member can't be followed by padding
+            if i > 0 && i == t.members.len() - 1 && tail_pad.is_none() && self.is_fam(t, m)? {
+                self.fam_array = Some(m.type_id);
+            }
+            let fname = self.field_name(m.name);
+            self.emit_type_decl(out, m.type_id, &fname, lvl + 1)?;
+            self.fam_array = None;
 
             if m.bit_size == 0 {
-                offset = m.bit_offset + self.btf.get_size_of(m.type_id) * 8;
+                let bits = self.btf.get_size_of(m.type_id).saturating_mul(8);
+                offset = m.bit_offset.saturating_add(bits);
             } else {
-                print!(": {}", m.bit_size);
-                offset = m.bit_offset + m.bit_size as u32;
+                emit!(out, ": {}", m.bit_size);
+                offset = m.bit_offset.saturating_add(m.bit_size as u32);
             }
-            print!(";");
+            emit!(out, ";");
         }
-        if !t.members.is_empty() {
-            print!("\n");
+        self.fam_array = outer_fam_array;
+        if let Some(pad) = tail_pad {
+            if t.is_struct {
+                self.emit_exact_padding(out, pad, lvl + 1);
+            } else {
+                // unnamed bitfields can't make union bigger than 8 bytes
+                emit!(
+                    out,
+                    "\n{}struct{}",
+                    self.pfx(lvl + 1),
+                    self.open_brace(lvl + 1)
+                );
+                self.emit_exact_padding(out, pad, lvl + 2);
+                emit!(out, "\n{}}};", self.pfx(lvl + 1));
+            }
+        }
+        if !t.members.is_empty() || tail_pad.is_some() {
+            emit!(out, "\n");
+        }
+        emit!(out, "{}}}", self.pfx(lvl));
+        if packed && !self.in_pragma_pack {
+            emit!(out, " __attribute__((packed))");
+        }
+        Ok(())
+    }
+
+    /// Whether `#pragma pack(1)` doesn't change layout of anonymous structs/unions defined inline
+    /// within given packed struct, i.e., they are all packed themselves.
+    fn is_pragma_packable(&self, t: &BtfComposite) -> BtfResult<bool> {
+        for m in self.btf.members(t) {
+            let id = self.skip_decl_chain(m.type_id)?;
+            if let BtfType::Struct(t) | BtfType::Union(t) = self.btf.try_type_by_id(id)? {
+                if self.is_anon(id, t.name)
+                    && !(self.is_struct_packed(id, t) && self.is_pragma_packable(t)?)
+                {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn emit_layout_asserts(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        t: &'a BtfComposite,
+    ) -> BtfResult<()> {
+        // unions emitted as structs don't have original layout
+        if !t.is_struct && self.cfg.union_as_struct {
+            return Ok(());
         }
-        print!("{}}}", pfx(lvl));
-        if packed {
-            print!(" __attribute__((packed))");
+        let keyword = if t.is_struct { "struct" } else { "union" };
+        let name = format!(
+            "{} {}",
+            keyword,
+            self.resolve_type_name(NamedKind::Type, id, t.name)?
+        );
+        let static_assert = self.static_assert_keyword();
+        emitln!(
+            out,
+            "{}(sizeof({}) == {}, \"{}: size\");",
+            static_assert,
+            name,
+            t.sz,
+            name
+        );
+        self.emit_offset_asserts(out, &name, t, 0)?;
+        emitln!(out, "");
+        Ok(())
+    }
+
+    fn emit_offset_asserts(
+        &mut self,
+        out: &mut Out,
+        name: &str,
+        t: &'a BtfComposite,
+        base_bit_off: u32,
+    ) -> BtfResult<()> {
+        for m in self.btf.members(t) {
+            let bit_off = base_bit_off + m.bit_offset;
+            if m.name.is_empty() {
+                // fields of anonymous struct/union are accessible directly
+                let id = self.btf.skip_mods_and_typedefs(m.type_id);
+                match self.btf.try_type_by_id(id)? {
+                    BtfType::Struct(t) | BtfType::Union(t) if t.name.is_empty() => {
+                        self.emit_offset_asserts(out, name, t, bit_off)?
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            // bitfields have no offsetof()
+            if m.bit_size != 0 || !bit_off.is_multiple_of(8) {
+                continue;
+            }
+            let static_assert = self.static_assert_keyword();
+            let fname = self.field_name(m.name);
+            emitln!(
+                out,
+                "{}(__builtin_offsetof({}, {}) == {}, \"{}: {} offset\");",
+                static_assert,
+                name,
+                fname,
+                bit_off / 8,
+                name,
+                fname
+            );
         }
+        Ok(())
+    }
+
+    /// Whether member, assuming it's the last one, is to be emitted as C99 flexible array member.
+    fn is_fam(&self, t: &BtfComposite, m: &BtfMember) -> BtfResult<bool> {
+        // C++ compilers don't allow structs with flexible array members to be embedded
+        if !t.is_struct || self.cfg.zero_len_arrays || self.cfg.lang == Lang::Cpp || m.bit_size != 0
+        {
+            return Ok(false);
+        }
+        Ok(matches!(self.btf.try_type_by_id(m.type_id)?, BtfType::Array(a) if a.nelems == 0))
     }
 
     fn is_struct_packed(&self, id: u32, t: &BtfComposite) -> bool {
+        if let Some(Some(layout)) = self.layouts.get(&id) {
+            return layout.packed;
+        }
         if !t.is_struct {
             return false;
         }
@@ -443,8 +1612,9 @@ impl<'a> CDumper<'a> {
             return true;
         }
         // all the non-bitfield fields have to be naturally aligned
-        for m in &t.members {
-            if m.bit_size == 0 && m.bit_offset % (self.btf.get_align_of(m.type_id) * 8) != 0 {
+        for m in self.btf.members(t) {
+            let align = self.btf.get_align_of(m.type_id).max(1);
+            if m.bit_size == 0 && m.bit_offset % (align * 8) != 0 {
                 return true;
             }
         }
@@ -453,7 +1623,14 @@ impl<'a> CDumper<'a> {
         return false;
     }
 
-    fn emit_bit_padding(&self, offset: u32, m: &BtfMember, packed: bool, lvl: usize) {
+    fn emit_bit_padding(
+        &mut self,
+        out: &mut Out,
+        offset: u32,
+        m: &BtfMember,
+        packed: bool,
+        lvl: usize,
+    ) {
         if offset >= m.bit_offset {
             return;
         }
@@ -479,10 +1656,157 @@ impl<'a> CDumper<'a> {
                 ("char", CDumper::chip_away_bits(bit_diff, 8))
             };
             bit_diff -= pad_bits;
-            print!("\n{}{}: {};", pfx(lvl), pad_type, pad_bits);
+            emit!(out, "\n{}{}: {};", self.pfx(lvl), pad_type, pad_bits);
+        }
+    }
+
+    /// Emit [start, end) bits of padding as unnamed bitfields, none of which crosses a storage
+    /// unit boundary of its type, so compiler places them exactly where expected.
+    fn emit_exact_padding(&mut self, out: &mut Out, (mut start, end): (u32, u32), lvl: usize) {
+        let ptr_sz_bits = self.btf.ptr_sz() * 8;
+        while start < end {
+            let (pad_type, pad_bits) = if start % 8 != 0 || end - start < 8 {
+                ("char", (end - start).min(8 - start % 8))
+            } else if ptr_sz_bits > 32 && start % 64 == 0 && end - start >= 64 {
+                ("long", 64)
+            } else if start % 32 == 0 && end - start >= 32 {
+                ("int", 32)
+            } else if start % 16 == 0 && end - start >= 16 {
+                ("short", 16)
+            } else {
+                ("char", 8)
+            };
+            emit!(out, "\n{}{}: {};", self.pfx(lvl), pad_type, pad_bits);
+            start += pad_bits;
+        }
+    }
+
+    /// Alignment type gets in emitted C, taking into account exact layouts of structs/unions.
+    fn emitted_align(&mut self, id: u32) -> BtfResult<u32> {
+        let btf = self.btf;
+        Ok(match btf.try_type_by_id(id)? {
+            BtfType::Struct(t) | BtfType::Union(t) => {
+                if !self.layouts.contains_key(&id) {
+                    let layout = self.plan_layout(t)?;
+                    self.layouts.insert(id, layout);
+                }
+                match &self.layouts[&id] {
+                    Some(layout) => layout.align,
+                    None if self.is_struct_packed(id, t) => 1,
+                    None => btf.get_align_of(id).max(1),
+                }
+            }
+            BtfType::Volatile(t) => self.emitted_align(t.type_id)?,
+            BtfType::Const(t) => self.emitted_align(t.type_id)?,
+            BtfType::Restrict(t) => self.emitted_align(t.type_id)?,
+            BtfType::Typedef(t) => self.emitted_align(t.type_id)?,
+            BtfType::TypeTag(t) => self.emitted_align(t.type_id)?,
+            BtfType::Array(t) => self.emitted_align(t.val_type_id)?,
+            _ => btf.get_align_of(id).max(1),
+        })
+    }
+
+    /// Find the padding (and whether packing is necessary), which make compiler lay out
+    /// struct/union exactly as described by BTF. Returns None, if that's impossible.
+    fn plan_layout(&mut self, t: &'a BtfComposite) -> BtfResult<Option<CompositeLayout>> {
+        if !t.is_struct && self.cfg.union_as_struct {
+            return Ok(None);
+        }
+        match self.simulate_layout(t, false)? {
+            Some(layout) => Ok(Some(layout)),
+            None => self.simulate_layout(t, true),
         }
     }
 
+    /// Lay out members the way C compiler does (following SysV ABI rules for bitfields), filling
+    /// gaps to reach BTF offsets and size with padding.
+    fn simulate_layout(
+        &mut self,
+        t: &'a BtfComposite,
+        packed: bool,
+    ) -> BtfResult<Option<CompositeLayout>> {
+        let mut pads = Vec::with_capacity(t.members.len() + 1);
+        let mut align = 1;
+        let mut offset = 0;
+        let mut end = 0;
+        for m in self.btf.members(t) {
+            let start = if t.is_struct { offset } else { 0 };
+            if m.bit_offset < start || (!t.is_struct && m.bit_offset != 0) {
+                return Ok(None);
+            }
+            // sizes and offsets overflowing u32 in bits come only from malformed BTF
+            let m_sz = match self.btf.get_size_of(m.type_id).checked_mul(8) {
+                Some(m_sz) => m_sz,
+                None => return Ok(None),
+            };
+            let m_align = if packed {
+                1
+            } else {
+                self.emitted_align(m.type_id)?
+            } * 8;
+            // where compiler would place member without any padding
+            let natural_off;
+            if m.bit_size == 0 {
+                // after padding, member's natural position has to be exactly its BTF offset
+                if !m.bit_offset.is_multiple_of(m_align) {
+                    return Ok(None);
+                }
+                natural_off = start.div_ceil(m_align) * m_align;
+                offset = match m.bit_offset.checked_add(m_sz) {
+                    Some(offset) => offset,
+                    None => return Ok(None),
+                };
+            } else {
+                // bitfield can't cross storage unit of its type, unless struct is packed
+                let unit = m_sz.max(8);
+                let crosses_unit = |off: u32| off / unit != (off + m.bit_size as u32 - 1) / unit;
+                if packed || !crosses_unit(start) {
+                    natural_off = start;
+                } else {
+                    natural_off = start.div_ceil(unit) * unit;
+                }
+                if !packed && crosses_unit(m.bit_offset) {
+                    return Ok(None);
+                }
+                offset = m.bit_offset + m.bit_size as u32;
+            }
+            // unnamed bitfields don't affect alignment
+            if m.bit_size == 0 || !m.name.is_empty() {
+                align = align.max(m_align / 8);
+            }
+            if natural_off == m.bit_offset {
+                pads.push((start, start));
+            } else {
+                pads.push((start, m.bit_offset));
+            }
+            end = end.max(offset);
+        }
+        let sz = match t.sz.checked_mul(8) {
+            Some(sz) => sz,
+            None => return Ok(None),
+        };
+        if end > sz || !sz.is_multiple_of(align * 8) {
+            return Ok(None);
+        }
+        // compiler rounds size up to alignment, so pad the rest explicitly only if needed
+        let natural_sz = match end.div_ceil(align * 8).checked_mul(align * 8) {
+            Some(natural_sz) => natural_sz,
+            None => return Ok(None),
+        };
+        if natural_sz == sz {
+            pads.push((end, end));
+        } else if t.is_struct {
+            pads.push((end, sz));
+        } else {
+            pads.push((0, sz));
+        }
+        Ok(Some(CompositeLayout {
+            packed,
+            align,
+            pads,
+        }))
+    }
+
     fn chip_away_bits(total: u32, at_most: u32) -> u32 {
         if total % at_most == 0 {
             at_most
@@ -491,70 +1815,197 @@ impl<'a> CDumper<'a> {
         }
     }
 
-    fn emit_enum_def(&mut self, id: u32, t: &'a BtfEnum, lvl: usize) {
-        if NAMES_BLACKLIST.is_match(&t.name) {
+    fn emit_enum_macros(&mut self, out: &mut Out, name: &str, vals: Vec<(&str, String)>) {
+        if vals.is_empty() {
             return;
         }
-        let name = self.resolve_type_name(NamedKind::Type, id, t.name);
+        if !name.is_empty() {
+            emitln!(out, "/* enum {} */", name);
+        }
+        for (name, val) in vals {
+            let mut uniq_name = self.resolve_name(NamedKind::Ident, name);
+            while self.macro_reserved.contains(&uniq_name) {
+                uniq_name = self.resolve_name(NamedKind::Ident, name);
+            }
+            emitln!(out, "#define {} {}", uniq_name, val);
+        }
+        emitln!(out, "");
+    }
+
+    fn emit_enum_def(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        t: &'a BtfEnum,
+        lvl: usize,
+    ) -> BtfResult<()> {
+        let name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
         if t.values.is_empty() {
             // enum fwd
-            print!("enum{}{}", sep(&name), name);
+            emit!(out, "enum{}{}", sep(&name), name);
         } else {
-            print!("enum{}{} {{", sep(&name), name);
-            for v in &t.values {
-                let val_uniq_name = self.resolve_name(NamedKind::Ident, &v.name);
-                print!("\n{}{} = {},", pfx(lvl + 1), &val_uniq_name, v.value);
+            let scoped = self.is_enum_scoped(&name, self.btf.enum_values(t).iter().map(|v| v.name));
+            if scoped {
+                let int_type = enum_int_type(self.btf, t);
+                emit!(
+                    out,
+                    "enum class {} : {}{}",
+                    name,
+                    int_type,
+                    self.open_brace(lvl)
+                );
+            } else {
+                emit!(out, "enum{}{}{}", sep(&name), name, self.open_brace(lvl));
+            }
+            for v in self.btf.enum_values(t) {
+                let val_uniq_name = self.resolve_enum_val_name(id, v.name, scoped)?;
+                emit!(
+                    out,
+                    "\n{}{} = {},",
+                    self.pfx(lvl + 1),
+                    &val_uniq_name,
+                    v.value
+                );
+            }
+            emit!(out, "\n{}}}", self.pfx(lvl));
+            // enums are int-sized, unless packed to the smallest type fitting all values
+            if !scoped && self.cfg.exact_layout && t.sz < 4 && packed_enum_sz(self.btf, t) == t.sz {
+                emit!(out, " __attribute__((packed))");
             }
-            print!("\n{}}}", pfx(lvl));
         }
+        Ok(())
     }
 
-    fn emit_enum64_def(&mut self, id: u32, t: &'a BtfEnum64, lvl: usize) {
-        if NAMES_BLACKLIST.is_match(&t.name) {
-            return;
+    /// In C++ mode, enums whose value names clash with already emitted identifiers are emitted
+    /// as `enum class`, so values can keep their names instead of getting ___N suffixes.
+    fn is_enum_scoped<'n>(&self, name: &str, mut val_names: impl Iterator<Item = &'n str>) -> bool {
+        if self.cfg.lang != Lang::Cpp || name.is_empty() {
+            return false;
+        }
+        val_names.any(|v| {
+            let v = self.field_name(&self.sanitizer.sanitize(v));
+            self.names.contains_key(&(NamedKind::Ident, v))
+        })
+    }
+
+    fn resolve_enum_val_name(&mut self, id: u32, name: &str, scoped: bool) -> BtfResult<String> {
+        if scoped {
+            Ok(self.field_name(&self.sanitizer.sanitize(name)))
+        } else {
+            self.resolve_owned_name(NamedKind::Ident, id, name)
         }
-        let name = self.resolve_type_name(NamedKind::Type, id, t.name);
+    }
+
+    fn emit_enum64_def(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        t: &'a BtfEnum64,
+        lvl: usize,
+    ) -> BtfResult<()> {
         if t.values.is_empty() {
             // enum fwd
-            print!("enum{}{}", sep(&name), name);
+            let name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
+            emit!(out, "enum{}{}", sep(&name), name);
+            return Ok(());
+        }
+        let int_type = enum64_int_type(self.btf, t);
+        let name = match int_type {
+            // enum itself can't have the right size, so its values are emitted as anonymous enum
+            // and the type becomes a typedef of integer of the right size
+            Some(_) => String::new(),
+            None => self.resolve_type_name(NamedKind::Type, id, t.name)?,
+        };
+        let scoped = self.is_enum_scoped(&name, self.btf.enum64_values(t).iter().map(|v| v.name));
+        if scoped {
+            emit!(
+                out,
+                "enum class {} : {}{}",
+                name,
+                int_type_name(t.sz, t.signed),
+                self.open_brace(lvl)
+            );
         } else {
-            print!("enum{}{} {{", sep(&name), name);
-            for v in &t.values {
-                let val_uniq_name = self.resolve_name(NamedKind::Ident, &v.name);
-                print!("\n{}{} = {},", pfx(lvl + 1), &val_uniq_name, v.value);
-            }
-            print!("\n{}}}", pfx(lvl));
+            emit!(out, "enum{}{}{}", sep(&name), name, self.open_brace(lvl));
         }
+        for v in self.btf.enum64_values(t) {
+            let val_uniq_name = self.resolve_enum_val_name(id, v.name, scoped)?;
+            let val = enum64_val_str(t, v.value);
+            emit!(out, "\n{}{} = {},", self.pfx(lvl + 1), &val_uniq_name, val);
+        }
+        emit!(out, "\n{}}}", self.pfx(lvl));
+        if let Some(int_type) = int_type {
+            let name = self.resolve_type_name(NamedKind::Ident, id, t.name)?;
+            emit!(out, ";\n\n{}typedef {} {}", self.pfx(lvl), int_type, name);
+        }
+        Ok(())
     }
 
-    fn emit_fwd_def(&mut self, id: u32, t: &'a BtfFwd) {
-        if NAMES_BLACKLIST.is_match(&t.name) {
-            return;
-        }
-        let name = self.resolve_type_name(NamedKind::Type, id, t.name);
+    fn emit_fwd_def(&mut self, out: &mut Out, id: u32, t: &'a BtfFwd) -> BtfResult<()> {
+        let name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
         match t.kind {
-            BtfFwdKind::Struct => print!("struct {}", name),
+            BtfFwdKind::Struct => emit!(out, "struct {}", name),
             BtfFwdKind::Union => {
                 if self.cfg.union_as_struct {
-                    print!("struct /*union*/ {}", name)
+                    emit!(out, "struct /*union*/ {}", name)
                 } else {
-                    print!("union {}", name)
+                    emit!(out, "union {}", name)
                 }
             }
         }
+        Ok(())
     }
 
-    fn emit_typedef_def(&mut self, id: u32, t: &'a BtfTypedef, lvl: usize) -> bool {
-        if NAMES_BLACKLIST.is_match(&t.name) {
-            return false;
+    /// Whether typedef is one of STD_INT_TYPES, resolving (only through other typedefs) to integer
+    /// of the same size and signedness as in preamble.
+    fn is_std_int_type(&self, t: &BtfTypedef) -> BtfResult<bool> {
+        let (sz, signed) = match STD_INT_TYPES.iter().find(|&&(name, _, _)| name == t.name) {
+            Some(&(_, sz, signed)) => (sz, signed),
+            None => return Ok(false),
+        };
+        let mut id = t.type_id;
+        while let BtfType::Typedef(t) = self.btf.try_type_by_id(id)? {
+            id = t.type_id;
         }
-        let name = self.resolve_type_name(NamedKind::Ident, id, t.name);
-        print!("typedef ");
-        self.emit_type_decl(t.type_id, &name, lvl);
-        return true;
+        Ok(match self.btf.try_type_by_id(id)? {
+            BtfType::Int(t) => {
+                let encoding = if signed {
+                    BtfIntEncoding::Signed
+                } else {
+                    BtfIntEncoding::None
+                };
+                t.offset == 0 && t.bits == sz * 8 && t.encoding == encoding
+            }
+            _ => false,
+        })
+    }
+
+    fn emit_std_int_types(&mut self, out: &mut Out) {
+        for &(name, sz, signed) in STD_INT_TYPES {
+            emitln!(out, "typedef {} {};", int_type_name(sz, signed), name);
+        }
+        emitln!(out, "");
+    }
+
+    fn emit_typedef_def(
+        &mut self,
+        out: &mut Out,
+        id: u32,
+        t: &'a BtfTypedef,
+        lvl: usize,
+    ) -> BtfResult<()> {
+        let name = self.resolve_type_name(NamedKind::Ident, id, t.name)?;
+        emit!(out, "typedef ");
+        self.emit_type_decl(out, t.type_id, &name, lvl)
     }
 
-    fn emit_type_decl(&mut self, mut id: u32, fname: &str, lvl: usize) {
+    fn emit_type_decl(
+        &mut self,
+        out: &mut Out,
+        mut id: u32,
+        fname: &str,
+        lvl: usize,
+    ) -> BtfResult<()> {
         // This algorithm emits correct C syntax for any type definition.
         //
         // For most types it's trivial, but there are few quirky type declaration  cases worth
@@ -570,93 +2021,129 @@ impl<'a> CDumper<'a> {
         let mut chain = Vec::new();
         loop {
             chain.push(id);
-            match self.btf.type_by_id(id) {
+            if let Some(invalid_id) = self.btf.placeholder_target(id) {
+                emit!(out, "/* invalid type {} */ ", invalid_id);
+            }
+            match self.btf.try_type_by_id(id)? {
                 BtfType::Ptr(t) => id = t.type_id,
                 BtfType::Const(t) => id = t.type_id,
                 BtfType::Volatile(t) => id = t.type_id,
                 BtfType::Restrict(t) => id = t.type_id,
+                BtfType::TypeTag(t) => id = t.type_id,
                 BtfType::Array(t) => id = t.val_type_id,
                 BtfType::FuncProto(t) => id = t.res_type_id,
-                BtfType::Var(_) | BtfType::Datasec(_) | BtfType::Func(_) => {
+                BtfType::Var(_) | BtfType::Datasec(_) | BtfType::Func(_) | BtfType::DeclTag(_) => {
                     chain.pop();
-                    print!("!@#! UNEXPECT TYPE DECL CHAIN ");
+                    emit!(out, "!@#! UNEXPECT TYPE DECL CHAIN ");
                     for parent_id in chain.iter().rev() {
-                        print!("[{}] --> ", parent_id);
+                        emit!(out, "[{}] --> ", parent_id);
                     }
-                    print!("[{}] {}", id, self.btf.type_by_id(id));
-                    return;
+                    emit!(
+                        out,
+                        "[{}] {}",
+                        id,
+                        self.btf.type_display(self.btf.try_type_by_id(id)?)
+                    );
+                    return Ok(());
                 }
                 _ => break,
             }
         }
-        self.emit_type_chain(chain, fname, lvl);
+        self.emit_type_chain(out, chain, fname, lvl)
     }
 
-    fn emit_type_chain(&mut self, mut chain: Vec<u32>, fname: &str, lvl: usize) {
+    fn emit_type_chain(
+        &mut self,
+        out: &mut Out,
+        mut chain: Vec<u32>,
+        fname: &str,
+        lvl: usize,
+    ) -> BtfResult<()> {
         // default to true, in case we have single ptr in a chain. E.g., in ptr -> func_proto case.
         // func_proto will start a new emit_type_chain with just ptr, which should be emitted as
         // (*) or (*<fname>), so we don't want to preprend space for that last ptr.
         let mut last_was_ptr = true;
         while let Some(id) = chain.pop() {
-            match self.btf.type_by_id(id) {
+            match self.btf.try_type_by_id(id)? {
                 BtfType::Void => {
-                    self.emit_mods(&mut chain);
-                    print!("void");
+                    self.emit_mods(out, &mut chain)?;
+                    emit!(out, "void");
                 }
                 BtfType::Int(t) => {
-                    self.emit_mods(&mut chain);
-                    print!("{}", t.name);
+                    self.emit_mods(out, &mut chain)?;
+                    if self.cfg.lang == Lang::Cpp && t.name == "_Bool" {
+                        emit!(out, "bool");
+                    } else {
+                        emit!(out, "{}", t.name);
+                    }
                 }
                 BtfType::Struct(t) | BtfType::Union(t) => {
-                    self.emit_mods(&mut chain);
-                    if t.name.is_empty() {
-                        self.emit_composite_def(id, t, lvl); // inline anonymous struct
+                    self.emit_mods(out, &mut chain)?;
+                    if self.is_anon(id, t.name) {
+                        self.emit_composite_def(out, id, t, lvl)?; // inline anonymous struct
                     } else {
-                        self.emit_composite_fwd(id, t);
+                        self.emit_composite_fwd(out, self.canon_id(id), t)?;
                     }
                 }
                 BtfType::Enum(t) => {
-                    self.emit_mods(&mut chain);
-                    if t.name.is_empty() {
-                        self.emit_enum_def(id, t, lvl); // inline anonymous enum
+                    self.emit_mods(out, &mut chain)?;
+                    if self.cfg.enums_as_macros {
+                        emit!(out, "{}", enum_int_type(self.btf, t));
+                    } else if self.is_anon(id, t.name) {
+                        self.emit_enum_def(out, id, t, lvl)?; // inline anonymous enum
                     } else {
-                        let uniq_name = self.resolve_type_name(NamedKind::Type, id, t.name);
-                        print!("enum {}", &uniq_name);
+                        let id = self.canon_id(id);
+                        let uniq_name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
+                        emit!(out, "enum {}", &uniq_name);
                     }
                 }
                 BtfType::Enum64(t) => {
-                    self.emit_mods(&mut chain);
-                    if t.name.is_empty() {
-                        self.emit_enum64_def(id, t, lvl); // inline anonymous enum
-                    } else {
-                        let uniq_name = self.resolve_type_name(NamedKind::Type, id, t.name);
-                        print!("enum {}", &uniq_name);
+                    self.emit_mods(out, &mut chain)?;
+                    let id = self.canon_id(id);
+                    match (self.is_anon(id, t.name), enum64_int_type(self.btf, t)) {
+                        _ if self.cfg.enums_as_macros => {
+                            emit!(out, "{}", int_type_name(t.sz, t.signed))
+                        }
+                        (true, None) => self.emit_enum64_def(out, id, t, lvl)?, // inline anonymous enum
+                        (true, Some(int_type)) => emit!(out, "{}", int_type),
+                        (false, None) => {
+                            let uniq_name = self.resolve_type_name(NamedKind::Type, id, t.name)?;
+                            emit!(out, "enum {}", &uniq_name);
+                        }
+                        (false, Some(_)) => {
+                            let uniq_name = self.resolve_type_name(NamedKind::Ident, id, t.name)?;
+                            emit!(out, "{}", &uniq_name);
+                        }
                     }
                 }
                 BtfType::Fwd(t) => {
-                    self.emit_mods(&mut chain);
-                    self.emit_fwd_def(id, t);
+                    self.emit_mods(out, &mut chain)?;
+                    self.emit_fwd_def(out, id, t)?;
                 }
                 BtfType::Typedef(t) => {
-                    self.emit_mods(&mut chain);
-                    let uniq_name = self.resolve_type_name(NamedKind::Ident, id, t.name);
-                    print!("{}", &uniq_name);
+                    self.emit_mods(out, &mut chain)?;
+                    let uniq_name = self.resolve_type_name(NamedKind::Ident, id, t.name)?;
+                    // in C++, typedef name can be hidden by a member with the same name
+                    if self.cfg.lang == Lang::Cpp && lvl > 0 {
+                        emit!(out, "::");
+                    }
+                    emit!(out, "{}", &uniq_name);
                 }
                 BtfType::Ptr(_) => {
                     if last_was_ptr {
-                        print!("*")
+                        emit!(out, "*")
                     } else {
-                        print!(" *")
+                        emit!(out, " *")
                     }
                 }
                 BtfType::Volatile(_) => {
-                    print!(" volatile");
+                    emit!(out, " volatile");
                 }
                 BtfType::Const(_) => {
-                    print!(" const");
+                    emit!(out, " const");
                 }
                 BtfType::Restrict(_) => {
-                    print!(" restrict");
+                    emit!(out, " {}", self.restrict_keyword());
                 }
                 BtfType::Array(t) => {
                     // GCC has a bug (https://gcc.gnu.org/bugzilla/show_bug.cgi?id=8354) which
@@ -665,7 +2152,7 @@ impl<'a> CDumper<'a> {
                     // In general, it doesn't seem very meaningful to have a const/volatile
                     // modifier for array, so we are going to silently skip them here.
                     while let Some(id) = chain.pop() {
-                        match self.btf.type_by_id(id) {
+                        match self.btf.try_type_by_id(id)? {
                             BtfType::Volatile(_) | BtfType::Const(_) | BtfType::Restrict(_) => {}
                             _ => {
                                 chain.push(id);
@@ -674,101 +2161,108 @@ impl<'a> CDumper<'a> {
                         }
                     }
                     if let Some(&next_id) = chain.last() {
-                        let t = self.btf.type_by_id(next_id);
+                        let t = self.btf.try_type_by_id(next_id)?;
                         if !fname.is_empty() && !last_was_ptr {
-                            print!(" ");
+                            emit!(out, " ");
                         }
                         if t.kind() != BtfKind::Array {
-                            print!("(");
+                            emit!(out, "(");
                         }
-                        self.emit_type_chain(chain, fname, lvl);
+                        self.emit_type_chain(out, chain, fname, lvl)?;
                         if t.kind() != BtfKind::Array {
-                            print!(")");
+                            emit!(out, ")");
                         }
                     } else {
-                        self.emit_name(fname, last_was_ptr);
+                        self.emit_name(out, fname, last_was_ptr);
+                    }
+                    if self.fam_array == Some(id) {
+                        emit!(out, "[]");
+                    } else {
+                        emit!(out, "[{}]", t.nelems);
                     }
-                    print!("[{}]", t.nelems);
-                    return;
+                    return Ok(());
                 }
                 BtfType::FuncProto(t) => {
-                    self.emit_mods(&mut chain);
+                    self.emit_mods(out, &mut chain)?;
                     if chain.is_empty() {
-                        self.emit_name(fname, last_was_ptr);
+                        self.emit_name(out, fname, last_was_ptr);
                     } else {
-                        print!(" (");
-                        self.emit_type_chain(chain, fname, lvl);
-                        print!(")");
+                        emit!(out, " (");
+                        self.emit_type_chain(out, chain, fname, lvl)?;
+                        emit!(out, ")");
                     }
-                    print!("(");
+                    emit!(out, "(");
                     //
                     // Clang for BPF target generates func_proto with no args as a func_proto with
                     // a single void arg (i.e., <ret-type> (*f)(void) vs just <ret_type> (*f)()).
                     // We are going to pretend there are no args for such case.
-                    let arg_cnt = t.params.len();
-                    if arg_cnt == 1 && t.params[0].type_id == 0 {
-                        print!(")");
-                        return;
+                    let params = self.btf.params(t);
+                    let arg_cnt = params.len();
+                    if arg_cnt == 1 && params[0].type_id == 0 {
+                        emit!(out, ")");
+                        return Ok(());
                     }
 
-                    for (i, p) in t.params.iter().enumerate() {
+                    for (i, p) in params.iter().enumerate() {
                         if i > 0 {
-                            print!(", ");
+                            emit!(out, ", ");
                         }
                         // func_proto with vararg has last arg of type 'void'
-                        if i == arg_cnt - 1 && t.params[arg_cnt - 1].type_id == 0 {
-                            print!("...");
+                        if i == arg_cnt - 1 && params[arg_cnt - 1].type_id == 0 {
+                            emit!(out, "...");
                         } else {
-                            self.emit_type_decl(p.type_id, &p.name, lvl);
+                            let fname = self.field_name(p.name);
+                            self.emit_type_decl(out, p.type_id, &fname, lvl)?;
                         }
                     }
-                    print!(")");
-                    return;
+                    emit!(out, ")");
+                    return Ok(());
                 }
                 BtfType::Float(t) => {
-                    self.emit_mods(&mut chain);
-                    print!("{}", t.name);
+                    self.emit_mods(out, &mut chain)?;
+                    emit!(out, "{}", t.name);
                 }
                 BtfType::TypeTag(t) => {
-                    self.emit_mods(&mut chain);
-                    print!(" __attribute__((btf_tag((\"{}\")))", &t.name);
+                    emit!(out, " __attribute__((btf_type_tag(\"{}\")))", &t.name);
                 }
                 BtfType::Func(_) | BtfType::Var(_) | BtfType::Datasec(_) | BtfType::DeclTag(_) => {
-                    print!(
+                    emit!(
+                        out,
                         "!@#! UNEXPECT TYPE DECL id: {}, type: {}",
                         id,
-                        self.btf.type_by_id(id)
+                        self.btf.type_display(self.btf.try_type_by_id(id)?)
                     );
                 }
             }
-            if let BtfType::Ptr(_) = self.btf.type_by_id(id) {
+            if let BtfType::Ptr(_) = self.btf.try_type_by_id(id)? {
                 last_was_ptr = true;
             } else {
                 last_was_ptr = false;
             }
         }
-        self.emit_name(fname, last_was_ptr);
+        self.emit_name(out, fname, last_was_ptr);
+        Ok(())
     }
 
-    fn emit_name(&self, fname: &str, last_was_ptr: bool) {
+    fn emit_name(&mut self, out: &mut Out, fname: &str, last_was_ptr: bool) {
         if last_was_ptr {
-            print!("{}", fname);
+            emit!(out, "{}", fname);
         } else {
-            print!("{}{}", sep(fname), fname);
+            emit!(out, "{}{}", sep(fname), fname);
         }
     }
 
-    fn emit_mods(&self, chain: &mut Vec<u32>) {
+    fn emit_mods(&mut self, out: &mut Out, chain: &mut Vec<u32>) -> BtfResult<()> {
         while let Some(id) = chain.pop() {
-            match self.btf.type_by_id(id) {
+            match self.btf.try_type_by_id(id)? {
                 BtfType::Volatile(_) => {
-                    print!("volatile ");
+                    emit!(out, "volatile ");
                 }
                 BtfType::Const(_) => {
-                    print!("const ");
+                    emit!(out, "const ");
                 }
                 BtfType::Restrict(_) => {
-                    print!("restrict ");
+                    emit!(out, "{} ", self.restrict_keyword());
                 }
                 _ => {
                     chain.push(id);
@@ -776,30 +2270,65 @@ impl<'a> CDumper<'a> {
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Name of struct/union member or func param; these don't need to be unique.
+    fn field_name(&self, name: &str) -> String {
+        if self.cfg.lang == Lang::Cpp && CPP_KEYWORDS.contains(&name) {
+            format!("{}_", name)
+        } else {
+            name.to_owned()
+        }
     }
 
-    fn resolve_type_name(&mut self, kind: NamedKind, id: u32, name: &'a str) -> String {
-        if name.is_empty() {
-            return EMPTY.to_owned();
-        }
-        let s = &mut self.state[id as usize];
-        if s.name.is_empty() {
-            let version = self.names.entry((kind, name)).or_insert(0);
-            *version += 1;
-            if *version == 1 {
-                s.name = name.to_string()
-            } else {
-                s.name = format!("{}___{}", name, version)
-            }
+    fn static_assert_keyword(&self) -> &'static str {
+        match self.cfg.lang {
+            Lang::C => "_Static_assert",
+            Lang::Cpp => "static_assert",
+        }
+    }
+
+    fn restrict_keyword(&self) -> &'static str {
+        match self.cfg.lang {
+            Lang::C => "restrict",
+            Lang::Cpp => "__restrict",
+        }
+    }
+
+    fn resolve_type_name(&mut self, kind: NamedKind, id: u32, name: &str) -> BtfResult<String> {
+        let name = match (name, self.anon_names.get(&id)) {
+            ("", None) => return Ok(EMPTY.to_owned()),
+            ("", Some(anon_name)) => anon_name.clone(),
+            (name, _) => name.to_owned(),
+        };
+        if self.state[id as usize].name.is_empty() {
+            self.state[id as usize].name = self.resolve_owned_name(kind, id, &name)?;
         }
-        s.name.clone()
+        Ok(self.state[id as usize].name.clone())
     }
 
-    fn resolve_name(&mut self, kind: NamedKind, name: &'a str) -> String {
-        let version = self.names.entry((kind, name)).or_insert(0);
+    /// Same as resolve_name(), but in CDumperCfg::hash_suffixes mode names of (or defined by)
+    /// type `id`, that are owned by another type, are suffixed with hash of its definition.
+    fn resolve_owned_name(&mut self, kind: NamedKind, id: u32, name: &str) -> BtfResult<String> {
+        Ok(match self.name_owners.get(&(kind, name)) {
+            Some(&owner) if owner != id => {
+                let mut sig = String::new();
+                anon_sig(self.btf, id, &mut sig, 0)?;
+                let hash = fnv1a(sig.as_bytes()) as u32;
+                self.resolve_name(kind, &format!("{}___{:08x}", name, hash))
+            }
+            _ => self.resolve_name(kind, name),
+        })
+    }
+
+    fn resolve_name(&mut self, kind: NamedKind, name: &str) -> String {
+        // names that sanitize into the same identifier get ___N suffixes just like duplicates
+        let name = self.field_name(&self.sanitizer.sanitize(name));
+        let version = self.names.entry((kind, name.clone())).or_insert(0);
         *version += 1;
         if *version == 1 {
-            name.to_string()
+            name
         } else {
             format!("{}___{}", name, version)
         }
@@ -811,22 +2340,300 @@ lazy_static! {
         RegexSet::new(&["__builtin_va_list"]).expect("invalid blacklist regexes");
 }
 
+/// C++ keywords (including alternative operator tokens and built-in types) that aren't reserved
+/// in C, so can be used as identifiers in C code.
+const CPP_KEYWORDS: &[&str] = &[
+    "alignas",
+    "alignof",
+    "and",
+    "and_eq",
+    "asm",
+    "bitand",
+    "bitor",
+    "bool",
+    "catch",
+    "char16_t",
+    "char32_t",
+    "char8_t",
+    "class",
+    "co_await",
+    "co_return",
+    "co_yield",
+    "compl",
+    "concept",
+    "const_cast",
+    "consteval",
+    "constexpr",
+    "constinit",
+    "decltype",
+    "delete",
+    "dynamic_cast",
+    "explicit",
+    "export",
+    "false",
+    "friend",
+    "mutable",
+    "namespace",
+    "new",
+    "noexcept",
+    "not",
+    "not_eq",
+    "nullptr",
+    "operator",
+    "or",
+    "or_eq",
+    "private",
+    "protected",
+    "public",
+    "reinterpret_cast",
+    "requires",
+    "static_assert",
+    "static_cast",
+    "template",
+    "this",
+    "thread_local",
+    "throw",
+    "true",
+    "try",
+    "typeid",
+    "typename",
+    "using",
+    "virtual",
+    "wchar_t",
+    "xor",
+    "xor_eq",
+];
+
+/// Macros used by BTF-defined maps, as in libbpf's bpf_helpers.h.
+const MAP_DEF_MACROS: &str = "#ifndef __uint
+#define __uint(name, val) int (*name)[val]
+#endif
+#ifndef __type
+#define __type(name, val) typeof(val) *name
+#endif
+#ifndef __array
+#define __array(name, val) typeof(val) *name[]
+#endif
+#ifndef SEC
+#define SEC(name) __attribute__((section(name), used))
+#endif
+";
+
+/// Attributes of externs resolved by libbpf, as in libbpf's bpf_helpers.h.
+const EXTERN_MACROS: &str = "#ifndef __ksym
+#define __ksym __attribute__((section(\".ksyms\")))
+#endif
+#ifndef __kconfig
+#define __kconfig __attribute__((section(\".kconfig\")))
+#endif
+#ifndef __weak
+#define __weak __attribute__((weak))
+#endif
+";
+
+/// Kernel integer typedefs (see include/uapi/asm-generic/int-ll64.h), with their sizes and
+/// signedness, emitted as a fixed preamble instead of BTF typedefs with CDumperCfg::std_int_types.
+const STD_INT_TYPES: &[(&str, u32, bool)] = &[
+    ("__s8", 1, true),
+    ("__u8", 1, false),
+    ("__s16", 2, true),
+    ("__u16", 2, false),
+    ("__s32", 4, true),
+    ("__u32", 4, false),
+    ("__s64", 8, true),
+    ("__u64", 8, false),
+    ("s8", 1, true),
+    ("u8", 1, false),
+    ("s16", 2, true),
+    ("u16", 2, false),
+    ("s32", 4, true),
+    ("u32", 4, false),
+    ("s64", 8, true),
+    ("u64", 8, false),
+    ("__le16", 2, false),
+    ("__be16", 2, false),
+    ("__le32", 4, false),
+    ("__be32", 4, false),
+    ("__le64", 8, false),
+    ("__be64", 8, false),
+];
+
 const EMPTY: &str = "";
 const SPACE: &str = " ";
-const PREFIXES: &str = "\t\t\t\t\t\t\t\t\t\t\t\t";
+const MAX_INDENT_LVL: usize = 12;
 
-fn sep(name: &str) -> &str {
-    if name.is_empty() {
-        EMPTY
+/// C compilers size enum as int, unless some value doesn't fit into 32 bits, in which case it's
+/// 8 bytes. If that doesn't match ENUM64's size, returns integer type to typedef it as instead.
+fn enum64_int_type(btf: &Btf, t: &BtfEnum64) -> Option<&'static str> {
+    let is_wide = |v: i64| {
+        if t.signed {
+            v < i64::from(i32::MIN) || v > i64::from(u32::MAX)
+        } else {
+            v as u64 > u64::from(u32::MAX)
+        }
+    };
+    let values = btf.enum64_values(t);
+    let natural_sz = if values.iter().any(|v| is_wide(v.value)) {
+        8
     } else {
-        SPACE
+        4
+    };
+    if values.is_empty() || natural_sz == t.sz {
+        return None;
+    }
+    Some(int_type_name(t.sz, t.signed))
+}
+
+/// Integer type of the same size and signedness as enum.
+fn enum_int_type(btf: &Btf, t: &BtfEnum) -> &'static str {
+    int_type_name(t.sz, btf.enum_values(t).iter().any(|v| v.value < 0))
+}
+
+/// Size of enum with all values, when marked with __attribute__((packed)).
+fn packed_enum_sz(btf: &Btf, t: &BtfEnum) -> u32 {
+    let values = btf.enum_values(t);
+    let min = values.iter().map(|v| v.value).min().unwrap_or(0);
+    let max = values.iter().map(|v| v.value).max().unwrap_or(0);
+    if (min >= i8::MIN as i32 && max <= i8::MAX as i32) || (min >= 0 && max <= u8::MAX as i32) {
+        1
+    } else if (min >= i16::MIN as i32 && max <= i16::MAX as i32)
+        || (min >= 0 && max <= u16::MAX as i32)
+    {
+        2
+    } else {
+        4
+    }
+}
+
+fn enum_val_str(val: i32) -> String {
+    if val < 0 {
+        format!("({})", val)
+    } else {
+        val.to_string()
+    }
+}
+
+fn int_type_name(sz: u32, signed: bool) -> &'static str {
+    match (sz, signed) {
+        (1, true) => "signed char",
+        (1, false) => "unsigned char",
+        (2, true) => "short",
+        (2, false) => "unsigned short",
+        (4, true) => "int",
+        (4, false) => "unsigned int",
+        (_, true) => "long long",
+        (_, false) => "unsigned long long",
     }
 }
 
-fn pfx(lvl: usize) -> &'static str {
-    if lvl >= PREFIXES.len() {
-        PREFIXES
+fn enum64_val_str(t: &BtfEnum64, val: i64) -> String {
+    if !t.signed {
+        format!("{}ULL", val as u64)
+    } else if val == i64::MIN {
+        // -9223372036854775808LL is a negated literal that doesn't fit into long long
+        format!("({}LL - 1)", val + 1)
     } else {
-        &PREFIXES[0..lvl]
+        format!("{}LL", val)
+    }
+}
+
+/// Signature of anonymous type, covering everything that goes into its C definition, so that
+/// types with the same signature are interchangeable. Named types are referenced by name only,
+/// except for the root type itself.
+fn anon_sig(btf: &Btf, id: u32, sig: &mut String, depth: usize) -> BtfResult<()> {
+    let t = btf.try_type_by_id(id)?;
+    sig.push_str(&shallow_sig(btf.arena(), t));
+    if (depth > 0 && !t.name().is_empty()) || depth > 32 {
+        return Ok(());
+    }
+    for ref_id in btf.type_refs(t) {
+        sig.push('(');
+        anon_sig(btf, ref_id, sig, depth + 1)?;
+        sig.push(')');
+    }
+    Ok(())
+}
+
+/// Writer joining lines of each top-level declaration into a single line, separating tokens
+/// that were on different lines by a single space and dropping blank lines. Preprocessor
+/// directives and comments between declarations stay on their own lines. Only the current line
+/// and declaration are buffered, everything else is passed through to the underlying writer.
+struct DeclJoiner<'w> {
+    w: &'w mut dyn io::Write,
+    line: Vec<u8>,
+    decl: String,
+    depth: isize,
+}
+
+impl<'w> DeclJoiner<'w> {
+    fn new(w: &'w mut dyn io::Write) -> DeclJoiner<'w> {
+        DeclJoiner {
+            w,
+            line: Vec::new(),
+            decl: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        if self.depth == 0 && line.starts_with('#') {
+            self.end_decl()?;
+            return writeln!(self.w, "{}", line);
+        }
+        if !self.decl.is_empty() {
+            self.decl.push(' ');
+        }
+        self.decl.push_str(line);
+        self.depth += line.matches('{').count() as isize - line.matches('}').count() as isize;
+        if self.depth <= 0 && (line.ends_with(';') || line.ends_with("*/")) {
+            self.end_decl()?;
+            self.depth = 0;
+        }
+        Ok(())
+    }
+
+    fn end_decl(&mut self) -> io::Result<()> {
+        if !self.decl.is_empty() {
+            writeln!(self.w, "{}", self.decl)?;
+            self.decl.clear();
+        }
+        Ok(())
+    }
+
+    /// Write out the last, possibly unterminated, line and declaration.
+    fn finish(mut self) -> io::Result<()> {
+        self.end_line()?;
+        self.end_decl()
+    }
+}
+
+impl io::Write for DeclJoiner<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            if b == b'\n' {
+                self.end_line()?;
+            } else {
+                self.line.push(b);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+fn sep(name: &str) -> &str {
+    if name.is_empty() {
+        EMPTY
+    } else {
+        SPACE
     }
 }
@@ -0,0 +1,123 @@
+use std::mem::size_of;
+
+use scroll::IOwrite as _;
+use scroll_derive::{IOwrite, Pwrite, SizeWith};
+
+use crate::types::{BTF_ELF_SEC, BTF_EXT_ELF_SEC};
+use crate::BtfResult;
+
+const EM_BPF: u16 = 247;
+const ET_REL: u16 = 1;
+const SHT_PROGBITS: u32 = 1;
+const SHT_STRTAB: u32 = 3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pwrite, IOwrite, SizeWith)]
+struct Elf64Header {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, Pwrite, IOwrite, SizeWith)]
+struct Elf64SectionHeader {
+    sh_name: u32,
+    sh_type: u32,
+    sh_flags: u64,
+    sh_addr: u64,
+    sh_offset: u64,
+    sh_size: u64,
+    sh_link: u32,
+    sh_info: u32,
+    sh_addralign: u64,
+    sh_entsize: u64,
+}
+
+/// Wrap raw .BTF (and, optionally, .BTF.ext) data into a minimal 64-bit relocatable ELF file,
+/// similar to kernel's .btf.vmlinux.bin.o. ELF endianness follows `endian`, machine is EM_BPF.
+pub fn write_btf_elf(
+    btf_data: &[u8],
+    btf_ext_data: Option<&[u8]>,
+    endian: scroll::Endian,
+) -> BtfResult<Vec<u8>> {
+    let mut secs = vec![(BTF_ELF_SEC, SHT_PROGBITS, btf_data)];
+    if let Some(data) = btf_ext_data {
+        secs.push((BTF_EXT_ELF_SEC, SHT_PROGBITS, data));
+    }
+    let mut shstrtab = vec![0u8];
+    let mut name_offs = Vec::new();
+    for &(name, _, _) in &secs {
+        name_offs.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+    name_offs.push(shstrtab.len() as u32);
+    shstrtab.extend_from_slice(b".shstrtab\0");
+    secs.push((".shstrtab", SHT_STRTAB, &shstrtab));
+
+    let align = |off: usize, a: usize| off.div_ceil(a) * a;
+    let mut shdrs = vec![Elf64SectionHeader::default()];
+    let mut off = size_of::<Elf64Header>();
+    for (i, &(_, sh_type, data)) in secs.iter().enumerate() {
+        let sh_addralign = if sh_type == SHT_STRTAB { 1 } else { 4 };
+        off = align(off, sh_addralign);
+        shdrs.push(Elf64SectionHeader {
+            sh_name: name_offs[i],
+            sh_type,
+            sh_offset: off as u64,
+            sh_size: data.len() as u64,
+            sh_addralign: sh_addralign as u64,
+            ..Default::default()
+        });
+        off += data.len();
+    }
+    let shoff = align(off, 8);
+
+    let mut e_ident = [0u8; 16];
+    e_ident[..4].copy_from_slice(b"\x7fELF");
+    e_ident[4] = 2; // ELFCLASS64
+    e_ident[5] = if endian.is_little() { 1 } else { 2 }; // ELFDATA2LSB/MSB
+    e_ident[6] = 1; // EV_CURRENT
+
+    let mut elf = Vec::with_capacity(shoff + shdrs.len() * size_of::<Elf64SectionHeader>());
+    elf.iowrite_with(
+        Elf64Header {
+            e_ident,
+            e_type: ET_REL,
+            e_machine: EM_BPF,
+            e_version: 1,
+            e_entry: 0,
+            e_phoff: 0,
+            e_shoff: shoff as u64,
+            e_flags: 0,
+            e_ehsize: size_of::<Elf64Header>() as u16,
+            e_phentsize: 0,
+            e_phnum: 0,
+            e_shentsize: size_of::<Elf64SectionHeader>() as u16,
+            e_shnum: shdrs.len() as u16,
+            e_shstrndx: (shdrs.len() - 1) as u16,
+        },
+        endian,
+    )?;
+    for (i, &(_, _, data)) in secs.iter().enumerate() {
+        elf.resize(shdrs[i + 1].sh_offset as usize, 0);
+        elf.extend_from_slice(data);
+    }
+    elf.resize(shoff, 0);
+    for shdr in shdrs {
+        elf.iowrite_with(shdr, endian)?;
+    }
+    Ok(elf)
+}
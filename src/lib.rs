@@ -1,41 +1,234 @@
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
+pub mod btf_core_apply;
+pub mod btf_dedup;
+pub mod btf_file;
 pub mod btf_index;
+#[cfg(all(target_os = "linux", feature = "bpf-syscall"))]
+pub mod btf_kernel;
+pub mod btf_lazy;
+pub mod btf_min_core;
+pub mod btf_patch;
+pub mod btf_path;
+pub mod btf_query;
+pub mod btf_relocate;
+pub mod btf_strtab;
+pub mod btf_synth;
+pub mod btf_writer;
 pub mod c_dumper;
+pub mod elf_writer;
+pub mod multi_header;
 pub mod relocator;
 pub mod types;
 
+/// Error of loading, validating or processing BTF.
+///
+/// Errors of malformed BTF data are distinguished by variants, while errors of underlying I/O,
+/// ELF and binary parsing are wrapped and available through `Error::source()`. Those that
+/// aren't cloneable themselves are shared through `Arc`, so that errors can be cloned.
 #[derive(Clone, Debug)]
-pub struct BtfError {
-    details: String,
+pub enum BtfError {
+    /// Invalid magic, version or layout of .BTF or .BTF.ext data
+    InvalidHeader(String),
+    /// Part of data described by headers doesn't fit into it
+    OutOfBounds {
+        what: String,
+        off: u64,
+        end: u64,
+        len: usize,
+    },
+    /// String offset outside of string section
+    BadStringOffset { off: u32 },
+    /// String at given offset isn't NUL-terminated or isn't valid UTF-8
+    BadString { off: u32 },
+    /// Type of unknown BTF kind
+    UnknownKind { kind: u32, id: u32 },
+    /// INT type with encoding other than none, signed, char or bool (e.g., GCC's signed char)
+    BadIntEncoding { id: u32, enc: u32 },
+    /// Reference to type ID, that doesn't exist in BTF
+    InvalidTypeId { id: u32 },
+    /// Type data ends before the end of type's description
+    TruncatedType { id: u32, source: Arc<scroll::Error> },
+    /// Failed I/O
+    Io(Arc<std::io::Error>),
+    /// Malformed ELF file
+    Elf(object::read::Error),
+    /// Failed reading of binary data (outside of type data)
+    Parse(Arc<scroll::Error>),
+    /// Failed formatting of output
+    Fmt(fmt::Error),
+    /// Invalid UTF-8 string
+    Utf8(std::str::Utf8Error),
+    /// Invalid number
+    ParseInt(std::num::ParseIntError),
+    /// Invalid regular expression
+    Regex(regex::Error),
+    /// Any other error, e.g., invalid type graph, arguments or failed relocation
+    Other(String),
 }
 
 impl BtfError {
     pub fn new(msg: &str) -> BtfError {
-        BtfError {
-            details: msg.to_string(),
-        }
+        BtfError::Other(msg.to_string())
     }
     pub fn new_owned(msg: String) -> BtfError {
-        BtfError { details: msg }
+        BtfError::Other(msg)
     }
 }
 
 impl fmt::Display for BtfError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
+        match self {
+            BtfError::InvalidHeader(msg) | BtfError::Other(msg) => write!(f, "{}", msg),
+            BtfError::OutOfBounds {
+                what,
+                off,
+                end,
+                len,
+            } => write!(
+                f,
+                "{} [{}, {}) is out of bounds of {} bytes of data",
+                what, off, end, len
+            ),
+            BtfError::BadStringOffset { off } => write!(f, "Invalid string offset: {}", off),
+            BtfError::BadString { off } => write!(f, "Invalid string at offset: {}", off),
+            BtfError::UnknownKind { kind, id } => {
+                write!(f, "Unknown BTF kind: {} of type [{}]", kind, id)
+            }
+            BtfError::BadIntEncoding { id, enc } => {
+                write!(f, "Unknown BTF int encoding: {} of type [{}]", enc, id)
+            }
+            BtfError::InvalidTypeId { id } => write!(f, "Invalid type ID: {}", id),
+            BtfError::TruncatedType { id, .. } => write!(f, "Truncated type [{}]", id),
+            BtfError::Io(e) => write!(f, "{}", e),
+            BtfError::Elf(e) => write!(f, "{}", e),
+            BtfError::Parse(e) => write!(f, "{}", e),
+            BtfError::Fmt(e) => write!(f, "{}", e),
+            BtfError::Utf8(e) => write!(f, "{}", e),
+            BtfError::ParseInt(e) => write!(f, "{}", e),
+            BtfError::Regex(e) => write!(f, "{}", e),
+        }
     }
 }
 
 impl Error for BtfError {
-    fn description(&self) -> &str {
-        &self.details
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BtfError::TruncatedType { source, .. } => Some(source.as_ref()),
+            BtfError::Io(e) => Some(e.as_ref()),
+            BtfError::Elf(e) => Some(e),
+            BtfError::Parse(e) => Some(e.as_ref()),
+            BtfError::Fmt(e) => Some(e),
+            BtfError::Utf8(e) => Some(e),
+            BtfError::ParseInt(e) => Some(e),
+            BtfError::Regex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BtfError {
+    fn from(e: std::io::Error) -> BtfError {
+        BtfError::Io(Arc::new(e))
+    }
+}
+
+impl From<object::read::Error> for BtfError {
+    fn from(e: object::read::Error) -> BtfError {
+        BtfError::Elf(e)
+    }
+}
+
+impl From<scroll::Error> for BtfError {
+    fn from(e: scroll::Error) -> BtfError {
+        BtfError::Parse(Arc::new(e))
+    }
+}
+
+impl From<fmt::Error> for BtfError {
+    fn from(e: fmt::Error) -> BtfError {
+        BtfError::Fmt(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for BtfError {
+    fn from(e: std::str::Utf8Error) -> BtfError {
+        BtfError::Utf8(e)
     }
 }
 
-pub type BtfResult<T> = Result<T, Box<dyn Error>>;
+impl From<std::string::FromUtf8Error> for BtfError {
+    fn from(e: std::string::FromUtf8Error) -> BtfError {
+        BtfError::Utf8(e.utf8_error())
+    }
+}
+
+impl From<std::num::ParseIntError> for BtfError {
+    fn from(e: std::num::ParseIntError) -> BtfError {
+        BtfError::ParseInt(e)
+    }
+}
+
+impl From<regex::Error> for BtfError {
+    fn from(e: regex::Error) -> BtfError {
+        BtfError::Regex(e)
+    }
+}
+
+pub type BtfResult<T> = Result<T, BtfError>;
 
 pub fn btf_error<T>(msg: String) -> BtfResult<T> {
-    Err(Box::new(BtfError::new_owned(msg)))
+    Err(BtfError::new_owned(msg))
+}
+
+pub(crate) const FNV1A_INIT: u64 = 0xcbf29ce484222325;
+
+/// Extend 64-bit FNV-1a hash with data. Unlike std's DefaultHasher, it's the same across runs,
+/// platforms and Rust releases, so it can be persisted.
+pub(crate) fn fnv1a_extend(h: u64, data: &[u8]) -> u64 {
+    data.iter()
+        .fold(h, |h, &b| (h ^ u64::from(b)).wrapping_mul(0x100000001b3))
+}
+
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    fnv1a_extend(FNV1A_INIT, data)
+}
+
+/// Apply `f` to each item, using up to `jobs` threads (defaults to number of CPUs), and return
+/// results in the order of items.
+pub fn par_map<T, R, F>(items: &[T], jobs: Option<usize>, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .clamp(1, items.len().max(1));
+    if jobs == 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results = std::sync::Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                if i >= items.len() {
+                    break;
+                }
+                let res = f(&items[i]);
+                results.lock().unwrap()[i] = Some(res);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect()
 }
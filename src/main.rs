@@ -1,23 +1,35 @@
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::error::Error;
+use std::collections::HashSet;
 use std::io::Write;
 
 use bitflags::bitflags;
 use clap::builder::TypedValueParser as _;
-use memmap;
-use object::{Object, ObjectSection};
-use regex::Regex;
+use object::{Object, ObjectSection, ObjectSymbol};
+use regex::{Regex, RegexBuilder, RegexSet};
 use scroll::Pread;
 use std::mem::size_of;
 use std::str::FromStr as _;
 
+use btf::btf_core_apply::apply_core_relocs;
+use btf::btf_dedup::unmatched_types;
+use btf::btf_file::{load_file, FileData};
+use btf::btf_index::{BtfIndex, BtfRefIndex};
+use btf::btf_lazy::LazyBtf;
+use btf::btf_min_core::MinCoreBtf;
+use btf::btf_patch::{encode_patched, BtfPatch};
+use btf::btf_query::{Query, Shape};
+use btf::btf_relocate::relocate_split;
+use btf::btf_writer;
 use btf::c_dumper;
+use btf::elf_writer::write_btf_elf;
+use btf::multi_header::{generate_multi_version_header, KernelVersion, VersionedBtf};
 use btf::relocator::{Relocator, RelocatorCfg};
 use btf::types::*;
-use btf::{btf_error, BtfError, BtfResult};
+use btf::{btf_error, par_map, BtfError, BtfResult};
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const SYS_BTF_DIR: &str = "/sys/kernel/btf";
 
 #[derive(Clone, Debug)]
 enum DumpFormat {
@@ -52,10 +64,11 @@ bitflags! {
         const FUNCINFOS     = 0b0010;
         const LINEINFOS     = 0b0100;
         const RELOCS        = 0b1000;
+        const STRINGS       = 0b10000;
 
         const DEFAULT = Self::TYPES.bits() | Self::RELOCS.bits();
         const EXT     = Self::FUNCINFOS.bits() | Self::LINEINFOS.bits() | Self::RELOCS.bits();
-        const ALL     = Self::TYPES.bits() | Self::EXT.bits();
+        const ALL     = Self::TYPES.bits() | Self::EXT.bits() | Self::STRINGS.bits();
     }
 }
 
@@ -75,6 +88,7 @@ impl std::str::FromStr for Datasets {
             "funcs" | "func" | "f" => Ok(Datasets::FUNCINFOS),
             "lines" | "line" | "l" => Ok(Datasets::LINEINFOS),
             "relocs" | "reloc" | "r" => Ok(Datasets::RELOCS),
+            "strings" | "string" | "s" => Ok(Datasets::STRINGS),
             "exts" | "ext" | "e" => Ok(Datasets::EXT),
             "all" | "a" => Ok(Datasets::ALL),
             "default" | "def" | "d" => Ok(Datasets::DEFAULT),
@@ -86,17 +100,73 @@ impl std::str::FromStr for Datasets {
     }
 }
 
-#[derive(clap::Parser)]
+#[derive(clap::Parser, Clone)]
 struct QueryArgs {
     #[clap(short = 'n', long = "name")]
     /// Regex of type names to include
     name: Option<String>,
+    #[clap(short = 'i', long = "ignore-case", requires = "name")]
+    /// Match --name regex case-insensitively
+    ignore_case: bool,
+    #[clap(long = "name-contains")]
+    /// Include types with names containing given substring
+    name_contains: Option<String>,
+    #[clap(long = "fuzzy", requires = "name_contains")]
+    /// Match --name-contains as case-insensitive subsequence (e.g., 'tskstr' finds task_struct)
+    fuzzy: bool,
     #[clap(short = 't', long = "type", use_value_delimiter = true)]
     /// BTF type kinds to include
     kinds: Vec<BtfKind>,
     #[clap(long = "id", use_value_delimiter = true)]
     /// Type IDs to include
     ids: Vec<u32>,
+    #[clap(long = "tag")]
+    /// Include types annotated with given DECL_TAG/TYPE_TAG value (directly or on a member/param)
+    tag: Option<String>,
+    #[clap(long = "referenced-by")]
+    /// Include types directly referencing (embedding, pointing to, etc) types with given name
+    referenced_by: Option<String>,
+    #[clap(long = "member-name")]
+    /// Include structs/unions with a member matching regex
+    member_name: Option<String>,
+    #[clap(long = "shape")]
+    /// Include structs/unions (incl. anonymous) with given members in order, as comma-separated
+    /// NAME[:TYPE] list, e.g., ':struct,rbnode:rb_node,...'
+    shape: Option<String>,
+    #[clap(long = "min-size")]
+    /// Include types of at least given size in bytes (modifiers and typedefs resolved); funcs,
+    /// vars and forward declarations have no size and are never included
+    min_size: Option<u32>,
+    #[clap(long = "max-size")]
+    /// Include types of at most given size in bytes (modifiers and typedefs resolved); funcs,
+    /// vars and forward declarations have no size and are never included
+    max_size: Option<u32>,
+    #[clap(long = "match-all", conflicts_with = "match_any")]
+    /// Include types matching all of given filters (default)
+    match_all: bool,
+    #[clap(long = "match-any")]
+    /// Include types matching any of given filters
+    match_any: bool,
+    #[clap(long = "where")]
+    /// Include types matching expression over kind, name, id, size and vlen (e.g.,
+    /// 'kind==struct && size>4096 && name=~"^net"')
+    where_expr: Option<String>,
+}
+
+impl QueryArgs {
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.name_contains.is_none()
+            && self.kinds.is_empty()
+            && self.ids.is_empty()
+            && self.tag.is_none()
+            && self.referenced_by.is_none()
+            && self.member_name.is_none()
+            && self.shape.is_none()
+            && self.where_expr.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+    }
 }
 
 #[derive(clap::Parser)]
@@ -106,7 +176,13 @@ enum Cmd {
     #[clap(name = "dump")]
     /// Query and pretty-print matching BTF data
     Dump {
-        file: std::path::PathBuf,
+        #[clap(default_value = "kernel:", value_parser = parse_btf_path)]
+        /// BTF or ELF files, or running kernel's BTF as 'kernel:' or 'module:<name>'
+        files: Vec<std::path::PathBuf>,
+        #[clap(long = "merge", conflicts_with_all = ["base_btf", "lenient", "lazy"])]
+        /// Dump multiple files as a single BTF with types shared between them deduplicated,
+        /// instead of dumping each file separately
+        merge: bool,
         #[clap(
             short = 'f',
             long = "format",
@@ -143,6 +219,9 @@ enum Cmd {
                 "relocs",
                 "reloc",
                 "r",
+                "strings",
+                "string",
+                "s",
                 "all",
                 "a",
                 "exts",
@@ -152,6 +231,9 @@ enum Cmd {
         )]
         /// Datasets to output
         datasets: Datasets,
+        #[clap(long = "sec")]
+        /// Regex of ELF section names to output func/line/reloc infos of
+        sec: Option<String>,
         #[clap(flatten)]
         query: QueryArgs,
         #[clap(short = 'v', long = "verbose")]
@@ -160,104 +242,687 @@ enum Cmd {
         #[clap(long = "union-as-struct")]
         /// Replace unions with structs (for BPF CORE)
         union_as_struct: bool,
+        #[clap(short = 'b', long = "base-btf")]
+        /// Base BTF (e.g., vmlinux) for split BTF (e.g., kernel module)
+        base_btf: Option<std::path::PathBuf>,
+        #[clap(
+            long = "ptr-size",
+            value_parser = clap::builder::PossibleValuesParser::new(["4", "8"])
+                .map(|s| s.parse::<u32>().unwrap()),
+        )]
+        /// Target's pointer size in bytes, overriding the one of ELF file or 64-bit assumed for
+        /// raw BTF
+        ptr_sz: Option<u32>,
+        #[clap(long = "keep-going")]
+        /// Skip types that can't be dumped as C, reporting them at the end
+        keep_going: bool,
+        #[clap(long = "lenient")]
+        /// Replace invalid type references with placeholders instead of rejecting malformed BTF
+        lenient: bool,
+        #[clap(long = "emit-vars")]
+        /// Emit variables of DATASECs (.data, .bss, .rodata) as C declarations
+        emit_vars: bool,
+        #[clap(long = "emit-funcs")]
+        /// Emit FUNCs as C prototypes (e.g., for kernel helpers and kfuncs)
+        emit_funcs: bool,
+        #[clap(
+            long = "sort",
+            default_value = "id",
+            value_parser = clap::builder::PossibleValuesParser::new(["id", "name", "size"])
+                .map(|s| c_dumper::TypeOrder::from_str(&s).unwrap()),
+        )]
+        /// Order of types in C output; dependencies are still emitted before their users
+        sort: c_dumper::TypeOrder,
+        #[clap(long = "with-deps")]
+        /// Also dump all types matching types depend on, including ones used through pointers
+        with_deps: bool,
+        #[clap(long = "exclude-name")]
+        /// Regex of type names whose definitions are omitted from C output (repeatable)
+        exclude_names: Vec<String>,
+        #[clap(long = "include-only")]
+        /// Regex of type names whose definitions are allowed in C output (repeatable)
+        include_only: Vec<String>,
+        #[clap(long = "emit-asserts")]
+        /// Emit _Static_assert checks of struct/union sizes and member offsets in C output
+        emit_asserts: bool,
+        #[clap(long = "name-anon")]
+        /// Give anonymous structs/unions/enums synthetic names instead of inlining them
+        name_anon: bool,
+        #[clap(long = "prologue")]
+        /// File whose contents are emitted verbatim before C output (e.g., license, includes)
+        prologue: Option<std::path::PathBuf>,
+        #[clap(long = "epilogue")]
+        /// File whose contents are emitted verbatim after C output
+        epilogue: Option<std::path::PathBuf>,
+        #[clap(long = "zero-len-arrays")]
+        /// Emit trailing zero-length arrays as GNU-style [0] instead of C99 flexible array members
+        zero_len_arrays: bool,
+        #[clap(
+            long = "lang",
+            default_value = "c",
+            value_parser = clap::builder::PossibleValuesParser::new(["c", "c++"])
+                .map(|s| c_dumper::Lang::from_str(&s).unwrap()),
+        )]
+        /// Language of C output: plain C or C++-compatible declarations wrapped in extern "C"
+        lang: c_dumper::Lang,
+        #[clap(long = "fwd-decls-only")]
+        /// Emit only forward declarations of structs/unions, plus typedefs and enums
+        fwd_decls_only: bool,
+        #[clap(long = "enums-as-macros")]
+        /// Emit enum values as #define macros and enum types as integers
+        enums_as_macros: bool,
+        #[clap(
+            long = "pack-style",
+            default_value = "attribute",
+            value_parser = clap::builder::PossibleValuesParser::new(["attribute", "pragma"])
+                .map(|s| c_dumper::PackStyle::from_str(&s).unwrap()),
+        )]
+        /// Mark packed structs with __attribute__((packed)) or wrap them in #pragma pack
+        pack_style: c_dumper::PackStyle,
+        #[clap(
+            long = "indent",
+            default_value = "tab",
+            value_parser = |s: &str| c_dumper::Indent::from_str(s),
+        )]
+        /// Indentation of nested declarations: 'tab' or number of spaces
+        indent: c_dumper::Indent,
+        #[clap(long = "brace-on-new-line")]
+        /// Put opening braces of struct/union/enum definitions on their own lines
+        brace_on_new_line: bool,
+        #[clap(long = "exact-layout")]
+        /// Pad and pack structs and unions so that compiler reproduces BTF layout byte-for-byte
+        exact_layout: bool,
+        #[clap(long = "diff-friendly")]
+        /// Emit each C declaration on a single line without blank lines, for diffing dumps
+        diff_friendly: bool,
+        #[clap(
+            long = "sort-members",
+            requires = "diff_friendly",
+            conflicts_with_all = ["exact_layout", "emit_asserts"]
+        )]
+        /// Sort struct/union members by name (breaks struct layout, meant for diffing only)
+        sort_members: bool,
+        #[clap(long = "std-int-types")]
+        /// Replace typedefs of kernel integer types (u32, __u32, etc.) with a fixed preamble
+        std_int_types: bool,
+        #[clap(long = "hash-suffixes")]
+        /// Disambiguate clashing type names by hash of type definition instead of a counter
+        hash_suffixes: bool,
+        #[clap(
+            long = "deps",
+            default_value = "all",
+            value_parser = clap::builder::PossibleValuesParser::new(["all", "fwd-only", "none"])
+                .map(|s| c_dumper::DepsMode::from_str(&s).unwrap()),
+        )]
+        /// How to emit types that dumped types depend on, but that don't match filters: complete
+        /// definitions, only forward declarations of structs/unions, or nothing
+        deps: c_dumper::DepsMode,
+        #[clap(
+            long = "lazy",
+            conflicts_with_all = ["base_btf", "lenient", "with_deps", "at_offset"]
+        )]
+        /// Decode only types matching --name, --name-contains, --type and --id filters, which is
+        /// much faster for targeted queries of huge BTFs; only types are dumped
+        lazy: bool,
+        #[clap(long = "at-offset", value_name = "TYPE:BYTE")]
+        /// Print path of the innermost field covering given byte offset (decimal or 0x-prefixed
+        /// hex) of a struct/union/typedef, instead of dumping
+        at_offset: Option<String>,
     },
     #[clap(name = "reloc")]
     /// Print detailed relocation information
     Reloc {
-        /// Kernel image (target BTF)
+        #[clap(value_parser = parse_btf_path)]
+        /// Kernel image (target BTF), 'kernel:' for running kernel's BTF
         targ_file: std::path::PathBuf,
         /// BPF program (local BTF)
         local_file: std::path::PathBuf,
+        #[clap(short = 'm', long = "module")]
+        /// Kernel module BTF (split on top of target BTF) or a directory of them (e.g.,
+        /// /sys/kernel/btf), as additional relocation targets
+        modules: Vec<std::path::PathBuf>,
         #[clap(short = 'v', long = "verbose")]
         /// Output verbose log
         verbose: bool,
+        #[clap(short = 'j', long = "jobs")]
+        /// Number of threads performing relocations (defaults to number of CPUs)
+        jobs: Option<usize>,
+        #[clap(long = "cache")]
+        /// File caching relocation candidates between runs with the same BPF program and kernel
+        /// (and modules); created if missing or stale
+        cache: Option<std::path::PathBuf>,
+        #[clap(long = "apply", requires = "output")]
+        /// Patch relocated instructions and write out pre-relocated BPF object file
+        apply: bool,
+        #[clap(long = "poison", requires = "apply")]
+        /// Poison instructions of failed relocations, like libbpf does, instead of failing
+        poison: bool,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for relocated BPF object file
+        output: Option<std::path::PathBuf>,
+    },
+    #[clap(name = "strip")]
+    /// Remove matching types and write out the rest as raw BTF
+    Strip {
+        file: std::path::PathBuf,
+        #[clap(flatten)]
+        query: QueryArgs,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: std::path::PathBuf,
+    },
+    #[clap(name = "extract")]
+    /// Write matching types and all types they depend on as raw BTF
+    Extract {
+        file: std::path::PathBuf,
+        #[clap(flatten)]
+        query: QueryArgs,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: std::path::PathBuf,
+        #[clap(long = "shallow")]
+        /// Don't pull in structs/unions referenced through pointers, emit them as fwds instead
+        shallow: bool,
+    },
+    #[clap(name = "elf-relocs")]
+    /// Print ELF relocations (map references, calls, globals) correlated with BTF
+    ElfRelocs { file: std::path::PathBuf },
+    #[clap(name = "multi-header")]
+    /// Generate C header for multiple kernel versions with #if guards around differences
+    MultiHeader {
+        #[clap(short = 'k', long = "kernel", required = true)]
+        /// Kernel BTF with its version, as <version>=<file> (e.g., 5.10=vmlinux-5.10)
+        kernels: Vec<String>,
+        #[clap(flatten)]
+        query: QueryArgs,
+        #[clap(long = "union-as-struct")]
+        /// Replace unions with structs (for BPF CORE)
+        union_as_struct: bool,
+    },
+    #[clap(name = "merge")]
+    /// Combine types of multiple BTFs into a single raw BTF
+    Merge {
+        #[clap(required = true)]
+        files: Vec<std::path::PathBuf>,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: std::path::PathBuf,
+        #[clap(long = "dedup")]
+        /// Emit structurally identical types just once
+        dedup: bool,
+    },
+    #[clap(name = "sanitize")]
+    /// Downgrade newer BTF kinds for older kernels and write result as raw BTF
+    Sanitize {
+        file: std::path::PathBuf,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: std::path::PathBuf,
+    },
+    #[clap(name = "diff-type")]
+    /// Compare layout of a single type between two BTFs
+    DiffType {
+        /// Old BTF
+        old_file: std::path::PathBuf,
+        /// New BTF
+        new_file: std::path::PathBuf,
+        /// Type to compare, e.g., 'struct sock', 'enum bpf_func_id' or just 'task_struct'
+        type_name: String,
+        #[clap(
+            long = "ptr-size",
+            value_parser = clap::builder::PossibleValuesParser::new(["4", "8"])
+                .map(|s| s.parse::<u32>().unwrap()),
+        )]
+        /// Target's pointer size in bytes, overriding the one of ELF file or 64-bit assumed for
+        /// raw BTF
+        ptr_sz: Option<u32>,
+    },
+    #[clap(name = "patch")]
+    /// Apply scripted edits to BTF types and write result as raw BTF
+    Patch {
+        file: std::path::PathBuf,
+        #[clap(long = "rename")]
+        /// Rename types, as <old>=<new>
+        renames: Vec<String>,
+        #[clap(long = "retype")]
+        /// Change referenced type, as var:<name>=<id>, typedef:<name>=<id> or <type>.<member>=<id>
+        retypes: Vec<String>,
+        #[clap(long = "resize")]
+        /// Change size of struct/union/enum/datasec, as <name>=<size>
+        resizes: Vec<String>,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: std::path::PathBuf,
+    },
+    #[clap(name = "convert")]
+    /// Convert between raw BTF and ELF: raw BTF is wrapped into ELF, ELF's .BTF is extracted
+    Convert {
+        /// Raw BTF or ELF file
+        file: std::path::PathBuf,
+        #[clap(short = 'o', long = "output")]
+        /// Output ELF (for raw BTF input) or raw .BTF (for ELF input) file
+        output: std::path::PathBuf,
+        #[clap(long = "ext")]
+        /// Raw .BTF.ext to add into ELF (for raw BTF input) or to extract into (for ELF input)
+        ext: Option<std::path::PathBuf>,
+    },
+    #[clap(name = "dedup")]
+    /// Deduplicate structurally identical types
+    Dedup {
+        file: std::path::PathBuf,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: Option<std::path::PathBuf>,
+        #[clap(long = "verify-against")]
+        /// Reference deduplicated BTF (e.g., produced by libbpf's btf__dedup()) to compare with
+        verify_against: Option<std::path::PathBuf>,
+    },
+    #[clap(name = "relocate")]
+    /// Relocate split BTF with distilled base (.BTF.base) onto a new base BTF
+    Relocate {
+        /// ELF file with split .BTF and distilled .BTF.base (e.g., kernel module)
+        file: std::path::PathBuf,
+        #[clap(short = 'b', long = "base-btf")]
+        /// New base BTF (e.g., /sys/kernel/btf/vmlinux)
+        base_btf: std::path::PathBuf,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw split BTF
+        output: std::path::PathBuf,
     },
     #[clap(name = "stat")]
     /// Stats about .BTF and .BTF.ext data
     Stat { file: std::path::PathBuf },
+    #[clap(name = "check")]
+    /// Check consistency between .BTF, .BTF.ext and ELF sections
+    Check { file: std::path::PathBuf },
+    #[clap(name = "preflight")]
+    /// Check whether BPF object is likely to load on a running kernel
+    Preflight {
+        /// BPF object file
+        file: std::path::PathBuf,
+        #[clap(
+            short = 'k',
+            long = "kernel-btf",
+            default_value = "/sys/kernel/btf/vmlinux"
+        )]
+        /// Kernel BTF to check against
+        kernel_btf: std::path::PathBuf,
+    },
+    #[clap(name = "min-core-btf")]
+    /// Generate minimal kernel BTF needed for CO-RE relocations of given BPF objects
+    MinCoreBtf {
+        #[clap(required = true)]
+        /// BPF object files
+        files: Vec<std::path::PathBuf>,
+        #[clap(
+            short = 'k',
+            long = "kernel-btf",
+            default_value = "/sys/kernel/btf/vmlinux"
+        )]
+        /// Kernel BTF to relocate against
+        kernel_btf: std::path::PathBuf,
+        #[clap(short = 'o', long = "output")]
+        /// Output file for resulting raw BTF
+        output: std::path::PathBuf,
+    },
+    #[clap(name = "btfhub")]
+    /// Generate minimal CO-RE BTFs for each kernel BTF of a BTFHub-like directory tree
+    Btfhub {
+        /// Directory with kernel BTFs (*.btf files), e.g., BTFHub archive
+        dir: std::path::PathBuf,
+        #[clap(required = true)]
+        /// BPF object files
+        files: Vec<std::path::PathBuf>,
+        #[clap(short = 'o', long = "output")]
+        /// Output directory, mirroring layout of input directory
+        output: std::path::PathBuf,
+        #[clap(short = 'j', long = "jobs")]
+        /// Number of kernels processed in parallel (defaults to number of CPUs)
+        jobs: Option<usize>,
+    },
+    #[clap(name = "reloc-matrix")]
+    /// Perform CO-RE relocations of BPF object against each kernel BTF in a directory tree
+    RelocMatrix {
+        /// BPF object file
+        file: std::path::PathBuf,
+        /// Directory with kernel BTFs (*.btf files), e.g., BTFHub archive
+        dir: std::path::PathBuf,
+        #[clap(short = 'j', long = "jobs")]
+        /// Number of kernels processed in parallel (defaults to number of CPUs)
+        jobs: Option<usize>,
+    },
+    #[clap(name = "verify-core")]
+    /// Check that all CO-RE relocations of BPF object resolve against each of given kernel BTFs
+    VerifyCore {
+        /// BPF object file
+        file: std::path::PathBuf,
+        #[clap(required = true)]
+        /// Kernel BTFs (vmlinux ELF or raw BTF)
+        kernels: Vec<std::path::PathBuf>,
+        #[clap(short = 'j', long = "jobs")]
+        /// Number of kernels processed in parallel (defaults to number of CPUs)
+        jobs: Option<usize>,
+    },
+    #[clap(name = "unused")]
+    /// Report types not reachable from any func, var or datasec
+    Unused {
+        file: std::path::PathBuf,
+        #[clap(long = "named-roots")]
+        /// Treat named structs, unions, enums and typedefs as roots as well
+        named_roots: bool,
+    },
+    #[clap(name = "ids")]
+    /// Resolve type names to type IDs and type IDs to types, printing their kind and size
+    Ids {
+        #[clap(value_parser = parse_btf_path)]
+        /// BTF or ELF file, or running kernel's BTF as 'kernel:' or 'module:<name>'
+        file: std::path::PathBuf,
+        #[clap(required = true)]
+        /// Type names or IDs
+        types: Vec<String>,
+        #[clap(short = 'b', long = "base-btf")]
+        /// Base BTF (e.g., vmlinux) for split BTF (e.g., kernel module)
+        base_btf: Option<std::path::PathBuf>,
+        #[clap(
+            long = "ptr-size",
+            value_parser = clap::builder::PossibleValuesParser::new(["4", "8"])
+                .map(|s| s.parse::<u32>().unwrap()),
+        )]
+        /// Target's pointer size in bytes, overriding the one of ELF file or 64-bit assumed for
+        /// raw BTF
+        ptr_sz: Option<u32>,
+        #[clap(short = 't', long = "type", use_value_delimiter = true)]
+        /// BTF type kinds to resolve names to
+        kinds: Vec<BtfKind>,
+    },
+
+    #[cfg(all(target_os = "linux", feature = "bpf-syscall"))]
+    #[clap(name = "kernel-btf")]
+    /// List BTF objects loaded into the running kernel or save one of them as raw BTF
+    KernelBtf {
+        /// ID of BTF object to save, all BTF objects are listed if not specified
+        id: Option<u32>,
+        #[clap(short = 'o', long = "output", requires = "id")]
+        /// Output file for raw BTF of given BTF object
+        output: Option<std::path::PathBuf>,
+    },
 
     #[clap(name = "version")]
     /// Print btfdump version
     Version,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> BtfResult<()> {
     let cmd = clap::Parser::parse();
 
     match cmd {
         Cmd::Dump {
-            file,
+            files,
+            merge,
             format,
             datasets,
+            sec,
             query,
             verbose,
             union_as_struct,
+            base_btf,
+            ptr_sz,
+            keep_going,
+            lenient,
+            emit_vars,
+            emit_funcs,
+            sort,
+            with_deps,
+            exclude_names,
+            include_only,
+            emit_asserts,
+            name_anon,
+            prologue,
+            epilogue,
+            zero_len_arrays,
+            lang,
+            fwd_decls_only,
+            enums_as_macros,
+            pack_style,
+            indent,
+            brace_on_new_line,
+            exact_layout,
+            diff_friendly,
+            sort_members,
+            std_int_types,
+            hash_suffixes,
+            deps,
+            lazy,
+            at_offset,
         } => {
-            let file = std::fs::File::open(&file)?;
-            let file = unsafe { memmap::Mmap::map(&file) }?;
-            let file = object::File::parse(&*file)?;
-            let btf = Btf::load(&file)?;
-            let filter = create_query_filter(query)?;
-
-            match format {
-                DumpFormat::Human => {
-                    if datasets.contains(Datasets::TYPES) {
-                        for (i, t) in btf.types().iter().enumerate() {
-                            if filter(i as u32, t) {
-                                println!("#{}: {}", i, t);
+            let mut mmaps = Vec::new();
+            for path in &files {
+                mmaps.push(load_file(path)?);
+            }
+            let merged;
+            let inputs: Vec<(Option<&std::path::Path>, &[u8])> = if merge && files.len() > 1 {
+                let btfs = load_btfs(&mmaps)?;
+                merged = btf_writer::encode_merged(&btfs.iter().collect::<Vec<_>>(), true)?;
+                vec![(None, &merged)]
+            } else {
+                files
+                    .iter()
+                    .map(|f| Some(f.as_path()))
+                    .zip(mmaps.iter().map(|m| &m[..]))
+                    .collect()
+            };
+            for (path, file) in inputs {
+                let path = match path {
+                    Some(path) if files.len() > 1 => {
+                        println!("{}:", path.display());
+                        Some(path)
+                    }
+                    path => path,
+                };
+                let base_btf = match (&base_btf, path) {
+                    (None, Some(path)) if !lazy => kernel_base_btf(path),
+                    (base_btf, _) => base_btf.clone(),
+                };
+                let base_file = match base_btf {
+                    Some(path) => Some(load_file(&path)?),
+                    None => None,
+                };
+                let base = match &base_file {
+                    Some(data) => Some(load_btf_as(data, ptr_sz)?),
+                    None => None,
+                };
+                // members of static archive are dumped as a single merged BTF
+                let merged;
+                let file: &[u8] = if is_archive(file) {
+                    merged = merge_archive(file)?;
+                    &merged
+                } else {
+                    file
+                };
+                if lazy {
+                    if !matches!(format, DumpFormat::Human) {
+                        return btf_error("--lazy supports only human-readable format".to_owned());
+                    }
+                    dump_lazy(&load_lazy_btf(file, ptr_sz)?, &query)?;
+                    continue;
+                }
+                let btf = match &base {
+                    _ if lenient => load_btf_lenient(file, base.as_ref(), ptr_sz)?,
+                    Some(base) => load_split_btf(file, base, ptr_sz)?,
+                    None => load_btf_as(file, ptr_sz)?,
+                };
+                if let Some(at_offset) = &at_offset {
+                    print_field_at_offset(&btf, at_offset)?;
+                    continue;
+                }
+                let mut filter = create_query_filter(query.clone(), &[&btf])?;
+                if with_deps {
+                    let roots = btf
+                        .types()
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, t)| *i > 0 && filter(*i as u32, t))
+                        .map(|(i, _)| i as u32)
+                        .collect::<Vec<u32>>();
+                    let keep = btf.type_closure(&roots, true);
+                    filter = Box::new(move |id: u32, _: &BtfType| keep[id as usize]);
+                }
+
+                match format {
+                    DumpFormat::Human => {
+                        let sec_regex = match &sec {
+                            Some(sec) => Some(Regex::new(sec)?),
+                            None => None,
+                        };
+                        let sec_match =
+                            |name: &str| sec_regex.as_ref().is_none_or(|r| r.is_match(name));
+                        if datasets.contains(Datasets::TYPES) {
+                            // for split BTF, base types are not dumped
+                            let skip = if btf.is_split() { btf.start_id() } else { 0 };
+                            for (i, t) in btf.types().iter().enumerate().skip(skip as usize) {
+                                if filter(i as u32, t) {
+                                    println!("#{}: {}", i, btf.type_display(t));
+                                    if let BtfType::DeclTag(tag) = t {
+                                        match btf.decl_tag_target(tag) {
+                                            Ok(target) => println!("\ttarget: {}", target),
+                                            Err(e) => println!("\ttarget: ERROR: {}", e),
+                                        }
+                                    }
+                                }
                             }
                         }
-                    }
-                    if datasets.contains(Datasets::FUNCINFOS) {
-                        for (i, sec) in btf.func_secs().iter().enumerate() {
-                            println!("\nFunc section #{} '{}':", i, sec.name);
-                            for (j, rec) in sec.recs.iter().enumerate() {
-                                println!("#{}: {}", j, rec);
+                        if datasets.contains(Datasets::FUNCINFOS) {
+                            for (i, sec) in btf.func_secs().iter().enumerate() {
+                                if !sec_match(sec.name) {
+                                    continue;
+                                }
+                                println!("\nFunc section #{} '{}':", i, sec.name);
+                                for (j, rec) in sec.recs.iter().enumerate() {
+                                    println!("#{}: {}", j, rec);
+                                }
                             }
                         }
-                    }
-                    if datasets.contains(Datasets::LINEINFOS) {
-                        for (i, sec) in btf.line_secs().iter().enumerate() {
-                            println!("\nLine section #{} '{}':", i, sec.name);
-                            for (j, rec) in sec.recs.iter().enumerate() {
-                                println!("#{}: {}", j, rec);
+                        if datasets.contains(Datasets::LINEINFOS) {
+                            for (i, sec) in btf.line_secs().iter().enumerate() {
+                                if !sec_match(sec.name) {
+                                    continue;
+                                }
+                                println!("\nLine section #{} '{}':", i, sec.name);
+                                for (j, rec) in sec.recs.iter().enumerate() {
+                                    println!("#{}: {}", j, rec);
+                                }
+                            }
+                        }
+                        if datasets.contains(Datasets::RELOCS) {
+                            for (i, sec) in btf.core_reloc_secs().iter().enumerate() {
+                                if !sec_match(sec.name) {
+                                    continue;
+                                }
+                                println!("\nCore reloc section #{} '{}':", i, sec.name);
+                                for (j, rec) in sec.recs.iter().enumerate() {
+                                    print!("#{}: {} --> ", j, rec);
+                                    std::io::stdout().flush()?;
+                                    match Relocator::pretty_print_access_spec(&btf, rec) {
+                                        Ok(s) => print!("{}", s),
+                                        Err(e) => print!(" ERROR: {}", e),
+                                    };
+                                    println!();
+                                }
                             }
                         }
+                        if datasets.contains(Datasets::STRINGS) {
+                            dump_strings(&btf);
+                        }
+                    }
+                    DumpFormat::Json | DumpFormat::JsonPretty => {
+                        return btf_error("JSON output is not yet supported!".to_owned())
                     }
-                    if datasets.contains(Datasets::RELOCS) {
-                        for (i, sec) in btf.core_reloc_secs().iter().enumerate() {
-                            println!("\nCore reloc section #{} '{}':", i, sec.name);
-                            for (j, rec) in sec.recs.iter().enumerate() {
-                                print!("#{}: {} --> ", j, rec);
-                                std::io::stdout().flush()?;
-                                match Relocator::pretty_print_access_spec(&btf, rec) {
-                                    Ok(s) => print!("{}", s),
-                                    Err(e) => print!(" ERROR: {}", e),
-                                };
-                                println!("");
+                    DumpFormat::C => {
+                        // options not exposed on command line yet keep their defaults
+                        #[allow(clippy::needless_update)]
+                        let cfg = c_dumper::CDumperCfg {
+                            verbose,
+                            union_as_struct,
+                            keep_going,
+                            emit_vars,
+                            emit_funcs,
+                            sort,
+                            emit_asserts,
+                            name_anon,
+                            zero_len_arrays,
+                            lang,
+                            fwd_decls_only,
+                            enums_as_macros,
+                            pack_style,
+                            indent,
+                            brace_on_new_line,
+                            exact_layout,
+                            diff_friendly,
+                            sort_members,
+                            std_int_types,
+                            hash_suffixes,
+                            weak_externs: weak_externs(file),
+                            exclude_names: RegexSet::new(&exclude_names)?,
+                            include_names: if include_only.is_empty() {
+                                None
+                            } else {
+                                Some(RegexSet::new(&include_only)?)
+                            },
+                            deps,
+                            ..Default::default()
+                        };
+                        let prologue = match &prologue {
+                            Some(path) => std::fs::read(path)?,
+                            None => Vec::new(),
+                        };
+                        let epilogue = match &epilogue {
+                            Some(path) => std::fs::read(path)?,
+                            None => Vec::new(),
+                        };
+                        let mut dumper = c_dumper::CDumper::new(&btf, cfg)?;
+                        let stdout = std::io::stdout();
+                        let mut out = std::io::BufWriter::new(stdout.lock());
+                        out.write_all(&prologue)?;
+                        if lang == c_dumper::Lang::Cpp {
+                            writeln!(out, "extern \"C\" {{\n")?;
+                        }
+                        let res = dumper.dump_types(filter, &mut out);
+                        if matches!(&res, Ok(failures) if failures.is_empty()) {
+                            if lang == c_dumper::Lang::Cpp {
+                                writeln!(out, "}} /* extern \"C\" */")?;
+                            }
+                            out.write_all(&epilogue)?;
+                        }
+                        out.flush()?;
+                        let failures = res?;
+                        if !failures.is_empty() {
+                            eprintln!("Failed to dump {} type(s):", failures.len());
+                            for (id, e) in &failures {
+                                eprintln!(
+                                    "\t[{}] {}: {}",
+                                    id,
+                                    btf.type_display(btf.type_by_id(*id)),
+                                    e
+                                );
                             }
+                            return btf_error(format!("Failed to dump {} type(s)", failures.len()));
                         }
                     }
                 }
-                DumpFormat::Json => panic!("JSON output is not yet supported!"),
-                DumpFormat::JsonPretty => panic!("JSON output is not yet supported!"),
-                DumpFormat::C => {
-                    let cfg = c_dumper::CDumperCfg {
-                        verbose: verbose,
-                        union_as_struct: union_as_struct,
-                    };
-                    let mut dumper = c_dumper::CDumper::new(&btf, cfg);
-                    dumper.dump_types(filter)?;
-                }
             }
         }
         Cmd::Reloc {
             targ_file,
             local_file,
+            modules,
             verbose,
+            jobs,
+            cache,
+            apply,
+            poison,
+            output,
         } => {
-            let local_file = std::fs::File::open(&local_file)?;
-            let local_mmap = unsafe { memmap::Mmap::map(&local_file) }?;
+            let local_mmap = load_file(&local_file)?;
             let local_elf = object::File::parse(&*local_mmap)?;
             let local_btf = Btf::load(&local_elf)?;
             if !local_btf.has_ext() {
@@ -266,23 +931,455 @@ fn main() -> Result<(), Box<dyn Error>> {
                     BTF_EXT_ELF_SEC
                 ));
             }
-            let targ_file = std::fs::File::open(&targ_file)?;
-            let targ_mmap = unsafe { memmap::Mmap::map(&targ_file) }?;
-            let targ_elf = object::File::parse(&*targ_mmap)?;
-            let targ_btf = Btf::load(&targ_elf)?;
-            let cfg = RelocatorCfg { verbose: verbose };
+            let targ_path = targ_file;
+            let targ_mmap = load_file(&targ_path)?;
+            let targ_btf = load_btf(&targ_mmap)?;
+            let mod_paths = find_module_btf_files(&modules)?;
+            let mut mod_mmaps = Vec::new();
+            for path in &mod_paths {
+                mod_mmaps.push(load_file(path)?);
+            }
+            let mut mod_btfs = Vec::new();
+            for (path, mmap) in mod_paths.iter().zip(&mod_mmaps) {
+                match load_split_btf(mmap, &targ_btf, None) {
+                    Ok(btf) => mod_btfs.push(btf),
+                    Err(e) => {
+                        return btf_error(format!(
+                            "Failed to load module BTF '{}': {}",
+                            path.display(),
+                            e
+                        ))
+                    }
+                }
+            }
+            let mut cfg = RelocatorCfg::new().verbose(verbose);
+            if let Some(jobs) = jobs {
+                cfg = cfg.jobs(jobs);
+            }
             let mut relocator = Relocator::new(&targ_btf, &local_btf, cfg);
-            let relocs = relocator.relocate()?;
-            for r in relocs {
-                println!("{}", r);
+            for btf in &mod_btfs {
+                relocator.add_module_btf(btf);
+            }
+            if let Some(cache) = &cache {
+                relocator.load_cache(cache)?;
+            }
+            let results = relocator.relocate();
+            if let Some(cache) = &cache {
+                relocator.save_cache(cache)?;
+            }
+            for line in relocator.log() {
+                println!("{}", line);
+            }
+            let targ_paths = std::iter::once(&targ_path)
+                .chain(&mod_paths)
+                .collect::<Vec<_>>();
+            let total = results.len();
+            let rec_ids = local_btf
+                .core_reloc_secs()
+                .iter()
+                .enumerate()
+                .flat_map(|(sec_id, sec)| (0..sec.recs.len()).map(move |id| (sec_id, id)));
+            let mut relocs = Vec::new();
+            let mut failed = Vec::new();
+            for (res, rec_id) in results.into_iter().zip(rec_ids) {
+                match res {
+                    Ok(r) => {
+                        if mod_paths.is_empty() {
+                            println!("{}", r);
+                        } else {
+                            println!("{} ({})", r, targ_paths[r.targ_btf_idx].display());
+                        }
+                        relocs.push(r);
+                    }
+                    Err(e) => {
+                        println!("sec#{}, r#{}: {}", rec_id.0, rec_id.1, e);
+                        failed.push(rec_id);
+                    }
+                }
+            }
+            if !failed.is_empty() && !poison {
+                return btf_error(format!("{} of {} relocations failed", failed.len(), total));
+            }
+            if apply {
+                let targ_btfs = std::iter::once(&targ_btf)
+                    .chain(&mod_btfs)
+                    .collect::<Vec<_>>();
+                let data =
+                    apply_core_relocs(&local_mmap, &local_btf, &targ_btfs, &relocs, &failed)?;
+                std::fs::write(output.unwrap(), data)?;
+                if !failed.is_empty() {
+                    println!(
+                        "{} of {} relocations failed, their instructions are poisoned",
+                        failed.len(),
+                        total
+                    );
+                }
+            }
+        }
+        Cmd::Strip {
+            file,
+            query,
+            output,
+        } => {
+            if query.is_empty() {
+                return btf_error("At least one type filter has to be specified".to_owned());
+            }
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            let filter = create_query_filter(query, &[&btf])?;
+            let keep = btf
+                .types()
+                .iter()
+                .enumerate()
+                .map(|(i, t)| i == 0 || !filter(i as u32, t))
+                .collect::<Vec<bool>>();
+            let data = btf_writer::encode_subset(&btf, &keep)?;
+            std::fs::write(&output, data)?;
+        }
+        Cmd::Extract {
+            file,
+            query,
+            output,
+            shallow,
+        } => {
+            if query.is_empty() {
+                return btf_error("At least one type filter has to be specified".to_owned());
+            }
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            let filter = create_query_filter(query, &[&btf])?;
+            let roots = btf
+                .types()
+                .iter()
+                .enumerate()
+                .filter(|(i, t)| *i > 0 && filter(*i as u32, t))
+                .map(|(i, _)| i as u32)
+                .collect::<Vec<u32>>();
+            let keep = btf.type_closure(&roots, !shallow);
+            let data = btf_writer::encode_subset(&btf, &keep)?;
+            std::fs::write(&output, data)?;
+        }
+        Cmd::ElfRelocs { file } => {
+            let file = load_file(&file)?;
+            let elf = object::File::parse(&*file)?;
+            let btf = Btf::load(&elf)?;
+            dump_elf_relocs(&elf, &btf)?;
+        }
+        Cmd::MultiHeader {
+            kernels,
+            query,
+            union_as_struct,
+        } => {
+            let mut mmaps = Vec::new();
+            let mut versions = Vec::new();
+            for k in &kernels {
+                let (version, path) = match k.split_once('=') {
+                    Some((v, p)) => (KernelVersion::from_str(v)?, p),
+                    None => return btf_error(format!("expected <version>=<file>, got: '{}'", k)),
+                };
+                mmaps.push(load_file(path)?);
+                versions.push(version);
+            }
+            let btfs = mmaps
+                .iter()
+                .map(|m| load_btf(m))
+                .collect::<BtfResult<Vec<Btf>>>()?;
+            let mut targets = versions
+                .into_iter()
+                .zip(btfs.iter())
+                .map(|(version, btf)| VersionedBtf { version, btf })
+                .collect::<Vec<VersionedBtf>>();
+            let filter = create_query_filter(query, &btfs.iter().collect::<Vec<&Btf>>())?;
+            let header = generate_multi_version_header(&mut targets, &filter, union_as_struct)?;
+            print!("{}", header);
+        }
+        Cmd::Merge {
+            files,
+            output,
+            dedup,
+        } => {
+            let mut mmaps = Vec::new();
+            for f in &files {
+                mmaps.push(load_file(f)?);
+            }
+            let btfs = load_btfs(&mmaps)?;
+            let btfs = btfs.iter().collect::<Vec<&Btf>>();
+            let data = btf_writer::encode_merged(&btfs, dedup)?;
+            std::fs::write(&output, data)?;
+        }
+        Cmd::Sanitize { file, output } => {
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            let data = btf_writer::encode_sanitized(&btf)?;
+            std::fs::write(&output, data)?;
+        }
+        Cmd::DiffType {
+            old_file,
+            new_file,
+            type_name,
+            ptr_sz,
+        } => {
+            let old_mmap = load_file(&old_file)?;
+            let old_btf = load_btf_as(&old_mmap, ptr_sz)?;
+            let new_mmap = load_file(&new_file)?;
+            let new_btf = load_btf_as(&new_mmap, ptr_sz)?;
+            diff_type(&old_btf, &new_btf, &type_name)?;
+        }
+        Cmd::Patch {
+            file,
+            renames,
+            retypes,
+            resizes,
+            output,
+        } => {
+            let mut patches = Vec::new();
+            for s in &renames {
+                patches.push(BtfPatch::parse_rename(s)?);
+            }
+            for s in &retypes {
+                patches.push(BtfPatch::parse_retype(s)?);
+            }
+            for s in &resizes {
+                patches.push(BtfPatch::parse_resize(s)?);
+            }
+            if patches.is_empty() {
+                return btf_error("At least one patch has to be specified".to_owned());
             }
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            let data = encode_patched(&btf, &patches)?;
+            std::fs::write(&output, data)?;
+        }
+        Cmd::Convert { file, output, ext } => {
+            let file = load_file(&file)?;
+            match Btf::raw_endian(&file) {
+                Some(endian) => {
+                    let ext_data = match &ext {
+                        Some(path) => Some(load_file(path)?),
+                        None => None,
+                    };
+                    let elf = write_btf_elf(&file, ext_data.as_deref(), endian)?;
+                    std::fs::write(&output, elf)?;
+                }
+                None => {
+                    let elf = object::File::parse(&*file)?;
+                    let btf_sec = match elf.section_by_name(BTF_ELF_SEC) {
+                        Some(sec) => sec,
+                        None => return btf_error(format!("No {} section found", BTF_ELF_SEC)),
+                    };
+                    std::fs::write(&output, btf_sec.data()?)?;
+                    if let Some(path) = ext {
+                        match elf.section_by_name(BTF_EXT_ELF_SEC) {
+                            Some(sec) => std::fs::write(path, sec.data()?)?,
+                            None => {
+                                return btf_error(format!("No {} section found", BTF_EXT_ELF_SEC))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Cmd::Dedup {
+            file,
+            output,
+            verify_against,
+        } => {
+            if output.is_none() && verify_against.is_none() {
+                return btf_error("Either output or reference BTF has to be specified".to_owned());
+            }
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            let data = btf_writer::encode_merged(&[&btf], true)?;
+            if let Some(output) = output {
+                std::fs::write(&output, &data)?;
+            }
+            if let Some(ref_path) = verify_against {
+                let deduped = Btf::load_raw(&data)?;
+                let ref_mmap = load_file(&ref_path)?;
+                let ref_btf = load_btf(&ref_mmap)?;
+                verify_dedup(&deduped, &ref_btf)?;
+            }
+        }
+        Cmd::Relocate {
+            file,
+            base_btf,
+            output,
+        } => {
+            let base_mmap = load_file(&base_btf)?;
+            let base = load_btf(&base_mmap)?;
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            let data = relocate_split(&btf, &base)?;
+            std::fs::write(&output, data)?;
         }
         Cmd::Stat { file } => {
-            let file = std::fs::File::open(&file)?;
-            let file = unsafe { memmap::Mmap::map(&file) }?;
+            let file = load_file(&file)?;
             let file = object::File::parse(&*file)?;
             stat_btf(&file)?;
         }
+        Cmd::Check { file } => {
+            let file = load_file(&file)?;
+            let elf = object::File::parse(&*file)?;
+            let btf = Btf::load(&elf)?;
+            check_btf(&elf, &btf)?;
+        }
+        Cmd::Preflight { file, kernel_btf } => {
+            let local_mmap = load_file(&file)?;
+            let local_elf = object::File::parse(&*local_mmap)?;
+            let local_btf = Btf::load(&local_elf)?;
+            let targ_mmap = load_file(&kernel_btf)?;
+            let targ_btf = load_btf(&targ_mmap)?;
+            preflight(&local_btf, &targ_btf)?;
+        }
+        Cmd::MinCoreBtf {
+            files,
+            kernel_btf,
+            output,
+        } => {
+            let targ_mmap = load_file(&kernel_btf)?;
+            let targ_btf = load_btf(&targ_mmap)?;
+            let mmaps = files.iter().map(load_file).collect::<BtfResult<Vec<_>>>()?;
+            let local_btfs = load_bpf_objects(&mmaps)?;
+            std::fs::write(&output, min_core_btf(&targ_btf, &local_btfs)?)?;
+        }
+        Cmd::Btfhub {
+            dir,
+            files,
+            output,
+            jobs,
+        } => {
+            let mmaps = files.iter().map(load_file).collect::<BtfResult<Vec<_>>>()?;
+            let local_btfs = load_bpf_objects(&mmaps)?;
+            btfhub_tailor(&dir, &output, &local_btfs, jobs)?;
+        }
+        Cmd::RelocMatrix { file, dir, jobs } => {
+            let mmap = load_file(&file)?;
+            let elf = object::File::parse(&*mmap)?;
+            let local_btf = Btf::load(&elf)?;
+            if !local_btf.has_ext() {
+                return btf_error(format!(
+                    "No {} section found for local ELF file, can't perform relocations.",
+                    BTF_EXT_ELF_SEC
+                ));
+            }
+            reloc_matrix(&local_btf, &dir, jobs)?;
+        }
+        Cmd::VerifyCore {
+            file,
+            kernels,
+            jobs,
+        } => {
+            let mmap = load_file(&file)?;
+            let elf = object::File::parse(&*mmap)?;
+            let local_btf = Btf::load(&elf)?;
+            if !local_btf.has_ext() {
+                return btf_error(format!(
+                    "No {} section found for local ELF file, can't perform relocations.",
+                    BTF_EXT_ELF_SEC
+                ));
+            }
+            verify_core(&local_btf, &kernels, jobs)?;
+        }
+        Cmd::Unused { file, named_roots } => {
+            let file = load_file(&file)?;
+            let btf = load_btf(&file)?;
+            unused_btf(&btf, named_roots);
+        }
+        Cmd::Ids {
+            file,
+            types,
+            base_btf,
+            ptr_sz,
+            kinds,
+        } => {
+            let base_btf = base_btf.or_else(|| kernel_base_btf(&file));
+            let base_file = match base_btf {
+                Some(path) => Some(load_file(&path)?),
+                None => None,
+            };
+            let base = match &base_file {
+                Some(data) => Some(load_btf_as(data, ptr_sz)?),
+                None => None,
+            };
+            let file = load_file(&file)?;
+            match &base {
+                Some(base) => {
+                    let btf = load_split_btf(&file, base, ptr_sz)?;
+                    let index = BtfIndex::new(&btf);
+                    print_type_ids(
+                        &types,
+                        &kinds,
+                        btf.type_cnt(),
+                        |name| Ok(index.get_by_name(name).to_vec()),
+                        |id| {
+                            let t = btf.type_by_id(id);
+                            Ok((t.kind(), t.name(), btf.get_size_of(id)))
+                        },
+                    )?;
+                }
+                // without base, only looked up types need to be decoded
+                None => {
+                    let btf = load_lazy_btf(&file, ptr_sz)?;
+                    print_type_ids(
+                        &types,
+                        &kinds,
+                        btf.type_cnt(),
+                        |name| btf.find_by_name(name),
+                        |id| Ok((btf.type_kind(id)?, btf.type_name(id)?, btf.get_size_of(id)?)),
+                    )?;
+                }
+            }
+        }
+        #[cfg(all(target_os = "linux", feature = "bpf-syscall"))]
+        Cmd::KernelBtf { id, output } => match (id, output) {
+            (Some(id), Some(output)) => {
+                let kbtf = btf::btf_kernel::kernel_btf_by_id(id)?;
+                std::fs::write(&output, &kbtf.data)?;
+            }
+            (Some(id), None) => {
+                let kbtf = btf::btf_kernel::kernel_btf_by_id(id)?;
+                let vmlinux = if kbtf.is_module() {
+                    let mut vmlinux = None;
+                    for id in btf::btf_kernel::kernel_btf_ids()? {
+                        let b = btf::btf_kernel::kernel_btf_by_id(id)?;
+                        if b.kernel_btf && b.name == "vmlinux" {
+                            vmlinux = Some(b);
+                            break;
+                        }
+                    }
+                    match vmlinux {
+                        Some(vmlinux) => Some(vmlinux),
+                        None => return btf_error("No vmlinux BTF object found".to_owned()),
+                    }
+                } else {
+                    None
+                };
+                let base = match &vmlinux {
+                    Some(vmlinux) => Some(load_btf(&vmlinux.data)?),
+                    None => None,
+                };
+                let btf = match &base {
+                    Some(base) => load_split_btf(&kbtf.data, base, None)?,
+                    None => load_btf(&kbtf.data)?,
+                };
+                let skip = if btf.is_split() { btf.start_id() } else { 0 };
+                for (i, t) in btf.types().iter().enumerate().skip(skip as usize) {
+                    println!("#{}: {}", i, btf.type_display(t));
+                }
+            }
+            _ => {
+                for id in btf::btf_kernel::kernel_btf_ids()? {
+                    let kbtf = btf::btf_kernel::kernel_btf_by_id(id)?;
+                    println!(
+                        "#{}: name: '{}', size: {}{}",
+                        kbtf.id,
+                        kbtf.name,
+                        kbtf.data.len(),
+                        if kbtf.kernel_btf { ", kernel" } else { "" }
+                    );
+                }
+            }
+        },
         Cmd::Version => {
             println!("btfdump v{}", VERSION);
         }
@@ -290,8 +1387,141 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn create_query_filter(q: QueryArgs) -> BtfResult<Box<dyn Fn(u32, &BtfType) -> bool>> {
-    let mut filters: Vec<Box<dyn Fn(u32, &BtfType) -> bool>> = Vec::new();
+/// Load BTF either from ELF file's .BTF/.BTF.ext sections or from raw BTF data.
+fn load_btf(data: &[u8]) -> BtfResult<Btf<'_>> {
+    load_btf_as(data, None)
+}
+
+/// Same as load_btf(), but with optionally overridden target's pointer size.
+fn load_btf_as(data: &[u8], ptr_sz: Option<u32>) -> BtfResult<Btf<'_>> {
+    let mut btf = if Btf::raw_endian(data).is_some() {
+        Btf::load_raw_unvalidated(data, None)?
+    } else {
+        Btf::load_unvalidated(&object::File::parse(data)?, None)?
+    };
+    if let Some(ptr_sz) = ptr_sz {
+        btf.set_ptr_sz(ptr_sz);
+    }
+    btf.validate()?;
+    Ok(btf)
+}
+
+fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(&object::archive::MAGIC)
+}
+
+/// Load BTFs of all members of static archive (.a) that have .BTF section.
+fn load_archive_btfs(data: &[u8]) -> BtfResult<Vec<Btf<'_>>> {
+    let archive = object::read::archive::ArchiveFile::parse(data)?;
+    let mut btfs = Vec::new();
+    for member in archive.members() {
+        let elf = object::File::parse(member?.data(data)?)?;
+        if elf.section_by_name(BTF_ELF_SEC).is_some() {
+            btfs.push(Btf::load(&elf)?);
+        }
+    }
+    Ok(btfs)
+}
+
+/// Load BTFs of multiple BTF or ELF files, expanding static archives into their members' BTFs.
+fn load_btfs(mmaps: &[FileData]) -> BtfResult<Vec<Btf<'_>>> {
+    let mut btfs = Vec::new();
+    for m in mmaps {
+        if is_archive(m) {
+            btfs.append(&mut load_archive_btfs(m)?);
+        } else {
+            btfs.push(load_btf(m)?);
+        }
+    }
+    Ok(btfs)
+}
+
+/// Merge BTFs of static archive members into a single raw BTF, deduplicating types they share.
+fn merge_archive(data: &[u8]) -> BtfResult<Vec<u8>> {
+    let btfs = load_archive_btfs(data)?;
+    if btfs.is_empty() {
+        return btf_error(format!(
+            "No archive members with {} section found",
+            BTF_ELF_SEC
+        ));
+    }
+    let btfs = btfs.iter().collect::<Vec<&Btf>>();
+    btf_writer::encode_merged(&btfs, true)
+}
+
+fn load_split_btf<'a>(data: &'a [u8], base: &Btf<'a>, ptr_sz: Option<u32>) -> BtfResult<Btf<'a>> {
+    let mut btf = if Btf::raw_endian(data).is_some() {
+        Btf::load_raw_unvalidated(data, Some(base))?
+    } else {
+        Btf::load_unvalidated(&object::File::parse(data)?, Some(base))?
+    };
+    if let Some(ptr_sz) = ptr_sz {
+        btf.set_ptr_sz(ptr_sz);
+    }
+    btf.validate()?;
+    Ok(btf)
+}
+
+/// Load (possibly split) BTF, replacing dangling type references with placeholders (with a
+/// warning for each), instead of rejecting it. Reference cycles are still rejected.
+fn load_btf_lenient<'a>(
+    data: &'a [u8],
+    base: Option<&Btf<'a>>,
+    ptr_sz: Option<u32>,
+) -> BtfResult<Btf<'a>> {
+    let mut btf = if Btf::raw_endian(data).is_some() {
+        Btf::load_raw_unvalidated(data, base)?
+    } else {
+        Btf::load_unvalidated(&object::File::parse(data)?, base)?
+    };
+    if let Some(ptr_sz) = ptr_sz {
+        btf.set_ptr_sz(ptr_sz);
+    }
+    for (id, invalid_id) in btf.replace_dangling_refs() {
+        let t = btf.type_by_id(id);
+        eprintln!(
+            "WARNING: [{}] {:?} '{}' refers to invalid type {}, replaced with placeholder",
+            id,
+            t.kind(),
+            t.name(),
+            invalid_id
+        );
+    }
+    btf.validate_types()?;
+    Ok(btf)
+}
+
+/// Names of weak undefined symbols of ELF file (e.g., optional ksyms/kconfig externs of BPF
+/// object); raw BTF doesn't record weakness.
+fn weak_externs(data: &[u8]) -> HashSet<String> {
+    if Btf::raw_endian(data).is_some() {
+        return HashSet::new();
+    }
+    let elf = match object::File::parse(data) {
+        Ok(elf) => elf,
+        Err(_) => return HashSet::new(),
+    };
+    elf.symbols()
+        .filter(|sym| sym.is_weak() && sym.is_undefined())
+        .filter_map(|sym| sym.name().ok().map(|name| name.to_string()))
+        .collect()
+}
+
+/// Whether type has a size, that size filters apply to: funcs, vars, fwds and void don't.
+fn has_size(t: &BtfType) -> bool {
+    !matches!(
+        t,
+        BtfType::Void
+            | BtfType::Fwd(_)
+            | BtfType::Func(_)
+            | BtfType::FuncProto(_)
+            | BtfType::Var(_)
+            | BtfType::DeclTag(_)
+    )
+}
+
+fn create_query_filter(q: QueryArgs, btfs: &[&Btf]) -> BtfResult<QueryFilter> {
+    let mut filters: Vec<QueryFilter> = Vec::new();
     if !q.kinds.is_empty() {
         let kinds = q.kinds;
         filters.push(Box::new(move |_id: u32, bt: &BtfType| -> bool {
@@ -305,24 +1535,297 @@ fn create_query_filter(q: QueryArgs) -> BtfResult<Box<dyn Fn(u32, &BtfType) -> b
         }));
     }
     if let Some(name) = q.name {
-        let name_regex = Regex::new(&name)?;
+        let name_regex = RegexBuilder::new(&name)
+            .case_insensitive(q.ignore_case)
+            .build()?;
         filters.push(Box::new(move |_id: u32, bt: &BtfType| -> bool {
             name_regex.is_match(bt.name())
         }));
     }
-    if !filters.is_empty() {
-        Ok(Box::new(move |id: u32, bt: &BtfType| -> bool {
-            for f in &filters {
-                if f(id, bt) {
-                    return true;
-                }
+    if let Some(substr) = q.name_contains {
+        if q.fuzzy {
+            let pattern = substr.to_lowercase();
+            filters.push(Box::new(move |_id: u32, bt: &BtfType| -> bool {
+                is_fuzzy_match(bt.name(), &pattern)
+            }));
+        } else {
+            filters.push(Box::new(move |_id: u32, bt: &BtfType| -> bool {
+                bt.name().contains(&substr)
+            }));
+        }
+    }
+    if let Some(tag) = q.tag {
+        // tagged types are matched by kind and name, as filter can be applied to multiple BTFs
+        let mut tagged: HashMap<String, HashSet<BtfKind>> = HashMap::new();
+        for btf in btfs {
+            for id in btf.tagged_types(&tag) {
+                let t = btf.type_by_id(id);
+                tagged
+                    .entry(t.name().to_owned())
+                    .or_default()
+                    .insert(t.kind());
             }
-            return false;
-        }))
-    } else {
-        Ok(Box::new(|_: u32, _: &BtfType| true))
+        }
+        filters.push(Box::new(move |_id: u32, bt: &BtfType| -> bool {
+            tagged
+                .get(bt.name())
+                .is_some_and(|kinds| kinds.contains(&bt.kind()))
+        }));
     }
-}
+    if let Some(name) = q.referenced_by {
+        filters.push(type_set_filter(btfs, |btf| {
+            let index = BtfIndex::new(btf);
+            let refs = BtfRefIndex::new(btf);
+            index
+                .get_by_name(&name)
+                .iter()
+                .flat_map(|&id| refs.get_referrers(id).iter().cloned())
+                .collect()
+        }));
+    }
+    if let Some(name) = q.member_name {
+        let name_regex = Regex::new(&name)?;
+        filters.push(type_set_filter(btfs, |btf| {
+            (0..btf.types().len() as u32)
+                .filter(|&id| has_member(btf, id, &name_regex, 0))
+                .collect()
+        }));
+    }
+    if let Some(shape) = q.shape {
+        let shape = Shape::parse(&shape)?;
+        filters.push(type_set_filter(btfs, |btf| {
+            (0..btf.types().len() as u32)
+                .filter(|&id| shape.matches(btf, id))
+                .collect()
+        }));
+    }
+    if let Some(expr) = q.where_expr {
+        let query = Query::parse(&expr)?;
+        filters.push(type_set_filter(btfs, |btf| {
+            (0..btf.types().len() as u32)
+                .filter(|&id| query.matches(btf, id))
+                .collect()
+        }));
+    }
+    if q.min_size.is_some() || q.max_size.is_some() {
+        let min_size = q.min_size.unwrap_or(0);
+        let max_size = q.max_size.unwrap_or(u32::MAX);
+        filters.push(type_set_filter(btfs, |btf| {
+            (0..btf.types().len() as u32)
+                .filter(|&id| has_size(btf.type_by_id(id)))
+                .filter(|&id| (min_size..=max_size).contains(&btf.get_size_of(id)))
+                .collect()
+        }));
+    }
+    if q.match_any {
+        Ok(Box::new(move |id: u32, bt: &BtfType| -> bool {
+            filters.is_empty() || filters.iter().any(|f| f(id, bt))
+        }))
+    } else {
+        Ok(Box::new(move |id: u32, bt: &BtfType| -> bool {
+            filters.iter().all(|f| f(id, bt))
+        }))
+    }
+}
+
+/// Print field covering byte offset, given as `<type>:<byte>`, along with its offset and size.
+fn print_field_at_offset(btf: &Btf, spec: &str) -> BtfResult<()> {
+    let (root, off) = match spec.rsplit_once(':') {
+        Some(parts) => parts,
+        None => return btf_error(format!("Expected <type>:<byte>, got '{}'", spec)),
+    };
+    let off = off.trim();
+    let off = match off.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => off.parse::<u32>(),
+    };
+    let off = match off {
+        Ok(off) => off,
+        Err(e) => return btf_error(format!("Invalid offset in '{}': {}", spec, e)),
+    };
+    let res = btf.field_at_offset(root, off)?;
+    let f = res.field;
+    let t = btf.type_by_id(f.type_id);
+    let mut line = format!("{} + {}", res.path, off - f.byte_offset());
+    if res.padding {
+        line.push_str(" (padding)");
+    }
+    if f.bit_size != 0 {
+        line.push_str(&format!(
+            ": bit_off {}, bit_sz {}",
+            f.bit_offset, f.bit_size
+        ));
+    } else {
+        line.push_str(&format!(": off {}, sz {}", f.byte_offset(), f.size));
+    }
+    println!("{} --> [{}] {:?} '{}'", line, f.type_id, t.kind(), t.name());
+    Ok(())
+}
+
+/// Print ID, kind, name and size of each type with given name or ID, one type per line.
+fn print_type_ids<'b, F, D>(
+    types: &[String],
+    kinds: &[BtfKind],
+    type_cnt: u32,
+    find_by_name: F,
+    describe: D,
+) -> BtfResult<()>
+where
+    F: Fn(&str) -> BtfResult<Vec<u32>>,
+    D: Fn(u32) -> BtfResult<(BtfKind, &'b str, u32)>,
+{
+    let mut missing = 0;
+    for spec in types {
+        let ids = match spec.parse::<u32>() {
+            Ok(id) if id < type_cnt => vec![id],
+            Ok(_) => Vec::new(),
+            Err(_) => {
+                let mut ids = Vec::new();
+                for id in find_by_name(spec)? {
+                    if kinds.is_empty() || kinds.contains(&describe(id)?.0) {
+                        ids.push(id);
+                    }
+                }
+                ids
+            }
+        };
+        if ids.is_empty() {
+            eprintln!("'{}': not found", spec);
+            missing += 1;
+        }
+        for id in ids {
+            let (kind, name, sz) = describe(id)?;
+            println!("[{}] {:?} '{}' size:{}", id, kind, name, sz);
+        }
+    }
+    if missing > 0 {
+        return btf_error(format!("{} of {} type(s) not found", missing, types.len()));
+    }
+    Ok(())
+}
+
+/// Dump types matching name, kind and ID filters, decoding only types that match.
+fn dump_lazy(btf: &LazyBtf, q: &QueryArgs) -> BtfResult<()> {
+    if q.tag.is_some()
+        || q.referenced_by.is_some()
+        || q.member_name.is_some()
+        || q.shape.is_some()
+        || q.where_expr.is_some()
+        || q.min_size.is_some()
+        || q.max_size.is_some()
+    {
+        return btf_error(
+            "--lazy supports only --name, --name-contains, --type and --id filters".to_owned(),
+        );
+    }
+    let name_regex = match &q.name {
+        Some(name) => Some(
+            RegexBuilder::new(name)
+                .case_insensitive(q.ignore_case)
+                .build()?,
+        ),
+        None => None,
+    };
+    let fuzzy_pattern = q.name_contains.as_ref().map(|s| s.to_lowercase());
+    for id in 0..btf.type_cnt() {
+        let mut matches = Vec::new();
+        if !q.kinds.is_empty() {
+            matches.push(q.kinds.contains(&btf.type_kind(id)?));
+        }
+        if !q.ids.is_empty() {
+            matches.push(q.ids.contains(&id));
+        }
+        if let Some(name_regex) = &name_regex {
+            matches.push(name_regex.is_match(btf.type_name(id)?));
+        }
+        if let Some(substr) = &q.name_contains {
+            let name = btf.type_name(id)?;
+            matches.push(match &fuzzy_pattern {
+                Some(pattern) if q.fuzzy => is_fuzzy_match(name, pattern),
+                _ => name.contains(substr.as_str()),
+            });
+        }
+        let matched = if q.match_any {
+            matches.is_empty() || matches.iter().any(|&m| m)
+        } else {
+            matches.iter().all(|&m| m)
+        };
+        if matched {
+            let (t, arena) = btf.try_type_with_arena(id)?;
+            println!("#{}: {}", id, arena.display(t));
+        }
+    }
+    Ok(())
+}
+
+/// Load BTF for decoding types on demand, either from ELF file's .BTF section or raw BTF data.
+fn load_lazy_btf(data: &[u8], ptr_sz: Option<u32>) -> BtfResult<LazyBtf<'_>> {
+    let mut btf = if Btf::raw_endian(data).is_some() {
+        LazyBtf::load_raw(data)?
+    } else {
+        LazyBtf::load(&object::File::parse(data)?)?
+    };
+    if let Some(ptr_sz) = ptr_sz {
+        btf.set_ptr_sz(ptr_sz);
+    }
+    Ok(btf)
+}
+
+/// Whether all characters of lowercase pattern appear in name in the same order, ignoring case.
+fn is_fuzzy_match(name: &str, pattern: &str) -> bool {
+    let mut name_chars = name.chars().flat_map(char::to_lowercase);
+    pattern.chars().all(|p| name_chars.any(|c| c == p))
+}
+
+type QueryFilter = Box<dyn Fn(u32, &BtfType) -> bool>;
+
+/// Filter matching types computed for each BTF. With multiple BTFs, type IDs differ between them,
+/// so types are matched by kind and name, and anonymous ones can't be matched.
+fn type_set_filter<F>(btfs: &[&Btf], matching_ids: F) -> QueryFilter
+where
+    F: Fn(&Btf) -> Vec<u32>,
+{
+    let single = btfs.len() == 1;
+    let mut ids = HashSet::new();
+    let mut named: HashMap<String, HashSet<BtfKind>> = HashMap::new();
+    for btf in btfs {
+        for id in matching_ids(btf) {
+            let t = btf.type_by_id(id);
+            if single {
+                ids.insert(id);
+            } else if !t.name().is_empty() {
+                named
+                    .entry(t.name().to_owned())
+                    .or_default()
+                    .insert(t.kind());
+            }
+        }
+    }
+    Box::new(move |id: u32, bt: &BtfType| -> bool {
+        ids.contains(&id)
+            || named
+                .get(bt.name())
+                .is_some_and(|kinds| kinds.contains(&bt.kind()))
+    })
+}
+
+/// Whether struct/union has a member with matching name, including members of embedded
+/// anonymous structs/unions, which are accessible as fields of outer type in C.
+fn has_member(btf: &Btf, type_id: u32, name_regex: &Regex, depth: usize) -> bool {
+    let members = match btf.type_by_id(type_id) {
+        BtfType::Struct(t) | BtfType::Union(t) => btf.members(t),
+        _ => return false,
+    };
+    members.iter().any(|m| {
+        if !m.name.is_empty() {
+            return name_regex.is_match(m.name);
+        }
+        depth < MAX_ANON_MEMBER_DEPTH
+            && has_member(btf, btf.skip_mods(m.type_id), name_regex, depth + 1)
+    })
+}
+
+const MAX_ANON_MEMBER_DEPTH: usize = 32;
 
 fn stat_btf(elf: &object::File) -> BtfResult<()> {
     let endian = if elf.is_little_endian() {
@@ -363,11 +1866,28 @@ fn stat_btf(elf: &object::File) -> BtfResult<()> {
     } else {
         println!("{} not found.", BTF_EXT_ELF_SEC);
     }
+    println!(
+        "\n{} ELF section\n========================================",
+        BTF_BASE_ELF_SEC
+    );
+    if let Some(base_section) = elf.section_by_name(BTF_BASE_ELF_SEC) {
+        let base_data = base_section.data()?;
+        let base_hdr = base_data.pread_with::<btf_header>(0, endian)?;
+        println!("Data size:\t{}", base_data.len());
+        println!("Header size:\t{}", base_hdr.hdr_len);
+        println!("Types size:\t{}", base_hdr.type_len);
+        println!("Strings size:\t{}", base_hdr.str_len);
+    } else {
+        println!("{} not found.", BTF_BASE_ELF_SEC);
+    }
     match Btf::load(elf) {
         Err(e) => println!("Failed to parse BTF data: {}", e),
         Ok(btf) => {
+            if let Some(base) = btf.distilled_base() {
+                println!("Base types:\t{}", base.type_cnt() - 1);
+            }
             let mut type_stats: HashMap<BtfKind, (usize, usize)> = HashMap::new();
-            for t in &btf.types()[1..] {
+            for t in &btf.types()[btf.start_id() as usize..] {
                 let (cnt, sz) = type_stats.entry(t.kind()).or_insert((0, 0));
                 *cnt += 1;
                 *sz += Btf::type_size(t);
@@ -389,6 +1909,8 @@ fn stat_btf(elf: &object::File) -> BtfResult<()> {
                 println!("{:10} {:9} bytes ({} types)", format!("{:?}:", k), sz, cnt);
             }
 
+            stat_info_bits(&btf);
+
             if btf.has_ext() {
                 struct Section {
                     func_cnt: usize,
@@ -485,5 +2007,1208 @@ fn stat_btf(elf: &object::File) -> BtfResult<()> {
             }
         }
     }
+    stat_producer(elf)?;
+    Ok(())
+}
+
+fn stat_producer(elf: &object::File) -> BtfResult<()> {
+    println!("\nBTF producer\n=======================================");
+    let mut comments = Vec::new();
+    if let Some(sec) = elf.section_by_name(".comment") {
+        for c in sec.data()?.split(|&b| b == 0) {
+            let c = String::from_utf8_lossy(c).trim().to_owned();
+            if !c.is_empty() {
+                comments.push(c);
+            }
+        }
+    }
+    for c in &comments {
+        println!("ELF .comment:\t{}", c);
+    }
+
+    let mut tells = Vec::new();
+    let mut guess = if comments.iter().any(|c| c.contains("clang")) {
+        "clang"
+    } else if comments.iter().any(|c| c.starts_with("GCC")) {
+        "GCC"
+    } else {
+        "unknown"
+    };
+    match Btf::load(elf) {
+        Err(BtfError::BadIntEncoding { .. }) => {
+            tells.push("non-standard INT encoding (GCC emits SIGNED|CHAR for 'char')");
+            guess = "GCC";
+        }
+        Err(_) => {}
+        Ok(btf) => {
+            let types = &btf.types()[btf.start_id() as usize..];
+            let has_name = |kind: BtfKind, name: &str| {
+                types.iter().any(|t| t.kind() == kind && t.name() == name)
+            };
+            let has_kind = |kind: BtfKind| types.iter().any(|t| t.kind() == kind);
+            if has_name(BtfKind::Datasec, ".data..percpu") {
+                tells.push("'.data..percpu' DATASEC (kernel BTF, generated by pahole)");
+                guess = "pahole";
+            }
+            if has_name(BtfKind::Int, "long unsigned int") {
+                tells.push("GCC-style INT names ('long unsigned int'), DWARF produced by GCC");
+            } else if has_name(BtfKind::Int, "unsigned long") {
+                tells.push("clang-style INT names ('unsigned long'), DWARF produced by clang");
+            }
+            if !btf.core_reloc_secs().is_empty() {
+                tells.push("CO-RE relocations in .BTF.ext (clang BPF target)");
+            }
+            let datasecs = types.iter().filter_map(|t| match t {
+                BtfType::Datasec(t) => Some(t),
+                _ => None,
+            });
+            if datasecs
+                .filter(|t| t.vars.len() > 1)
+                .any(|t| t.vars.iter().all(|v| v.offset == 0))
+            {
+                tells.push("DATASEC vars without offsets (GCC -gbtf)");
+            }
+            if has_kind(BtfKind::Enum64) {
+                tells.push("ENUM64 kind (clang 15+ or pahole 1.24+)");
+            }
+            if has_kind(BtfKind::DeclTag) || has_kind(BtfKind::TypeTag) {
+                tells.push("DECL_TAG/TYPE_TAG kinds (clang 14+ or pahole 1.23+)");
+            }
+            if has_kind(BtfKind::Float) {
+                tells.push("FLOAT kind (clang 12+ or pahole 1.21+)");
+            }
+        }
+    }
+    for t in &tells {
+        println!("Tell:\t\t{}", t);
+    }
+    println!("Best guess:\t{}", guess);
+    Ok(())
+}
+
+fn stat_info_bits(btf: &Btf) {
+    let mut comps: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    let mut fwds: BTreeMap<String, usize> = BTreeMap::new();
+    let mut ints: BTreeMap<String, usize> = BTreeMap::new();
+    let mut funcs: BTreeMap<String, usize> = BTreeMap::new();
+    let mut vars: BTreeMap<String, usize> = BTreeMap::new();
+    for t in &btf.types()[btf.start_id() as usize..] {
+        match t {
+            BtfType::Struct(t) | BtfType::Union(t) => {
+                let kind = if t.is_struct { "struct" } else { "union" };
+                let bits = if t.bitfield_offs {
+                    "bitfields"
+                } else {
+                    "plain"
+                };
+                *comps.entry((kind, bits)).or_insert(0) += 1;
+            }
+            BtfType::Fwd(t) => *fwds.entry(t.kind.to_string()).or_insert(0) += 1,
+            BtfType::Int(t) => *ints.entry(t.encoding.to_string()).or_insert(0) += 1,
+            BtfType::Func(t) => *funcs.entry(t.kind.to_string()).or_insert(0) += 1,
+            BtfType::Var(t) => *vars.entry(t.kind.to_string()).or_insert(0) += 1,
+            _ => {}
+        }
+    }
+
+    println!("\nBTF info bits\n=======================================");
+    for ((kind, bits), cnt) in &comps {
+        println!("{:28} {:9} types", format!("{} ({}):", kind, bits), cnt);
+    }
+    let groups = [
+        ("fwd", &fwds),
+        ("int enc", &ints),
+        ("func linkage", &funcs),
+        ("var linkage", &vars),
+    ];
+    for (title, stats) in groups.iter() {
+        for (k, cnt) in stats.iter() {
+            println!("{:28} {:9} types", format!("{} ({}):", title, k), cnt);
+        }
+    }
+}
+
+fn unused_btf(btf: &Btf, named_roots: bool) {
+    let roots = btf
+        .types()
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| match t {
+            BtfType::Func(_) | BtfType::Var(_) | BtfType::Datasec(_) => true,
+            BtfType::Struct(_)
+            | BtfType::Union(_)
+            | BtfType::Enum(_)
+            | BtfType::Enum64(_)
+            | BtfType::Fwd(_)
+            | BtfType::Typedef(_) => named_roots && !t.name().is_empty(),
+            _ => false,
+        })
+        .map(|(i, _)| i as u32)
+        .collect::<Vec<u32>>();
+    let reached = btf.type_closure(&roots, true);
+
+    let mut stats: HashMap<BtfKind, (usize, usize)> = HashMap::new();
+    for (i, t) in btf.types().iter().enumerate() {
+        if reached[i] {
+            continue;
+        }
+        println!("#{}: {}", i, btf.type_display(t));
+        let (cnt, sz) = stats.entry(t.kind()).or_insert((0, 0));
+        *cnt += 1;
+        *sz += Btf::type_size(t);
+    }
+    let total_cnt: usize = stats.values().map(|(cnt, _)| cnt).sum();
+    let total_sz: usize = stats.values().map(|(_, sz)| sz).sum();
+    let mut stats = stats
+        .into_iter()
+        .map(|(k, (cnt, sz))| (k, cnt, sz))
+        .collect::<Vec<(BtfKind, usize, usize)>>();
+    stats.sort_by_key(|&(_, _, sz)| std::cmp::Reverse(sz));
+    println!("\nUnused BTF types\n=======================================");
+    println!("{:10} {:9} bytes ({} types)", "Total", total_sz, total_cnt);
+    for (k, cnt, sz) in stats {
+        println!("{:10} {:9} bytes ({} types)", format!("{:?}:", k), sz, cnt);
+    }
+}
+
+fn dump_strings(btf: &Btf) {
+    let mut type_strs = HashSet::new();
+    for t in btf.types() {
+        type_strs.extend(btf.arena().names(t));
+    }
+    let mut ext_strs = HashSet::new();
+    for sec in btf.func_secs() {
+        ext_strs.insert(sec.name);
+    }
+    for sec in btf.line_secs() {
+        ext_strs.insert(sec.name);
+        for rec in &sec.recs {
+            ext_strs.insert(rec.file_name);
+            ext_strs.insert(rec.src_line);
+        }
+    }
+    for sec in btf.core_reloc_secs() {
+        ext_strs.insert(sec.name);
+        for rec in &sec.recs {
+            ext_strs.insert(rec.access_spec_str);
+        }
+    }
+
+    println!("\nStrings:");
+    for (off, s) in btf.strings() {
+        let src = match (type_strs.contains(&*s), ext_strs.contains(&*s)) {
+            (true, true) => "types,ext",
+            (true, false) => "types",
+            (false, true) => "ext",
+            (false, false) => "unused",
+        };
+        println!("@{}: '{}' ({})", off, s, src);
+    }
+}
+
+fn verify_dedup(deduped: &Btf, ref_btf: &Btf) -> BtfResult<()> {
+    const MAX_SHOWN: usize = 20;
+    let (ours, theirs) = unmatched_types(deduped, ref_btf);
+    println!(
+        "Deduplicated types: {}, reference types: {}",
+        deduped.type_cnt() - 1,
+        ref_btf.type_cnt() - 1
+    );
+    for (what, btf, ids) in [
+        ("deduplicated BTF only", deduped, &ours),
+        ("reference BTF only", ref_btf, &theirs),
+    ] {
+        if ids.is_empty() {
+            continue;
+        }
+        println!("\nTypes in {} ({}):", what, ids.len());
+        for &id in ids.iter().take(MAX_SHOWN) {
+            println!("#{}: {}", id, btf.type_display(btf.type_by_id(id)));
+        }
+        if ids.len() > MAX_SHOWN {
+            println!("... and {} more", ids.len() - MAX_SHOWN);
+        }
+    }
+    if !ours.is_empty() || !theirs.is_empty() {
+        return btf_error("Deduplicated BTF is not equivalent to reference BTF".to_owned());
+    }
+    if deduped.type_cnt() != ref_btf.type_cnt() {
+        // same set of types, but one of BTFs still has duplicates
+        return btf_error(
+            "Deduplicated BTF has different number of types than reference".to_owned(),
+        );
+    }
+    println!("Deduplicated BTF is equivalent to reference BTF.");
+    Ok(())
+}
+
+fn find_named_type(btf: &Btf, spec: &str) -> BtfResult<u32> {
+    let (kinds, name): (&[BtfKind], &str) = match spec.trim().split_once(' ') {
+        Some(("struct", name)) => (&[BtfKind::Struct], name),
+        Some(("union", name)) => (&[BtfKind::Union], name),
+        Some(("enum", name)) => (&[BtfKind::Enum, BtfKind::Enum64], name),
+        Some(("typedef", name)) => (&[BtfKind::Typedef], name),
+        Some(_) => return btf_error(format!("Invalid type specification: '{}'", spec)),
+        None => (
+            &[
+                BtfKind::Struct,
+                BtfKind::Union,
+                BtfKind::Enum,
+                BtfKind::Enum64,
+                BtfKind::Typedef,
+            ],
+            spec.trim(),
+        ),
+    };
+    let name = name.trim();
+    match btf
+        .types()
+        .iter()
+        .position(|t| t.name() == name && kinds.contains(&t.kind()))
+    {
+        Some(id) => Ok(btf.skip_mods_and_typedefs(id as u32)),
+        None => btf_error(format!("Type '{}' not found", spec)),
+    }
+}
+
+/// Short C-like description of a type, e.g., `struct list_head *`.
+fn type_desc(btf: &Btf, id: u32) -> String {
+    let named = |kw: &str, name: &str| {
+        if name.is_empty() {
+            format!("{} {{...}}", kw)
+        } else {
+            format!("{} {}", kw, name)
+        }
+    };
+    match btf.type_by_id(id) {
+        BtfType::Void => "void".to_owned(),
+        BtfType::Int(t) => t.name.to_owned(),
+        BtfType::Float(t) => t.name.to_owned(),
+        BtfType::Typedef(t) => t.name.to_owned(),
+        BtfType::Struct(t) => named("struct", t.name),
+        BtfType::Union(t) => named("union", t.name),
+        BtfType::Enum(t) => named("enum", t.name),
+        BtfType::Enum64(t) => named("enum", t.name),
+        BtfType::Fwd(t) => named(&t.kind.to_string(), t.name),
+        BtfType::Ptr(t) => format!("{} *", type_desc(btf, t.type_id)),
+        BtfType::Array(t) => format!("{}[{}]", type_desc(btf, t.val_type_id), t.nelems),
+        BtfType::Const(t) => format!("const {}", type_desc(btf, t.type_id)),
+        BtfType::Volatile(t) => format!("volatile {}", type_desc(btf, t.type_id)),
+        BtfType::Restrict(t) => format!("restrict {}", type_desc(btf, t.type_id)),
+        BtfType::TypeTag(t) => type_desc(btf, t.type_id),
+        BtfType::FuncProto(_) => "func_proto".to_owned(),
+        t => format!("{:?} '{}'", t.kind(), t.name()),
+    }
+}
+
+struct DiffField {
+    name: String,
+    bit_off: i64,
+    bit_sz: u32,
+    desc: String,
+}
+
+fn diff_fields(btf: &Btf, id: u32) -> (u32, Vec<DiffField>) {
+    let mut anon_cnt = 0;
+    let mut anon_name = || {
+        anon_cnt += 1;
+        format!("<anon#{}>", anon_cnt)
+    };
+    match btf.type_by_id(id) {
+        BtfType::Struct(t) | BtfType::Union(t) => (
+            t.sz,
+            btf.members(t)
+                .iter()
+                .map(|m| DiffField {
+                    name: if m.name.is_empty() {
+                        anon_name()
+                    } else {
+                        m.name.to_owned()
+                    },
+                    bit_off: i64::from(m.bit_offset),
+                    bit_sz: u32::from(m.bit_size),
+                    desc: type_desc(btf, m.type_id),
+                })
+                .collect(),
+        ),
+        // for enums, enumerator value is compared instead of offset
+        BtfType::Enum(t) => (
+            t.sz,
+            btf.enum_values(t)
+                .iter()
+                .map(|v| DiffField {
+                    name: v.name.to_owned(),
+                    bit_off: i64::from(v.value),
+                    bit_sz: 0,
+                    desc: String::new(),
+                })
+                .collect(),
+        ),
+        BtfType::Enum64(t) => (
+            t.sz,
+            btf.enum64_values(t)
+                .iter()
+                .map(|v| DiffField {
+                    name: v.name.to_owned(),
+                    bit_off: v.value,
+                    bit_sz: 0,
+                    desc: String::new(),
+                })
+                .collect(),
+        ),
+        _ => (btf.get_size_of(id), Vec::new()),
+    }
+}
+
+fn diff_type(old_btf: &Btf, new_btf: &Btf, spec: &str) -> BtfResult<()> {
+    let old_id = find_named_type(old_btf, spec)?;
+    let new_id = find_named_type(new_btf, spec)?;
+    let is_enum = matches!(
+        new_btf.type_by_id(new_id),
+        BtfType::Enum(_) | BtfType::Enum64(_)
+    );
+    let (old_sz, old_fields) = diff_fields(old_btf, old_id);
+    let (new_sz, new_fields) = diff_fields(new_btf, new_id);
+    let fmt_off = |f: &DiffField| {
+        if is_enum {
+            f.bit_off.to_string()
+        } else if f.bit_sz == 0 && f.bit_off % 8 == 0 {
+            (f.bit_off / 8).to_string()
+        } else {
+            format!("{}:{}", f.bit_off / 8, f.bit_off % 8)
+        }
+    };
+    let fmt_delta = |d: i64| {
+        if d == 0 {
+            String::new()
+        } else if is_enum || d % 8 != 0 {
+            format!("{:+}", d)
+        } else {
+            format!("{:+}", d / 8)
+        }
+    };
+
+    println!(
+        "{}: [{}] sz:{} --> [{}] sz:{} {}",
+        spec,
+        old_id,
+        old_sz,
+        new_id,
+        new_sz,
+        fmt_delta((i64::from(new_sz) - i64::from(old_sz)) * 8)
+    );
+    println!(
+        "\n{:32} {:>8} {:32} {:>8} {:32} {:>6}",
+        "NAME",
+        if is_enum { "OLD VAL" } else { "OLD OFF" },
+        "OLD TYPE",
+        if is_enum { "NEW VAL" } else { "NEW OFF" },
+        "NEW TYPE",
+        "DELTA"
+    );
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut moved = Vec::new();
+    let mut retyped = Vec::new();
+    let old_by_name = old_fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.name.as_str(), (i, f)))
+        .collect::<HashMap<&str, (usize, &DiffField)>>();
+    let new_names = new_fields
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect::<HashSet<&str>>();
+    let print_row = |name: &str, old: Option<&DiffField>, new: Option<&DiffField>, delta: &str| {
+        println!(
+            "{:32} {:>8} {:32} {:>8} {:32} {:>6}",
+            name,
+            old.map_or("-".to_owned(), fmt_off),
+            old.map_or("-", |f| f.desc.as_str()),
+            new.map_or("-".to_owned(), fmt_off),
+            new.map_or("-", |f| f.desc.as_str()),
+            delta
+        );
+    };
+    // removed fields are shown right before the first new field that follows them in old type
+    let mut old_pos = 0;
+    let mut flush_removed = |upto: usize| {
+        while old_pos < upto {
+            let of = &old_fields[old_pos];
+            if !new_names.contains(of.name.as_str()) {
+                print_row(&of.name, Some(of), None, "REMOVED");
+                removed.push(of.name.as_str());
+            }
+            old_pos += 1;
+        }
+    };
+    for nf in &new_fields {
+        match old_by_name.get(nf.name.as_str()) {
+            Some(&(i, of)) => {
+                flush_removed(i);
+                let delta = nf.bit_off - of.bit_off;
+                if delta != 0 || nf.bit_sz != of.bit_sz {
+                    moved.push(nf.name.as_str());
+                }
+                if nf.desc != of.desc {
+                    retyped.push(nf.name.as_str());
+                }
+                print_row(&nf.name, Some(of), Some(nf), &fmt_delta(delta));
+            }
+            None => {
+                print_row(&nf.name, None, Some(nf), "ADDED");
+                added.push(nf.name.as_str());
+            }
+        }
+    }
+    flush_removed(old_fields.len());
+
+    println!("\nSummary\n=======================================");
+    for (what, names) in [
+        ("Added", &added),
+        ("Removed", &removed),
+        (if is_enum { "Changed value" } else { "Moved" }, &moved),
+        ("Changed type", &retyped),
+    ] {
+        if names.is_empty() {
+            println!("{:14} 0", format!("{}:", what));
+        } else {
+            println!(
+                "{:14} {} ({})",
+                format!("{}:", what),
+                names.len(),
+                names.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_btf(elf: &object::File, btf: &Btf) -> BtfResult<()> {
+    if !btf.has_ext() {
+        println!("No {} section found, nothing to check.", BTF_EXT_ELF_SEC);
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for sec in btf.func_secs() {
+        let mut errs = Vec::new();
+        let sec_sz = check_ext_sec(elf, sec.name, &mut errs);
+        for (i, rec) in sec.recs.iter().enumerate() {
+            if let Some(e) = check_insn_off(sec_sz, rec.insn_off) {
+                errs.push(format!("#{}: {}: {}", i, rec, e));
+            }
+            match check_ext_type(btf, rec.type_id) {
+                Err(e) => errs.push(format!("#{}: {}: {}", i, rec, e)),
+                Ok(BtfType::Func(_)) => {}
+                Ok(t) => errs.push(format!(
+                    "#{}: {}: expected FUNC, got {:?}",
+                    i,
+                    rec,
+                    t.kind()
+                )),
+            }
+        }
+        failed += report_ext_sec("Func", sec.name, sec.recs.len(), &errs);
+    }
+    for sec in btf.line_secs() {
+        let mut errs = Vec::new();
+        let sec_sz = check_ext_sec(elf, sec.name, &mut errs);
+        for (i, rec) in sec.recs.iter().enumerate() {
+            if let Some(e) = check_insn_off(sec_sz, rec.insn_off) {
+                errs.push(format!("#{}: insn #{}: {}", i, rec.insn_off / 8, e));
+            }
+        }
+        failed += report_ext_sec("Line", sec.name, sec.recs.len(), &errs);
+    }
+    for sec in btf.core_reloc_secs() {
+        let mut errs = Vec::new();
+        let sec_sz = check_ext_sec(elf, sec.name, &mut errs);
+        for (i, rec) in sec.recs.iter().enumerate() {
+            if let Some(e) = check_insn_off(sec_sz, rec.insn_off) {
+                errs.push(format!("#{}: {}: {}", i, rec, e));
+            }
+            let chain = match check_ext_type(btf, rec.type_id) {
+                Ok(_) => btf.resolve_chain(rec.type_id),
+                Err(e) => {
+                    errs.push(format!("#{}: {}: {}", i, rec, e));
+                    continue;
+                }
+            };
+            let root = btf.type_by_id(chain[chain.len() - 1]);
+            let ok = match rec.kind {
+                BtfCoreRelocKind::ByteOff
+                | BtfCoreRelocKind::ByteSz
+                | BtfCoreRelocKind::FieldExists
+                | BtfCoreRelocKind::Signed
+                | BtfCoreRelocKind::LShiftU64
+                | BtfCoreRelocKind::RShiftU64 => {
+                    matches!(root, BtfType::Struct(_) | BtfType::Union(_))
+                }
+                BtfCoreRelocKind::EnumvalExists | BtfCoreRelocKind::EnumvalValue => {
+                    matches!(root, BtfType::Enum(_) | BtfType::Enum64(_))
+                }
+                BtfCoreRelocKind::LocalTypeId
+                | BtfCoreRelocKind::TargetTypeId
+                | BtfCoreRelocKind::TypeExists
+                | BtfCoreRelocKind::TypeSize
+                | BtfCoreRelocKind::TypeMatches => !root.name().is_empty(),
+            };
+            if !ok {
+                let chain = chain
+                    .iter()
+                    .map(|&id| format!("[{}] {:?}", id, btf.type_by_id(id).kind()))
+                    .collect::<Vec<String>>();
+                errs.push(format!(
+                    "#{}: {}: unexpected type for {} relocation: {}",
+                    i,
+                    rec,
+                    rec.kind,
+                    chain.join(" -> ")
+                ));
+            } else if let Some(e) = check_access_spec(btf, rec) {
+                errs.push(format!("#{}: {}: {}", i, rec, e));
+            }
+        }
+        failed += report_ext_sec("Core reloc", sec.name, sec.recs.len(), &errs);
+    }
+
+    if failed > 0 {
+        return btf_error(format!("{} section(s) failed consistency check", failed));
+    }
+    println!("\nAll checks passed.");
+    Ok(())
+}
+
+fn load_bpf_objects(mmaps: &[FileData]) -> BtfResult<Vec<Btf<'_>>> {
+    let mut btfs = Vec::new();
+    for mmap in mmaps {
+        let elf = object::File::parse(&**mmap)?;
+        btfs.push(Btf::load(&elf)?);
+    }
+    Ok(btfs)
+}
+
+/// Minimal target BTF needed to perform CO-RE relocations of all given BPF objects.
+fn min_core_btf(targ_btf: &Btf, local_btfs: &[Btf]) -> BtfResult<Vec<u8>> {
+    let mut min_btf = MinCoreBtf::new(targ_btf);
+    for local_btf in local_btfs {
+        // btfhub already processes kernels in parallel
+        let cfg = RelocatorCfg::new().jobs(1);
+        let mut relocator = Relocator::new(targ_btf, local_btf, cfg);
+        let relocs = relocator
+            .relocate()
+            .into_iter()
+            .collect::<BtfResult<Vec<_>>>()?;
+        min_btf.add_relocs(local_btf, &relocs)?;
+    }
+    min_btf.encode()
+}
+
+/// Resolve 'kernel:' and 'module:<name>' pseudo-paths to running kernel's BTF files.
+fn parse_btf_path(s: &str) -> Result<std::path::PathBuf, String> {
+    let dir = std::path::Path::new(SYS_BTF_DIR);
+    if s == "kernel:" {
+        return Ok(dir.join("vmlinux"));
+    }
+    match s.strip_prefix("module:") {
+        Some(name) if name.is_empty() || name.contains('/') => {
+            Err(format!("Invalid kernel module name: '{}'", name))
+        }
+        Some(name) => Ok(dir.join(name)),
+        None => Ok(std::path::PathBuf::from(s)),
+    }
+}
+
+/// Running kernel's vmlinux BTF, if given file is kernel module's split BTF from /sys/kernel/btf.
+fn kernel_base_btf(file: &std::path::Path) -> Option<std::path::PathBuf> {
+    let dir = std::path::Path::new(SYS_BTF_DIR);
+    if file.parent() == Some(dir) && file.file_name().is_some_and(|name| name != "vmlinux") {
+        Some(dir.join("vmlinux"))
+    } else {
+        None
+    }
+}
+
+/// Expand paths of kernel module BTFs, replacing directories (e.g., /sys/kernel/btf) with module
+/// BTF files in them, except for vmlinux BTF itself.
+fn find_module_btf_files(paths: &[std::path::PathBuf]) -> BtfResult<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut dir_files = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_file() && path.file_name().is_some_and(|name| name != "vmlinux") {
+                dir_files.push(path);
+            }
+        }
+        dir_files.sort();
+        files.append(&mut dir_files);
+    }
+    Ok(files)
+}
+
+fn find_btf_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) -> BtfResult<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_btf_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "btf") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn btfhub_tailor_one(
+    path: &std::path::Path,
+    out_path: &std::path::Path,
+    local_btfs: &[Btf],
+) -> BtfResult<()> {
+    let mmap = load_file(path)?;
+    let targ_btf = load_btf(&mmap)?;
+    let data = min_core_btf(&targ_btf, local_btfs)?;
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(out_path, data)?;
+    Ok(())
+}
+
+/// Generate minimal CO-RE BTF for each kernel BTF found under `dir`, processing kernels in
+/// parallel. Results are written under `out_dir` with the same relative paths.
+fn btfhub_tailor(
+    dir: &std::path::Path,
+    out_dir: &std::path::Path,
+    local_btfs: &[Btf],
+    jobs: Option<usize>,
+) -> BtfResult<()> {
+    let mut paths = Vec::new();
+    find_btf_files(dir, &mut paths)?;
+    paths.sort();
+    if paths.is_empty() {
+        return btf_error(format!("No *.btf files found in {}", dir.display()));
+    }
+
+    let results = par_map(&paths, jobs, |path| {
+        let rel_path = path.strip_prefix(dir).unwrap_or(path);
+        btfhub_tailor_one(path, &out_dir.join(rel_path), local_btfs).map_err(|e| e.to_string())
+    });
+
+    let mut failed = 0;
+    for (path, res) in paths.iter().zip(results) {
+        match res {
+            Ok(()) => println!("OK   {}", path.display()),
+            Err(e) => {
+                println!("FAIL {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} kernels: {} OK, {} FAIL",
+        paths.len(),
+        paths.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        return btf_error(format!("Failed to tailor BTF for {} kernel(s)", failed));
+    }
+    Ok(())
+}
+
+/// Relocate BPF object against one kernel BTF; for each relocation, None if it succeeded, or
+/// reason of failure.
+fn reloc_matrix_one(local_btf: &Btf, path: &std::path::Path) -> BtfResult<Vec<Option<String>>> {
+    let mmap = load_file(path)?;
+    let targ_btf = load_btf(&mmap)?;
+    let cfg = RelocatorCfg::new().jobs(1);
+    let mut relocator = Relocator::new(&targ_btf, local_btf, cfg);
+    Ok(relocator
+        .relocate()
+        .into_iter()
+        .map(|res| res.err().map(|e| e.to_string()))
+        .collect())
+}
+
+/// Relocate BPF object against each kernel BTF found under `dir`, in parallel, and report which
+/// relocations fail on which kernels: first per kernel, then per relocation.
+fn reloc_matrix(local_btf: &Btf, dir: &std::path::Path, jobs: Option<usize>) -> BtfResult<()> {
+    let mut paths = Vec::new();
+    find_btf_files(dir, &mut paths)?;
+    paths.sort();
+    if paths.is_empty() {
+        return btf_error(format!("No *.btf files found in {}", dir.display()));
+    }
+    let results = par_map(&paths, jobs, |path| {
+        reloc_matrix_one(local_btf, path).map_err(|e| e.to_string())
+    });
+    let recs = local_btf
+        .core_reloc_secs()
+        .iter()
+        .enumerate()
+        .flat_map(|(sec_id, sec)| {
+            sec.recs
+                .iter()
+                .enumerate()
+                .map(move |(reloc_id, rec)| (sec_id, reloc_id, rec))
+        })
+        .collect::<Vec<_>>();
+    let names = paths
+        .iter()
+        .map(|p| p.strip_prefix(dir).unwrap_or(p).display().to_string())
+        .collect::<Vec<_>>();
+    let width = names
+        .iter()
+        .map(|n| n.len())
+        .max()
+        .unwrap_or(0)
+        .max("KERNEL".len());
+
+    println!(
+        "{:<width$}  {:>5}  {:>5}  FAILED RELOCATIONS",
+        "KERNEL", "OK", "FAIL"
+    );
+    let mut failed_kernels = 0;
+    for (name, res) in names.iter().zip(&results) {
+        let fails = match res {
+            Ok(fails) => fails,
+            Err(e) => {
+                println!("{:<width$}  ERROR: {}", name, e);
+                failed_kernels += 1;
+                continue;
+            }
+        };
+        let failed = recs
+            .iter()
+            .zip(fails)
+            .filter(|(_, f)| f.is_some())
+            .map(|((sec_id, reloc_id, _), _)| format!("sec#{}/r#{}", sec_id, reloc_id))
+            .collect::<Vec<_>>();
+        if !failed.is_empty() {
+            failed_kernels += 1;
+        }
+        let line = format!(
+            "{:<width$}  {:>5}  {:>5}  {}",
+            name,
+            recs.len() - failed.len(),
+            failed.len(),
+            failed.join(", ")
+        );
+        println!("{}", line.trim_end());
+    }
+
+    let mut header = false;
+    for (i, &(sec_id, reloc_id, rec)) in recs.iter().enumerate() {
+        let fails = names
+            .iter()
+            .zip(&results)
+            .filter_map(|(name, res)| match res {
+                Ok(fails) => fails[i].as_ref().map(|e| (name, e)),
+                Err(_) => None,
+            })
+            .collect::<Vec<_>>();
+        if fails.is_empty() {
+            continue;
+        }
+        if !header {
+            println!("\nFailed relocations:");
+            header = true;
+        }
+        let spec = Relocator::pretty_print_access_spec(local_btf, rec)?;
+        println!(
+            "sec#{}, r#{}: {} --> {}: fails on {} of {} kernel(s)",
+            sec_id,
+            reloc_id,
+            rec,
+            spec,
+            fails.len(),
+            paths.len()
+        );
+        for (name, e) in fails {
+            println!("\t{}: {}", name, e);
+        }
+    }
+    if failed_kernels > 0 {
+        return btf_error(format!(
+            "Relocations failed on {} of {} kernel(s)",
+            failed_kernels,
+            paths.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Relocate BPF object against each of given kernel BTFs, in parallel, and print one line per
+/// kernel, followed by first line of failure reason for each failed relocation. Fails if any
+/// relocation fails on any kernel, for use as a CI check.
+fn verify_core(
+    local_btf: &Btf,
+    paths: &[std::path::PathBuf],
+    jobs: Option<usize>,
+) -> BtfResult<()> {
+    let results = par_map(paths, jobs, |path| {
+        reloc_matrix_one(local_btf, path).map_err(|e| e.to_string())
+    });
+    let recs = local_btf
+        .core_reloc_secs()
+        .iter()
+        .enumerate()
+        .flat_map(|(sec_id, sec)| {
+            sec.recs
+                .iter()
+                .enumerate()
+                .map(move |(reloc_id, rec)| (sec_id, reloc_id, rec))
+        })
+        .collect::<Vec<_>>();
+    let mut failed_kernels = 0;
+    for (path, res) in paths.iter().zip(&results) {
+        let fails = match res {
+            Ok(fails) => fails,
+            Err(e) => {
+                println!("FAIL {}: {}", path.display(), e);
+                failed_kernels += 1;
+                continue;
+            }
+        };
+        let fail_cnt = fails.iter().filter(|f| f.is_some()).count();
+        if fail_cnt == 0 {
+            println!("OK   {}: {} relocations", path.display(), recs.len());
+            continue;
+        }
+        failed_kernels += 1;
+        println!(
+            "FAIL {}: {} of {} relocations failed",
+            path.display(),
+            fail_cnt,
+            recs.len()
+        );
+        for (&(sec_id, reloc_id, rec), e) in recs.iter().zip(fails) {
+            if let Some(e) = e {
+                let spec = Relocator::pretty_print_access_spec(local_btf, rec)?;
+                println!(
+                    "\tsec#{}, r#{}: {} {}: {}",
+                    sec_id,
+                    reloc_id,
+                    rec.kind,
+                    spec,
+                    e.lines().next().unwrap_or("")
+                );
+            }
+        }
+    }
+    if failed_kernels > 0 {
+        return btf_error(format!(
+            "CO-RE relocations failed on {} of {} kernel(s)",
+            failed_kernels,
+            paths.len()
+        ));
+    }
+    Ok(())
+}
+
+fn preflight(local_btf: &Btf, targ_btf: &Btf) -> BtfResult<()> {
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+
+    // newer BTF kinds unknown to kernel are sanitized by libbpf, so they are not fatal
+    for kind in [
+        BtfKind::Float,
+        BtfKind::DeclTag,
+        BtfKind::TypeTag,
+        BtfKind::Enum64,
+    ] {
+        let has_kind = |btf: &Btf| btf.types().iter().any(|t| t.kind() == kind);
+        if has_kind(local_btf) && !has_kind(targ_btf) {
+            warnings.push(format!(
+                "object uses {:?} BTF kind, not found in kernel BTF; kernel might not support it",
+                kind
+            ));
+        }
+    }
+
+    let reloc_cnt: usize = local_btf
+        .core_reloc_secs()
+        .iter()
+        .map(|s| s.recs.len())
+        .sum();
+    if reloc_cnt > 0 {
+        let cfg = RelocatorCfg::new();
+        let mut relocator = Relocator::new(targ_btf, local_btf, cfg);
+        let mut failed = 0;
+        for res in relocator.relocate() {
+            if let Err(e) = res {
+                failed += 1;
+                problems.push(format!("CO-RE relocation failed: {}", e));
+            }
+        }
+        if failed == 0 {
+            println!("CO-RE relocations: {} OK", reloc_cnt);
+        } else {
+            println!(
+                "CO-RE relocations: {} OK, {} FAIL",
+                reloc_cnt - failed,
+                failed
+            );
+        }
+    } else {
+        println!("CO-RE relocations: none");
+    }
+
+    // externs from .kconfig are resolved by libbpf from kernel config, not kernel BTF
+    let kconfig_vars = local_btf
+        .types()
+        .iter()
+        .filter_map(|t| match t {
+            BtfType::Datasec(t) if t.name == ".kconfig" => Some(t.vars.iter().map(|v| v.type_id)),
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<u32>>();
+    let targ_index = BtfIndex::new(targ_btf);
+    let has_kernel_type =
+        |name: &str, kind: BtfKind| targ_index.get_by_name_kind(name, kind).next().is_some();
+    let mut extern_cnt = 0;
+    let mut missing = Vec::new();
+    for (id, t) in local_btf.types().iter().enumerate() {
+        match t {
+            BtfType::Func(f) if f.kind == BtfFuncKind::Extern => {
+                extern_cnt += 1;
+                if !has_kernel_type(f.name, BtfKind::Func) {
+                    missing.push(format!("extern function '{}' not found in kernel", f.name));
+                }
+            }
+            BtfType::Var(v) if v.kind == BtfVarKind::GlobalExtern => {
+                if kconfig_vars.contains(&(id as u32)) {
+                    continue;
+                }
+                extern_cnt += 1;
+                if v.type_id == 0 {
+                    // typeless ksym, resolved through kallsyms
+                    continue;
+                }
+                if !has_kernel_type(v.name, BtfKind::Var) {
+                    missing.push(format!("extern variable '{}' not found in kernel", v.name));
+                }
+            }
+            _ => {}
+        }
+    }
+    println!(
+        "Kernel externs: {} OK, {} missing",
+        extern_cnt - missing.len(),
+        missing.len()
+    );
+    problems.extend(missing);
+
+    for w in &warnings {
+        println!("WARNING: {}", w);
+    }
+    for p in &problems {
+        println!("PROBLEM: {}", p);
+    }
+    if !problems.is_empty() {
+        println!("\nVerdict: object will NOT load on this kernel.");
+        return btf_error(format!("{} problem(s) found", problems.len()));
+    }
+    println!("\nVerdict: object should load on this kernel.");
+    Ok(())
+}
+
+fn check_ext_sec(elf: &object::File, sec_name: &str, errs: &mut Vec<String>) -> Option<u64> {
+    match elf.section_by_name(sec_name) {
+        Some(sec) => Some(sec.size()),
+        None => {
+            errs.push(format!("ELF section '{}' not found", sec_name));
+            None
+        }
+    }
+}
+
+fn check_insn_off(sec_sz: Option<u64>, insn_off: u32) -> Option<String> {
+    match sec_sz {
+        Some(sz) if u64::from(insn_off) >= sz => Some(format!(
+            "instruction is out of section bounds ({} bytes)",
+            sz
+        )),
+        _ => None,
+    }
+}
+
+fn check_ext_type<'a>(btf: &'a Btf, type_id: u32) -> BtfResult<&'a BtfType<'a>> {
+    if type_id == 0 || type_id >= btf.type_cnt() {
+        return btf_error(format!("invalid type ID [{}]", type_id));
+    }
+    Ok(btf.type_by_id(type_id))
+}
+
+/// Validate that each index of access spec stays within the type it walks: enumerator index
+/// within enum's values and member indices within members of structs/unions on the way.
+fn check_access_spec(btf: &Btf, rec: &BtfExtCoreReloc) -> Option<String> {
+    let spec = &rec.access_spec;
+    let mut id = btf.skip_mods_and_typedefs(rec.type_id);
+    match rec.kind {
+        BtfCoreRelocKind::EnumvalExists | BtfCoreRelocKind::EnumvalValue => {
+            let cnt = match btf.type_by_id(id) {
+                BtfType::Enum(t) => t.values.len(),
+                BtfType::Enum64(t) => t.values.len(),
+                _ => return None,
+            };
+            if spec.len() != 1 || spec[0] >= cnt {
+                return Some(format!(
+                    "access spec {} doesn't select one of {} enumerators of [{}]",
+                    rec.access_spec_str, cnt, id
+                ));
+            }
+        }
+        BtfCoreRelocKind::ByteOff
+        | BtfCoreRelocKind::ByteSz
+        | BtfCoreRelocKind::FieldExists
+        | BtfCoreRelocKind::Signed
+        | BtfCoreRelocKind::LShiftU64
+        | BtfCoreRelocKind::RShiftU64 => {
+            for (i, &idx) in spec.iter().enumerate().skip(1) {
+                id = match btf.type_by_id(id) {
+                    BtfType::Struct(t) | BtfType::Union(t) => match btf.members(t).get(idx) {
+                        Some(m) => btf.skip_mods_and_typedefs(m.type_id),
+                        None => {
+                            return Some(format!(
+                                "access spec {}, at #{}: member index {} is out of range of {} members of [{}]",
+                                rec.access_spec_str,
+                                i,
+                                idx,
+                                t.members.len(),
+                                id
+                            ))
+                        }
+                    },
+                    // array index isn't bounded by nelems, arrays can be flexible
+                    BtfType::Array(t) => btf.skip_mods_and_typedefs(t.val_type_id),
+                    t => {
+                        return Some(format!(
+                            "access spec {}, at #{}: [{}] {:?} is not struct/union/array",
+                            rec.access_spec_str,
+                            i,
+                            id,
+                            t.kind()
+                        ))
+                    }
+                };
+            }
+        }
+        _ => {}
+    }
+    None
+}
+
+fn report_ext_sec(what: &str, sec_name: &str, rec_cnt: usize, errs: &[String]) -> usize {
+    if errs.is_empty() {
+        println!("{} section '{}': {} records, OK", what, sec_name, rec_cnt);
+        return 0;
+    }
+    println!(
+        "{} section '{}': {} records, FAIL ({} errors)",
+        what,
+        sec_name,
+        rec_cnt,
+        errs.len()
+    );
+    for e in errs {
+        println!("\t{}", e);
+    }
+    1
+}
+
+fn dump_elf_relocs(elf: &object::File, btf: &Btf) -> BtfResult<()> {
+    const BPF_INSN_SZ: u64 = 8;
+    const BPF_LD_IMM64: u8 = 0x18;
+    const BPF_CALL: u8 = 0x85;
+
+    let is_bpf = elf.architecture() == object::Architecture::Bpf;
+    let index = BtfIndex::new(btf);
+    for sec in elf.sections() {
+        let relocs = sec.relocations().collect::<Vec<_>>();
+        if relocs.is_empty() || sec.name()?.starts_with(".BTF") {
+            continue;
+        }
+        let data = sec.data()?;
+        println!("\nRelocations for section '{}':", sec.name()?);
+        for (i, (off, reloc)) in relocs.iter().enumerate() {
+            if is_bpf {
+                let insn = match data.get(*off as usize) {
+                    Some(&BPF_LD_IMM64) => "ld_imm64",
+                    Some(&BPF_CALL) => "call",
+                    _ => "insn",
+                };
+                print!("#{}: {} #{}", i, insn, off / BPF_INSN_SZ);
+            } else {
+                print!("#{}: off {}", i, off);
+            }
+            let sym = match reloc.target() {
+                object::RelocationTarget::Symbol(idx) => elf.symbol_by_index(idx)?,
+                t => {
+                    println!(" --> {:?}", t);
+                    continue;
+                }
+            };
+            let sym_name = sym.name()?;
+            let sym_sec = match sym.section_index() {
+                Some(idx) => elf.section_by_index(idx)?.name()?,
+                None => "<extern>",
+            };
+            print!(" --> '{}' in '{}'", sym_name, sym_sec);
+            let mut found = false;
+            if sym.kind() == object::SymbolKind::Section {
+                // section-relative reference, addend is either explicit (RELA) or stored in
+                // instruction's immediate field (REL, which is what BPF uses)
+                let sec_off = if is_bpf && reloc.has_implicit_addend() {
+                    i64::from(data.pread_with::<i32>(*off as usize + 4, btf.endian())?)
+                } else {
+                    reloc.addend()
+                };
+                print!(" + {}", sec_off);
+                for id in index.get_by_name_kind(sym_sec, BtfKind::Datasec) {
+                    if let BtfType::Datasec(t) = btf.type_by_id(id) {
+                        for v in &t.vars {
+                            let (start, end) =
+                                (i64::from(v.offset), i64::from(v.offset) + i64::from(v.sz));
+                            if start <= sec_off && sec_off < end {
+                                print!(
+                                    " --> [{}] {}",
+                                    v.type_id,
+                                    btf.type_display(btf.type_by_id(v.type_id))
+                                );
+                                found = true;
+                            }
+                        }
+                    }
+                }
+                if !found {
+                    print!(" (no BTF)");
+                }
+                println!();
+                continue;
+            }
+            for &id in index.get_by_name(sym_name) {
+                match btf.type_by_id(id) {
+                    t @ BtfType::Func(_) | t @ BtfType::Var(_) => {
+                        print!(" --> [{}] {}", id, t);
+                        found = true;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            if !found {
+                print!(" (no BTF)");
+            }
+            println!();
+        }
+    }
     Ok(())
 }
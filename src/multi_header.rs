@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::c_dumper::{CDumper, CDumperCfg};
+use crate::types::*;
+use crate::{BtfError, BtfResult};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl std::str::FromStr for KernelVersion {
+    type Err = BtfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split('.')
+            .map(|p| p.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| BtfError::new_owned(format!("invalid kernel version: '{}'", s)))?;
+        match parts[..] {
+            [major, minor] => Ok(KernelVersion {
+                major,
+                minor,
+                patch: 0,
+            }),
+            [major, minor, patch] => Ok(KernelVersion {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(BtfError::new_owned(format!(
+                "invalid kernel version: '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug)]
+pub struct VersionedBtf<'a> {
+    pub version: KernelVersion,
+    pub btf: &'a Btf<'a>,
+}
+
+/// Generate single C header out of multiple kernel versions' BTFs.
+///
+/// Each BTF is dumped as C independently, then top-level declarations are merged, preserving
+/// per-version declaration order. Declarations that are not identical across all versions are
+/// wrapped into `#if LINUX_VERSION_CODE ...` guards selecting the range of versions they belong
+/// to. Versions are treated as lower bounds: kernels in between two given versions get the
+/// definitions of the older one.
+pub fn generate_multi_version_header(
+    targets: &mut [VersionedBtf],
+    filter: &dyn Fn(u32, &BtfType) -> bool,
+    union_as_struct: bool,
+) -> BtfResult<String> {
+    targets.sort_by_key(|t| t.version);
+
+    let mut merged: Vec<(String, Vec<bool>)> = Vec::new();
+    for (k, target) in targets.iter().enumerate() {
+        let cfg = CDumperCfg {
+            union_as_struct,
+            ..Default::default()
+        };
+        let mut dumper = CDumper::new(target.btf, cfg)?;
+        let mut decls = Vec::new();
+        for id in 1..target.btf.type_cnt() {
+            if filter(id, target.btf.type_by_id(id)) {
+                let out = dumper.type_to_string(id)?;
+                decls.extend(out.split_inclusive(";\n\n").map(|s| s.to_owned()));
+            }
+        }
+        merged = merge_decls(merged, decls, k, targets.len());
+    }
+
+    let mut buf = String::new();
+    let versions = targets
+        .iter()
+        .map(|t| t.version.to_string())
+        .collect::<Vec<String>>();
+    writeln!(
+        buf,
+        "/* Generated from BTF of kernel versions: {} */",
+        versions.join(", ")
+    )?;
+    writeln!(buf, "#ifndef KERNEL_VERSION")?;
+    writeln!(
+        buf,
+        "#define KERNEL_VERSION(a, b, c) (((a) << 16) + ((b) << 8) + ((c) > 255 ? 255 : (c)))"
+    )?;
+    writeln!(buf, "#endif\n")?;
+
+    let mut i = 0;
+    while i < merged.len() {
+        let mask = &merged[i].1;
+        let mut j = i;
+        while j < merged.len() && &merged[j].1 == mask {
+            j += 1;
+        }
+        let guarded = mask.iter().any(|&present| !present);
+        if guarded {
+            writeln!(buf, "#if {}\n", version_cond(targets, mask))?;
+        }
+        for (decl, _) in &merged[i..j] {
+            buf.push_str(decl);
+        }
+        if guarded {
+            writeln!(buf, "#endif\n")?;
+        }
+        i = j;
+    }
+    Ok(buf)
+}
+
+/// Merge declarations of k-th version into already merged list, preserving relative order of
+/// declarations within each version.
+fn merge_decls(
+    merged: Vec<(String, Vec<bool>)>,
+    decls: Vec<String>,
+    k: usize,
+    n: usize,
+) -> Vec<(String, Vec<bool>)> {
+    let mut positions: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (decl, _)) in merged.iter().enumerate() {
+        positions.entry(decl.as_str()).or_default().push(i);
+    }
+    let mut matches = Vec::with_capacity(decls.len());
+    let mut pos = 0;
+    for decl in &decls {
+        let m = positions.get(decl.as_str()).and_then(|ps| {
+            let idx = ps.partition_point(|&p| p < pos);
+            ps.get(idx).copied()
+        });
+        if let Some(p) = m {
+            pos = p + 1;
+        }
+        matches.push(m);
+    }
+
+    let mut res = Vec::with_capacity(merged.len() + decls.len());
+    let mut merged = merged.into_iter().enumerate();
+    for (decl, m) in decls.into_iter().zip(matches) {
+        match m {
+            Some(p) => {
+                for (i, (d, mut mask)) in merged.by_ref() {
+                    if i == p {
+                        mask[k] = true;
+                        res.push((d, mask));
+                        break;
+                    }
+                    res.push((d, mask));
+                }
+            }
+            None => {
+                let mut mask = vec![false; n];
+                mask[k] = true;
+                res.push((decl, mask));
+            }
+        }
+    }
+    res.extend(merged.map(|(_, e)| e));
+    res
+}
+
+fn version_cond(targets: &[VersionedBtf], mask: &[bool]) -> String {
+    let kver = |v: &KernelVersion| format!("KERNEL_VERSION({}, {}, {})", v.major, v.minor, v.patch);
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < mask.len() {
+        if !mask[i] {
+            i += 1;
+            continue;
+        }
+        let mut j = i;
+        while j + 1 < mask.len() && mask[j + 1] {
+            j += 1;
+        }
+        let mut conds = Vec::new();
+        if i > 0 {
+            conds.push(format!(
+                "LINUX_VERSION_CODE >= {}",
+                kver(&targets[i].version)
+            ));
+        }
+        if j + 1 < mask.len() {
+            conds.push(format!(
+                "LINUX_VERSION_CODE < {}",
+                kver(&targets[j + 1].version)
+            ));
+        }
+        ranges.push(conds.join(" && "));
+        i = j + 1;
+    }
+    if ranges.len() == 1 {
+        ranges.pop().unwrap()
+    } else {
+        ranges
+            .iter()
+            .map(|r| format!("({})", r))
+            .collect::<Vec<String>>()
+            .join(" || ")
+    }
+}
@@ -1,28 +1,75 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Write;
+use std::path::Path;
 
 use crate::btf_index::BtfIndex;
 use crate::types::*;
-use crate::{btf_error, BtfResult};
+use crate::{btf_error, fnv1a_extend, par_map, BtfError, BtfResult, FNV1A_INIT};
 
 #[derive(Debug)]
 pub struct Reloc {
     pub sec_id: usize,
     pub reloc_id: usize,
+    pub kind: BtfCoreRelocKind,
     pub local_type_id: u32,
     pub local_offset: usize,
     pub local_spec: Vec<usize>,
+    /// Target BTF that satisfied relocation: 0 for main target BTF, then module BTFs in order
+    /// they were added
+    pub targ_btf_idx: usize,
+    /// Target type matched by relocation; 0 if type-based relocation found no candidate
     pub targ_type_id: u32,
     pub targ_offset: usize,
     pub targ_spec: Vec<usize>,
+    /// Resolved values of relocation (byte offset, size, shifts, signedness or existence of field,
+    /// type ID, existence or size of type, existence or value of enumerator), that would be
+    /// patched into instruction, for local and target type
+    pub local_value: u64,
+    pub targ_value: u64,
+    /// Type (with modifiers and typedefs skipped) and byte size of accessed non-bitfield field,
+    /// which libbpf uses to adjust size of memory access; 0 for bitfields, unmatched fields and
+    /// non-field-based relocations
+    pub local_field_type_id: u32,
+    pub local_field_sz: u32,
+    pub targ_field_type_id: u32,
+    pub targ_field_sz: u32,
 }
 
 impl fmt::Display for Reloc {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if Relocator::relo_is_type_based(self.kind) {
+            return write!(
+                f,
+                "sec#{}, r#{}: {} [{}] = {} --> [{}] = {}",
+                self.sec_id,
+                self.reloc_id,
+                self.kind,
+                self.local_type_id,
+                self.local_value,
+                self.targ_type_id,
+                self.targ_value,
+            );
+        }
+        if Relocator::relo_is_enumval_based(self.kind) {
+            return write!(
+                f,
+                "sec#{}, r#{}: {} [{}] ({}) = {} --> [{}] ({}) = {}",
+                self.sec_id,
+                self.reloc_id,
+                self.kind,
+                self.local_type_id,
+                Relocator::spec_to_str(&self.local_spec),
+                self.local_value,
+                self.targ_type_id,
+                Relocator::spec_to_str(&self.targ_spec),
+                self.targ_value,
+            );
+        }
         write!(
             f,
-            "sec#{}, r#{}: [{}] + {} ({}) --> [{}] + {} ({})",
+            "sec#{}, r#{}: [{}] + {} ({}) --> [{}] + {} ({}), {}: {} --> {}",
             self.sec_id,
             self.reloc_id,
             self.local_type_id,
@@ -31,7 +78,18 @@ impl fmt::Display for Reloc {
             self.targ_type_id,
             self.targ_offset,
             Relocator::spec_to_str(&self.targ_spec),
-        )
+            self.kind,
+            self.local_value,
+            self.targ_value,
+        )?;
+        if self.kind == BtfCoreRelocKind::ByteOff && self.local_field_type_id != 0 {
+            write!(
+                f,
+                " (sz: {} --> {})",
+                self.local_field_sz, self.targ_field_sz
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -61,128 +119,801 @@ impl fmt::Display for Accessor {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct RelocatorCfg {
+    /// Collect log of candidate matching, available through Relocator::log() after relocation
     pub verbose: bool,
+    /// Number of threads relocating records in parallel (defaults to number of CPUs)
+    pub jobs: Option<usize>,
 }
 
-#[derive(Debug)]
+impl RelocatorCfg {
+    pub fn new() -> RelocatorCfg {
+        RelocatorCfg::default()
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> RelocatorCfg {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> RelocatorCfg {
+        self.jobs = Some(jobs);
+        self
+    }
+}
+
+/// Policy hook deciding which target types are considered as relocation candidates.
+///
+/// Called once per local type and target BTF with candidate ids found by name in that target BTF.
+/// Returned ids are matched in order and cached for subsequent relocations against the same local
+/// type. Selector can reorder or drop candidates, as well as add any other types of target BTF
+/// (e.g., renamed between kernel versions); IDs outside of target BTF are ignored. Selection
+/// happens before relocations are performed in parallel.
+pub trait CandidateSelector: Sync {
+    fn select(
+        &self,
+        local_btf: &Btf,
+        local_type_id: u32,
+        targ_btf: &Btf,
+        cand_ids: &[u32],
+    ) -> Vec<u32>;
+}
+
+impl<F> CandidateSelector for F
+where
+    F: Fn(&Btf, u32, &Btf, &[u32]) -> Vec<u32> + Sync,
+{
+    fn select(
+        &self,
+        local_btf: &Btf,
+        local_type_id: u32,
+        targ_btf: &Btf,
+        cand_ids: &[u32],
+    ) -> Vec<u32> {
+        self(local_btf, local_type_id, targ_btf, cand_ids)
+    }
+}
+
+/// Candidate target types matched against local field access spec, in order, as (target BTF
+/// index, type ID, target access spec or reason of mismatch).
+type SpecMatches = Vec<(usize, u32, Result<Vec<usize>, String>)>;
+
+const CACHE_MAGIC: &str = "btf-reloc-cache v1";
+
 pub struct Relocator<'a, 'b> {
     cfg: RelocatorCfg,
-    targ_btf: &'a Btf<'a>,
+    targ_btfs: Vec<&'a Btf<'a>>,
     local_btf: &'b Btf<'b>,
-    targ_index: BtfIndex<'a>,
-    type_map: HashMap<u32, Vec<u32>>,
+    targ_indexes: Vec<BtfIndex<'a>>,
+    type_map: HashMap<u32, Result<Vec<(usize, u32)>, String>>,
+    spec_map: HashMap<(u32, Vec<usize>), Result<SpecMatches, String>>,
+    /// Fingerprint of local and target BTFs, computed by load_cache()
+    fingerprint: Option<u64>,
+    selector: Option<Box<dyn CandidateSelector + 'a>>,
+    log: Vec<String>,
+}
+
+impl<'a, 'b> fmt::Debug for Relocator<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Relocator")
+            .field("cfg", &self.cfg)
+            .field("targ_btfs", &self.targ_btfs)
+            .field("local_btf", &self.local_btf)
+            .field("targ_indexes", &self.targ_indexes)
+            .field("type_map", &self.type_map)
+            .field("spec_map", &self.spec_map)
+            .field("fingerprint", &self.fingerprint)
+            .field("has_selector", &self.selector.is_some())
+            .field("log", &self.log)
+            .finish()
+    }
 }
 
 impl<'a, 'b> Relocator<'a, 'b> {
     pub fn new(targ_btf: &'a Btf, local_btf: &'b Btf, cfg: RelocatorCfg) -> Relocator<'a, 'b> {
         Relocator {
             cfg: cfg,
-            targ_btf: targ_btf,
+            targ_btfs: vec![targ_btf],
             local_btf: local_btf,
-            targ_index: BtfIndex::new(targ_btf),
+            targ_indexes: vec![BtfIndex::new(targ_btf)],
             type_map: HashMap::new(),
+            spec_map: HashMap::new(),
+            fingerprint: None,
+            selector: None,
+            log: Vec::new(),
         }
     }
 
-    pub fn relocate(&mut self) -> BtfResult<Vec<Reloc>> {
-        let mut relocs = Vec::new();
+    /// Add BTF of kernel module, split on top of main target BTF, as another source of relocation
+    /// candidates. Only module's own types are considered, base types are matched in main target
+    /// BTF only once.
+    pub fn add_module_btf(&mut self, btf: &'a Btf<'a>) {
+        self.targ_btfs.push(btf);
+        self.targ_indexes.push(BtfIndex::new(btf));
+        self.type_map.clear();
+        self.spec_map.clear();
+        self.fingerprint = None;
+    }
+
+    pub fn set_candidate_selector(&mut self, selector: Box<dyn CandidateSelector + 'a>) {
+        self.selector = Some(selector);
+        self.type_map.clear();
+        self.spec_map.clear();
+    }
+
+    /// Perform all CO-RE relocations of local BTF. Failure of one relocation doesn't stop the
+    /// rest, results are returned for each relocation record, in order of sections and their
+    /// records.
+    ///
+    /// Candidates are looked up once per local type upfront and field access specs are matched
+    /// against them once per distinct (local type, access spec) pair, after which records are
+    /// relocated independently of each other on multiple threads, as target BTFs are read-only.
+    /// Lookups and matches are cached across relocate() calls and can be persisted with
+    /// save_cache().
+    pub fn relocate(&mut self) -> Vec<BtfResult<Reloc>> {
+        let mut recs = Vec::new();
         for (sec_id, sec) in self.local_btf.core_reloc_secs().iter().enumerate() {
             for (reloc_id, rec) in sec.recs.iter().enumerate() {
-                let local_type = self.local_btf.type_by_id(rec.type_id);
-                let local_off = self.calc_off(self.local_btf, rec.type_id, &rec.access_spec)?;
-                let local_access =
-                    self.transform_access(self.local_btf, rec.type_id, &rec.access_spec)?;
-                if self.cfg.verbose {
-                    print!("sec#{}, r#{}: accessors = ", sec_id, reloc_id);
-                    for a in &local_access {
-                        print!("{}, ", a);
+                recs.push((sec_id, reloc_id, rec));
+            }
+        }
+        let mut spec_keys = Vec::new();
+        let mut seen = HashSet::new();
+        for &(_, _, rec) in &recs {
+            let local_id = self.cand_local_id(rec);
+            if !self.type_map.contains_key(&local_id) {
+                let cands = self.type_candidates(local_id);
+                self.type_map.insert(local_id, cands);
+            }
+            if Relocator::relo_is_field_based(rec.kind) {
+                let key = (rec.type_id, rec.access_spec.clone());
+                if !self.spec_map.contains_key(&key) && seen.insert(key.clone()) {
+                    spec_keys.push(key);
+                }
+            }
+        }
+
+        let this = &*self;
+        let matches = par_map(&spec_keys, self.cfg.jobs, |(local_id, spec)| {
+            this.spec_matches(*local_id, spec)
+        });
+        self.spec_map.extend(spec_keys.into_iter().zip(matches));
+
+        let this = &*self;
+        let results = par_map(&recs, self.cfg.jobs, |&(sec_id, reloc_id, rec)| {
+            let mut log = Vec::new();
+            let res = if Relocator::relo_is_type_based(rec.kind) {
+                this.relocate_type_based(sec_id, reloc_id, rec, &mut log)
+            } else if Relocator::relo_is_enumval_based(rec.kind) {
+                this.relocate_enumval_based(sec_id, reloc_id, rec, &mut log)
+            } else {
+                this.relocate_field_based(sec_id, reloc_id, rec, &mut log)
+            };
+            (res, log)
+        });
+        self.log.clear();
+        let mut relocs = Vec::new();
+        for (res, mut log) in results {
+            self.log.append(&mut log);
+            relocs.push(res);
+        }
+        relocs
+    }
+
+    /// Log of the last relocate() call in verbose mode, in order of relocation records.
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+
+    /// Write candidate lookups and access spec matches out to a file, to be reused by subsequent
+    /// runs relocating the same local BTF against the same target BTFs with load_cache().
+    pub fn save_cache(&self, path: &Path) -> BtfResult<()> {
+        let fingerprint = match self.fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => self.calc_fingerprint()?,
+        };
+        let mut out = format!("{} {:016x}\n", CACHE_MAGIC, fingerprint);
+        let mut type_ids = self.type_map.keys().collect::<Vec<_>>();
+        type_ids.sort();
+        for id in type_ids {
+            match &self.type_map[id] {
+                Ok(cands) => {
+                    write!(out, "type {} ok", id)?;
+                    for (btf_idx, targ_id) in cands {
+                        write!(out, " {}:{}", btf_idx, targ_id)?;
                     }
-                    println!("");
+                    writeln!(out)?;
                 }
+                Err(e) => writeln!(out, "type {} err {}", id, escape_cache_str(e))?,
+            }
+        }
+        let mut keys = self.spec_map.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let spec = Relocator::spec_to_str(&key.1);
+            match &self.spec_map[key] {
+                Ok(matches) => {
+                    writeln!(out, "spec {} {} ok", key.0, spec)?;
+                    for (btf_idx, targ_id, res) in matches {
+                        match res {
+                            Ok(s) => writeln!(
+                                out,
+                                "cand {} {} ok {}",
+                                btf_idx,
+                                targ_id,
+                                Relocator::spec_to_str(s)
+                            )?,
+                            Err(e) => writeln!(
+                                out,
+                                "cand {} {} err {}",
+                                btf_idx,
+                                targ_id,
+                                escape_cache_str(e)
+                            )?,
+                        }
+                    }
+                }
+                Err(e) => writeln!(out, "spec {} {} err {}", key.0, spec, escape_cache_str(e))?,
+            }
+        }
+        std::fs::write(path, out)?;
+        Ok(())
+    }
 
-                let mut targ_off = 0;
-                let mut targ_type_id = 0;
-                let mut targ_spec = Vec::new();
+    /// Load candidate lookups and access spec matches saved by save_cache(). Returns false,
+    /// leaving cache empty, if file doesn't exist or was saved for different local or target
+    /// BTFs. Candidate selector isn't accounted for, so cache should only be reused with the same
+    /// selector.
+    pub fn load_cache(&mut self, path: &Path) -> BtfResult<bool> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let fingerprint = self.calc_fingerprint()?;
+        self.fingerprint = Some(fingerprint);
+        let mut lines = data.lines();
+        let header = format!("{} {:016x}", CACHE_MAGIC, fingerprint);
+        if lines.next() != Some(header.as_str()) {
+            return Ok(false);
+        }
+        let mut type_map = HashMap::new();
+        let mut spec_map = HashMap::new();
+        let mut cur_key = None;
+        for (i, line) in lines.enumerate() {
+            let entry = match parse_cache_line(line) {
+                Some(entry) => entry,
+                None => return btf_error(format!("malformed cache line {}: '{}'", i + 2, line)),
+            };
+            match entry {
+                CacheLine::Type(id, cands) => {
+                    type_map.insert(id, cands);
+                }
+                CacheLine::Spec(key, res) => {
+                    spec_map.insert(key.clone(), res.map(|_| Vec::new()));
+                    cur_key = Some(key);
+                }
+                CacheLine::Cand(btf_idx, id, res) => {
+                    match cur_key.as_ref().and_then(|k| spec_map.get_mut(k)) {
+                        Some(Ok(matches)) => matches.push((btf_idx, id, res)),
+                        _ => {
+                            return btf_error(format!(
+                                "cache line {} doesn't follow matched spec",
+                                i + 2
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        self.type_map = type_map;
+        self.spec_map = spec_map;
+        Ok(true)
+    }
 
-                let mut matched_ids = Vec::new();
-                let cand_targ_ids = if self.type_map.contains_key(&rec.type_id) {
-                    self.type_map.get(&rec.type_id).unwrap()
-                } else {
-                    //TODO: strip __suffix, kernel version suffix, etc
-                    self.targ_index.get_by_name(local_type.name())
-                };
-                for &id in cand_targ_ids {
+    /// Hash of raw data of local and target BTFs, identifying cache contents. BTFs, whose types
+    /// were changed after loading, can't be identified, so can't be cached.
+    fn calc_fingerprint(&self) -> BtfResult<u64> {
+        let mut h = FNV1A_INIT;
+        for btf in std::iter::once(self.local_btf).chain(self.targ_btfs.iter().cloned()) {
+            let data = match btf.raw_data() {
+                Some(data) => data,
+                None => return btf_error("Can't cache relocations of modified BTF".to_owned()),
+            };
+            h = fnv1a_extend(h, &btf.start_id().to_le_bytes());
+            h = fnv1a_extend(h, &btf.ptr_sz().to_le_bytes());
+            h = fnv1a_extend(h, &(data.len() as u64).to_le_bytes());
+            h = fnv1a_extend(h, data);
+        }
+        Ok(h)
+    }
+
+    /// Local type, whose target candidates relocation is resolved against.
+    fn cand_local_id(&self, rec: &BtfExtCoreReloc) -> u32 {
+        if Relocator::relo_is_enumval_based(rec.kind) {
+            self.local_btf.skip_mods_and_typedefs(rec.type_id)
+        } else {
+            rec.type_id
+        }
+    }
+
+    /// Candidates of local type, looked up before relocating.
+    fn cached_candidates(&self, local_id: u32) -> BtfResult<&[(usize, u32)]> {
+        match self.type_map.get(&local_id) {
+            Some(Ok(cands)) => Ok(cands),
+            Some(Err(e)) => btf_error(e.clone()),
+            None => btf_error(format!("no candidates looked up for type id {}", local_id)),
+        }
+    }
+
+    /// Match local field access spec against each candidate target type of local type.
+    fn spec_matches(&self, local_id: u32, spec: &[usize]) -> Result<SpecMatches, String> {
+        let local_access = self
+            .transform_access(self.local_btf, local_id, spec)
+            .map_err(|e| e.to_string())?;
+        let cands = self
+            .cached_candidates(local_id)
+            .map_err(|e| e.to_string())?;
+        Ok(cands
+            .iter()
+            .map(|&(btf_idx, id)| {
+                let targ_btf = self.targ_btfs[btf_idx];
+                let res = self.calc_targ_spec(targ_btf, &local_access, id);
+                (btf_idx, id, res.map_err(|e| e.to_string()))
+            })
+            .collect())
+    }
+
+    /// Target matches of local field access spec, computed before relocating.
+    fn cached_spec_matches(&self, local_id: u32, spec: &[usize]) -> BtfResult<&SpecMatches> {
+        match self.spec_map.get(&(local_id, spec.to_vec())) {
+            Some(Ok(matches)) => Ok(matches),
+            Some(Err(e)) => btf_error(e.clone()),
+            None => btf_error(format!(
+                "no candidates matched for type id {}, spec {}",
+                local_id,
+                Relocator::spec_to_str(spec)
+            )),
+        }
+    }
+
+    /// Resolve field-based relocation: local access spec is matched against each candidate
+    /// target type with the same name (ignoring local ___flavor suffix), and all matching
+    /// candidates have to agree on offset and resolved value.
+    fn relocate_field_based(
+        &self,
+        sec_id: usize,
+        reloc_id: usize,
+        rec: &BtfExtCoreReloc,
+        log: &mut Vec<String>,
+    ) -> BtfResult<Reloc> {
+        let local_off = self.calc_off(self.local_btf, rec.type_id, &rec.access_spec)?;
+        let local_value =
+            Relocator::calc_field_value(self.local_btf, rec.kind, rec.type_id, &rec.access_spec)?;
+        let local_access = self.transform_access(self.local_btf, rec.type_id, &rec.access_spec)?;
+        let (local_field_type_id, local_field_sz) =
+            Relocator::field_type_and_size(self.local_btf, rec.type_id, &rec.access_spec)?;
+        if self.cfg.verbose {
+            let mut line = format!("sec#{}, r#{}: accessors = ", sec_id, reloc_id);
+            for a in &local_access {
+                write!(line, "{}, ", a)?;
+            }
+            log.push(line);
+        }
+
+        let mut targ_off = 0;
+        let mut targ_btf_idx = 0;
+        let mut targ_type_id = 0;
+        let mut targ_spec = Vec::new();
+        let mut targ_value = 0;
+
+        let mut targ_field_type_id = 0;
+        let mut targ_field_sz = 0;
+        let mut matched = false;
+        for (btf_idx, id, res) in self.cached_spec_matches(rec.type_id, &rec.access_spec)? {
+            let (btf_idx, id) = (*btf_idx, *id);
+            let targ_btf = self.targ_btfs[btf_idx];
+            if self.cfg.verbose {
+                log.push(format!(
+                    "sec#{}, r#{}: matching to {}[{}]",
+                    sec_id,
+                    reloc_id,
+                    targ_prefix(btf_idx),
+                    id
+                ));
+            }
+            match res {
+                Ok(spec) => {
                     if self.cfg.verbose {
-                        println!("sec#{}, r#{}: matching to [{}]", sec_id, reloc_id, id);
+                        log.push(format!(
+                            "sec#{}, r#{}: targ_spec: {}",
+                            sec_id,
+                            reloc_id,
+                            Relocator::spec_to_str(spec)
+                        ));
                     }
-                    match self.calc_targ_spec(&local_access, id) {
-                        Ok(spec) => {
-                            if self.cfg.verbose {
-                                println!(
-                                    "sec#{}, r#{}: targ_spec: {}",
-                                    sec_id,
-                                    reloc_id,
-                                    Relocator::spec_to_str(&spec)
-                                );
-                            }
-                            let off = self.calc_off(self.targ_btf, id, &spec)?;
-                            if !matched_ids.is_empty() {
-                                if off != targ_off {
-                                    btf_error(format!(
-                                        concat!(
-                                            "ambiguous offset for local type (id: {}, spec: {}),",
-                                            " at least 2 different target type matched",
-                                            " with different offsets: ",
-                                            "(id: {}, off: {}, spec: {}) vs ",
-                                            "(id: {}, off: {}, spec: {})"
-                                        ),
-                                        rec.type_id,
-                                        rec.access_spec_str,
-                                        targ_type_id,
-                                        targ_off,
-                                        Relocator::spec_to_str(&targ_spec),
-                                        id,
-                                        off,
-                                        Relocator::spec_to_str(&spec)
-                                    ))?;
-                                }
-                            } else {
-                                targ_off = off;
-                                targ_type_id = id;
-                                targ_spec = spec;
-                            }
-                            matched_ids.push(id);
-                        }
-                        Err(e) => {
-                            if self.cfg.verbose {
-                                println!(
-                                    "sec#{}, r#{}: failed to match targ [{}]: {}",
-                                    sec_id, reloc_id, id, e
-                                );
-                            }
-                            continue;
+                    let off = self.calc_off(targ_btf, id, spec)?;
+                    let value = Relocator::calc_field_value(targ_btf, rec.kind, id, spec)?;
+                    if matched {
+                        if off != targ_off || value != targ_value {
+                            btf_error(format!(
+                                concat!(
+                                    "ambiguous {} for local type (id: {}, spec: {}),",
+                                    " at least 2 different target type matched",
+                                    " with different offsets or values: ",
+                                    "(id: {}{}, off: {}, spec: {}, value: {}) vs ",
+                                    "(id: {}{}, off: {}, spec: {}, value: {})"
+                                ),
+                                rec.kind,
+                                rec.type_id,
+                                rec.access_spec_str,
+                                targ_prefix(targ_btf_idx),
+                                targ_type_id,
+                                targ_off,
+                                Relocator::spec_to_str(&targ_spec),
+                                targ_value,
+                                targ_prefix(btf_idx),
+                                id,
+                                off,
+                                Relocator::spec_to_str(spec),
+                                value
+                            ))?;
                         }
+                    } else {
+                        targ_off = off;
+                        targ_btf_idx = btf_idx;
+                        targ_type_id = id;
+                        (targ_field_type_id, targ_field_sz) =
+                            Relocator::field_type_and_size(targ_btf, id, spec)?;
+                        targ_spec = spec.clone();
+                        targ_value = value;
                     }
+                    matched = true;
                 }
-                if matched_ids.is_empty() {
-                    btf_error(format!("failed to find any candidate for reloc {}", rec))?;
+                Err(e) => {
+                    if self.cfg.verbose {
+                        log.push(format!(
+                            "sec#{}, r#{}: failed to match targ {}[{}]: {}",
+                            sec_id,
+                            reloc_id,
+                            targ_prefix(btf_idx),
+                            id,
+                            e
+                        ));
+                    }
+                    continue;
                 }
-                self.type_map.insert(rec.type_id, matched_ids);
-                relocs.push(Reloc {
-                    sec_id: sec_id,
-                    reloc_id: reloc_id,
-                    local_type_id: rec.type_id,
-                    local_offset: local_off as usize,
-                    local_spec: rec.access_spec.clone(),
-                    targ_type_id: targ_type_id,
-                    targ_offset: targ_off as usize,
-                    targ_spec: targ_spec,
-                });
             }
         }
-        Ok(relocs)
+        // non-existing field is a valid outcome of field_exists relocation
+        if !matched && rec.kind != BtfCoreRelocKind::FieldExists {
+            btf_error(format!("failed to find any candidate for reloc {}", rec))?;
+        }
+        Ok(Reloc {
+            sec_id: sec_id,
+            reloc_id: reloc_id,
+            kind: rec.kind,
+            local_type_id: rec.type_id,
+            local_offset: local_off as usize,
+            local_spec: rec.access_spec.clone(),
+            targ_btf_idx,
+            targ_type_id: targ_type_id,
+            targ_offset: targ_off as usize,
+            targ_spec: targ_spec,
+            local_value,
+            targ_value,
+            local_field_type_id,
+            local_field_sz,
+            targ_field_type_id,
+            targ_field_sz,
+        })
+    }
+
+    /// Resolve local_type_id/target_type_id/type_exists/type_matches/type_size relocation the
+    /// way libbpf does: candidates are target types of the same kind with the same name (ignoring
+    /// local ___flavor suffix), that are compatible with local type. If there are none,
+    /// relocation resolves to 0, all other candidates have to agree on resolved value.
+    fn relocate_type_based(
+        &self,
+        sec_id: usize,
+        reloc_id: usize,
+        rec: &BtfExtCoreReloc,
+        log: &mut Vec<String>,
+    ) -> BtfResult<Reloc> {
+        let local_value = self.type_reloc_value(self.local_btf, rec.kind, rec.type_id)?;
+        let mut targ_btf_idx = 0;
+        let mut targ_type_id = 0;
+        let mut targ_value = 0;
+        if rec.kind == BtfCoreRelocKind::LocalTypeId {
+            targ_type_id = rec.type_id;
+            targ_value = local_value;
+        } else {
+            let mut matched = false;
+            for &(btf_idx, id) in self.cached_candidates(rec.type_id)? {
+                let targ_btf = self.targ_btfs[btf_idx];
+                let compat = if rec.kind == BtfCoreRelocKind::TypeMatches {
+                    types_match(self.local_btf, rec.type_id, targ_btf, id)?
+                } else {
+                    self.are_types_compat(targ_btf, rec.type_id, id, 0)?
+                };
+                if self.cfg.verbose {
+                    log.push(format!(
+                        "sec#{}, r#{}: candidate {}[{}] {}",
+                        sec_id,
+                        reloc_id,
+                        targ_prefix(btf_idx),
+                        id,
+                        if compat { "matches" } else { "is incompatible" }
+                    ));
+                }
+                if !compat {
+                    continue;
+                }
+                let value = self.type_reloc_value(targ_btf, rec.kind, id)?;
+                if matched && value != targ_value {
+                    btf_error(format!(
+                        concat!(
+                            "ambiguous {} for local type (id: {}), at least 2 different ",
+                            "target types matched with different values: ",
+                            "(id: {}{}, value: {}) vs (id: {}{}, value: {})"
+                        ),
+                        rec.kind,
+                        rec.type_id,
+                        targ_prefix(targ_btf_idx),
+                        targ_type_id,
+                        targ_value,
+                        targ_prefix(btf_idx),
+                        id,
+                        value
+                    ))?;
+                }
+                if !matched {
+                    targ_btf_idx = btf_idx;
+                    targ_type_id = id;
+                    targ_value = value;
+                    matched = true;
+                }
+            }
+        }
+        Ok(Reloc {
+            sec_id,
+            reloc_id,
+            kind: rec.kind,
+            local_type_id: rec.type_id,
+            local_offset: 0,
+            local_spec: rec.access_spec.clone(),
+            targ_btf_idx,
+            targ_type_id,
+            targ_offset: 0,
+            targ_spec: Vec::new(),
+            local_value,
+            targ_value,
+            local_field_type_id: 0,
+            local_field_sz: 0,
+            targ_field_type_id: 0,
+            targ_field_sz: 0,
+        })
+    }
+
+    /// Resolve enumval_exists/enumval_value relocation: enumerator of local enum, selected by
+    /// access spec, is looked up by name (ignoring ___flavor suffixes) in candidate enums, which
+    /// can be either 32-bit or 64-bit ones. If it's not found anywhere, enumval_exists resolves
+    /// to 0, while enumval_value fails.
+    fn relocate_enumval_based(
+        &self,
+        sec_id: usize,
+        reloc_id: usize,
+        rec: &BtfExtCoreReloc,
+        log: &mut Vec<String>,
+    ) -> BtfResult<Reloc> {
+        let local_id = self.local_btf.skip_mods_and_typedefs(rec.type_id);
+        let local_vals = match enum_values(self.local_btf, local_id)? {
+            Some(vals) => vals,
+            None => spec_error(
+                &rec.access_spec,
+                0,
+                "must be enum",
+                local_id,
+                self.local_btf.try_type_by_id(local_id)?,
+            )?,
+        };
+        let (local_name, local_val) = match local_vals.get(rec.access_spec[0]) {
+            Some(&v) => v,
+            None => spec_error(
+                &rec.access_spec,
+                0,
+                "enumerator index out of range",
+                local_id,
+                self.local_btf.try_type_by_id(local_id)?,
+            )?,
+        };
+        let is_exists = rec.kind == BtfCoreRelocKind::EnumvalExists;
+        let local_value = if is_exists { 1 } else { local_val };
+
+        let mut matched: Option<(usize, u32, usize, u64)> = None;
+        for &(btf_idx, id) in self.cached_candidates(local_id)? {
+            let targ_vals = match enum_values(self.targ_btfs[btf_idx], id)? {
+                Some(vals) => vals,
+                None => continue,
+            };
+            let idx = targ_vals
+                .iter()
+                .position(|&(name, _)| essential_name(name) == essential_name(local_name));
+            let idx = match idx {
+                Some(idx) => idx,
+                None => {
+                    if self.cfg.verbose {
+                        log.push(format!(
+                            "sec#{}, r#{}: candidate {}[{}] has no enumerator '{}'",
+                            sec_id,
+                            reloc_id,
+                            targ_prefix(btf_idx),
+                            id,
+                            local_name
+                        ));
+                    }
+                    continue;
+                }
+            };
+            let value = if is_exists { 1 } else { targ_vals[idx].1 };
+            if self.cfg.verbose {
+                log.push(format!(
+                    "sec#{}, r#{}: candidate {}[{}] matches enumerator #{} = {}",
+                    sec_id,
+                    reloc_id,
+                    targ_prefix(btf_idx),
+                    id,
+                    idx,
+                    targ_vals[idx].1
+                ));
+            }
+            match matched {
+                Some((targ_idx, targ_id, _, targ_value)) if targ_value != value => {
+                    btf_error(format!(
+                        concat!(
+                            "ambiguous {} for local enumerator {} (id: {}), at least 2 different ",
+                            "target types matched with different values: ",
+                            "(id: {}{}, value: {}) vs (id: {}{}, value: {})"
+                        ),
+                        rec.kind,
+                        local_name,
+                        rec.type_id,
+                        targ_prefix(targ_idx),
+                        targ_id,
+                        targ_value,
+                        targ_prefix(btf_idx),
+                        id,
+                        value
+                    ))?
+                }
+                Some(_) => {}
+                None => matched = Some((btf_idx, id, idx, value)),
+            }
+        }
+        let (targ_btf_idx, targ_type_id, targ_spec, targ_value) = match matched {
+            Some((btf_idx, id, idx, value)) => (btf_idx, id, vec![idx], value),
+            None if is_exists => (0, 0, Vec::new(), 0),
+            None => btf_error(format!("failed to find any candidate for reloc {}", rec))?,
+        };
+        Ok(Reloc {
+            sec_id,
+            reloc_id,
+            kind: rec.kind,
+            local_type_id: rec.type_id,
+            local_offset: 0,
+            local_spec: rec.access_spec.clone(),
+            targ_btf_idx,
+            targ_type_id,
+            targ_offset: 0,
+            targ_spec,
+            local_value,
+            targ_value,
+            local_field_type_id: 0,
+            local_field_sz: 0,
+            targ_field_type_id: 0,
+            targ_field_sz: 0,
+        })
+    }
+
+    fn type_reloc_value(&self, btf: &Btf, kind: BtfCoreRelocKind, type_id: u32) -> BtfResult<u64> {
+        Ok(match kind {
+            BtfCoreRelocKind::LocalTypeId | BtfCoreRelocKind::TargetTypeId => u64::from(type_id),
+            BtfCoreRelocKind::TypeSize => u64::from(Relocator::type_size(btf, type_id)?),
+            _ => 1,
+        })
+    }
+
+    /// Target types with the same essential name and kind as local type (local type name might
+    /// have ___flavor suffix, target type won't).
+    fn type_candidates(&self, local_id: u32) -> Result<Vec<(usize, u32)>, String> {
+        let local_type = self
+            .local_btf
+            .try_type_by_id(local_id)
+            .map_err(|e| e.to_string())?;
+        if local_type.name().is_empty() {
+            return Err(format!(
+                "relocation against anonymous type, id: {}, type: {}",
+                local_id, local_type
+            ));
+        }
+        self.candidates(local_id, essential_name(local_type.name()), |t| {
+            are_kinds_core_compat(local_type, t)
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    /// Target types with given name, accepted by filter and candidate selector, from main target
+    /// BTF, followed by module BTFs' own types, as (target BTF index, type ID) pairs.
+    fn candidates<F>(&self, local_id: u32, name: &str, filter: F) -> BtfResult<Vec<(usize, u32)>>
+    where
+        F: Fn(&BtfType) -> bool,
+    {
+        let mut cands = Vec::new();
+        for (btf_idx, (btf, index)) in self.targ_btfs.iter().zip(&self.targ_indexes).enumerate() {
+            let start_id = if btf_idx == 0 { 0 } else { btf.start_id() };
+            let mut ids = Vec::new();
+            for &id in index.get_by_name(name) {
+                if id >= start_id && filter(btf.try_type_by_id(id)?) {
+                    ids.push(id);
+                }
+            }
+            let ids = match &self.selector {
+                Some(s) => s.select(self.local_btf, local_id, btf, &ids),
+                None => ids,
+            };
+            let type_cnt = btf.types().len() as u32;
+            cands.extend(
+                ids.into_iter()
+                    .filter(|&id| id > 0 && id < type_cnt)
+                    .map(|id| (btf_idx, id)),
+            );
+        }
+        Ok(cands)
+    }
+
+    /// Type compatibility, as defined by libbpf for type-based relocations: modifiers and
+    /// typedefs are skipped, kinds have to match (so struct is never compatible with union),
+    /// pointers, arrays and function prototypes are compared recursively, while names, sizes and
+    /// members don't matter.
+    fn are_types_compat(
+        &self,
+        targ_btf: &Btf,
+        local_id: u32,
+        targ_id: u32,
+        depth: usize,
+    ) -> BtfResult<bool> {
+        if depth > MAX_COMPAT_DEPTH {
+            return Ok(false);
+        }
+        let local_id = self.local_btf.skip_mods_and_typedefs(local_id);
+        let targ_id = targ_btf.skip_mods_and_typedefs(targ_id);
+        let local_type = self.local_btf.try_type_by_id(local_id)?;
+        let targ_type = targ_btf.try_type_by_id(targ_id)?;
+        if !are_kinds_core_compat(local_type, targ_type) {
+            return Ok(false);
+        }
+        Ok(match (local_type, targ_type) {
+            (BtfType::Int(l), BtfType::Int(t)) => l.offset == 0 && t.offset == 0,
+            (BtfType::Ptr(l), BtfType::Ptr(t)) => {
+                self.are_types_compat(targ_btf, l.type_id, t.type_id, depth + 1)?
+            }
+            (BtfType::Array(l), BtfType::Array(t)) => {
+                self.are_types_compat(targ_btf, l.val_type_id, t.val_type_id, depth + 1)?
+            }
+            (BtfType::FuncProto(l), BtfType::FuncProto(t)) => {
+                if l.params.len() != t.params.len() {
+                    return Ok(false);
+                }
+                for (l, t) in self.local_btf.params(l).iter().zip(targ_btf.params(t)) {
+                    if !self.are_types_compat(targ_btf, l.type_id, t.type_id, depth + 1)? {
+                        return Ok(false);
+                    }
+                }
+                self.are_types_compat(targ_btf, l.res_type_id, t.res_type_id, depth + 1)?
+            }
+            _ => true,
+        })
     }
 
     fn transform_access(
@@ -199,9 +930,9 @@ impl<'a, 'b> Relocator<'a, 'b> {
         });
         for i in 1..spec.len() {
             id = btf.skip_mods_and_typedefs(id);
-            match btf.type_by_id(id) {
+            match btf.try_type_by_id(id)? {
                 BtfType::Struct(t) => {
-                    let m = &t.members[spec[i]];
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
                     let next_id = btf.skip_mods_and_typedefs(m.type_id);
                     if !m.name.is_empty() {
                         res.push(Accessor::Field {
@@ -213,7 +944,7 @@ impl<'a, 'b> Relocator<'a, 'b> {
                     id = next_id;
                 }
                 BtfType::Union(t) => {
-                    let m = &t.members[spec[i]];
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
                     let next_id = btf.skip_mods_and_typedefs(m.type_id);
                     if !m.name.is_empty() {
                         res.push(Accessor::Field {
@@ -236,7 +967,7 @@ impl<'a, 'b> Relocator<'a, 'b> {
                     i,
                     "must be struct/union/array",
                     id,
-                    btf.type_by_id(id),
+                    btf.try_type_by_id(id)?,
                 )?,
             }
         }
@@ -245,23 +976,24 @@ impl<'a, 'b> Relocator<'a, 'b> {
 
     fn calc_off(&self, btf: &Btf, type_id: u32, spec: &[usize]) -> BtfResult<u32> {
         let mut id = btf.skip_mods_and_typedefs(type_id);
-        let mut off = spec[0] as u32 * Relocator::type_size(btf, id)?;
+        let mut off = spec_off(spec, 0, Relocator::type_size(btf, id)?, 1)?;
 
         for i in 1..spec.len() {
             id = btf.skip_mods_and_typedefs(id);
-            match btf.type_by_id(id) {
+            match btf.try_type_by_id(id)? {
                 BtfType::Struct(t) => {
-                    let m = &t.members[spec[i]];
-                    off += m.bit_offset / 8;
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
+                    off = spec_add(spec, off, m.bit_offset / 8)?;
                     id = m.type_id;
                 }
                 BtfType::Union(t) => {
-                    let m = &t.members[spec[i]];
-                    off += m.bit_offset / 8;
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
+                    off = spec_add(spec, off, m.bit_offset / 8)?;
                     id = m.type_id;
                 }
                 BtfType::Array(t) => {
-                    off += spec[i] as u32 * Relocator::type_size(btf, t.val_type_id)?;
+                    let elem_off = spec_off(spec, i, Relocator::type_size(btf, t.val_type_id)?, 1)?;
+                    off = spec_add(spec, off, elem_off)?;
                     id = t.val_type_id;
                 }
                 _ => spec_error(
@@ -269,17 +1001,136 @@ impl<'a, 'b> Relocator<'a, 'b> {
                     i,
                     "must be struct/union/array",
                     id,
-                    btf.type_by_id(id),
+                    btf.try_type_by_id(id)?,
                 )?,
             }
         }
         Ok(off)
     }
 
-    fn calc_targ_spec(&self, local_spec: &[Accessor], mut targ_id: u32) -> BtfResult<Vec<usize>> {
-        targ_id = self.targ_btf.skip_mods_and_typedefs(targ_id);
-        let mut targ_type = self.targ_btf.type_by_id(targ_id);
+    /// Value of field-based relocation, computed the way libbpf does it. For bitfields, byte
+    /// offset and size are those of the smallest naturally aligned integer, containing the whole
+    /// bitfield, and shifts extract the bitfield from it, once it's loaded into u64.
+    fn calc_field_value(
+        btf: &Btf,
+        kind: BtfCoreRelocKind,
+        type_id: u32,
+        spec: &[usize],
+    ) -> BtfResult<u64> {
+        let mut id = btf.skip_mods_and_typedefs(type_id);
+        let mut bit_off = spec_off(spec, 0, Relocator::type_size(btf, id)?, 8)?;
+        // member accessed last, None for array element access
+        let mut member = None;
+        for i in 1..spec.len() {
+            id = btf.skip_mods_and_typedefs(id);
+            match btf.try_type_by_id(id)? {
+                BtfType::Struct(t) | BtfType::Union(t) => {
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
+                    bit_off = spec_add(spec, bit_off, m.bit_offset)?;
+                    id = m.type_id;
+                    member = Some(m);
+                }
+                BtfType::Array(t) => {
+                    let elem_off = spec_off(spec, i, Relocator::type_size(btf, t.val_type_id)?, 8)?;
+                    bit_off = spec_add(spec, bit_off, elem_off)?;
+                    id = t.val_type_id;
+                    member = None;
+                }
+                _ => spec_error(
+                    spec,
+                    i,
+                    "must be struct/union/array",
+                    id,
+                    btf.try_type_by_id(id)?,
+                )?,
+            }
+        }
+        let m = match (member, kind) {
+            (_, BtfCoreRelocKind::FieldExists) => return Ok(1),
+            (None, BtfCoreRelocKind::ByteOff) => return Ok(u64::from(bit_off / 8)),
+            (None, BtfCoreRelocKind::ByteSz) => {
+                return Ok(u64::from(Relocator::type_size(btf, id)?))
+            }
+            (None, _) => btf_error(format!(
+                "{} relocation can't be applied to array access {}",
+                kind,
+                Relocator::spec_to_str(spec)
+            ))?,
+            (Some(m), _) => m,
+        };
+        let mt_id = btf.skip_mods_and_typedefs(m.type_id);
+        let mut byte_sz = Relocator::type_size(btf, mt_id)?;
+        let (byte_off, bit_sz) = if m.bit_size > 0 {
+            let bit_sz = u32::from(m.bit_size);
+            if byte_sz == 0 {
+                return btf_error(format!("zero-sized bitfield type, type_id: {}", mt_id));
+            }
+            let mut byte_off = bit_off / 8 / byte_sz * byte_sz;
+            let bit_end = spec_add(spec, bit_off, bit_sz)?;
+            // smallest integer that can be loaded to get the whole bitfield
+            while bit_end - byte_off * 8 > byte_sz * 8 {
+                if byte_sz >= 8 {
+                    return btf_error(format!(
+                        "bitfield at bit offset {} of {} bits can't be read with 64-bit load",
+                        bit_off, bit_sz
+                    ));
+                }
+                byte_sz *= 2;
+                byte_off = bit_off / 8 / byte_sz * byte_sz;
+            }
+            (byte_off, bit_sz)
+        } else {
+            let bit_sz = byte_sz.checked_mul(8).ok_or_else(|| {
+                BtfError::new_owned(format!("byte size {} of field overflows u32 bits", byte_sz))
+            })?;
+            (bit_off / 8, bit_sz)
+        };
+        Ok(u64::from(match kind {
+            BtfCoreRelocKind::ByteOff => byte_off,
+            BtfCoreRelocKind::ByteSz => byte_sz,
+            BtfCoreRelocKind::Signed => match btf.try_type_by_id(mt_id)? {
+                BtfType::Int(t) => (t.encoding == BtfIntEncoding::Signed) as u32,
+                // kind_flag (signedness) of 32-bit enums isn't preserved, but compilers only
+                // make enums signed if they have negative values
+                BtfType::Enum(t) => btf.enum_values(t).iter().any(|v| v.value < 0) as u32,
+                BtfType::Enum64(t) => t.signed as u32,
+                _ => 0,
+            },
+            BtfCoreRelocKind::LShiftU64 => {
+                let shift = match btf.endian() {
+                    scroll::Endian::Little => 64u32.checked_sub(bit_off - byte_off * 8 + bit_sz),
+                    scroll::Endian::Big => {
+                        (8u32.checked_sub(byte_sz)).map(|pad| pad * 8 + (bit_off - byte_off * 8))
+                    }
+                };
+                shift.ok_or_else(|| {
+                    BtfError::new_owned(format!(
+                        "field of {} bytes can't be read with 64-bit load",
+                        byte_sz
+                    ))
+                })?
+            }
+            BtfCoreRelocKind::RShiftU64 => 64u32.checked_sub(bit_sz).ok_or_else(|| {
+                BtfError::new_owned(format!(
+                    "field of {} bits can't be read with 64-bit load",
+                    bit_sz
+                ))
+            })?,
+            _ => btf_error(format!("{} is not a field-based relocation", kind))?,
+        }))
+    }
+
+    fn calc_targ_spec(
+        &self,
+        targ_btf: &Btf,
+        local_spec: &[Accessor],
+        mut targ_id: u32,
+    ) -> BtfResult<Vec<usize>> {
+        targ_id = targ_btf.skip_mods_and_typedefs(targ_id);
+        let mut targ_type = targ_btf.try_type_by_id(targ_id)?;
         let mut targ_spec = Vec::new();
+        // whether last matched target accessor is the last member of its struct
+        let mut targ_is_last_field = false;
 
         match local_spec[0] {
             Accessor::Array { arr_idx, .. } => targ_spec.push(arr_idx),
@@ -294,9 +1145,20 @@ impl<'a, 'b> Relocator<'a, 'b> {
             match s {
                 &Accessor::Array { arr_idx, .. } => match targ_type {
                     BtfType::Array(t) => {
-                        targ_id = self.targ_btf.skip_mods_and_typedefs(t.val_type_id);
-                        targ_type = self.targ_btf.type_by_id(targ_id);
+                        // flexible array member can be indexed beyond its zero size
+                        if arr_idx >= t.nelems as usize && !(t.nelems == 0 && targ_is_last_field) {
+                            access_error(
+                                s,
+                                i,
+                                "target array index out of bounds",
+                                targ_id,
+                                targ_type,
+                            )?
+                        }
+                        targ_id = targ_btf.skip_mods_and_typedefs(t.val_type_id);
+                        targ_type = targ_btf.try_type_by_id(targ_id)?;
                         targ_spec.push(arr_idx);
+                        targ_is_last_field = false;
                     }
                     _ => access_error(s, i, "target must be array", targ_id, targ_type)?,
                 },
@@ -305,24 +1167,40 @@ impl<'a, 'b> Relocator<'a, 'b> {
                     field_idx,
                     ..
                 } => {
-                    let local_type = self.local_btf.type_by_id(*local_id);
+                    let local_type = self.local_btf.try_type_by_id(*local_id)?;
                     let local_members = match local_type {
-                        BtfType::Struct(t) => &t.members,
-                        BtfType::Union(t) => &t.members,
+                        BtfType::Struct(t) => self.local_btf.members(t),
+                        BtfType::Union(t) => self.local_btf.members(t),
                         _ => {
                             access_error(s, i, "local must be struct/union", *local_id, local_type)?
                         }
                     };
-                    let local_member = &local_members[*field_idx];
+                    let local_member = match local_members.get(*field_idx) {
+                        Some(m) => m,
+                        None => access_error(
+                            s,
+                            i,
+                            "local member index out of range",
+                            *local_id,
+                            local_type,
+                        )?,
+                    };
                     let targ_members = match targ_type {
-                        BtfType::Struct(t) => &t.members,
-                        BtfType::Union(t) => &t.members,
+                        BtfType::Struct(t) => targ_btf.members(t),
+                        BtfType::Union(t) => targ_btf.members(t),
                         _ => access_error(s, i, "target must be struct/union", targ_id, targ_type)?,
                     };
-                    match self.targ_member_spec(local_member, targ_members) {
+                    match self.targ_member_spec(targ_btf, local_member, targ_members) {
                         Ok(Some((t_id, mut t_spec))) => {
+                            let mut members: &[BtfMember] = targ_members;
+                            for &idx in &t_spec[..t_spec.len() - 1] {
+                                members = self
+                                    .get_composite_members(targ_btf, members[idx].type_id)?
+                                    .unwrap_or_default();
+                            }
+                            targ_is_last_field = t_spec.last() == Some(&(members.len() - 1));
                             targ_id = t_id;
-                            targ_type = self.targ_btf.type_by_id(targ_id);
+                            targ_type = targ_btf.try_type_by_id(targ_id)?;
                             targ_spec.append(&mut t_spec);
                         }
                         Ok(None) => {
@@ -338,14 +1216,15 @@ impl<'a, 'b> Relocator<'a, 'b> {
 
     fn targ_member_spec(
         &self,
+        targ_btf: &Btf,
         local_member: &BtfMember,
         targ_members: &[BtfMember],
     ) -> BtfResult<Option<(u32, Vec<usize>)>> {
         for (i, m) in targ_members.iter().enumerate() {
             if m.name == local_member.name {
                 let local_id = self.local_btf.skip_mods_and_typedefs(local_member.type_id);
-                let targ_id = self.targ_btf.skip_mods_and_typedefs(m.type_id);
-                if self.are_kinds_compat(local_id, targ_id) {
+                let targ_id = targ_btf.skip_mods_and_typedefs(m.type_id);
+                if self.are_fields_compat(targ_btf, local_id, targ_id)? {
                     return Ok(Some((targ_id, vec![i])));
                 } else {
                     return btf_error(format!(
@@ -356,14 +1235,14 @@ impl<'a, 'b> Relocator<'a, 'b> {
                         ),
                         local_member.name,
                         local_id,
-                        self.local_btf.type_by_id(local_id).kind(),
+                        self.local_btf.try_type_by_id(local_id)?.kind(),
                         targ_id,
-                        self.targ_btf.type_by_id(targ_id).kind()
+                        targ_btf.try_type_by_id(targ_id)?.kind()
                     ));
                 }
             } else if m.name.is_empty() {
-                if let Some(members) = self.get_composite_members(self.targ_btf, m.type_id) {
-                    match self.targ_member_spec(local_member, members) {
+                if let Some(members) = self.get_composite_members(targ_btf, m.type_id)? {
+                    match self.targ_member_spec(targ_btf, local_member, members) {
                         Ok(Some((t_id, mut spec))) => {
                             spec.insert(0, i);
                             return Ok(Some((t_id, spec)));
@@ -377,34 +1256,97 @@ impl<'a, 'b> Relocator<'a, 'b> {
         Ok(None)
     }
 
-    fn get_composite_members<'c>(&self, btf: &'c Btf, type_id: u32) -> Option<&'c [BtfMember<'c>]> {
+    fn get_composite_members<'c>(
+        &self,
+        btf: &'c Btf,
+        type_id: u32,
+    ) -> BtfResult<Option<&'c [BtfMember<'c>]>> {
         let id = btf.skip_mods(type_id);
-        match btf.type_by_id(id) {
-            BtfType::Struct(t) => Some(&t.members),
-            BtfType::Union(t) => Some(&t.members),
+        Ok(match btf.try_type_by_id(id)? {
+            BtfType::Struct(t) => Some(btf.members(t)),
+            BtfType::Union(t) => Some(btf.members(t)),
             _ => None,
+        })
+    }
+
+    /// Field type compatibility, as defined by libbpf's bpf_core_fields_are_compat(): any two
+    /// structs/unions are compatible, as are any pointers, floats and non-bitfield integers
+    /// (regardless of size and signedness); enums (32-bit or 64-bit) and forward declarations
+    /// must have the same names (ignoring ___flavor suffixes), unless one of them is anonymous;
+    /// arrays are compatible if their element types are.
+    fn are_fields_compat(
+        &self,
+        targ_btf: &Btf,
+        mut local_id: u32,
+        mut targ_id: u32,
+    ) -> BtfResult<bool> {
+        loop {
+            local_id = self.local_btf.skip_mods_and_typedefs(local_id);
+            targ_id = targ_btf.skip_mods_and_typedefs(targ_id);
+            let local_type = self.local_btf.try_type_by_id(local_id)?;
+            let targ_type = targ_btf.try_type_by_id(targ_id)?;
+            let is_composite = |t: &BtfType| matches!(t, BtfType::Struct(_) | BtfType::Union(_));
+            if is_composite(local_type) && is_composite(targ_type) {
+                return Ok(true);
+            }
+            if !are_kinds_core_compat(local_type, targ_type) {
+                return Ok(false);
+            }
+            return Ok(match (local_type, targ_type) {
+                (BtfType::Ptr(_), _) | (BtfType::Float(_), _) => true,
+                (BtfType::Fwd(_), _) | (BtfType::Enum(_), _) | (BtfType::Enum64(_), _) => {
+                    let local_name = essential_name(local_type.name());
+                    let targ_name = essential_name(targ_type.name());
+                    local_name.is_empty() || targ_name.is_empty() || local_name == targ_name
+                }
+                (BtfType::Int(l), BtfType::Int(t)) => l.offset == 0 && t.offset == 0,
+                (BtfType::Array(l), BtfType::Array(t)) => {
+                    local_id = l.val_type_id;
+                    targ_id = t.val_type_id;
+                    continue;
+                }
+                _ => false,
+            });
         }
     }
 
-    fn are_kinds_compat(&self, local_id: u32, targ_id: u32) -> bool {
-        let local_kind = self.local_btf.type_by_id(local_id).kind();
-        let targ_kind = self.targ_btf.type_by_id(targ_id).kind();
-        local_kind == targ_kind || (local_kind == BtfKind::Struct && targ_kind == BtfKind::Union)
+    /// Type of non-bitfield field (or array element) accessed by access spec, with modifiers and
+    /// typedefs skipped, and its byte size; zeroes for bitfields.
+    fn field_type_and_size(btf: &Btf, type_id: u32, spec: &[usize]) -> BtfResult<(u32, u32)> {
+        let mut id = btf.skip_mods_and_typedefs(type_id);
+        for &idx in &spec[1..] {
+            id = match btf.try_type_by_id(id)? {
+                BtfType::Struct(t) | BtfType::Union(t) => match btf.members(t).get(idx) {
+                    Some(m) if m.bit_size == 0 => btf.skip_mods_and_typedefs(m.type_id),
+                    _ => return Ok((0, 0)),
+                },
+                BtfType::Array(t) => btf.skip_mods_and_typedefs(t.val_type_id),
+                _ => return Ok((0, 0)),
+            };
+        }
+        Ok((id, btf.get_size_of(id)))
     }
 
     fn type_size(btf: &Btf, type_id: u32) -> BtfResult<u32> {
         let id = btf.skip_mods_and_typedefs(type_id);
-        Ok(match btf.type_by_id(id) {
+        Ok(match btf.try_type_by_id(id)? {
             BtfType::Int(t) if t.offset == 0 && t.bits % 8 == 0 => t.bits / 8,
             BtfType::Enum(t) => t.sz,
+            BtfType::Enum64(t) => t.sz,
+            BtfType::Float(t) => t.sz,
             BtfType::Struct(t) => t.sz,
             BtfType::Union(t) => t.sz,
-            BtfType::Array(t) => t.nelems * Relocator::type_size(btf, t.val_type_id)?,
+            BtfType::Array(t) => t
+                .nelems
+                .checked_mul(Relocator::type_size(btf, t.val_type_id)?)
+                .ok_or_else(|| {
+                    BtfError::new_owned(format!("byte size of type_id: {} overflows u32", id))
+                })?,
             BtfType::Ptr(_) => btf.ptr_sz(),
             _ => btf_error(format!(
                 "can't calculate byte size of type_id: {}, type: {}",
                 id,
-                btf.type_by_id(id),
+                btf.try_type_by_id(id)?,
             ))?,
         })
     }
@@ -443,7 +1385,7 @@ impl<'a, 'b> Relocator<'a, 'b> {
         let mut buf = String::new();
         let spec = &rec.access_spec;
         let mut id = rec.type_id;
-        match btf.type_by_id(id) {
+        match btf.try_type_by_id(id)? {
             BtfType::Struct(t) => {
                 write!(
                     buf,
@@ -472,6 +1414,13 @@ impl<'a, 'b> Relocator<'a, 'b> {
                     if t.name.is_empty() { "<anon>" } else { &t.name }
                 )?;
             }
+            BtfType::Enum64(t) => {
+                write!(
+                    buf,
+                    "enum64 {}",
+                    if t.name.is_empty() { "<anon>" } else { &t.name }
+                )?;
+            }
             BtfType::Ptr(t) => {
                 write!(buf, "ptr -> [{}]", t.type_id)?;
             }
@@ -486,7 +1435,7 @@ impl<'a, 'b> Relocator<'a, 'b> {
                 0,
                 "must be struct/union/typedef/enum/ptr/array",
                 id,
-                btf.type_by_id(id),
+                btf.try_type_by_id(id)?,
             )?,
         }
 
@@ -496,12 +1445,38 @@ impl<'a, 'b> Relocator<'a, 'b> {
 
         if Relocator::relo_is_enumval_based(rec.kind) {
             id = btf.skip_mods_and_typedefs(rec.type_id);
-            match btf.type_by_id(id) {
+            match btf.try_type_by_id(id)? {
                 BtfType::Enum(t) => {
-                    let e = &t.values[spec[0]];
+                    let e = match btf.enum_values(t).get(spec[0]) {
+                        Some(e) => e,
+                        None => spec_error(
+                            spec,
+                            0,
+                            "enumerator index out of range",
+                            id,
+                            btf.try_type_by_id(id)?,
+                        )?,
+                    };
                     write!(buf, "::{} = {}", &e.name, e.value)?;
                 }
-                _ => spec_error(spec, 0, "must be enum", id, btf.type_by_id(id))?,
+                BtfType::Enum64(t) => {
+                    let e = match btf.enum64_values(t).get(spec[0]) {
+                        Some(e) => e,
+                        None => spec_error(
+                            spec,
+                            0,
+                            "enumerator index out of range",
+                            id,
+                            btf.try_type_by_id(id)?,
+                        )?,
+                    };
+                    if t.signed {
+                        write!(buf, "::{} = {}", &e.name, e.value)?;
+                    } else {
+                        write!(buf, "::{} = {}", &e.name, e.value as u64)?;
+                    }
+                }
+                _ => spec_error(spec, 0, "must be enum", id, btf.try_type_by_id(id)?)?,
             }
             return Ok(buf);
         }
@@ -516,14 +1491,14 @@ impl<'a, 'b> Relocator<'a, 'b> {
         }
 
         for i in 1..spec.len() {
-            match btf.type_by_id(id) {
+            match btf.try_type_by_id(id)? {
                 BtfType::Struct(t) => {
-                    let m = &t.members[spec[i] as usize];
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
                     write!(buf, ".{}", m.name)?;
                     id = btf.skip_mods_and_typedefs(m.type_id);
                 }
                 BtfType::Union(t) => {
-                    let m = &t.members[spec[i] as usize];
+                    let m = spec_member(btf, spec, i, id, btf.members(t))?;
                     if !m.name.is_empty() {
                         write!(buf, ".{}", m.name)?;
                     } else {
@@ -540,7 +1515,7 @@ impl<'a, 'b> Relocator<'a, 'b> {
                     i,
                     "must be struct/union/array",
                     id,
-                    btf.type_by_id(id),
+                    btf.try_type_by_id(id)?,
                 )?,
             }
         }
@@ -555,6 +1530,247 @@ impl<'a, 'b> Relocator<'a, 'b> {
     }
 }
 
+const MAX_COMPAT_DEPTH: usize = 32;
+
+/// Entry of saved relocation cache: candidates of local type, match result of local access spec
+/// (followed by its candidates' matches) or target access spec of one candidate.
+enum CacheLine {
+    Type(u32, Result<Vec<(usize, u32)>, String>),
+    Spec((u32, Vec<usize>), Result<(), String>),
+    Cand(usize, u32, Result<Vec<usize>, String>),
+}
+
+fn parse_cache_line(line: &str) -> Option<CacheLine> {
+    let parts = line.splitn(5, ' ').collect::<Vec<_>>();
+    let tail = |idx: usize| unescape_cache_str(parts.get(idx).unwrap_or(&""));
+    Some(match parts.as_slice() {
+        ["type", id, "ok", ..] => {
+            let mut cands = Vec::new();
+            for c in line.split(' ').skip(3) {
+                let (btf_idx, targ_id) = c.split_once(':')?;
+                cands.push((btf_idx.parse().ok()?, targ_id.parse().ok()?));
+            }
+            CacheLine::Type(id.parse().ok()?, Ok(cands))
+        }
+        ["type", id, "err", ..] => {
+            let e = line.splitn(4, ' ').nth(3).unwrap_or("");
+            CacheLine::Type(id.parse().ok()?, Err(unescape_cache_str(e)))
+        }
+        ["spec", id, spec, res, ..] => {
+            let key = (id.parse().ok()?, parse_cache_spec(spec)?);
+            match *res {
+                "ok" => CacheLine::Spec(key, Ok(())),
+                "err" => CacheLine::Spec(key, Err(tail(4))),
+                _ => return None,
+            }
+        }
+        ["cand", btf_idx, id, res, ..] => {
+            let res = match *res {
+                "ok" => Ok(parse_cache_spec(&tail(4))?),
+                "err" => Err(tail(4)),
+                _ => return None,
+            };
+            CacheLine::Cand(btf_idx.parse().ok()?, id.parse().ok()?, res)
+        }
+        _ => return None,
+    })
+}
+
+/// Error messages can span multiple lines, so newlines (and backslashes) are escaped.
+fn escape_cache_str(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_cache_str(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                res.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                res.push('\\');
+                chars.next();
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+fn parse_cache_spec(s: &str) -> Option<Vec<usize>> {
+    s.split(':').map(|x| x.parse().ok()).collect()
+}
+
+/// Prefix of target type ID in messages, telling which module BTF it comes from; empty for main
+/// target BTF.
+fn targ_prefix(btf_idx: usize) -> String {
+    if btf_idx == 0 {
+        String::new()
+    } else {
+        format!("mod#{}:", btf_idx)
+    }
+}
+
+/// Name without ___flavor suffix, which local types use to have multiple definitions of the
+/// same target type.
+fn essential_name(name: &str) -> &str {
+    let b = name.as_bytes();
+    for i in (0..b.len().saturating_sub(4)).rev() {
+        if b[i] != b'_' && &b[i + 1..i + 4] == b"___" && b[i + 4] != b'_' {
+            return &name[..i + 1];
+        }
+    }
+    name
+}
+
+/// Whether local type "matches" target type, as defined by libbpf's bpf_core_types_match() for
+/// type_matches relocations. Unlike compatibility check of other type-based relocations, names
+/// (modulo ___flavor suffixes), integer sizes and signedness, array lengths, enumerators and
+/// members (recursively) are taken into account: every local enumerator and member has to have
+/// a matching counterpart in target type, which can have more of them. Behind pointers, structs
+/// and unions match their forward declarations and their members aren't compared.
+pub fn types_match(
+    local_btf: &Btf,
+    local_id: u32,
+    targ_btf: &Btf,
+    targ_id: u32,
+) -> BtfResult<bool> {
+    types_match_lvl(
+        local_btf,
+        local_id,
+        targ_btf,
+        targ_id,
+        false,
+        MAX_COMPAT_DEPTH,
+    )
+}
+
+fn types_match_lvl(
+    local_btf: &Btf,
+    local_id: u32,
+    targ_btf: &Btf,
+    targ_id: u32,
+    behind_ptr: bool,
+    lvl: usize,
+) -> BtfResult<bool> {
+    if lvl == 0 {
+        return Ok(false);
+    }
+    let local_id = local_btf.skip_mods_and_typedefs(local_id);
+    let targ_id = targ_btf.skip_mods_and_typedefs(targ_id);
+    let local_type = local_btf.try_type_by_id(local_id)?;
+    let targ_type = targ_btf.try_type_by_id(targ_id)?;
+    if !names_match(local_type.name(), targ_type.name()) {
+        return Ok(false);
+    }
+    let recur = |local_id, targ_id, behind_ptr| {
+        types_match_lvl(local_btf, local_id, targ_btf, targ_id, behind_ptr, lvl - 1)
+    };
+    Ok(match (local_type, targ_type) {
+        (BtfType::Void, t) => matches!(t, BtfType::Void),
+        (BtfType::Fwd(l), BtfType::Fwd(t)) => l.kind == t.kind,
+        (BtfType::Fwd(l), BtfType::Struct(_)) => behind_ptr && l.kind == BtfFwdKind::Struct,
+        (BtfType::Fwd(l), BtfType::Union(_)) => behind_ptr && l.kind == BtfFwdKind::Union,
+        (BtfType::Enum(_), _) | (BtfType::Enum64(_), _) => {
+            let (local_vals, targ_vals) = match (
+                enum_values(local_btf, local_id)?,
+                enum_values(targ_btf, targ_id)?,
+            ) {
+                (Some(l), Some(t)) => (l, t),
+                _ => return Ok(false),
+            };
+            local_btf.get_size_of(local_id) == targ_btf.get_size_of(targ_id)
+                && local_vals.len() <= targ_vals.len()
+                && local_vals
+                    .iter()
+                    .all(|&(l, _)| targ_vals.iter().any(|&(t, _)| names_match(l, t)))
+        }
+        (BtfType::Struct(_), BtfType::Struct(_)) | (BtfType::Union(_), BtfType::Union(_))
+            if behind_ptr =>
+        {
+            true
+        }
+        (BtfType::Struct(_), BtfType::Fwd(t)) => behind_ptr && t.kind == BtfFwdKind::Struct,
+        (BtfType::Union(_), BtfType::Fwd(t)) => behind_ptr && t.kind == BtfFwdKind::Union,
+        (BtfType::Struct(l), BtfType::Struct(t)) | (BtfType::Union(l), BtfType::Union(t)) => {
+            if l.members.len() > t.members.len() {
+                return Ok(false);
+            }
+            for lm in local_btf.members(l) {
+                let mut found = false;
+                for tm in targ_btf.members(t) {
+                    if names_match(lm.name, tm.name) && recur(lm.type_id, tm.type_id, behind_ptr)? {
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        (BtfType::Int(l), BtfType::Int(t)) => {
+            let is_signed = |e: BtfIntEncoding| e == BtfIntEncoding::Signed;
+            local_btf.get_size_of(local_id) == targ_btf.get_size_of(targ_id)
+                && is_signed(l.encoding) == is_signed(t.encoding)
+        }
+        (BtfType::Ptr(l), BtfType::Ptr(t)) => recur(l.type_id, t.type_id, true)?,
+        (BtfType::Array(l), BtfType::Array(t)) => {
+            l.nelems == t.nelems && recur(l.val_type_id, t.val_type_id, behind_ptr)?
+        }
+        (BtfType::FuncProto(l), BtfType::FuncProto(t)) => {
+            if l.params.len() != t.params.len() {
+                return Ok(false);
+            }
+            for (l, t) in local_btf.params(l).iter().zip(targ_btf.params(t)) {
+                if !recur(l.type_id, t.type_id, behind_ptr)? {
+                    return Ok(false);
+                }
+            }
+            recur(l.res_type_id, t.res_type_id, behind_ptr)?
+        }
+        _ => false,
+    })
+}
+
+/// Names match ignoring ___flavor suffixes; anonymous target type matches only anonymous type.
+fn names_match(local: &str, targ: &str) -> bool {
+    if targ.is_empty() {
+        return local.is_empty();
+    }
+    essential_name(local) == essential_name(targ)
+}
+
+/// Names and values of enumerators of 32-bit or 64-bit enum, with values sign-extended to 64 bits
+/// like libbpf does.
+fn enum_values<'c>(btf: &'c Btf, type_id: u32) -> BtfResult<Option<Vec<(&'c str, u64)>>> {
+    Ok(match btf.try_type_by_id(type_id)? {
+        BtfType::Enum(t) => Some(
+            btf.enum_values(t)
+                .iter()
+                .map(|v| (v.name, i64::from(v.value) as u64))
+                .collect(),
+        ),
+        BtfType::Enum64(t) => Some(
+            btf.enum64_values(t)
+                .iter()
+                .map(|v| (v.name, v.value as u64))
+                .collect(),
+        ),
+        _ => None,
+    })
+}
+
+/// Kinds match, except that enums and 64-bit enums are interchangeable.
+fn are_kinds_core_compat(local: &BtfType, targ: &BtfType) -> bool {
+    let is_enum = |t: &BtfType| matches!(t, BtfType::Enum(_) | BtfType::Enum64(_));
+    local.kind() == targ.kind() || (is_enum(local) && is_enum(targ))
+}
+
 fn spec_error<T>(
     spec: &[usize],
     idx: usize,
@@ -571,6 +1787,47 @@ fn spec_error<T>(
         bt,
     ))?
 }
+/// Struct/union member, selected by access spec at position idx.
+/// Offset `spec[idx] * elem_sz * scale`, checked to fit into u32.
+fn spec_off(spec: &[usize], idx: usize, elem_sz: u32, scale: u32) -> BtfResult<u32> {
+    u32::try_from(spec[idx])
+        .ok()
+        .and_then(|n| n.checked_mul(elem_sz))
+        .and_then(|off| off.checked_mul(scale))
+        .ok_or_else(|| spec_overflow(spec))
+}
+
+/// Sum of offsets, checked to fit into u32.
+fn spec_add(spec: &[usize], off: u32, delta: u32) -> BtfResult<u32> {
+    off.checked_add(delta).ok_or_else(|| spec_overflow(spec))
+}
+
+fn spec_overflow(spec: &[usize]) -> BtfError {
+    BtfError::new_owned(format!(
+        "Offset of accessor {} overflows u32",
+        Relocator::spec_to_str(spec)
+    ))
+}
+
+fn spec_member<'c, 'm>(
+    btf: &Btf,
+    spec: &[usize],
+    idx: usize,
+    type_id: u32,
+    members: &'c [BtfMember<'m>],
+) -> BtfResult<&'c BtfMember<'m>> {
+    match members.get(spec[idx]) {
+        Some(m) => Ok(m),
+        None => spec_error(
+            spec,
+            idx,
+            "member index out of range",
+            type_id,
+            btf.try_type_by_id(type_id)?,
+        ),
+    }
+}
+
 fn access_error<T>(
     spec: &Accessor,
     idx: usize,
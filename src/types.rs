@@ -1,7 +1,9 @@
+use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::ffi::{c_char, CStr};
+use std::collections::HashMap;
 use std::fmt;
 use std::mem::size_of;
+use std::ops::Range;
 
 use object::{Object, ObjectSection};
 use scroll::Pread;
@@ -11,6 +13,7 @@ use crate::{btf_error, BtfError, BtfResult};
 
 pub const BTF_ELF_SEC: &str = ".BTF";
 pub const BTF_EXT_ELF_SEC: &str = ".BTF.ext";
+pub const BTF_BASE_ELF_SEC: &str = ".BTF.base";
 
 pub const BTF_MAGIC: u16 = 0xeB9F;
 pub const BTF_VERSION: u8 = 1;
@@ -230,7 +233,7 @@ impl fmt::Display for BtfIntEncoding {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfInt<'a> {
     pub name: &'a str,
     pub bits: u32,
@@ -256,7 +259,7 @@ impl<'a> fmt::Display for BtfInt<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfPtr {
     pub type_id: u32,
 }
@@ -267,7 +270,7 @@ impl fmt::Display for BtfPtr {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfArray {
     pub nelems: u32,
     pub idx_type_id: u32,
@@ -284,7 +287,7 @@ impl fmt::Display for BtfArray {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfMember<'a> {
     pub name: &'a str,
     pub type_id: u32,
@@ -302,12 +305,15 @@ impl<'a> fmt::Display for BtfMember<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfComposite<'a> {
     pub is_struct: bool,
     pub name: &'a str,
     pub sz: u32,
-    pub members: Vec<BtfMember<'a>>,
+    /// Whether member offsets carry bitfield sizes (kind_flag), as loaded or to be written
+    pub bitfield_offs: bool,
+    /// Members in BTF's member table, see Btf::members()
+    pub members: Range<u32>,
 }
 
 impl<'a> fmt::Display for BtfComposite<'a> {
@@ -319,15 +325,11 @@ impl<'a> fmt::Display for BtfComposite<'a> {
             disp_name(self.name),
             self.sz,
             self.members.len()
-        )?;
-        for i in 0..self.members.len() {
-            write!(f, "\n\t#{:02} {}", i, self.members[i])?;
-        }
-        Ok(())
+        )
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfEnumValue<'a> {
     pub name: &'a str,
     pub value: i32,
@@ -339,11 +341,12 @@ impl<'a> fmt::Display for BtfEnumValue<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfEnum<'a> {
     pub name: &'a str,
     pub sz: u32,
-    pub values: Vec<BtfEnumValue<'a>>,
+    /// Enumerators in BTF's enum value table, see Btf::enum_values()
+    pub values: Range<u32>,
 }
 
 impl<'a> fmt::Display for BtfEnum<'a> {
@@ -355,15 +358,11 @@ impl<'a> fmt::Display for BtfEnum<'a> {
             disp_name(self.name),
             self.sz,
             self.values.len()
-        )?;
-        for i in 0..self.values.len() {
-            write!(f, "\n\t#{:02} {}", i, self.values[i])?;
-        }
-        Ok(())
+        )
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfEnum64Value<'a> {
     pub name: &'a str,
     pub value: i64,
@@ -375,11 +374,14 @@ impl<'a> fmt::Display for BtfEnum64Value<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfEnum64<'a> {
     pub name: &'a str,
     pub sz: u32,
-    pub values: Vec<BtfEnum64Value<'a>>,
+    /// Whether values are signed (kind_flag), otherwise they are to be interpreted as u64
+    pub signed: bool,
+    /// Enumerators in BTF's 64-bit enum value table, see Btf::enum64_values()
+    pub values: Range<u32>,
 }
 
 impl<'a> fmt::Display for BtfEnum64<'a> {
@@ -391,11 +393,7 @@ impl<'a> fmt::Display for BtfEnum64<'a> {
             disp_name(self.name),
             self.sz,
             self.values.len()
-        )?;
-        for i in 0..self.values.len() {
-            write!(f, "\n\t#{:02} {}", i, self.values[i])?;
-        }
-        Ok(())
+        )
     }
 }
 
@@ -414,7 +412,7 @@ impl fmt::Display for BtfFwdKind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfFwd<'a> {
     pub name: &'a str,
     pub kind: BtfFwdKind,
@@ -432,7 +430,7 @@ impl<'a> fmt::Display for BtfFwd<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfTypedef<'a> {
     pub name: &'a str,
     pub type_id: u32,
@@ -450,7 +448,7 @@ impl<'a> fmt::Display for BtfTypedef<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfVolatile {
     pub type_id: u32,
 }
@@ -461,7 +459,7 @@ impl fmt::Display for BtfVolatile {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfConst {
     pub type_id: u32,
 }
@@ -472,7 +470,7 @@ impl fmt::Display for BtfConst {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfRestrict {
     pub type_id: u32,
 }
@@ -502,7 +500,7 @@ impl fmt::Display for BtfFuncKind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfFunc<'a> {
     pub name: &'a str,
     pub proto_type_id: u32,
@@ -522,7 +520,7 @@ impl<'a> fmt::Display for BtfFunc<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfFuncParam<'a> {
     pub name: &'a str,
     pub type_id: u32,
@@ -534,13 +532,14 @@ impl<'a> fmt::Display for BtfFuncParam<'a> {
     }
 }
 
-#[derive(Debug)]
-pub struct BtfFuncProto<'a> {
+#[derive(Debug, Clone)]
+pub struct BtfFuncProto {
     pub res_type_id: u32,
-    pub params: Vec<BtfFuncParam<'a>>,
+    /// Params in BTF's param table, see Btf::params()
+    pub params: Range<u32>,
 }
 
-impl<'a> fmt::Display for BtfFuncProto<'a> {
+impl fmt::Display for BtfFuncProto {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -548,11 +547,7 @@ impl<'a> fmt::Display for BtfFuncProto<'a> {
             "FUNC_PROTO",
             self.res_type_id,
             self.params.len()
-        )?;
-        for i in 0..self.params.len() {
-            write!(f, "\n\t#{:02} {}", i, self.params[i])?;
-        }
-        Ok(())
+        )
     }
 }
 
@@ -573,7 +568,7 @@ impl fmt::Display for BtfVarKind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfVar<'a> {
     pub name: &'a str,
     pub type_id: u32,
@@ -593,7 +588,7 @@ impl<'a> fmt::Display for BtfVar<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfDatasecVar {
     pub type_id: u32,
     pub offset: u32,
@@ -610,7 +605,7 @@ impl fmt::Display for BtfDatasecVar {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfDatasec<'a> {
     pub name: &'a str,
     pub sz: u32,
@@ -634,7 +629,7 @@ impl<'a> fmt::Display for BtfDatasec<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfFloat<'a> {
     pub name: &'a str,
     pub sz: u32,
@@ -647,7 +642,7 @@ impl<'a> fmt::Display for BtfFloat<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BtfDeclTag<'a> {
     pub name: &'a str,
     pub type_id: u32,
@@ -667,7 +662,53 @@ impl<'a> fmt::Display for BtfDeclTag<'a> {
     }
 }
 
-#[derive(Debug)]
+/// What DECL_TAG annotates: whole type, member of struct/union, or parameter of function.
+#[derive(Debug, Copy, Clone)]
+pub enum BtfDeclTagTarget<'b, 'a> {
+    Type(u32),
+    Member {
+        type_id: u32,
+        idx: usize,
+        member: &'b BtfMember<'a>,
+    },
+    Param {
+        func_id: u32,
+        idx: usize,
+        param: &'b BtfFuncParam<'a>,
+    },
+}
+
+impl<'b, 'a> fmt::Display for BtfDeclTagTarget<'b, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BtfDeclTagTarget::Type(type_id) => write!(f, "type [{}]", type_id),
+            BtfDeclTagTarget::Member {
+                type_id,
+                idx,
+                member,
+            } => write!(
+                f,
+                "member #{} '{}' of [{}]",
+                idx,
+                disp_name(member.name),
+                type_id
+            ),
+            BtfDeclTagTarget::Param {
+                func_id,
+                idx,
+                param,
+            } => write!(
+                f,
+                "param #{} '{}' of [{}]",
+                idx,
+                disp_name(param.name),
+                func_id
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct BtfTypeTag<'a> {
     pub name: &'a str,
     pub type_id: u32,
@@ -685,7 +726,7 @@ impl<'a> fmt::Display for BtfTypeTag<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BtfType<'a> {
     Void,
     Int(BtfInt<'a>),
@@ -700,7 +741,7 @@ pub enum BtfType<'a> {
     Const(BtfConst),
     Restrict(BtfRestrict),
     Func(BtfFunc<'a>),
-    FuncProto(BtfFuncProto<'a>),
+    FuncProto(BtfFuncProto),
     Var(BtfVar<'a>),
     Datasec(BtfDatasec<'a>),
     Float(BtfFloat<'a>),
@@ -788,6 +829,232 @@ impl<'a> BtfType<'a> {
     }
 }
 
+/// Members of structs/unions, params of func protos and enumerators of enums of all types of a
+/// BTF, stored in flat tables. Types refer to their members, params or enumerators by ranges of
+/// indices into these tables, which saves an allocation per type and keeps them close in memory.
+#[derive(Debug, Clone, Default)]
+pub struct BtfArena<'a> {
+    members: Vec<BtfMember<'a>>,
+    params: Vec<BtfFuncParam<'a>>,
+    enum_values: Vec<BtfEnumValue<'a>>,
+    enum64_values: Vec<BtfEnum64Value<'a>>,
+}
+
+fn arena_idx(r: &Range<u32>) -> Range<usize> {
+    r.start as usize..r.end as usize
+}
+
+fn arena_push<T>(table: &mut Vec<T>, items: impl IntoIterator<Item = T>) -> Range<u32> {
+    let start = table.len() as u32;
+    table.extend(items);
+    start..table.len() as u32
+}
+
+impl<'a> BtfArena<'a> {
+    pub fn members(&self, t: &BtfComposite) -> &[BtfMember<'a>] {
+        &self.members[arena_idx(&t.members)]
+    }
+
+    pub fn members_mut(&mut self, t: &BtfComposite) -> &mut [BtfMember<'a>] {
+        &mut self.members[arena_idx(&t.members)]
+    }
+
+    pub fn params(&self, t: &BtfFuncProto) -> &[BtfFuncParam<'a>] {
+        &self.params[arena_idx(&t.params)]
+    }
+
+    pub fn enum_values(&self, t: &BtfEnum) -> &[BtfEnumValue<'a>] {
+        &self.enum_values[arena_idx(&t.values)]
+    }
+
+    pub fn enum64_values(&self, t: &BtfEnum64) -> &[BtfEnum64Value<'a>] {
+        &self.enum64_values[arena_idx(&t.values)]
+    }
+
+    /// Append members of a new struct/union, returns range to put into its BtfComposite.
+    pub fn add_members(&mut self, members: impl IntoIterator<Item = BtfMember<'a>>) -> Range<u32> {
+        arena_push(&mut self.members, members)
+    }
+
+    /// Append params of a new func proto, returns range to put into its BtfFuncProto.
+    pub fn add_params(&mut self, params: impl IntoIterator<Item = BtfFuncParam<'a>>) -> Range<u32> {
+        arena_push(&mut self.params, params)
+    }
+
+    /// Append enumerators of a new enum, returns range to put into its BtfEnum.
+    pub fn add_enum_values(
+        &mut self,
+        values: impl IntoIterator<Item = BtfEnumValue<'a>>,
+    ) -> Range<u32> {
+        arena_push(&mut self.enum_values, values)
+    }
+
+    /// Append enumerators of a new 64-bit enum, returns range to put into its BtfEnum64.
+    pub fn add_enum64_values(
+        &mut self,
+        values: impl IntoIterator<Item = BtfEnum64Value<'a>>,
+    ) -> Range<u32> {
+        arena_push(&mut self.enum64_values, values)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.members.shrink_to_fit();
+        self.params.shrink_to_fit();
+        self.enum_values.shrink_to_fit();
+        self.enum64_values.shrink_to_fit();
+    }
+
+    /// Whether all member/param/enumerator ranges of the type are within the tables.
+    fn contains(&self, t: &BtfType) -> bool {
+        let (r, len) = match t {
+            BtfType::Struct(t) | BtfType::Union(t) => (&t.members, self.members.len()),
+            BtfType::FuncProto(t) => (&t.params, self.params.len()),
+            BtfType::Enum(t) => (&t.values, self.enum_values.len()),
+            BtfType::Enum64(t) => (&t.values, self.enum64_values.len()),
+            _ => return true,
+        };
+        r.start <= r.end && r.end as usize <= len
+    }
+
+    /// All names used by given type: its own name and names of members, enumerators or params.
+    pub fn names<'s>(&'s self, t: &'s BtfType<'a>) -> Vec<&'s str> {
+        let mut names = vec![t.name()];
+        match t {
+            BtfType::Struct(t) | BtfType::Union(t) => {
+                names.extend(self.members(t).iter().map(|m| m.name))
+            }
+            BtfType::Enum(t) => names.extend(self.enum_values(t).iter().map(|v| v.name)),
+            BtfType::Enum64(t) => names.extend(self.enum64_values(t).iter().map(|v| v.name)),
+            BtfType::FuncProto(t) => names.extend(self.params(t).iter().map(|p| p.name)),
+            _ => {}
+        }
+        names
+    }
+
+    /// Mutable references to type IDs referenced by given type, in the same order as
+    /// type_refs().
+    fn type_refs_mut<'s>(&'s mut self, t: &'s mut BtfType<'a>) -> Vec<&'s mut u32> {
+        match t {
+            BtfType::Void
+            | BtfType::Int(_)
+            | BtfType::Enum(_)
+            | BtfType::Fwd(_)
+            | BtfType::Float(_)
+            | BtfType::Enum64(_) => Vec::new(),
+            BtfType::Ptr(t) => vec![&mut t.type_id],
+            BtfType::Array(t) => vec![&mut t.val_type_id, &mut t.idx_type_id],
+            BtfType::Struct(t) | BtfType::Union(t) => self
+                .members_mut(t)
+                .iter_mut()
+                .map(|m| &mut m.type_id)
+                .collect(),
+            BtfType::Typedef(t) => vec![&mut t.type_id],
+            BtfType::Volatile(t) => vec![&mut t.type_id],
+            BtfType::Const(t) => vec![&mut t.type_id],
+            BtfType::Restrict(t) => vec![&mut t.type_id],
+            BtfType::Func(t) => vec![&mut t.proto_type_id],
+            BtfType::FuncProto(t) => std::iter::once(&mut t.res_type_id)
+                .chain(
+                    self.params[arena_idx(&t.params)]
+                        .iter_mut()
+                        .map(|p| &mut p.type_id),
+                )
+                .collect(),
+            BtfType::Var(t) => vec![&mut t.type_id],
+            BtfType::Datasec(t) => t.vars.iter_mut().map(|v| &mut v.type_id).collect(),
+            BtfType::DeclTag(t) => vec![&mut t.type_id],
+            BtfType::TypeTag(t) => vec![&mut t.type_id],
+        }
+    }
+
+    /// Type IDs directly referenced by given type, in BTF encoding order.
+    pub fn type_refs(&self, t: &BtfType) -> Vec<u32> {
+        match t {
+            BtfType::Void
+            | BtfType::Int(_)
+            | BtfType::Enum(_)
+            | BtfType::Fwd(_)
+            | BtfType::Float(_)
+            | BtfType::Enum64(_) => Vec::new(),
+            BtfType::Ptr(t) => vec![t.type_id],
+            BtfType::Array(t) => vec![t.val_type_id, t.idx_type_id],
+            BtfType::Struct(t) | BtfType::Union(t) => {
+                self.members(t).iter().map(|m| m.type_id).collect()
+            }
+            BtfType::Typedef(t) => vec![t.type_id],
+            BtfType::Volatile(t) => vec![t.type_id],
+            BtfType::Const(t) => vec![t.type_id],
+            BtfType::Restrict(t) => vec![t.type_id],
+            BtfType::Func(t) => vec![t.proto_type_id],
+            BtfType::FuncProto(t) => std::iter::once(t.res_type_id)
+                .chain(self.params(t).iter().map(|p| p.type_id))
+                .collect(),
+            BtfType::Var(t) => vec![t.type_id],
+            BtfType::Datasec(t) => t.vars.iter().map(|v| v.type_id).collect(),
+            BtfType::DeclTag(t) => vec![t.type_id],
+            BtfType::TypeTag(t) => vec![t.type_id],
+        }
+    }
+
+    /// Display given type along with its members, enumerators or params, one per line.
+    pub fn display<'s>(&'s self, t: &'s BtfType<'a>) -> BtfTypeDisplay<'s, 'a> {
+        BtfTypeDisplay { arena: self, t }
+    }
+}
+
+/// Type formatted along with its members, enumerators or params, see BtfArena::display().
+pub struct BtfTypeDisplay<'s, 'a> {
+    arena: &'s BtfArena<'a>,
+    t: &'s BtfType<'a>,
+}
+
+impl<'s, 'a> fmt::Display for BtfTypeDisplay<'s, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.t)?;
+        match self.t {
+            BtfType::Struct(t) | BtfType::Union(t) => {
+                for (i, m) in self.arena.members(t).iter().enumerate() {
+                    write!(f, "\n\t#{:02} {}", i, m)?;
+                }
+            }
+            BtfType::Enum(t) => {
+                for (i, v) in self.arena.enum_values(t).iter().enumerate() {
+                    write!(f, "\n\t#{:02} {}", i, v)?;
+                }
+            }
+            BtfType::Enum64(t) => {
+                for (i, v) in self.arena.enum64_values(t).iter().enumerate() {
+                    if t.signed {
+                        write!(f, "\n\t#{:02} {}", i, v)?;
+                    } else {
+                        write!(
+                            f,
+                            "\n\t#{:02} {} = {}",
+                            i,
+                            disp_name(v.name),
+                            v.value as u64
+                        )?;
+                    }
+                }
+            }
+            BtfType::FuncProto(t) => {
+                for (i, p) in self.arena.params(t).iter().enumerate() {
+                    write!(f, "\n\t#{:02} {}", i, p)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum OrderState {
+    NotVisited,
+    Visiting,
+    Visited,
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
 pub enum BtfKind {
     Void,
@@ -955,13 +1222,25 @@ impl<'a> fmt::Display for BtfExtCoreReloc<'a> {
 pub struct Btf<'a> {
     endian: scroll::Endian,
     types: Vec<BtfType<'a>>,
+    arena: BtfArena<'a>,
     ptr_sz: u32,
+    strs: &'a [u8],
+    /// .BTF data types were loaded from, None once types are added or changed
+    raw_data: Option<&'a [u8]>,
+
+    // split BTF stuff
+    start_id: u32,
+    base_strs: &'a [u8],
+    distilled_base: Option<Box<Btf<'a>>>,
 
     // .BTF.ext stuff
     has_ext: bool,
     func_secs: Vec<BtfExtSection<'a, BtfExtFunc>>,
     line_secs: Vec<BtfExtSection<'a, BtfExtLine<'a>>>,
     core_reloc_secs: Vec<BtfExtSection<'a, BtfExtCoreReloc<'a>>>,
+
+    // placeholder types substituted for dangling references, mapped to referenced type IDs
+    placeholders: HashMap<u32, u32>,
 }
 
 impl<'a> Btf<'a> {
@@ -969,6 +1248,26 @@ impl<'a> Btf<'a> {
         self.ptr_sz
     }
 
+    /// Override target's pointer size (in bytes), e.g., for raw BTF of 32-bit target, as raw BTF
+    /// doesn't record it.
+    pub fn set_ptr_sz(&mut self, ptr_sz: u32) {
+        self.ptr_sz = ptr_sz;
+    }
+
+    /// ID of the first type that belongs to this BTF itself (1, unless it's a split BTF).
+    pub fn start_id(&self) -> u32 {
+        self.start_id
+    }
+
+    /// Distilled base BTF from .BTF.base ELF section, if any; this BTF is split on top of it.
+    pub fn distilled_base(&self) -> Option<&Btf<'a>> {
+        self.distilled_base.as_deref()
+    }
+
+    pub fn is_split(&self) -> bool {
+        self.start_id > 1
+    }
+
     pub fn types(&self) -> &[BtfType] {
         &self.types
     }
@@ -977,10 +1276,197 @@ impl<'a> Btf<'a> {
         &self.types[type_id as usize]
     }
 
+    /// Members, params and enumerators of all types, see BtfArena.
+    pub fn arena(&self) -> &BtfArena<'a> {
+        &self.arena
+    }
+
+    pub fn members(&self, t: &BtfComposite) -> &[BtfMember<'a>] {
+        self.arena.members(t)
+    }
+
+    pub fn members_mut(&mut self, t: &BtfComposite) -> &mut [BtfMember<'a>] {
+        self.raw_data = None;
+        self.arena.members_mut(t)
+    }
+
+    pub fn params(&self, t: &BtfFuncProto) -> &[BtfFuncParam<'a>] {
+        self.arena.params(t)
+    }
+
+    pub fn enum_values(&self, t: &BtfEnum) -> &[BtfEnumValue<'a>] {
+        self.arena.enum_values(t)
+    }
+
+    pub fn enum64_values(&self, t: &BtfEnum64) -> &[BtfEnum64Value<'a>] {
+        self.arena.enum64_values(t)
+    }
+
+    /// Arena to append members, params and enumerators of types to be added with add_type() or
+    /// replace_type() to.
+    pub fn arena_mut(&mut self) -> &mut BtfArena<'a> {
+        self.raw_data = None;
+        &mut self.arena
+    }
+
+    /// Type IDs directly referenced by given type, see BtfArena::type_refs().
+    pub fn type_refs(&self, t: &BtfType) -> Vec<u32> {
+        self.arena.type_refs(t)
+    }
+
+    /// Display type along with its members, enumerators or params, see BtfArena::display().
+    pub fn type_display<'s>(&'s self, t: &'s BtfType<'a>) -> BtfTypeDisplay<'s, 'a> {
+        self.arena.display(t)
+    }
+
+    /// Same as type_by_id(), but returns an error instead of panicking on invalid type ID.
+    pub fn try_type_by_id(&self, type_id: u32) -> BtfResult<&BtfType<'a>> {
+        self.types
+            .get(type_id as usize)
+            .ok_or(BtfError::InvalidTypeId { id: type_id })
+    }
+
+    /// Raw .BTF data (header, types and strings) this BTF was loaded from, as long as its types
+    /// weren't added or changed since.
+    pub fn raw_data(&self) -> Option<&'a [u8]> {
+        self.raw_data
+    }
+
     pub fn type_cnt(&self) -> u32 {
         self.types.len() as u32
     }
 
+    /// Append (synthetic) type to this BTF, returns its type ID. All types it refers to have
+    /// to exist already.
+    pub fn add_type(&mut self, t: BtfType<'a>) -> BtfResult<u32> {
+        let id = self.type_cnt();
+        self.check_arena_ranges(&t)?;
+        for ref_id in self.arena.type_refs(&t) {
+            if ref_id >= id {
+                return Err(BtfError::InvalidTypeId { id: ref_id });
+            }
+        }
+        self.types.push(t);
+        self.raw_data = None;
+        Ok(id)
+    }
+
+    /// Replace type with given ID by a new one, keeping its type ID. All types the new type
+    /// refers to have to exist already.
+    pub fn replace_type(&mut self, type_id: u32, t: BtfType<'a>) -> BtfResult<()> {
+        self.check_own_type_id(type_id)?;
+        self.check_arena_ranges(&t)?;
+        for ref_id in self.arena.type_refs(&t) {
+            if ref_id >= self.type_cnt() {
+                return Err(BtfError::InvalidTypeId { id: ref_id });
+            }
+        }
+        self.types[type_id as usize] = t;
+        self.raw_data = None;
+        Ok(())
+    }
+
+    /// Remove type with given ID. Types following it are shifted down by one ID, with all
+    /// references to them (including .BTF.ext func infos and CO-RE relocations) remapped. Type
+    /// can't be removed while it's still referenced.
+    pub fn remove_type(&mut self, type_id: u32) -> BtfResult<()> {
+        self.check_own_type_id(type_id)?;
+        for (id, t) in self.types.iter().enumerate() {
+            if id as u32 != type_id && self.arena.type_refs(t).contains(&type_id) {
+                return btf_error(format!(
+                    "Type [{}] is still referenced by type [{}]",
+                    type_id, id
+                ));
+            }
+        }
+        let ext_refs = self
+            .func_secs
+            .iter()
+            .flat_map(|sec| sec.recs.iter().map(move |rec| (sec.name, rec.type_id)))
+            .chain(
+                self.core_reloc_secs
+                    .iter()
+                    .flat_map(|sec| sec.recs.iter().map(move |rec| (sec.name, rec.type_id))),
+            );
+        for (sec_name, ref_id) in ext_refs {
+            if ref_id == type_id {
+                return btf_error(format!(
+                    "Type [{}] is still referenced by .BTF.ext section '{}'",
+                    type_id, sec_name
+                ));
+            }
+        }
+        self.types.remove(type_id as usize);
+        self.placeholders.remove(&type_id);
+        self.remap_type_ids(&|id| if id > type_id { id - 1 } else { id });
+        Ok(())
+    }
+
+    fn check_arena_ranges(&self, t: &BtfType) -> BtfResult<()> {
+        if !self.arena.contains(t) {
+            return btf_error(format!(
+                "Members, params or enumerators of {} are outside of BTF's arena",
+                t
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_own_type_id(&self, type_id: u32) -> BtfResult<()> {
+        if type_id < self.start_id || type_id >= self.type_cnt() {
+            return btf_error(format!(
+                "Type ID {} is outside of this BTF's types [{}, {})",
+                type_id,
+                self.start_id,
+                self.type_cnt()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rewrite all type references of types and .BTF.ext records with given mapping.
+    fn remap_type_ids(&mut self, remap: &dyn Fn(u32) -> u32) {
+        for t in self.types.iter_mut() {
+            for ref_id in self.arena.type_refs_mut(t) {
+                *ref_id = remap(*ref_id);
+            }
+        }
+        for rec in self
+            .func_secs
+            .iter_mut()
+            .flat_map(|sec| sec.recs.iter_mut())
+        {
+            rec.type_id = remap(rec.type_id);
+        }
+        for rec in self
+            .core_reloc_secs
+            .iter_mut()
+            .flat_map(|sec| sec.recs.iter_mut())
+        {
+            rec.type_id = remap(rec.type_id);
+        }
+        self.placeholders = self
+            .placeholders
+            .drain()
+            .map(|(id, inv_id)| (remap(id), inv_id))
+            .collect();
+    }
+
+    /// Strings of this BTF's own string section along with their offsets, in section order.
+    pub fn strings(&self) -> Vec<(u32, Cow<'a, str>)> {
+        let base_len = self.base_strs.len() as u32;
+        let mut strs = Vec::new();
+        let mut off = 0;
+        for s in self.strs.split(|&b| b == 0) {
+            if off >= self.strs.len() {
+                break;
+            }
+            strs.push((base_len + off as u32, String::from_utf8_lossy(s)));
+            off += s.len() + 1;
+        }
+        strs
+    }
+
     pub fn has_ext(&self) -> bool {
         self.has_ext
     }
@@ -1005,7 +1491,7 @@ impl<'a> Btf<'a> {
             BtfType::Const(t) => self.get_size_of(t.type_id),
             BtfType::Restrict(t) => self.get_size_of(t.type_id),
             BtfType::Ptr(_) => self.ptr_sz,
-            BtfType::Array(t) => t.nelems * self.get_size_of(t.val_type_id),
+            BtfType::Array(t) => t.nelems.saturating_mul(self.get_size_of(t.val_type_id)),
             BtfType::FuncProto(_) => 0,
             BtfType::Struct(t) => t.sz,
             BtfType::Union(t) => t.sz,
@@ -1034,14 +1520,14 @@ impl<'a> Btf<'a> {
             BtfType::FuncProto(_) => 0,
             BtfType::Struct(t) => {
                 let mut align = 1;
-                for m in &t.members {
+                for m in self.members(t) {
                     align = max(align, self.get_align_of(m.type_id));
                 }
                 align
             }
             BtfType::Union(t) => {
                 let mut align = 1;
-                for m in &t.members {
+                for m in self.members(t) {
                     align = max(align, self.get_align_of(m.type_id));
                 }
                 align
@@ -1084,67 +1570,358 @@ impl<'a> Btf<'a> {
         }
     }
 
+    /// Resolve what given DECL_TAG annotates.
+    pub fn decl_tag_target<'b>(&'b self, tag: &BtfDeclTag) -> BtfResult<BtfDeclTagTarget<'b, 'a>> {
+        if tag.comp_idx == u32::MAX {
+            return Ok(BtfDeclTagTarget::Type(tag.type_id));
+        }
+        let idx = tag.comp_idx as usize;
+        let target = match self.types.get(tag.type_id as usize) {
+            Some(BtfType::Struct(t)) | Some(BtfType::Union(t)) => {
+                self.members(t)
+                    .get(idx)
+                    .map(|member| BtfDeclTagTarget::Member {
+                        type_id: tag.type_id,
+                        idx,
+                        member,
+                    })
+            }
+            Some(BtfType::Func(t)) => match self.types.get(t.proto_type_id as usize) {
+                Some(BtfType::FuncProto(proto)) => {
+                    self.params(proto)
+                        .get(idx)
+                        .map(|param| BtfDeclTagTarget::Param {
+                            func_id: tag.type_id,
+                            idx,
+                            param,
+                        })
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match target {
+            Some(target) => Ok(target),
+            None => btf_error(format!(
+                "Invalid DECL_TAG target: [{}] comp_idx:{}",
+                tag.type_id, tag.comp_idx
+            )),
+        }
+    }
+
+    /// IDs of types annotated with given tag: types (including structs/unions and functions
+    /// whose members or params are tagged) targeted by DECL_TAGs, as well as TYPE_TAGs.
+    pub fn tagged_types(&self, tag: &str) -> Vec<u32> {
+        let mut ids = Vec::new();
+        for (id, t) in self.types.iter().enumerate() {
+            let tagged_id = match t {
+                BtfType::TypeTag(t) if t.name == tag => id as u32,
+                BtfType::DeclTag(t) if t.name == tag => match self.decl_tag_target(t) {
+                    Ok(BtfDeclTagTarget::Type(type_id)) => type_id,
+                    Ok(BtfDeclTagTarget::Member { type_id, .. }) => type_id,
+                    Ok(BtfDeclTagTarget::Param { func_id, .. }) => func_id,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            ids.push(tagged_id);
+        }
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Same as skip_mods(), but returns all traversed type IDs, starting with `type_id` itself
+    /// and ending with the resolved type ID.
+    pub fn skip_mods_chain(&self, type_id: u32) -> Vec<u32> {
+        self.walk_chain(type_id, false)
+    }
+
+    /// Same as skip_mods_and_typedefs(), but returns all traversed type IDs (modifiers,
+    /// typedefs, type tags), starting with `type_id` itself and ending with the resolved type ID.
+    pub fn resolve_chain(&self, type_id: u32) -> Vec<u32> {
+        self.walk_chain(type_id, true)
+    }
+
+    fn walk_chain(&self, mut type_id: u32, typedefs: bool) -> Vec<u32> {
+        let mut chain = vec![type_id];
+        loop {
+            type_id = match self.type_by_id(type_id) {
+                BtfType::Volatile(t) => t.type_id,
+                BtfType::Const(t) => t.type_id,
+                BtfType::Restrict(t) => t.type_id,
+                BtfType::TypeTag(t) => t.type_id,
+                BtfType::Typedef(t) if typedefs => t.type_id,
+                _ => return chain,
+            };
+            chain.push(type_id);
+        }
+    }
+
+    /// Mark all types transitively reachable from given root type IDs (roots included).
+    ///
+    /// If `through_ptrs` is false, named structs/unions referenced only through pointers are not
+    /// traversed. DECL_TAGs are considered reachable if the type they annotate is reachable.
+    pub fn type_closure(&self, roots: &[u32], through_ptrs: bool) -> Vec<bool> {
+        let mut reached = vec![false; self.types.len()];
+        reached[0] = true;
+        let mut queue = roots.to_vec();
+        while let Some(id) = queue.pop() {
+            if reached[id as usize] && id != 0 {
+                continue;
+            }
+            reached[id as usize] = true;
+            if let (false, BtfType::Ptr(t)) = (through_ptrs, self.type_by_id(id)) {
+                let mut ref_id = t.type_id;
+                loop {
+                    match self.type_by_id(ref_id) {
+                        BtfType::Volatile(BtfVolatile { type_id })
+                        | BtfType::Const(BtfConst { type_id })
+                        | BtfType::Restrict(BtfRestrict { type_id })
+                        | BtfType::TypeTag(BtfTypeTag { type_id, .. }) => {
+                            reached[ref_id as usize] = true;
+                            ref_id = *type_id;
+                        }
+                        BtfType::Struct(t) | BtfType::Union(t) if !t.name.is_empty() => break,
+                        _ => {
+                            queue.push(ref_id);
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            for ref_id in self.type_refs(self.type_by_id(id)) {
+                if !reached[ref_id as usize] {
+                    queue.push(ref_id);
+                }
+            }
+        }
+        for (id, t) in self.types.iter().enumerate() {
+            if let BtfType::DeclTag(t) = t {
+                reached[id] = reached[t.type_id as usize];
+            }
+        }
+        reached
+    }
+
+    /// Replace references to non-existing types with placeholder `void *` types, so that the
+    /// rest of BTF can still be used. Returns (referencing type ID, invalid type ID) pairs.
+    pub fn replace_dangling_refs(&mut self) -> Vec<(u32, u32)> {
+        let type_cnt = self.type_cnt();
+        let mut replaced = Vec::new();
+        let mut placeholder_ids = HashMap::new();
+        for id in 0..type_cnt {
+            let t = &mut self.types[id as usize];
+            for ref_id in self.arena.type_refs_mut(t) {
+                if *ref_id < type_cnt {
+                    continue;
+                }
+                replaced.push((id, *ref_id));
+                let next_id = type_cnt + placeholder_ids.len() as u32;
+                *ref_id = *placeholder_ids.entry(*ref_id).or_insert(next_id);
+            }
+        }
+        for _ in 0..placeholder_ids.len() {
+            self.types.push(BtfType::Ptr(BtfPtr { type_id: 0 }));
+        }
+        if !replaced.is_empty() {
+            self.raw_data = None;
+        }
+        self.placeholders
+            .extend(placeholder_ids.into_iter().map(|(inv, id)| (id, inv)));
+        replaced
+    }
+
+    /// If type is a placeholder for dangling reference (see replace_dangling_refs()), returns
+    /// invalid type ID it replaced.
+    pub fn placeholder_target(&self, type_id: u32) -> Option<u32> {
+        self.placeholders.get(&type_id).cloned()
+    }
+
+    /// Check that all type references (including ones from .BTF.ext) point to existing types,
+    /// and that there are no reference cycles that don't go through a pointer (e.g., typedef
+    /// referring to itself or struct embedding itself), which would make types infinite.
+    pub fn validate(&self) -> BtfResult<()> {
+        self.validate_types()?;
+        let type_cnt = self.type_cnt();
+        for sec in &self.func_secs {
+            for rec in &sec.recs {
+                match self.types.get(rec.type_id as usize) {
+                    Some(BtfType::Func(_)) => {}
+                    _ => {
+                        return btf_error(format!(
+                            "Func info of section '{}' refers to invalid func type: {}",
+                            sec.name, rec.type_id
+                        ))
+                    }
+                }
+            }
+        }
+        for sec in &self.core_reloc_secs {
+            for rec in &sec.recs {
+                if rec.type_id >= type_cnt {
+                    return btf_error(format!(
+                        "CO-RE relocation of section '{}' refers to invalid type: {}",
+                        sec.name, rec.type_id
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as validate(), but checks only types, not .BTF.ext records.
+    pub fn validate_types(&self) -> BtfResult<()> {
+        let type_cnt = self.type_cnt();
+        for (id, t) in self.types.iter().enumerate() {
+            if let Some(ref_id) = self.type_refs(t).into_iter().find(|&r| r >= type_cnt) {
+                return btf_error(format!(
+                    "Dangling type reference from [{}] {:?} '{}': {}",
+                    id,
+                    t.kind(),
+                    t.name(),
+                    ref_id
+                ));
+            }
+        }
+
+        // DFS over references that embed referenced type by value
+        let value_refs = |t: &BtfType| -> Vec<u32> {
+            match t {
+                BtfType::Volatile(BtfVolatile { type_id })
+                | BtfType::Const(BtfConst { type_id })
+                | BtfType::Restrict(BtfRestrict { type_id })
+                | BtfType::Typedef(BtfTypedef { type_id, .. })
+                | BtfType::TypeTag(BtfTypeTag { type_id, .. }) => vec![*type_id],
+                BtfType::Array(t) => vec![t.val_type_id],
+                BtfType::Struct(t) | BtfType::Union(t) => {
+                    self.members(t).iter().map(|m| m.type_id).collect()
+                }
+                _ => Vec::new(),
+            }
+        };
+        let mut state = vec![OrderState::NotVisited; self.types.len()];
+        for root in 1..type_cnt {
+            if state[root as usize] != OrderState::NotVisited {
+                continue;
+            }
+            let mut stack = vec![(root, value_refs(self.type_by_id(root)))];
+            state[root as usize] = OrderState::Visiting;
+            while let Some((id, refs)) = stack.last_mut() {
+                match refs.pop() {
+                    Some(ref_id) => match state[ref_id as usize] {
+                        OrderState::NotVisited => {
+                            state[ref_id as usize] = OrderState::Visiting;
+                            let refs = value_refs(self.type_by_id(ref_id));
+                            stack.push((ref_id, refs));
+                        }
+                        OrderState::Visiting => {
+                            return btf_error(format!(
+                                "Type reference cycle through [{}] {:?} '{}'",
+                                ref_id,
+                                self.type_by_id(ref_id).kind(),
+                                self.type_by_id(ref_id).name()
+                            ))
+                        }
+                        OrderState::Visited => {}
+                    },
+                    None => {
+                        state[*id as usize] = OrderState::Visited;
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        // members have to fit into struct/union (as kernel enforces as well), otherwise sizes of
+        // padding, layouts, etc. computed from them overflow or are meaningless
+        for (id, t) in self.types.iter().enumerate() {
+            if let BtfType::Struct(c) | BtfType::Union(c) = t {
+                for m in self.members(c) {
+                    let m_bits = if m.bit_size == 0 {
+                        u64::from(self.get_size_of(m.type_id)) * 8
+                    } else {
+                        u64::from(m.bit_size)
+                    };
+                    if u64::from(m.bit_offset) + m_bits > u64::from(c.sz) * 8 {
+                        return btf_error(format!(
+                            "Member '{}' of [{}] {:?} '{}' exceeds its size of {} bytes",
+                            m.name,
+                            id,
+                            t.kind(),
+                            t.name(),
+                            c.sz
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Load BTF from ELF and validate() it.
     pub fn load(elf: &object::File<'a>) -> BtfResult<Btf<'a>> {
+        let btf = Btf::load_unvalidated(elf, None)?;
+        btf.validate()?;
+        Ok(btf)
+    }
+
+    /// Load split BTF from ELF (e.g., kernel module), on top of given base BTF.
+    pub fn load_split(elf: &object::File<'a>, base: &Btf<'a>) -> BtfResult<Btf<'a>> {
+        let btf = Btf::load_unvalidated(elf, Some(base))?;
+        btf.validate()?;
+        Ok(btf)
+    }
+
+    /// Same as load()/load_split(), but without validate(), for callers that have to adjust
+    /// loaded BTF first (override pointer size, replace dangling references) and validate it
+    /// themselves. Until then, type graph might have dangling references and cycles.
+    pub fn load_unvalidated(elf: &object::File<'a>, base: Option<&Btf<'a>>) -> BtfResult<Btf<'a>> {
         let endian = if elf.is_little_endian() {
             scroll::LE
         } else {
             scroll::BE
         };
-        let mut btf = Btf::<'a> {
-            endian: endian,
-            ptr_sz: if elf.is_64() { 8 } else { 4 },
-            types: vec![BtfType::Void],
-            has_ext: false,
-            func_secs: Vec::new(),
-            line_secs: Vec::new(),
-            core_reloc_secs: Vec::new(),
+        let ptr_sz = if elf.is_64() { 8 } else { 4 };
+
+        // relocatable module BTF is split BTF on top of distilled base, which takes precedence
+        // over provided base BTF, as split BTF's type IDs refer to distilled base types
+        let distilled_base = match elf.section_by_name(BTF_BASE_ELF_SEC) {
+            Some(sec) => {
+                let data = sec.data()?;
+                let mut base = Btf::new_empty(endian, ptr_sz, None)?;
+                base.load_types(data)?;
+                Some(Box::new(base))
+            }
+            None => None,
         };
+        let base = distilled_base.as_deref().or(base);
+        let mut btf = Btf::new_empty(endian, ptr_sz, base)?;
+        btf.distilled_base = distilled_base;
 
         let btf_section = elf
             .section_by_name(BTF_ELF_SEC)
-            .ok_or_else(|| Box::new(BtfError::new("No .BTF section found!")))?;
-        let data = match btf_section.data() {
-            Ok(d) => d,
-            _ => panic!("expected borrowed data"),
-        };
-        let hdr = data.pread_with::<btf_header>(0, endian)?;
-        if hdr.magic != BTF_MAGIC {
-            return btf_error(format!("Invalid BTF magic: {}", hdr.magic));
-        }
-        if hdr.version != BTF_VERSION {
-            return btf_error(format!(
-                "Unsupported BTF version: {}, expect: {}",
-                hdr.version, BTF_VERSION
-            ));
-        }
-
-        let str_off = (hdr.hdr_len + hdr.str_off) as usize;
-        let str_data = &data[str_off..str_off + hdr.str_len as usize];
-
-        let type_off = (hdr.hdr_len + hdr.type_off) as usize;
-        let type_data = &data[type_off..type_off + hdr.type_len as usize];
-        let mut off: usize = 0;
-        while off < hdr.type_len as usize {
-            let t = btf.load_type(&type_data[off..], str_data)?;
-            off += Btf::type_size(&t);
-            btf.types.push(t);
-        }
+            .ok_or_else(|| BtfError::new("No .BTF section found!"))?;
+        let data = btf_section.data()?;
+        let str_data = btf.load_types(data)?;
 
         if let Some(ext_section) = elf.section_by_name(BTF_EXT_ELF_SEC) {
             btf.has_ext = true;
-            let ext_data = match ext_section.data() {
-                Ok(d) => d,
-                _ => panic!("expected borrowed data"),
-            };
+            let ext_data = ext_section.data()?;
             let ext_hdr = ext_data.pread_with::<btf_ext_header_v1>(0, endian)?;
             if ext_hdr.magic != BTF_MAGIC {
-                return btf_error(format!("Invalid .BTF.ext magic: {}", ext_hdr.magic));
+                return Err(BtfError::InvalidHeader(format!(
+                    "Invalid .BTF.ext magic: {}",
+                    ext_hdr.magic
+                )));
             }
             if ext_hdr.version != BTF_VERSION {
-                return btf_error(format!(
+                return Err(BtfError::InvalidHeader(format!(
                     "Unsupported .BTF.ext version: {}, expect: {}",
                     ext_hdr.version, BTF_VERSION
-                ));
+                )));
             }
             let ext_hdr2 = if ext_hdr.hdr_len >= size_of::<btf_ext_header_v2>() as u32 {
                 Some(ext_data.pread_with::<btf_ext_header_v2>(0, endian)?)
@@ -1152,19 +1929,34 @@ impl<'a> Btf<'a> {
                 None
             };
             if ext_hdr.func_info_len > 0 {
-                let func_off = (ext_hdr.hdr_len + ext_hdr.func_info_off) as usize;
-                let func_data = &ext_data[func_off..func_off + ext_hdr.func_info_len as usize];
+                let func_off = u64::from(ext_hdr.hdr_len) + u64::from(ext_hdr.func_info_off);
+                let func_data = sub_slice(
+                    ext_data,
+                    func_off,
+                    ext_hdr.func_info_len,
+                    "BTF.ext func info",
+                )?;
                 btf.func_secs = btf.load_func_secs(func_data, str_data)?;
             }
             if ext_hdr.line_info_len > 0 {
-                let line_off = (ext_hdr.hdr_len + ext_hdr.line_info_off) as usize;
-                let line_data = &ext_data[line_off..line_off + ext_hdr.line_info_len as usize];
+                let line_off = u64::from(ext_hdr.hdr_len) + u64::from(ext_hdr.line_info_off);
+                let line_data = sub_slice(
+                    ext_data,
+                    line_off,
+                    ext_hdr.line_info_len,
+                    "BTF.ext line info",
+                )?;
                 btf.line_secs = btf.load_line_secs(line_data, str_data)?;
             }
             if let Some(h) = ext_hdr2 {
                 if h.core_reloc_len > 0 {
-                    let reloc_off = (h.hdr_len + h.core_reloc_off) as usize;
-                    let reloc_data = &ext_data[reloc_off..reloc_off + h.core_reloc_len as usize];
+                    let reloc_off = u64::from(h.hdr_len) + u64::from(h.core_reloc_off);
+                    let reloc_data = sub_slice(
+                        ext_data,
+                        reloc_off,
+                        h.core_reloc_len,
+                        "BTF.ext CO-RE relocs",
+                    )?;
                     btf.core_reloc_secs = btf.load_core_reloc_secs(reloc_data, str_data)?;
                 }
             }
@@ -1173,6 +1965,151 @@ impl<'a> Btf<'a> {
         Ok(btf)
     }
 
+    /// Load raw BTF data (e.g., /sys/kernel/btf/vmlinux), not wrapped into ELF.
+    ///
+    /// Raw BTF carries no information about target pointer size, so 64-bit is assumed.
+    pub fn load_raw(data: &'a [u8]) -> BtfResult<Btf<'a>> {
+        let btf = Btf::load_raw_unvalidated(data, None)?;
+        btf.validate()?;
+        Ok(btf)
+    }
+
+    /// Load raw split BTF data (e.g., /sys/kernel/btf/<module>) on top of given base BTF.
+    ///
+    /// Type IDs starting from base's type count and string offsets beyond base's string
+    /// section refer to split BTF itself, everything below is resolved against base BTF.
+    pub fn load_split_raw(data: &'a [u8], base: &Btf<'a>) -> BtfResult<Btf<'a>> {
+        let btf = Btf::load_raw_unvalidated(data, Some(base))?;
+        btf.validate()?;
+        Ok(btf)
+    }
+
+    /// Same as load_raw()/load_split_raw(), but without validate(), see load_unvalidated().
+    pub fn load_raw_unvalidated(data: &'a [u8], base: Option<&Btf<'a>>) -> BtfResult<Btf<'a>> {
+        let endian = match Btf::raw_endian(data) {
+            Some(endian) => endian,
+            None => {
+                return Err(BtfError::InvalidHeader(
+                    "Not a raw BTF data: invalid magic".to_owned(),
+                ))
+            }
+        };
+        let ptr_sz = base.map_or(8, |b| b.ptr_sz);
+        let mut btf = Btf::new_empty(endian, ptr_sz, base)?;
+        btf.load_types(data)?;
+        Ok(btf)
+    }
+
+    pub(crate) fn new_empty(
+        endian: scroll::Endian,
+        ptr_sz: u32,
+        base: Option<&Btf<'a>>,
+    ) -> BtfResult<Btf<'a>> {
+        let mut btf = Btf::<'a> {
+            endian,
+            ptr_sz,
+            strs: &[],
+            raw_data: None,
+            types: vec![BtfType::Void],
+            arena: BtfArena::default(),
+            start_id: 1,
+            base_strs: &[],
+            distilled_base: None,
+            has_ext: false,
+            func_secs: Vec::new(),
+            line_secs: Vec::new(),
+            core_reloc_secs: Vec::new(),
+            placeholders: HashMap::new(),
+        };
+        if let Some(base) = base {
+            if base.is_split() {
+                return btf_error("Base BTF can't be a split BTF itself".to_owned());
+            }
+            if base.endian != endian {
+                return btf_error("Split BTF endianness doesn't match base BTF".to_owned());
+            }
+            btf.types = base.types.clone();
+            btf.arena = base.arena.clone();
+            btf.start_id = base.type_cnt();
+            btf.base_strs = base.strs;
+        }
+        Ok(btf)
+    }
+
+    /// Detect endianness of raw BTF data by its magic, returns None if it's not a raw BTF.
+    pub fn raw_endian(data: &[u8]) -> Option<scroll::Endian> {
+        match data.pread_with::<u16>(0, scroll::LE) {
+            Ok(BTF_MAGIC) => Some(scroll::LE),
+            Ok(m) if m == BTF_MAGIC.swap_bytes() => Some(scroll::BE),
+            _ => None,
+        }
+    }
+
+    pub fn endian(&self) -> scroll::Endian {
+        self.endian
+    }
+
+    pub(crate) fn strs(&self) -> &'a [u8] {
+        self.strs
+    }
+
+    fn load_types(&mut self, data: &'a [u8]) -> BtfResult<&'a [u8]> {
+        let type_data = self.load_sections(data)?;
+        let mut arena = std::mem::take(&mut self.arena);
+        let mut off: usize = 0;
+        while off < type_data.len() {
+            let id = self.types.len() as u32;
+            let t = match self.load_type(id, &type_data[off..], self.strs, &mut arena) {
+                Ok(t) => t,
+                Err(BtfError::Parse(source)) => return Err(BtfError::TruncatedType { id, source }),
+                Err(e) => return Err(e),
+            };
+            off += Btf::type_size(&t);
+            self.types.push(t);
+        }
+        self.types.shrink_to_fit();
+        arena.shrink_to_fit();
+        self.arena = arena;
+        Ok(self.strs)
+    }
+
+    /// Parse BTF header and set up string section, returns type section data.
+    pub(crate) fn load_sections(&mut self, data: &'a [u8]) -> BtfResult<&'a [u8]> {
+        let hdr = data.pread_with::<btf_header>(0, self.endian)?;
+        if hdr.magic != BTF_MAGIC {
+            return Err(BtfError::InvalidHeader(format!(
+                "Invalid BTF magic: {}",
+                hdr.magic
+            )));
+        }
+        if hdr.version != BTF_VERSION {
+            return Err(BtfError::InvalidHeader(format!(
+                "Unsupported BTF version: {}, expect: {}",
+                hdr.version, BTF_VERSION
+            )));
+        }
+
+        let str_off = u64::from(hdr.hdr_len) + u64::from(hdr.str_off);
+        let str_data = sub_slice(data, str_off, hdr.str_len, "BTF string section")?;
+        // validate string section once, so that lookups of in-bounds offsets always find NUL
+        let base_len = self.base_strs.len() as u32;
+        if let (Some(&first), Some(&last)) = (str_data.first(), str_data.last()) {
+            if self.base_strs.is_empty() && first != 0 {
+                return Err(BtfError::BadString { off: 0 });
+            }
+            if last != 0 {
+                return Err(BtfError::BadString {
+                    off: base_len + hdr.str_len - 1,
+                });
+            }
+        }
+        self.strs = str_data;
+        self.raw_data = Some(data);
+
+        let type_off = u64::from(hdr.hdr_len) + u64::from(hdr.type_off);
+        sub_slice(data, type_off, hdr.type_len, "BTF type section")
+    }
+
     pub fn type_size(t: &BtfType) -> usize {
         let common = size_of::<btf_type>();
         match t {
@@ -1197,27 +2134,33 @@ impl<'a> Btf<'a> {
         }
     }
 
-    fn load_type(&self, data: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
+    /// Decode type record, appending its members, params or enumerators to given arena.
+    pub(crate) fn load_type(
+        &self,
+        id: u32,
+        data: &'a [u8],
+        strs: &'a [u8],
+        arena: &mut BtfArena<'a>,
+    ) -> BtfResult<BtfType<'a>> {
         let t = data.pread_with::<btf_type>(0, self.endian)?;
         let extra = &data[size_of::<btf_type>()..];
         let kind = Btf::get_kind(t.info);
         match kind {
-            BTF_KIND_INT => self.load_int(&t, extra, strs),
+            BTF_KIND_INT => self.load_int(id, &t, extra, strs),
             BTF_KIND_PTR => Ok(BtfType::Ptr(BtfPtr { type_id: t.type_id })),
             BTF_KIND_ARRAY => self.load_array(extra),
-            BTF_KIND_STRUCT => self.load_struct(&t, extra, strs),
-            BTF_KIND_UNION => self.load_union(&t, extra, strs),
-            BTF_KIND_ENUM => self.load_enum(&t, extra, strs),
+            BTF_KIND_STRUCT | BTF_KIND_UNION => self.load_composite(&t, extra, strs, arena),
+            BTF_KIND_ENUM => self.load_enum(&t, extra, strs, arena),
             BTF_KIND_FWD => self.load_fwd(&t, strs),
             BTF_KIND_TYPEDEF => Ok(BtfType::Typedef(BtfTypedef {
-                name: Btf::get_btf_str(strs, t.name_off)?,
+                name: self.get_btf_str(strs, t.name_off)?,
                 type_id: t.type_id,
             })),
             BTF_KIND_VOLATILE => Ok(BtfType::Volatile(BtfVolatile { type_id: t.type_id })),
             BTF_KIND_CONST => Ok(BtfType::Const(BtfConst { type_id: t.type_id })),
             BTF_KIND_RESTRICT => Ok(BtfType::Restrict(BtfRestrict { type_id: t.type_id })),
             BTF_KIND_FUNC => Ok(BtfType::Func(BtfFunc {
-                name: Btf::get_btf_str(strs, t.name_off)?,
+                name: self.get_btf_str(strs, t.name_off)?,
                 proto_type_id: t.type_id,
                 kind: match Btf::get_vlen(t.info) {
                     BTF_FUNC_STATIC => BtfFuncKind::Static,
@@ -1226,30 +2169,42 @@ impl<'a> Btf<'a> {
                     _ => BtfFuncKind::Unknown,
                 },
             })),
-            BTF_KIND_FUNC_PROTO => self.load_func_proto(&t, extra, strs),
+            BTF_KIND_FUNC_PROTO => self.load_func_proto(&t, extra, strs, arena),
             BTF_KIND_VAR => self.load_var(&t, extra, strs),
             BTF_KIND_DATASEC => self.load_datasec(&t, extra, strs),
             BTF_KIND_FLOAT => Ok(BtfType::Float(BtfFloat {
-                name: Btf::get_btf_str(strs, t.name_off)?,
+                name: self.get_btf_str(strs, t.name_off)?,
                 sz: t.type_id,
             })),
             BTF_KIND_DECL_TAG => self.load_decl_tag(&t, extra, strs),
             BTF_KIND_TYPE_TAG => Ok(BtfType::TypeTag(BtfTypeTag {
-                name: Btf::get_btf_str(strs, t.name_off)?,
+                name: self.get_btf_str(strs, t.name_off)?,
                 type_id: t.type_id,
             })),
-            BTF_KIND_ENUM64 => self.load_enum64(&t, extra, strs),
-            _ => btf_error(format!("Unknown BTF kind: {}", kind)),
+            BTF_KIND_ENUM64 => self.load_enum64(&t, extra, strs, arena),
+            _ => Err(BtfError::UnknownKind { kind, id }),
         }
     }
 
-    fn load_int(&self, t: &btf_type, extra: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
+    fn load_int(
+        &self,
+        id: u32,
+        t: &btf_type,
+        extra: &'a [u8],
+        strs: &'a [u8],
+    ) -> BtfResult<BtfType<'a>> {
         let info = extra.pread_with::<u32>(0, self.endian)?;
         let enc = (info >> 24) & 0xf;
         let off = (info >> 16) & 0xff;
         let bits = info & 0xff;
+        if off + bits > 128 {
+            return btf_error(format!(
+                "Int [{}] has invalid size of {} bits at bit offset {}",
+                id, bits, off
+            ));
+        }
         Ok(BtfType::Int(BtfInt {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             bits: bits,
             offset: off,
             encoding: match enc {
@@ -1257,9 +2212,7 @@ impl<'a> Btf<'a> {
                 BTF_INT_SIGNED => BtfIntEncoding::Signed,
                 BTF_INT_CHAR => BtfIntEncoding::Char,
                 BTF_INT_BOOL => BtfIntEncoding::Bool,
-                _ => {
-                    return btf_error(format!("Unknown BTF int encoding: {}", enc));
-                }
+                _ => return Err(BtfError::BadIntEncoding { id, enc }),
             },
         }))
     }
@@ -1273,88 +2226,92 @@ impl<'a> Btf<'a> {
         }))
     }
 
-    fn load_struct(&self, t: &btf_type, extra: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
-        Ok(BtfType::Struct(BtfComposite {
-            is_struct: true,
-            name: Btf::get_btf_str(strs, t.name_off)?,
-            sz: t.type_id, // it's a type/size union in C
-            members: self.load_members(t, extra, strs)?,
-        }))
-    }
-
-    fn load_union(&self, t: &btf_type, extra: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
-        Ok(BtfType::Union(BtfComposite {
-            is_struct: false,
-            name: Btf::get_btf_str(strs, t.name_off)?,
-            sz: t.type_id, // it's a type/size union in C
-            members: self.load_members(t, extra, strs)?,
-        }))
-    }
-
-    fn load_members(
+    fn load_composite(
         &self,
         t: &btf_type,
         extra: &'a [u8],
         strs: &'a [u8],
-    ) -> BtfResult<Vec<BtfMember<'a>>> {
-        let mut res = Vec::new();
-        let mut off: usize = 0;
+        arena: &mut BtfArena<'a>,
+    ) -> BtfResult<BtfType<'a>> {
         let bits = Btf::get_kind_flag(t.info);
-
+        let start = arena.members.len() as u32;
+        let mut off: usize = 0;
         for _ in 0..Btf::get_vlen(t.info) {
             let m = extra.pread_with::<btf_member>(off, self.endian)?;
-            res.push(BtfMember {
-                name: Btf::get_btf_str(strs, m.name_off)?,
+            arena.members.push(BtfMember {
+                name: self.get_btf_str(strs, m.name_off)?,
                 type_id: m.type_id,
                 bit_size: if bits { (m.offset >> 24) as u8 } else { 0 },
                 bit_offset: if bits { m.offset & 0xffffff } else { m.offset },
             });
             off += size_of::<btf_member>();
         }
-        Ok(res)
+        let c = BtfComposite {
+            is_struct: Btf::get_kind(t.info) == BTF_KIND_STRUCT,
+            name: self.get_btf_str(strs, t.name_off)?,
+            sz: t.type_id, // it's a type/size union in C
+            bitfield_offs: bits,
+            members: start..arena.members.len() as u32,
+        };
+        Ok(if c.is_struct {
+            BtfType::Struct(c)
+        } else {
+            BtfType::Union(c)
+        })
     }
 
-    fn load_enum(&self, t: &btf_type, extra: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
-        let mut vals = Vec::new();
+    fn load_enum(
+        &self,
+        t: &btf_type,
+        extra: &'a [u8],
+        strs: &'a [u8],
+        arena: &mut BtfArena<'a>,
+    ) -> BtfResult<BtfType<'a>> {
+        let start = arena.enum_values.len() as u32;
         let mut off: usize = 0;
-
         for _ in 0..Btf::get_vlen(t.info) {
             let v = extra.pread_with::<btf_enum>(off, self.endian)?;
-            vals.push(BtfEnumValue {
-                name: Btf::get_btf_str(strs, v.name_off)?,
+            arena.enum_values.push(BtfEnumValue {
+                name: self.get_btf_str(strs, v.name_off)?,
                 value: v.val,
             });
             off += size_of::<btf_enum>();
         }
         Ok(BtfType::Enum(BtfEnum {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             sz: t.type_id, // it's a type/size union in C
-            values: vals,
+            values: start..arena.enum_values.len() as u32,
         }))
     }
 
-    fn load_enum64(&self, t: &btf_type, extra: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
-        let mut vals = Vec::new();
+    fn load_enum64(
+        &self,
+        t: &btf_type,
+        extra: &'a [u8],
+        strs: &'a [u8],
+        arena: &mut BtfArena<'a>,
+    ) -> BtfResult<BtfType<'a>> {
+        let start = arena.enum64_values.len() as u32;
         let mut off: usize = 0;
-
         for _ in 0..Btf::get_vlen(t.info) {
             let v = extra.pread_with::<btf_enum64>(off, self.endian)?;
-            vals.push(BtfEnum64Value {
-                name: Btf::get_btf_str(strs, v.name_off)?,
-                value: i64::from(v.val_lo32) + i64::from(v.val_hi32) << 32,
+            arena.enum64_values.push(BtfEnum64Value {
+                name: self.get_btf_str(strs, v.name_off)?,
+                value: ((u64::from(v.val_hi32) << 32) | u64::from(v.val_lo32)) as i64,
             });
             off += size_of::<btf_enum64>();
         }
         Ok(BtfType::Enum64(BtfEnum64 {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             sz: t.type_id, // it's a type/size union in C
-            values: vals,
+            signed: Btf::get_kind_flag(t.info),
+            values: start..arena.enum64_values.len() as u32,
         }))
     }
 
     fn load_fwd(&self, t: &btf_type, strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
         Ok(BtfType::Fwd(BtfFwd {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             kind: if Btf::get_kind_flag(t.info) {
                 BtfFwdKind::Union
             } else {
@@ -1368,28 +2325,28 @@ impl<'a> Btf<'a> {
         t: &btf_type,
         extra: &'a [u8],
         strs: &'a [u8],
+        arena: &mut BtfArena<'a>,
     ) -> BtfResult<BtfType<'a>> {
-        let mut params = Vec::new();
+        let start = arena.params.len() as u32;
         let mut off: usize = 0;
-
         for _ in 0..Btf::get_vlen(t.info) {
             let p = extra.pread_with::<btf_param>(off, self.endian)?;
-            params.push(BtfFuncParam {
-                name: Btf::get_btf_str(strs, p.name_off)?,
+            arena.params.push(BtfFuncParam {
+                name: self.get_btf_str(strs, p.name_off)?,
                 type_id: p.type_id,
             });
             off += size_of::<btf_param>();
         }
         Ok(BtfType::FuncProto(BtfFuncProto {
             res_type_id: t.type_id,
-            params: params,
+            params: start..arena.params.len() as u32,
         }))
     }
 
     fn load_var(&self, t: &btf_type, extra: &'a [u8], strs: &'a [u8]) -> BtfResult<BtfType<'a>> {
         let kind = extra.pread_with::<u32>(0, self.endian)?;
         Ok(BtfType::Var(BtfVar {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             type_id: t.type_id,
             kind: match kind {
                 BTF_VAR_STATIC => BtfVarKind::Static,
@@ -1408,7 +2365,7 @@ impl<'a> Btf<'a> {
         extra: &'a [u8],
         strs: &'a [u8],
     ) -> BtfResult<BtfType<'a>> {
-        let mut vars = Vec::new();
+        let mut vars = Vec::with_capacity(Btf::vlen_capacity::<btf_datasec_var>(t, extra));
         let mut off: usize = 0;
 
         for _ in 0..Btf::get_vlen(t.info) {
@@ -1421,7 +2378,7 @@ impl<'a> Btf<'a> {
             off += size_of::<btf_datasec_var>();
         }
         Ok(BtfType::Datasec(BtfDatasec {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             sz: t.type_id, // it's a type/size union in C
             vars: vars,
         }))
@@ -1435,17 +2392,41 @@ impl<'a> Btf<'a> {
     ) -> BtfResult<BtfType<'a>> {
         let comp_idx = extra.pread_with::<u32>(0, self.endian)?;
         Ok(BtfType::DeclTag(BtfDeclTag {
-            name: Btf::get_btf_str(strs, t.name_off)?,
+            name: self.get_btf_str(strs, t.name_off)?,
             type_id: t.type_id,
             comp_idx: comp_idx,
         }))
     }
 
+    /// Size of type record with given btf_type::info, without decoding it, None for unknown kind.
+    pub(crate) fn raw_type_size(info: u32) -> Option<usize> {
+        let common = size_of::<btf_type>();
+        let vlen = Btf::get_vlen(info) as usize;
+        Some(match Btf::get_kind(info) {
+            BTF_KIND_PTR | BTF_KIND_FWD | BTF_KIND_TYPEDEF | BTF_KIND_VOLATILE | BTF_KIND_CONST
+            | BTF_KIND_RESTRICT | BTF_KIND_FUNC | BTF_KIND_FLOAT | BTF_KIND_TYPE_TAG => common,
+            BTF_KIND_INT | BTF_KIND_VAR | BTF_KIND_DECL_TAG => common + size_of::<u32>(),
+            BTF_KIND_ARRAY => common + size_of::<btf_array>(),
+            BTF_KIND_STRUCT | BTF_KIND_UNION => common + vlen * size_of::<btf_member>(),
+            BTF_KIND_ENUM => common + vlen * size_of::<btf_enum>(),
+            BTF_KIND_ENUM64 => common + vlen * size_of::<btf_enum64>(),
+            BTF_KIND_FUNC_PROTO => common + vlen * size_of::<btf_param>(),
+            BTF_KIND_DATASEC => common + vlen * size_of::<btf_datasec_var>(),
+            _ => return None,
+        })
+    }
+
     fn get_vlen(info: u32) -> u32 {
         info & 0xffff
     }
 
-    fn get_kind(info: u32) -> u32 {
+    /// Exact number of vlen records to preallocate, bounded by what extra data can fit, so
+    /// that corrupted vlen doesn't cause huge allocations.
+    fn vlen_capacity<T>(t: &btf_type, extra: &[u8]) -> usize {
+        (Btf::get_vlen(t.info) as usize).min(extra.len() / size_of::<T>())
+    }
+
+    pub(crate) fn get_kind(info: u32) -> u32 {
         (info >> 24) & 0x1f
     }
 
@@ -1473,22 +2454,23 @@ impl<'a> Btf<'a> {
             let sec_hdr = data.pread_with::<btf_ext_info_sec>(0, self.endian)?;
             data = &data[size_of::<btf_ext_info_sec>()..];
 
+            let recs_len = u64::from(sec_hdr.num_info) * u64::from(rec_sz);
+            let recs_data = sub_slice(data, 0, recs_len, "BTF.ext records")?;
             let mut recs = Vec::new();
-            for i in 0..sec_hdr.num_info {
-                let off = (i * rec_sz) as usize;
-                let rec = data.pread_with::<btf_ext_func_info>(off, self.endian)?;
+            for off in (0..recs_data.len()).step_by(rec_sz as usize) {
+                let rec = recs_data.pread_with::<btf_ext_func_info>(off, self.endian)?;
                 recs.push(BtfExtFunc {
                     insn_off: rec.insn_off,
                     type_id: rec.type_id,
                 });
             }
             secs.push(BtfExtSection::<BtfExtFunc> {
-                name: Btf::get_btf_str(strs, sec_hdr.sec_name_off)?,
+                name: self.get_btf_str(strs, sec_hdr.sec_name_off)?,
                 rec_sz: rec_sz as usize,
                 recs: recs,
             });
 
-            data = &data[(sec_hdr.num_info * rec_sz) as usize..];
+            data = &data[recs_data.len()..];
         }
         Ok(secs)
     }
@@ -1512,25 +2494,26 @@ impl<'a> Btf<'a> {
             let sec_hdr = data.pread_with::<btf_ext_info_sec>(0, self.endian)?;
             data = &data[size_of::<btf_ext_info_sec>()..];
 
+            let recs_len = u64::from(sec_hdr.num_info) * u64::from(rec_sz);
+            let recs_data = sub_slice(data, 0, recs_len, "BTF.ext records")?;
             let mut recs = Vec::new();
-            for i in 0..sec_hdr.num_info {
-                let off = (i * rec_sz) as usize;
-                let rec = data.pread_with::<btf_ext_line_info>(off, self.endian)?;
+            for off in (0..recs_data.len()).step_by(rec_sz as usize) {
+                let rec = recs_data.pread_with::<btf_ext_line_info>(off, self.endian)?;
                 recs.push(BtfExtLine {
                     insn_off: rec.insn_off,
-                    file_name: Btf::get_btf_str(strs, rec.file_name_off)?,
-                    src_line: Btf::get_btf_str(strs, rec.line_off)?,
+                    file_name: self.get_btf_str(strs, rec.file_name_off)?,
+                    src_line: self.get_btf_str(strs, rec.line_off)?,
                     line_num: rec.line_col >> 10,
                     col_num: rec.line_col & 0x3ff,
                 });
             }
             secs.push(BtfExtSection::<BtfExtLine> {
-                name: Btf::get_btf_str(strs, sec_hdr.sec_name_off)?,
+                name: self.get_btf_str(strs, sec_hdr.sec_name_off)?,
                 rec_sz: rec_sz as usize,
                 recs: recs,
             });
 
-            data = &data[(sec_hdr.num_info * rec_sz) as usize..];
+            data = &data[recs_data.len()..];
         }
         Ok(secs)
     }
@@ -1554,10 +2537,11 @@ impl<'a> Btf<'a> {
             let sec_hdr = data.pread_with::<btf_ext_info_sec>(0, self.endian)?;
             data = &data[size_of::<btf_ext_info_sec>()..];
 
+            let recs_len = u64::from(sec_hdr.num_info) * u64::from(rec_sz);
+            let recs_data = sub_slice(data, 0, recs_len, "BTF.ext records")?;
             let mut recs = Vec::new();
-            for i in 0..sec_hdr.num_info {
-                let off = (i * rec_sz) as usize;
-                let rec = data.pread_with::<btf_ext_core_reloc>(off, self.endian)?;
+            for off in (0..recs_data.len()).step_by(rec_sz as usize) {
+                let rec = recs_data.pread_with::<btf_ext_core_reloc>(off, self.endian)?;
                 let kind = match rec.kind {
                     BTF_FIELD_BYTE_OFFSET => BtfCoreRelocKind::ByteOff,
                     BTF_FIELD_BYTE_SIZE => BtfCoreRelocKind::ByteSz,
@@ -1577,7 +2561,7 @@ impl<'a> Btf<'a> {
                     }
                 };
                 let relo = {
-                    let access_spec_str = Btf::get_btf_str(strs, rec.access_spec_off)?;
+                    let access_spec_str = self.get_btf_str(strs, rec.access_spec_off)?;
                     let access_spec = Btf::parse_reloc_access_spec(&access_spec_str)?;
                     BtfExtCoreReloc {
                         insn_off: rec.insn_off,
@@ -1590,12 +2574,12 @@ impl<'a> Btf<'a> {
                 recs.push(relo);
             }
             secs.push(BtfExtSection::<BtfExtCoreReloc> {
-                name: Btf::get_btf_str(strs, sec_hdr.sec_name_off)?,
+                name: self.get_btf_str(strs, sec_hdr.sec_name_off)?,
                 rec_sz: rec_sz as usize,
                 recs: recs,
             });
 
-            data = &data[(sec_hdr.num_info * rec_sz) as usize..];
+            data = &data[recs_data.len()..];
         }
         Ok(secs)
     }
@@ -1607,8 +2591,33 @@ impl<'a> Btf<'a> {
         Ok(spec)
     }
 
-    fn get_btf_str(strs: &[u8], off: u32) -> BtfResult<&str> {
-        let c_str = unsafe { CStr::from_ptr(&strs[off as usize] as *const u8 as *const c_char) };
-        Ok(c_str.to_str()?)
+    pub(crate) fn get_btf_str(&self, strs: &'a [u8], off: u32) -> BtfResult<&'a str> {
+        let base_len = self.base_strs.len() as u32;
+        let (strs, rel_off) = if off < base_len {
+            (self.base_strs, off)
+        } else {
+            (strs, off - base_len)
+        };
+        let s = match strs.get(rel_off as usize..) {
+            Some(s) if !s.is_empty() => s,
+            _ => return Err(BtfError::BadStringOffset { off }),
+        };
+        // string sections are validated to be NUL-terminated at load time
+        let len = s.iter().position(|&b| b == 0).unwrap_or(s.len());
+        std::str::from_utf8(&s[..len]).map_err(|_| BtfError::BadString { off })
+    }
+}
+
+/// Bounds-checked sub-slice of `len` bytes at `off`, described as `what` in error message.
+fn sub_slice<'d>(data: &'d [u8], off: u64, len: impl Into<u64>, what: &str) -> BtfResult<&'d [u8]> {
+    let end = off + len.into();
+    if end > data.len() as u64 {
+        return Err(BtfError::OutOfBounds {
+            what: what.to_owned(),
+            off,
+            end,
+            len: data.len(),
+        });
     }
+    Ok(&data[off as usize..end as usize])
 }
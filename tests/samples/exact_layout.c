@@ -0,0 +1,38 @@
+enum small {
+	SMALL_A,
+	SMALL_B,
+} __attribute__((packed));
+
+struct aligned {
+	int a;
+	int b __attribute__((aligned(16)));
+} __attribute__((aligned(32)));
+
+struct bits {
+	char c;
+	long x: 60;
+	int: 0;
+	short y: 3;
+	enum small e;
+	struct aligned nested;
+};
+
+union big {
+	int a;
+	char b;
+} __attribute__((aligned(16)));
+
+struct holder {
+	char c;
+	union big u;
+	struct {
+		char x;
+		long y;
+	} __attribute__((aligned(64))) anon;
+};
+
+int main() {
+	static struct bits bits;
+	static struct holder holder;
+	return 0;
+}
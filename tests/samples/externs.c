@@ -0,0 +1,18 @@
+#define __ksym __attribute__((section(".ksyms")))
+#define __kconfig __attribute__((section(".kconfig")))
+#define __weak __attribute__((weak))
+
+struct rq {
+	int cpu;
+};
+
+extern int LINUX_KERNEL_VERSION __kconfig;
+extern _Bool CONFIG_BPF_SYSCALL __kconfig __weak;
+extern const struct rq runqueues __ksym;
+extern const void bpf_prog_active __ksym __weak;
+extern struct rq *bpf_rq_acquire(struct rq *rq) __ksym;
+
+int main() {
+	return LINUX_KERNEL_VERSION + CONFIG_BPF_SYSCALL + runqueues.cpu +
+	       (long)&bpf_prog_active + (long)bpf_rq_acquire(0);
+}
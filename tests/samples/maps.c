@@ -0,0 +1,38 @@
+#define __uint(name, val) int (*name)[val]
+#define __type(name, val) typeof(val) *name
+#define __array(name, val) typeof(val) *name[]
+#define SEC(name) __attribute__((section(name), used))
+
+struct val { int a; long b; };
+typedef unsigned int u32;
+
+struct {
+	__uint(type, 1);
+	__uint(max_entries, 1024);
+	__type(key, u32);
+	__type(value, struct val);
+} hash_map SEC(".maps");
+
+struct inner {
+	__uint(type, 2);
+	__uint(max_entries, 1);
+	__type(key, int);
+	__type(value, int);
+};
+
+struct {
+	__uint(type, 12);
+	__uint(max_entries, 4);
+	__type(key, int);
+	__array(values, struct inner);
+} outer SEC(".maps") = { .values = { 0 } };
+
+static struct {
+	__uint(type, 27);
+	__uint(max_entries, 4096);
+} rb SEC(".maps");
+
+int x SEC(".data") = 1;
+int main() {
+	return (long)&rb + x;
+}
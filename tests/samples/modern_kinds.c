@@ -0,0 +1,32 @@
+#define __tag(x) __attribute__((btf_type_tag(x)))
+#define __decl_tag(x) __attribute__((btf_decl_tag(x)))
+
+enum e64 {
+	E64_SMALL = 1,
+	E64_BIG = 0xffffffffffffffffULL,
+};
+
+enum e64_neg {
+	E64_NEG = -1,
+	E64_POS = 0x100000000LL,
+};
+
+struct f {
+	float a;
+	double b;
+	long double c;
+};
+
+struct tags {
+	int __tag("user") *a;
+	const int __tag("rcu") __tag("percpu") *b;
+	struct f __tag("user") *c;
+	int d __decl_tag("member");
+} __decl_tag("struct");
+
+int main() {
+	static enum e64 e64;
+	static enum e64_neg e64_neg;
+	static struct tags tags;
+	return 0;
+}
@@ -66,7 +66,7 @@ fn dump() {
                 status,
                 stdout,
                 stderr,
-            } = cmd.arg("dump").arg(dst).output().unwrap();
+            } = cmd.arg("dump").arg(&dst).output().unwrap();
             let stdout = std::str::from_utf8(&stdout);
             let stderr = std::str::from_utf8(&stderr);
             assert_eq!(
@@ -82,5 +82,677 @@ fn dump() {
             assert!(!stdout.is_empty(), "{:?}", stdout);
             assert!(stderr.is_empty(), "{:?}", stderr);
         }
+
+        // Compile exact layout dump, with asserts checking it against BTF.
+        {
+            let header = dst.with_extension("h");
+            let mut cmd = std::process::Command::new(env!("CARGO_BIN_EXE_btf"));
+            let output = cmd
+                .args(["dump", "-f", "c", "--exact-layout", "--emit-asserts"])
+                .arg(&dst)
+                .output()
+                .unwrap();
+            assert_eq!(output.status.code(), Some(0), "{:?} failed", cmd);
+            std::fs::write(&header, output.stdout).unwrap();
+
+            let mut cmd = std::process::Command::new("clang");
+            let std::process::Output { status, stderr, .. } = cmd
+                .args(["-target", bpf_arch, "-nostdinc", "-fsyntax-only"])
+                .arg(&header)
+                .output()
+                .unwrap();
+            assert_eq!(
+                status.code(),
+                Some(0),
+                "{:?} failed: stderr={:?}",
+                cmd,
+                std::str::from_utf8(&stderr)
+            );
+        }
+    }
+}
+
+/// Commands that don't fail on samples that can't be loaded: `stat` reports parse errors as part
+/// of its output, `dump --lenient` replaces what it can't resolve with placeholders, `convert`
+/// wraps or extracts BTF sections without parsing them, `ids` and `dump --lazy` decode only the
+/// types they look up, and `dump -b`/`reloc -m` load the sample as split BTF, which shifts its
+/// type IDs and string offsets and so can turn corrupted references into valid ones.
+const CORRUPT_TOLERANT: &[&[&str]] = &[
+    &["stat"],
+    &["dump", "-f", "c", "--lenient"],
+    &["convert"],
+    &["ids"],
+    &["dump", "--lazy"],
+    &["dump", "-b"],
+    &["reloc", "-m"],
+];
+
+/// Samples whose corruption leaves .BTF and .BTF.ext loadable (bit flips in string data, CO-RE
+/// relocations with wrong access specs, etc.), so commands are allowed to succeed on them.
+const CORRUPT_LOADABLE: &[&str] = &[
+    "bitflip_00.btf",
+    "bitflip_02.btf",
+    "bitflip_03.btf",
+    "bitflip_03_ext.o",
+    "bitflip_04.btf",
+    "bitflip_05.btf",
+    "bitflip_09.btf",
+    "bitflip_10.btf",
+    "ext_reloc_spec_mismatch.o",
+    "ext_reloc_spec_range.o",
+];
+
+/// Every command should report an error on malformed BTF/BTF.ext instead of panicking, hanging
+/// or silently accepting it.
+///
+/// Samples in tests/corrupt are derived from a small valid BTF (tests/corrupt/ref/core.o) by
+/// truncation, bit flips and targeted corruption of header offsets, vlens, type and string
+/// references. Failing commands have to exit with 1 and print an error.
+#[test]
+fn corrupt() {
+    let manifest_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let corrupt_dir = manifest_dir.join("tests").join("corrupt");
+    let good = corrupt_dir.join("ref").join("core.o");
+    let good = good.to_str().unwrap();
+    let tempdir = tempfile::tempdir().unwrap();
+    let out = tempdir.path().join("out");
+    let out = out.to_str().unwrap();
+    let err_path = tempdir.path().join("stderr");
+    let hub = tempdir.path().join("hub");
+    std::fs::create_dir(&hub).unwrap();
+
+    let mut samples = corrupt_dir
+        .read_dir()
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.is_dir())
+        .collect::<Vec<_>>();
+    samples.sort();
+    assert!(!samples.is_empty());
+    for path in &samples {
+        std::fs::copy(
+            path,
+            hub.join(path.file_name().unwrap()).with_extension("btf"),
+        )
+        .unwrap();
+    }
+    let hub = hub.to_str().unwrap();
+
+    let mut violations = Vec::new();
+    for path in &samples {
+        let f = path.to_str().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let loadable = CORRUPT_LOADABLE.contains(&name);
+        let kernel = format!("6.0={}", f);
+        let cmds: &[&[&str]] = &[
+            &["dump", f],
+            &["dump", "-f", "c", f],
+            &["dump", "-f", "c", "--keep-going", f],
+            &["dump", "-f", "c", "--lenient", f],
+            &["dump", "-d", "all", f],
+            &["dump", "-d", "exts", "--sec", "^tp", f],
+            &["dump", "-d", "relocs", f],
+            &["dump", "-b", good, f],
+            &["dump", "--referenced-by", "list_head", f],
+            &["dump", "--name-contains", "tskstr", "--fuzzy", f],
+            &["dump", "-t", "struct", "-n", "task", "--match-any", f],
+            &["dump", "--min-size", "64", "--max-size", "4096", f],
+            &["dump", "-i", "-n", "^TASK_", f],
+            &["dump", "-t", "struct", "--member-name", "^pid$", f],
+            &["dump", "--shape", "next:list_head*,prev,...", f],
+            &["dump", "--at-offset", "task_struct:8", f],
+            &["dump", "-f", "c", "-t", "struct", "--deps", "fwd-only", f],
+            &["dump", "--where", "kind==struct && size>64 || vlen>=3", f],
+            &["ids", f, "task_struct", "1"],
+            &["dump", "--lazy", "-n", "task", f],
+            &["reloc", good, f],
+            &["reloc", f, good],
+            &["reloc", "--apply", "-o", out, good, f],
+            &["reloc", "--apply", "--poison", "-o", out, good, f],
+            &["reloc", "-m", f, good, good],
+            &["reloc", "--cache", out, good, f],
+            &["reloc", "--cache", out, f, good],
+            &["strip", "--name", "task", "-o", out, f],
+            &["extract", "--name", "task", "-o", out, f],
+            &["elf-relocs", f],
+            &[
+                "multi-header",
+                "-k",
+                &format!("5.0={}", good),
+                "-k",
+                &kernel,
+            ],
+            &["merge", "-o", out, good, f],
+            &["sanitize", "-o", out, f],
+            &["diff-type", good, f, "task_struct"],
+            &["patch", "--rename", "task_struct=x", "-o", out, f],
+            &["convert", "-o", out, f],
+            &["dedup", "-o", out, f],
+            &["relocate", "-b", good, "-o", out, f],
+            &["stat", f],
+            &["check", f],
+            &["preflight", "-k", good, f],
+            &["min-core-btf", "-k", f, "-o", out, good],
+            &["min-core-btf", "-k", good, "-o", out, f],
+            &["btfhub", "-o", out, hub, good],
+            &["reloc-matrix", good, hub],
+            &["reloc-matrix", f, hub],
+            &["verify-core", good, f, good],
+            &["verify-core", f, good],
+            &["unused", f],
+        ];
+        for args in cmds {
+            let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_btf"))
+                .args(args.iter())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::fs::File::create(&err_path).unwrap())
+                .spawn()
+                .unwrap();
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+            let status = loop {
+                if let Some(status) = child.try_wait().unwrap() {
+                    break status;
+                }
+                if std::time::Instant::now() > deadline {
+                    child.kill().unwrap();
+                    panic!("btf {:?} timed out", args);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            };
+            let stderr = std::fs::read_to_string(&err_path).unwrap();
+            let has_error = stderr
+                .lines()
+                .any(|l| l.starts_with("Error:") && !l["Error:".len()..].trim().is_empty());
+            let tolerant = CORRUPT_TOLERANT.iter().any(|t| args.starts_with(t));
+            match status.code() {
+                Some(0) if loadable || tolerant => {}
+                Some(0) => violations.push(format!("btf {:?} accepted corrupt sample", args)),
+                Some(1) if has_error => {}
+                Some(1) => violations.push(format!("btf {:?} failed without error", args)),
+                _ => violations.push(format!("btf {:?} crashed: {}: {}", args, status, stderr)),
+            }
+        }
+    }
+    assert!(violations.is_empty(), "{}", violations.join("\n"));
+}
+
+/// CO-RE relocation results should be the same as libbpf's.
+///
+/// tests/reloc/core_compat.o uses `struct list_head` through typedefs `lh_t` and `lh_ptr_t`
+/// (pointer to it) and as a field of `struct task_struct`, while in target BTF
+/// (tests/reloc/core_compat_targ.btf) `list_head` is a union and `task_struct` has an extra
+/// field in front. libbpf's type compatibility requires the same kind, so both typedefs don't
+/// exist in target, but field compatibility accepts any struct/union, so field is relocated.
+/// Target BTF packed the way BTFHub does it (.btf.tar.xz) should give the same results.
+#[test]
+fn reloc() {
+    let manifest_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reloc_dir = manifest_dir.join("tests").join("reloc");
+
+    for targ in ["core_compat_targ.btf", "core_compat_targ.btf.tar.xz"] {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_btf"))
+            .arg("reloc")
+            .arg(reloc_dir.join(targ))
+            .arg(reloc_dir.join("core_compat.o"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0), "{}: {:?}", targ, output);
+        assert_eq!(
+            std::str::from_utf8(&output.stdout).unwrap(),
+            concat!(
+                "sec#0, r#0: type_exists [3] = 1 --> [0] = 0\n",
+                "sec#0, r#1: type_exists [5] = 1 --> [0] = 0\n",
+                "sec#0, r#2: [6] + 8 (0:1:0) --> [6] + 16 (0:2:0), byte_off: 8 --> 16 (sz: 4 --> 4)\n",
+                "sec#0, r#3: type_exists [6] = 1 --> [6] = 1\n",
+            ),
+            "{}",
+            targ
+        );
+    }
+}
+
+/// Run btf with given arguments, expecting it to succeed, and return its stdout.
+fn run_btf(args: &[&str]) -> String {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_btf"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(0),
+        "btf {:?}: {:?}",
+        args,
+        output
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// BTF written out by commands producing raw BTF or ELF should load back with expected types.
+///
+/// All commands start from tests/corrupt/ref/core.o, except for `relocate`, which needs split
+/// BTF with distilled base: tests/roundtrip/module.o has `struct task_wrap` (embedding
+/// `struct sched_entity`) and a pointer to it on top of a distilled base with `int` and
+/// `sched_entity`, which are relocated onto core.o's types.
+#[test]
+fn roundtrip() {
+    let manifest_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let core = manifest_dir
+        .join("tests")
+        .join("corrupt")
+        .join("ref")
+        .join("core.o");
+    let core = core.to_str().unwrap();
+    let module = manifest_dir
+        .join("tests")
+        .join("roundtrip")
+        .join("module.o");
+    let module = module.to_str().unwrap();
+    let tempdir = tempfile::tempdir().unwrap();
+    let out = tempdir.path().join("out");
+    let out = out.to_str().unwrap();
+    let elf = tempdir.path().join("out.o");
+    let elf = elf.to_str().unwrap();
+
+    let core_types = concat!(
+        "#0: <VOID>\n",
+        "#1: <INT> 'int' bits:32 off:0 enc:signed\n",
+        "#2: <INT> 'long long unsigned int' bits:64 off:0\n",
+        "#3: <STRUCT> 'sched_entity' sz:8 n:1\n",
+        "\t#00 'vruntime' off:0 --> [2]\n",
+        "#4: <STRUCT> 'task_struct' sz:16 n:2\n",
+        "\t#00 'pid' off:0 --> [1]\n",
+        "\t#01 'se' off:64 --> [3]\n",
+    );
+    let list_head = "#5: <STRUCT> 'list_head' sz:16 n:0\n";
+    let all_types = format!("{}{}", core_types, list_head);
+
+    let cases: &[(&[&str], &[&str], String)] = &[
+        (
+            &["strip", "-n", "^list_head$", "-o", out, core],
+            &["dump", out],
+            core_types.to_owned(),
+        ),
+        (
+            &["extract", "-n", "^task_struct$", "-o", out, core],
+            &["dump", out],
+            core_types.to_owned(),
+        ),
+        (
+            &["merge", "--dedup", "-o", out, core, core],
+            &["dump", out],
+            all_types.clone(),
+        ),
+        (
+            &["sanitize", "-o", out, core],
+            &["dump", out],
+            all_types.clone(),
+        ),
+        (
+            &["convert", "-o", out, core],
+            &["dump", out],
+            all_types.clone(),
+        ),
+        (
+            &["convert", "-o", elf, out],
+            &["dump", elf],
+            all_types.clone(),
+        ),
+        (
+            &[
+                "patch",
+                "--rename",
+                "task_struct=task",
+                "--retype",
+                "task.pid=2",
+                "--resize",
+                "list_head=24",
+                "-o",
+                out,
+                core,
+            ],
+            &["dump", out],
+            concat!(
+                "#0: <VOID>\n",
+                "#1: <INT> 'int' bits:32 off:0 enc:signed\n",
+                "#2: <INT> 'long long unsigned int' bits:64 off:0\n",
+                "#3: <STRUCT> 'sched_entity' sz:8 n:1\n",
+                "\t#00 'vruntime' off:0 --> [2]\n",
+                "#4: <STRUCT> 'task' sz:16 n:2\n",
+                "\t#00 'pid' off:0 --> [2]\n",
+                "\t#01 'se' off:64 --> [3]\n",
+                "#5: <STRUCT> 'list_head' sz:24 n:0\n",
+            )
+            .to_owned(),
+        ),
+        (
+            &["relocate", "-b", core, "-o", out, module],
+            &["dump", "-b", core, out],
+            concat!(
+                "#6: <STRUCT> 'task_wrap' sz:16 n:2\n",
+                "\t#00 'pid' off:0 --> [1]\n",
+                "\t#01 'se' off:64 --> [3]\n",
+                "#7: <PTR> --> [6]\n",
+            )
+            .to_owned(),
+        ),
+    ];
+    for (write, dump, expected) in cases {
+        run_btf(write);
+        assert_eq!(&run_btf(dump), expected, "btf {:?}", write);
+    }
+}
+
+/// C output options that rename or restructure declarations should produce expected C.
+///
+/// tests/cdump/types.btf has a typedef of a kernel integer type (`u32`), an enum, a packed struct,
+/// a struct with members out of name order embedding an anonymous struct, and two different
+/// structs named `dup`.
+#[test]
+fn c_dump_options() {
+    let manifest_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let types = manifest_dir.join("tests").join("cdump").join("types.btf");
+    let types = types.to_str().unwrap();
+
+    let cases: &[(&[&str], &str)] = &[
+        (
+            &[],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "\n",
+                "enum state {\n",
+                "\tRUNNING = 0,\n",
+                "\tSTOPPED = 4,\n",
+                "};\n",
+                "\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\tu32 len;\n",
+                "} __attribute__((packed));\n",
+                "\n",
+                "struct task {\n",
+                "\tu32 zpid;\n",
+                "\tenum state st;\n",
+                "\tstruct {\n",
+                "\t\tint x;\n",
+                "\t} a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___2 {\n",
+                "\tu32 b;\n",
+                "\tu32 c;\n",
+                "};\n",
+                "\n",
+            ),
+        ),
+        (
+            &["--enums-as-macros"],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "\n",
+                "/* enum state */\n",
+                "#define RUNNING 0\n",
+                "#define STOPPED 4\n",
+                "\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\tu32 len;\n",
+                "} __attribute__((packed));\n",
+                "\n",
+                "struct task {\n",
+                "\tu32 zpid;\n",
+                "\tunsigned int st;\n",
+                "\tstruct {\n",
+                "\t\tint x;\n",
+                "\t} a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___2 {\n",
+                "\tu32 b;\n",
+                "\tu32 c;\n",
+                "};\n",
+                "\n",
+            ),
+        ),
+        (
+            &["--pack-style", "pragma"],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "\n",
+                "enum state {\n",
+                "\tRUNNING = 0,\n",
+                "\tSTOPPED = 4,\n",
+                "};\n",
+                "\n",
+                "#pragma pack(push, 1)\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\tu32 len;\n",
+                "};\n",
+                "#pragma pack(pop)\n",
+                "\n",
+                "struct task {\n",
+                "\tu32 zpid;\n",
+                "\tenum state st;\n",
+                "\tstruct {\n",
+                "\t\tint x;\n",
+                "\t} a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___2 {\n",
+                "\tu32 b;\n",
+                "\tu32 c;\n",
+                "};\n",
+                "\n",
+            ),
+        ),
+        (
+            &["--lang", "c++"],
+            concat!(
+                "extern \"C\" {\n",
+                "\n",
+                "typedef unsigned int u32;\n",
+                "\n",
+                "enum state {\n",
+                "\tRUNNING = 0,\n",
+                "\tSTOPPED = 4,\n",
+                "};\n",
+                "\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\t::u32 len;\n",
+                "} __attribute__((packed));\n",
+                "\n",
+                "struct task {\n",
+                "\t::u32 zpid;\n",
+                "\tenum state st;\n",
+                "\tstruct {\n",
+                "\t\tint x;\n",
+                "\t} a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___2 {\n",
+                "\t::u32 b;\n",
+                "\t::u32 c;\n",
+                "};\n",
+                "\n",
+                "} /* extern \"C\" */\n",
+            ),
+        ),
+        (
+            &["--std-int-types"],
+            concat!(
+                "typedef signed char __s8;\n",
+                "typedef unsigned char __u8;\n",
+                "typedef short __s16;\n",
+                "typedef unsigned short __u16;\n",
+                "typedef int __s32;\n",
+                "typedef unsigned int __u32;\n",
+                "typedef long long __s64;\n",
+                "typedef unsigned long long __u64;\n",
+                "typedef signed char s8;\n",
+                "typedef unsigned char u8;\n",
+                "typedef short s16;\n",
+                "typedef unsigned short u16;\n",
+                "typedef int s32;\n",
+                "typedef unsigned int u32;\n",
+                "typedef long long s64;\n",
+                "typedef unsigned long long u64;\n",
+                "typedef unsigned short __le16;\n",
+                "typedef unsigned short __be16;\n",
+                "typedef unsigned int __le32;\n",
+                "typedef unsigned int __be32;\n",
+                "typedef unsigned long long __le64;\n",
+                "typedef unsigned long long __be64;\n",
+                "\n",
+                "enum state {\n",
+                "\tRUNNING = 0,\n",
+                "\tSTOPPED = 4,\n",
+                "};\n",
+                "\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\tu32 len;\n",
+                "} __attribute__((packed));\n",
+                "\n",
+                "struct task {\n",
+                "\tu32 zpid;\n",
+                "\tenum state st;\n",
+                "\tstruct {\n",
+                "\t\tint x;\n",
+                "\t} a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___2 {\n",
+                "\tu32 b;\n",
+                "\tu32 c;\n",
+                "};\n",
+                "\n",
+            ),
+        ),
+        (
+            &["--hash-suffixes"],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "\n",
+                "enum state {\n",
+                "\tRUNNING = 0,\n",
+                "\tSTOPPED = 4,\n",
+                "};\n",
+                "\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\tu32 len;\n",
+                "} __attribute__((packed));\n",
+                "\n",
+                "struct task {\n",
+                "\tu32 zpid;\n",
+                "\tenum state st;\n",
+                "\tstruct {\n",
+                "\t\tint x;\n",
+                "\t} a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___d4996f1b {\n",
+                "\tu32 b;\n",
+                "\tu32 c;\n",
+                "};\n",
+                "\n",
+            ),
+        ),
+        (
+            &["--diff-friendly"],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "enum state { RUNNING = 0, STOPPED = 4, };\n",
+                "struct pkt { unsigned char tag; u32 len; } __attribute__((packed));\n",
+                "struct task { u32 zpid; enum state st; struct { int x; } a; struct pkt p; };\n",
+                "struct dup { int a; };\n",
+                "struct dup___2 { u32 b; u32 c; };\n",
+            ),
+        ),
+        (
+            &["--diff-friendly", "--sort-members"],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "enum state { RUNNING = 0, STOPPED = 4, };\n",
+                "struct pkt { u32 len; unsigned char tag; } __attribute__((packed));\n",
+                "struct task { struct { int x; } a; struct pkt p; enum state st; u32 zpid; };\n",
+                "struct dup { int a; };\n",
+                "struct dup___2 { u32 b; u32 c; };\n",
+            ),
+        ),
+        (
+            &["--name-anon"],
+            concat!(
+                "typedef unsigned int u32;\n",
+                "\n",
+                "enum state {\n",
+                "\tRUNNING = 0,\n",
+                "\tSTOPPED = 4,\n",
+                "};\n",
+                "\n",
+                "struct anon_struct__cb38cd5e75561a1e {\n",
+                "\tint x;\n",
+                "};\n",
+                "\n",
+                "struct pkt {\n",
+                "\tunsigned char tag;\n",
+                "\tu32 len;\n",
+                "} __attribute__((packed));\n",
+                "\n",
+                "struct task {\n",
+                "\tu32 zpid;\n",
+                "\tenum state st;\n",
+                "\tstruct anon_struct__cb38cd5e75561a1e a;\n",
+                "\tstruct pkt p;\n",
+                "};\n",
+                "\n",
+                "struct dup {\n",
+                "\tint a;\n",
+                "};\n",
+                "\n",
+                "struct dup___2 {\n",
+                "\tu32 b;\n",
+                "\tu32 c;\n",
+                "};\n",
+                "\n",
+            ),
+        ),
+    ];
+    for (opts, expected) in cases {
+        let mut args = vec!["dump", "-f", "c"];
+        args.extend_from_slice(opts);
+        args.push(types);
+        assert_eq!(run_btf(&args), *expected, "{:?}", opts);
     }
 }